@@ -0,0 +1,540 @@
+use std::{collections::BTreeMap, path::{Path, PathBuf}, sync::atomic::Ordering, time::Instant};
+
+use serde_json::{Map, Value};
+use tracing::warn;
+use twee_parser::{parse_archive, parse_twee3, serialize_html_with, xmltree::EmitterConfig, HtmlOptions, Passage, PassageOrder, Pipeline, Report, StartFallback, Story, StoryTransform, TransformError, Warning};
+
+use crate::{check_metadata, includes::set_include_graph, includes::display_path, provenance::{clear_passage_sources, record_source, record_twee_sources, set_source, twee_sources}, passage_source, timings::{clear_timings, record_timing}, DuplicatePassages, Error, PassageSource, IncludeGraph, IncludeKind, glob, is_url, join_url, read_remote, RemoteDirs, print_warning, read_source, Config, WARNING_COUNT};
+
+/// Reads a file named in the file at `path`, relative to it, or the remote file if the name is a URL or `path` is the
+/// URL of a remote file. Names in remote files are always resolved against their URL, so they can't read local files.
+/// Returns the path, which is the URL for remote files, with the contents.
+fn read_relative(name: &str, path: &Path, remote: &RemoteDirs) -> anyhow::Result<(PathBuf, String)> {
+    let base = path.to_string_lossy();
+    if is_url(name) || is_url(&base) {
+        let url = if is_url(&base) { join_url(&base, name) } else { name.to_string() };
+        return Ok((PathBuf::from(&url), read_remote(remote, &url)?));
+    }
+    let file = path.parent().unwrap_or(Path::new("")).join(name);
+    let contents = read_source(&file)?;
+    Ok((file, contents))
+}
+
+/// Reads the files matching an include pattern from the file at `path`, or the remote file if the pattern is a URL
+/// or `path` is the URL of a remote file. Returns the paths, which are the URLs for remote files, with the contents.
+fn read_include(pattern: &str, path: &Path, remote: &RemoteDirs) -> anyhow::Result<Vec<(PathBuf, String)>> {
+    if is_url(pattern) || is_url(&path.to_string_lossy()) {
+        return Ok(vec![read_relative(pattern, path, remote)?]);
+    }
+    let files = glob(pattern, path.parent().unwrap().to_path_buf())?;
+    if files.is_empty() {
+        warn!(code = "no-match", "No matching file found for pattern: {}", pattern);
+    }
+    files.into_iter().map(|f| {
+        let contents = read_source(&f)?;
+        Ok((f, contents))
+    }).collect()
+}
+
+/// Adds the passages included from the file to the story. A passage the story already has is a conflict: it fails
+/// the build or replaces the earlier passage in place, depending on `duplicates`.
+fn merge_passages(story: &mut Story, passages: Vec<Passage>, file: &Path, sources: &BTreeMap<String, PassageSource>, duplicates: DuplicatePassages) -> anyhow::Result<()> {
+    for p in passages {
+        let Some(i) = story.passages.iter().position(|e| e.name == p.name) else {
+            story.passages.push(p);
+            continue;
+        };
+        let new = sources.get(&p.name).cloned().unwrap_or(PassageSource { file: display_path(file), line: None });
+        let old = passage_source(&p.name).map(|o| o.to_string()).unwrap_or("an earlier file".to_string());
+        if duplicates == DuplicatePassages::Error {
+            return Err(Error::PassageConflict(p.name, old, new.to_string()).into());
+        }
+        WARNING_COUNT.fetch_add(1, Ordering::Relaxed);
+        warn!(code = "passage-conflict", passage = p.name, "Passage \"{}\" is defined in both {} and {}, using the last one.", p.name, old, new);
+        set_source(&p.name, new);
+        story.passages[i] = p;
+    }
+    Ok(())
+}
+
+/// Adds the passages of the twee files matching the pattern to the story, skipping files that were already included.
+fn include_twee(story: &mut Story, pattern: &str, path: &Path, kind: IncludeKind, graph: &mut IncludeGraph, remote: &RemoteDirs, duplicates: DuplicatePassages) -> anyhow::Result<()> {
+    let explicit = ! pattern.contains(['*', '?', '[']);
+    for (twee, contents) in read_include(pattern, path, remote)? {
+        if graph.enter(&twee, kind, explicit)? {
+            let (mut part, warnings) = parse_twee3(&contents)?;
+            for w in warnings {
+                match &w {
+                    Warning::StoryMetadataMalformed => {},
+                    Warning::StoryTitleMissing => {},
+                    _ => print_warning(w)
+                }
+            }
+            record_twee_sources(&twee, &contents);
+            process_story_fragment(&mut part, &twee, graph, remote, duplicates)?;
+            graph.leave();
+            merge_passages(story, part.passages, &twee, &twee_sources(&twee, &contents), duplicates)?;
+        }
+    }
+    Ok(())
+}
+
+/// Reads the files matching an include pattern as the content of a passage.
+fn include_content(pattern: &str, path: &Path, graph: &mut IncludeGraph, remote: &RemoteDirs) -> anyhow::Result<String> {
+    let mut content = String::new();
+    for (f, contents) in read_include(pattern, path, remote)? {
+        graph.content(&f);
+        content += &contents;
+    }
+    Ok(content)
+}
+
+fn process_story_fragment(story: &mut Story, path: &Path, graph: &mut IncludeGraph, remote: &RemoteDirs, duplicates: DuplicatePassages) -> anyhow::Result<()> {
+    for p in &mut story.passages {
+        if let Some(i) = p.tags.iter().position(|t| t == "twee-cmd") {
+            p.tags.remove(i);
+            if let Some(contents) = serde_json::from_str::<serde_json::Value>(&p.content)?.as_array() {
+                p.content = String::new();
+                for v in contents {
+                    match v {
+                        Value::String(s) => {
+                            p.content += s;
+                        },
+                        Value::Object(m) => {
+                            if let Some(s) = m.get("include").and_then(|i| i.as_str()) {
+                                p.content += &include_content(s, path, graph, remote)?;
+                                continue;
+                            }
+                            warn!(code = "twee-cmd-unknown", "[twee-cmd] entry was not a recognized command and has been discarded");
+                        }
+                        _ => {
+                            warn!(code = "twee-cmd-invalid", "[twee-cmd] entry was neither a string nor an object and has been discarded");
+                        }
+                    }
+                }
+            } else {
+                warn!(code = "twee-cmd-malformed", "[twee-cmd] passage is not a JSON array and has been discarded");
+            }
+        }
+        if let Some(Value::String(f)) = p.meta.get("include") {
+            p.content = include_content(f, path, graph, remote)?;
+            p.meta.remove("include");
+        }
+        if let Some(Value::Array(f)) = p.meta.get("include") {
+            p.content = String::new();
+            for f in f {
+                if let Some(s) = f.as_str() {
+                    p.content += &include_content(s, path, graph, remote)?;
+                } else {
+                    warn!(code = "include-invalid", "include entry wasn\'t a string and has been ignored: {}", serde_json::to_string(f)?);
+                }
+            }
+            p.meta.remove("include");
+        }
+        if let Some(Value::String(f)) = p.meta.get("include-before") {
+            let (f, contents) = read_relative(f, path, remote)?;
+            graph.content(&f);
+            p.content = contents + &p.content;
+            p.meta.remove("include-before");
+        }
+        if let Some(Value::String(f)) = p.meta.get("include-after") {
+            let (f, contents) = read_relative(f, path, remote)?;
+            graph.content(&f);
+            p.content += &contents;
+            p.meta.remove("include-after");
+        }
+        if let Some(Value::String(f)) = p.meta.get("prepend") {
+            p.content = f.clone() + &p.content;
+            p.meta.remove("prepend");
+        }
+        if let Some(Value::String(f)) = p.meta.get("append") {
+            p.content += f;
+            p.meta.remove("append");
+        }
+    }
+    if let Some(p) = story.passages.iter().position(|p| p.name == "TweeTools") {
+        let p = story.passages.remove(p);
+        if let Some(contents) = serde_json::from_str::<serde_json::Value>(&p.content)?.as_object() {
+            if let Some(includes) = contents.get("include").and_then(|i| i.as_array()) {
+                for i in includes {
+                    if let Some(s) = i.as_str() {
+                        include_twee(story, s, path, IncludeKind::Twee, graph, remote, duplicates)?;
+                    } else {
+                        warn!(code = "include-invalid", "include entry wasn\'t a string and has been ignored: {}", serde_json::to_string(i)?);
+                    }
+                }
+            }
+            if let Some(includes) = contents.get("include-archive").and_then(|i| i.as_array()) {
+                for i in includes {
+                    if let Some(f) = i.as_str() {
+                        let (f, source) = read_relative(f, path, remote)?;
+                        if ! graph.enter(&f, IncludeKind::Archive, true)? {
+                            continue;
+                        }
+                        let stories = parse_archive(&source)?;
+                        for s in stories {
+                            let (mut part, warnings) = s;
+                            for w in warnings {
+                                match &w {
+                                    Warning::StoryMetadataMalformed => {},
+                                    Warning::StoryTitleMissing => {},
+                                    _ => print_warning(w)
+                                }
+                            }
+                            for p in &part.passages {
+                                record_source(&p.name, &f, None);
+                            }
+                            process_story_fragment(&mut part, &f, graph, remote, duplicates)?;
+                            merge_passages(story, part.passages, &f, &BTreeMap::new(), duplicates)?;
+                        }
+                        graph.leave();
+                    } else {
+                        warn!(code = "include-invalid", "include entry wasn\'t a string and has been ignored: {}", serde_json::to_string(i)?);
+                    }
+                }
+            }
+        } else {
+            warn!(code = "tweetools-malformed", "TweeTools passage is not a JSON object and has been discarded");
+        }
+    }
+    Ok(())
+}
+
+/// Serializes the story into a &lt;tw-storydata&gt; tag.
+pub fn storydata_html(story: &Story, config: &Config) -> anyhow::Result<String> {
+    let mut html: Vec<u8> = Vec::new();
+    let options = HtmlOptions { start: StartFallback::First, order: (&config.passage_order).into() };
+    let (storydata, warnings) = serialize_html_with(story, &options)?;
+    for w in warnings {
+        print_warning(w);
+    }
+    storydata.write_with_config(&mut html, EmitterConfig {
+        normalize_empty_elements: false,
+        write_document_declaration: false,
+        ..Default::default()})?;
+    Ok(String::from_utf8(html)?)
+}
+
+/// Reads the main twee file and runs the transforms of the build: debug mode, includes, table of contents, scripts
+/// and stylesheets.
+pub fn build_story(config: &Config, debug: bool) -> Result<Story, anyhow::Error> {
+    clear_timings();
+    let start = Instant::now();
+    let twee = read_source(&config.main)?;
+    let (mut story, warnings) = parse_twee3(&twee)?;
+    record_timing("parse", start.elapsed());
+    clear_passage_sources();
+    record_twee_sources(Path::new(&config.main), &twee);
+    for w in warnings {
+        print_warning(w);
+    }
+    if story.title.is_empty() {
+        story.title = "Story".to_string();
+    }
+    let report = Pipeline::new()
+        .then(DebugMode(debug))
+        .then(Includes {
+            main: PathBuf::from(&config.main),
+            include: config.include.clone(),
+            remote: RemoteDirs::of(config),
+            duplicates: config.duplicate_passages,
+        })
+        .then(TableOfContents {
+            tag: config.toc.tag.clone(),
+            passage: config.toc.passage.clone().unwrap_or("Contents".to_string()),
+            order: (&config.toc.order).into(),
+        })
+        .then(AttachFiles { tag: "script", files: config.script.clone() })
+        .then(AttachFiles { tag: "stylesheet", files: config.style.clone() })
+        .run(&mut story)?;
+    for (stage, time) in &report.timings {
+        record_timing(stage, *time);
+    }
+    let start = Instant::now();
+    for w in report.warnings.into_iter().chain(story.validate()) {
+        print_warning(w);
+    }
+    for p in check_metadata(&config.metadata, &story) {
+        WARNING_COUNT.fetch_add(1, Ordering::Relaxed);
+        match passage_source(&p.passage) {
+            Some(source) => warn!(code = "metadata-schema", passage = p.passage, source = %source, "Passage \"{}\" ({}): {}.", p.passage, source, p.message),
+            None => warn!(code = "metadata-schema", passage = p.passage, "Passage \"{}\": {}.", p.passage, p.message),
+        }
+    }
+    record_timing("validate", start.elapsed());
+    Ok(story)
+}
+
+/// Turns on the debug mode of the story format.
+pub struct DebugMode(pub bool);
+
+impl StoryTransform for DebugMode {
+    fn name(&self) -> &str {
+        "debug"
+    }
+    
+    fn transform(&self, story: &mut Story) -> Result<Report, TransformError> {
+        let mut options = story.options();
+        if self.0 && ! options.iter().any(|o| o == "debug") {
+            options.push("debug".to_string());
+            story.set_options(&options);
+        }
+        Ok(Report::default())
+    }
+}
+
+/// Processes the includes and `twee-cmd` passages of the story read from the main twee file, then includes the
+/// twee files from the `include` config value. Twee files and archives are included only once, and including a file
+/// that includes itself again is an error naming the chain of includes. The files are recorded in the
+/// [include graph](crate::include_graph).
+pub struct Includes {
+    pub main: PathBuf,
+    pub include: Vec<String>,
+    /// Where remote includes are read from.
+    pub remote: RemoteDirs,
+    /// What happens when included files define the same passage.
+    pub duplicates: DuplicatePassages,
+}
+
+impl StoryTransform for Includes {
+    fn name(&self) -> &str {
+        "includes"
+    }
+    
+    fn transform(&self, story: &mut Story) -> Result<Report, TransformError> {
+        let mut graph = IncludeGraph::default();
+        graph.enter(&self.main, IncludeKind::Main, true)?;
+        process_story_fragment(story, &self.main, &mut graph, &self.remote, self.duplicates)?;
+        graph.leave();
+        for pattern in &self.include {
+            include_twee(story, pattern, Path::new("config.toml"), IncludeKind::Config, &mut graph, &self.remote, self.duplicates)?;
+        }
+        set_include_graph(graph);
+        Ok(Report::default())
+    }
+}
+
+/// Generates a passage with links to all passages with the tag, replacing the content of the passage if it exists.
+pub struct TableOfContents {
+    pub tag: Option<String>,
+    pub passage: String,
+    pub order: PassageOrder,
+}
+
+impl StoryTransform for TableOfContents {
+    fn name(&self) -> &str {
+        "toc"
+    }
+    
+    fn transform(&self, story: &mut Story) -> Result<Report, TransformError> {
+        let Some(tag) = &self.tag else {
+            return Ok(Report::default());
+        };
+        let mut passages: Vec<&Passage> = story.passages.iter().filter(|p| p.name != self.passage && p.tags.contains(tag)).collect();
+        self.order.sort(&mut passages);
+        let content = passages.iter().map(|p| format!("[[{}]]", p.name)).collect::<Vec<_>>().join("\n");
+        if let Some(p) = story.passages.iter_mut().find(|p| p.name == self.passage) {
+            p.content = content;
+        } else {
+            story.passages.push(Passage { name: self.passage.clone(), tags: vec![], meta: Map::new(), content });
+        }
+        Ok(Report::default())
+    }
+}
+
+/// Adds the files as passages with the tag, numbered like `script1`.
+pub struct AttachFiles {
+    pub tag: &'static str,
+    pub files: Vec<String>,
+}
+
+impl StoryTransform for AttachFiles {
+    fn name(&self) -> &str {
+        self.tag
+    }
+    
+    fn transform(&self, story: &mut Story) -> Result<Report, TransformError> {
+        for (i, f) in self.files.iter().enumerate() {
+            let name = self.tag.to_string() + &(i + 1).to_string();
+            record_source(&name, Path::new(f), Some(1));
+            story.passages.push(Passage {
+                name,
+                tags: vec![self.tag.to_string()],
+                meta: Map::new(),
+                content: read_source(f)?
+            });
+        }
+        Ok(Report::default())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::{remote::cache_key, vendor_remote};
+
+    use super::*;
+    
+    /// Puts a fresh copy of the URL into the cache, so it's read without a request.
+    fn cache_remote(cache: &Path, url: &str, body: &str) {
+        let dir = cache.join("remote");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join(cache_key(url) + ".json"), serde_json::json!({ "url": url, "etag": null, "expires": u64::MAX }).to_string()).unwrap();
+        std::fs::write(dir.join(cache_key(url) + ".body"), body).unwrap();
+    }
+    
+    fn fragment(source: &str, path: &Path, remote: &RemoteDirs) -> anyhow::Result<Story> {
+        let mut story = parse_twee3(source)?.0;
+        process_story_fragment(&mut story, path, &mut IncludeGraph::default(), remote, DuplicatePassages::LastWins)?;
+        Ok(story)
+    }
+    
+    #[test]
+    fn remote_fragment_includes_resolve_against_url() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let remote = RemoteDirs { vendor: dir.join("vendor"), cache: dir.join("cache") };
+        std::fs::write(dir.join("local.txt"), "local").unwrap();
+        cache_remote(&remote.cache, "https://example.com/lib/local.txt", "remote");
+        let source = ":: A {\"include-before\": \"local.txt\", \"include-after\": \"local.txt\"}\n text\n";
+        let story = fragment(source, Path::new("https://example.com/lib/story.twee"), &remote).unwrap();
+        assert_eq!(story.passage("A").unwrap().content, "remote textremote");
+        // Local files next to the fragment are read for local fragments.
+        let story = fragment(source, &dir.join("story.twee"), &remote).unwrap();
+        assert_eq!(story.passage("A").unwrap().content, "local textlocal");
+    }
+    
+    #[test]
+    fn remote_fragment_cant_read_local_files() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let secret = dir.join("secret.txt");
+        std::fs::write(&secret, "secret").unwrap();
+        let remote = RemoteDirs { vendor: dir.join("vendor"), cache: dir.join("cache") };
+        let secret = secret.to_string_lossy();
+        // Absolute paths are root-relative in a remote file, so they're read from its server.
+        cache_remote(&remote.cache, &format!("https://example.com{}", secret), "remote");
+        let story = fragment(&format!(":: A {{\"include-after\": {:?}}}\n", secret), Path::new("https://example.com/lib/story.twee"), &remote).unwrap();
+        assert_eq!(story.passage("A").unwrap().content, "remote");
+    }
+    
+    #[test]
+    fn vendored_remote_includes_are_preferred() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let remote = RemoteDirs { vendor: dir.join("vendor"), cache: dir.join("cache") };
+        let url = "https://example.com/lib/text.txt";
+        cache_remote(&remote.cache, url, "downloaded");
+        let source = format!(":: A {{\"include\": {:?}}}\n", url);
+        assert_eq!(fragment(&source, &dir.join("story.twee"), &remote).unwrap().passage("A").unwrap().content, "downloaded");
+        let vendored = vendor_remote(&remote, url).unwrap();
+        assert!(vendored.starts_with(&remote.vendor));
+        // The vendored copy is used even when the cache is gone, and is kept if the remote file changes.
+        cache_remote(&remote.cache, url, "changed");
+        std::fs::write(&vendored, "vendored").unwrap();
+        assert_eq!(fragment(&source, &dir.join("story.twee"), &remote).unwrap().passage("A").unwrap().content, "vendored");
+        std::fs::remove_dir_all(&remote.cache).unwrap();
+        assert_eq!(fragment(&source, &dir.join("story.twee"), &remote).unwrap().passage("A").unwrap().content, "vendored");
+    }
+    
+    #[test]
+    fn include_cycles_fail() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        std::fs::write(dir.join("a.twee"), ":: TweeTools\n{\"include\": [\"b.twee\"]}\n").unwrap();
+        std::fs::write(dir.join("b.twee"), ":: TweeTools\n{\"include\": [\"a.twee\"]}\n").unwrap();
+        std::fs::write(dir.join("self.twee"), ":: TweeTools\n{\"include\": [\"self.twee\"]}\n").unwrap();
+        for (main, chain) in [("a.twee", vec!["a.twee", "b.twee", "a.twee"]), ("self.twee", vec!["self.twee", "self.twee"])] {
+            let main = dir.join(main);
+            let mut graph = IncludeGraph::default();
+            graph.enter(&main, IncludeKind::Main, true).unwrap();
+            let mut story = parse_twee3(&read_source(&main).unwrap()).unwrap().0;
+            let error = process_story_fragment(&mut story, &main, &mut graph, &RemoteDirs::default(), DuplicatePassages::LastWins).unwrap_err();
+            let files: Vec<String> = chain.iter().map(|f| display_path(&dir.join(f).canonicalize().unwrap())).collect();
+            assert_eq!(error.to_string(), Error::IncludeCycle(files.join(" -> ")).to_string());
+        }
+    }
+    
+    #[test]
+    fn include_patterns_in_dirs_with_glob_characters() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().join("proj [v2]");
+        std::fs::create_dir_all(dir.join("parts")).unwrap();
+        std::fs::write(dir.join("config.toml"), "style = []\nscript = []\nprebuild = []\nmain = \"main.twee\"\ninclude = [\"parts/*.twee\"]\n").unwrap();
+        std::fs::write(dir.join("main.twee"), ":: Start\nstart\n\n:: TweeTools\n{\"include\": [\"parts/b*.twee\"]}\n").unwrap();
+        std::fs::write(dir.join("parts").join("a.twee"), ":: A\na\n").unwrap();
+        std::fs::write(dir.join("parts").join("b.twee"), ":: B\nb\n").unwrap();
+        let story = build_story(&crate::load_config_in(&dir).unwrap(), false).unwrap();
+        assert!(story.passage("A").is_some());
+        assert!(story.passage("B").is_some());
+    }
+    
+    #[test]
+    fn builds_read_changed_sources() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        std::fs::write(dir.join("config.toml"), "style = []\nscript = []\nprebuild = []\nmain = \"main.twee\"\ninclude = [\"part.twee\"]\n").unwrap();
+        std::fs::write(dir.join("main.twee"), ":: Start\nstart\n").unwrap();
+        std::fs::write(dir.join("part.twee"), ":: Part\nbefore\n").unwrap();
+        let project = crate::Project::load(dir.join("config.toml")).unwrap();
+        let part = || project.story(&crate::Profile::default()).unwrap().passage("Part").unwrap().content.clone();
+        assert_eq!(part(), "before");
+        std::fs::write(dir.join("part.twee"), ":: Part\nafter\n").unwrap();
+        assert_eq!(part(), "after");
+        // Builds in the same session share the files read.
+        let cache = crate::SourceCache::new();
+        assert_eq!(cache.enter(part), "after");
+        std::fs::write(dir.join("part.twee"), ":: Part\nagain\n").unwrap();
+        assert_eq!(cache.enter(part), "after");
+        assert_eq!(part(), "again");
+    }
+    
+    /// The log of a closure, without colors.
+    fn logged<T>(f: impl FnOnce() -> T) -> (T, String) {
+        #[derive(Clone, Default)]
+        struct Log(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+        impl std::io::Write for Log {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.lock().unwrap().write(buf)
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+        let log = Log::default();
+        let writer = log.clone();
+        let subscriber = tracing_subscriber::fmt().with_ansi(false).with_writer(move || writer.clone()).finish();
+        let res = tracing::subscriber::with_default(subscriber, f);
+        let log = String::from_utf8(log.0.lock().unwrap().clone()).unwrap();
+        (res, log)
+    }
+    
+    #[test]
+    fn passage_conflicts() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        std::fs::write(dir.join("a.twee"), ":: Shared\nfrom a\n").unwrap();
+        std::fs::write(dir.join("b.twee"), ":: Other\nb\n\n:: Shared\nfrom b\n").unwrap();
+        let main = dir.join("main.twee");
+        std::fs::write(&main, ":: TweeTools\n{\"include\": [\"a.twee\", \"b.twee\"]}\n").unwrap();
+        let include = |duplicates| {
+            clear_passage_sources();
+            let mut story = parse_twee3(&read_source(&main).unwrap()).unwrap().0;
+            let mut graph = IncludeGraph::default();
+            graph.enter(&main, IncludeKind::Main, true).unwrap();
+            process_story_fragment(&mut story, &main, &mut graph, &RemoteDirs::default(), duplicates).map(|_| story)
+        };
+        let a = format!("{}:1", display_path(&dir.join("a.twee").canonicalize().unwrap()));
+        let b = format!("{}:4", display_path(&dir.join("b.twee").canonicalize().unwrap()));
+        
+        let warnings = WARNING_COUNT.load(Ordering::Relaxed);
+        let (story, log) = logged(|| include(DuplicatePassages::LastWins).unwrap());
+        assert_eq!(story.passages.iter().map(|p| (p.name.as_str(), p.content.as_str())).collect::<Vec<_>>(), [("Shared", "from b"), ("Other", "b")]);
+        assert!(log.contains(&format!("Passage \"Shared\" is defined in both {} and {}, using the last one.", a, b)), "{}", log);
+        assert!(WARNING_COUNT.load(Ordering::Relaxed) > warnings);
+        assert_eq!(passage_source("Shared").unwrap().to_string(), b);
+        
+        let error = include(DuplicatePassages::Error).unwrap_err();
+        assert_eq!(error.to_string(), Error::PassageConflict("Shared".to_string(), a, b).to_string());
+    }
+}
@@ -0,0 +1,225 @@
+use std::collections::{HashMap, HashSet};
+
+use twee_parser::Story;
+
+use crate::{storydata_html, Config};
+
+const DECOMPRESS_SHIM: &str = include_str!("../templates/compress/decompress.js");
+
+const BASE64: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/=";
+
+/// The state of the lz-string compression.
+struct Compressor {
+    dictionary: HashMap<Vec<u16>, u32>,
+    /// Single characters that are in the dictionary, but haven't been written yet.
+    to_create: HashSet<Vec<u16>>,
+    enlarge_in: u32,
+    num_bits: u32,
+    out: String,
+    value: u32,
+    position: u32,
+}
+
+impl Compressor {
+    /// Writes the lowest `n` bits of the value, lowest first, 6 bits per base64 character.
+    fn push(&mut self, mut value: u32, n: u32) {
+        for _ in 0..n {
+            self.value = (self.value << 1) | (value & 1);
+            value >>= 1;
+            if self.position == 5 {
+                self.out.push(BASE64[self.value as usize] as char);
+                self.value = 0;
+                self.position = 0;
+            } else {
+                self.position += 1;
+            }
+        }
+    }
+    
+    fn count_entry(&mut self) {
+        self.enlarge_in -= 1;
+        if self.enlarge_in == 0 {
+            self.enlarge_in = 1 << self.num_bits;
+            self.num_bits += 1;
+        }
+    }
+    
+    /// Writes the code of the phrase, or the character itself the first time it's used.
+    fn emit(&mut self, w: &Vec<u16>) {
+        if self.to_create.remove(w) {
+            if w[0] < 256 {
+                self.push(0, self.num_bits);
+                self.push(w[0] as u32, 8);
+            } else {
+                self.push(1, self.num_bits);
+                self.push(w[0] as u32, 16);
+            }
+            self.count_entry();
+        } else {
+            self.push(self.dictionary[w], self.num_bits);
+        }
+        self.count_entry();
+    }
+}
+
+/// Compresses the text like `LZString.compressToBase64` from lz-string, which works on UTF-16 code units.
+pub fn lz_compress(text: &str) -> String {
+    let mut c = Compressor {
+        dictionary: HashMap::new(),
+        to_create: HashSet::new(),
+        enlarge_in: 2,
+        num_bits: 2,
+        out: String::new(),
+        value: 0,
+        position: 0,
+    };
+    let mut dict_size: u32 = 3;
+    let mut w: Vec<u16> = vec![];
+    for unit in text.encode_utf16() {
+        if ! c.dictionary.contains_key(&[unit][..]) {
+            c.dictionary.insert(vec![unit], dict_size);
+            dict_size += 1;
+            c.to_create.insert(vec![unit]);
+        }
+        w.push(unit);
+        if ! c.dictionary.contains_key(&w) {
+            let wc = w.clone();
+            w.pop();
+            c.emit(&w);
+            c.dictionary.insert(wc, dict_size);
+            dict_size += 1;
+            w = vec![unit];
+        }
+    }
+    if ! w.is_empty() {
+        c.emit(&w);
+    }
+    // The end of the stream, flushed like lz-string does, which always adds at least one bit.
+    c.push(2, c.num_bits);
+    loop {
+        c.value <<= 1;
+        if c.position == 5 {
+            c.out.push(BASE64[c.value as usize] as char);
+            break;
+        }
+        c.position += 1;
+    }
+    let padding = (4 - c.out.len() % 4) % 4;
+    c.out + &"=".repeat(padding)
+}
+
+/// Serializes the story into a &lt;tw-storydata&gt; tag with the passage text compressed, followed by a script that
+/// decompresses it before the story format starts. Scripts and stylesheets aren't compressed.  
+/// The tw-storydata gets a `twee-compressed` attribute, as tools other than the bundled script can't read it.
+pub fn compressed_storydata_html(story: &Story, config: &Config) -> anyhow::Result<String> {
+    let mut story = story.clone();
+    for p in &mut story.passages {
+        if ! p.tags.iter().any(|t| t == "script" || t == "stylesheet") {
+            p.content = lz_compress(&p.content);
+        }
+    }
+    story.meta.insert("twee-compressed".to_string(), "lz-string".into());
+    Ok(format!("{}<script>{}</script>", storydata_html(&story, config)?, DECOMPRESS_SHIM))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    /// `LZString.decompressFromBase64`, written like the decompression script.
+    fn lz_decompress(input: &str) -> Option<String> {
+        let values: Vec<u32> = input.bytes().map(|b| BASE64.iter().position(|k| *k == b).unwrap() as u32).collect();
+        let index = std::cell::Cell::new(1);
+        let (mut val, mut position) = (values[0], 32);
+        let mut read = |n: u32| {
+            let mut bits = 0;
+            for power in 0..n {
+                let b = val & position;
+                position >>= 1;
+                if position == 0 {
+                    position = 32;
+                    val = values.get(index.get()).copied().unwrap_or(0);
+                    index.set(index.get() + 1);
+                }
+                bits |= ((b > 0) as u32) << power;
+            }
+            bits
+        };
+        let mut dictionary: Vec<Vec<u16>> = vec![vec![], vec![], vec![]];
+        let (mut enlarge_in, mut num_bits) = (4, 3);
+        let mut w = match read(2) {
+            0 => vec![read(8) as u16],
+            1 => vec![read(16) as u16],
+            _ => return Some(String::new()),
+        };
+        dictionary.push(w.clone());
+        let mut result = w.clone();
+        while index.get() <= values.len() {
+            let mut c = read(num_bits);
+            if c == 0 || c == 1 {
+                let unit = read(if c == 0 { 8 } else { 16 }) as u16;
+                dictionary.push(vec![unit]);
+                c = dictionary.len() as u32 - 1;
+                enlarge_in -= 1;
+            } else if c == 2 {
+                return String::from_utf16(&result).ok();
+            }
+            if enlarge_in == 0 {
+                enlarge_in = 1 << num_bits;
+                num_bits += 1;
+            }
+            let entry = match dictionary.get(c as usize) {
+                Some(entry) => entry.clone(),
+                None if c as usize == dictionary.len() => [w.clone(), vec![w[0]]].concat(),
+                None => return None,
+            };
+            result.extend(&entry);
+            dictionary.push([w, vec![entry[0]]].concat());
+            enlarge_in -= 1;
+            w = entry;
+            if enlarge_in == 0 {
+                enlarge_in = 1 << num_bits;
+                num_bits += 1;
+            }
+        }
+        None
+    }
+    
+    const TEXTS: [&str; 5] = [
+        "a",
+        "Hello, world!",
+        "You go [[north->North]] or [[south->South]].\nYou go north, and then north again.",
+        "Grüße aus Köln, 日本語 and 😀 surrogate pairs 😀😀",
+        "abababababababababababababababababababababababababababababababab",
+    ];
+    
+    #[test]
+    fn lz_round_trip() {
+        for text in TEXTS {
+            assert_eq!(lz_decompress(&lz_compress(text)).as_deref(), Some(text));
+        }
+        let long: String = (0..2000).map(|i| format!("Passage {} links to [[Passage {}]].\n", i, i * 7 % 2000)).collect();
+        assert_eq!(lz_decompress(&lz_compress(&long)), Some(long));
+    }
+    
+    /// Runs the decompression script with node on a story compressed by [compressed_storydata_html]. Run with
+    /// `cargo test -- --ignored` where node is installed.
+    #[test]
+    #[ignore = "needs node"]
+    fn lz_decompression_script() {
+        let compressed: Vec<String> = TEXTS.iter().map(|t| lz_compress(t)).collect();
+        let script = format!(r#"
+            var passages = {}.map(function (t) {{ return {{ textContent: t }}; }});
+            var document = {{ querySelector: function () {{ return {{
+                querySelectorAll: function () {{ return passages; }},
+                removeAttribute: function () {{}},
+            }}; }} }};
+            {}
+            console.log(JSON.stringify(passages.map(function (p) {{ return p.textContent; }})));
+        "#, serde_json::to_string(&compressed).unwrap(), DECOMPRESS_SHIM);
+        let output = std::process::Command::new("node").arg("-e").arg(script).output().expect("node isn't installed");
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        let texts: Vec<String> = serde_json::from_slice(&output.stdout).unwrap();
+        assert_eq!(texts, TEXTS);
+    }
+}
@@ -0,0 +1,777 @@
+use std::{collections::BTreeMap, path::{Path, PathBuf}};
+
+use schemars::{schema_for, JsonSchema};
+use serde::{de::Error as _, Deserialize, Deserializer, Serialize};
+use twee_parser::PassageOrder;
+
+use crate::{glob_in, is_url, Error, Project};
+
+const DEFAULT_CACHE_DIR: &str = ".twee-cache";
+
+/// The config.toml of a story project.
+#[derive(Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// The HTML file to generate. Defaults to the story name in the project directory.
+    pub output: Option<String>,
+    /// The stylesheet files.
+    pub style: Vec<String>,
+    /// The story script files.
+    pub script: Vec<String>,
+    /// The main twee file.
+    pub main: String,
+    /// Globs of more twee files to put into the story, relative to the project directory.
+    #[serde(default)]
+    pub include: Vec<String>,
+    /// The commands to run before building.
+    #[serde(deserialize_with = "build_commands")]
+    #[schemars(with = "Vec<CommandTable>")]
+    pub prebuild: Vec<BuildCommand>,
+    /// The commands to run after writing the output.
+    #[serde(default, deserialize_with = "build_commands")]
+    #[schemars(with = "Vec<CommandTable>")]
+    pub postbuild: Vec<BuildCommand>,
+    /// The directory `twee vendor` copies story formats and remote includes into. Defaults to `vendor`.
+    pub vendor: Option<String>,
+    /// The format.js files of story formats `twee vendor` downloads into the vendor directory, as URLs or paths. For
+    /// format versions that aren't bundled.
+    #[serde(default)]
+    pub formats: Vec<String>,
+    /// The directory caches are kept in. Defaults to `.twee-cache`.
+    pub cache: Option<String>,
+    /// The directories referenced files like images and audio are looked up in. Defaults to the project directory.
+    #[serde(default)]
+    pub asset_roots: Vec<String>,
+    /// Fails the build instead of warning if the story references files that don't exist.
+    #[serde(default)]
+    pub missing_asset_error: bool,
+    /// The maximum size of the output file in bytes.
+    pub max_size: Option<u64>,
+    /// Fails the build instead of warning if the output is bigger than `max_size`.
+    #[serde(default)]
+    pub max_size_error: bool,
+    /// Stamps the story metadata with the build date, git commit, twee-tools version and build profile.
+    #[serde(default)]
+    pub fingerprint: bool,
+    /// The name of a passage to add with the build information.
+    pub fingerprint_passage: Option<String>,
+    /// The creator attribute of the story, or `false` to leave it out. Defaults to twee-tools.
+    pub creator: Option<Creator>,
+    /// The creator-version attribute of the story. Defaults to the twee-tools version.
+    pub creator_version: Option<String>,
+    /// Compresses the passage text, which is decompressed by an injected script when the story is opened.  
+    /// The output isn't standard Twine HTML anymore, so other tools can't read the passages.
+    #[serde(default)]
+    pub compress: bool,
+    /// Lightly encodes the passage text, which is decoded by an injected script when the story is opened, so players
+    /// can't read ahead in a text editor. Not needed with `compress`, which also makes the text unreadable.
+    #[serde(default)]
+    pub obfuscate: bool,
+    /// The order of the passages in the output, which also decides their pids. Defaults to the order in the source.
+    #[serde(default)]
+    pub passage_order: PassageOrderConfig,
+    /// What happens when included files define the same passage. Defaults to using the last one with a warning.
+    #[serde(default)]
+    pub duplicate_passages: DuplicatePassages,
+    /// Options for `twee package`.
+    #[serde(default)]
+    pub package: PackageConfig,
+    /// Options for `twee publish itch`.
+    #[serde(default)]
+    pub itch: ItchConfig,
+    /// Options for `twee export desktop`.
+    #[serde(default)]
+    pub desktop: DesktopConfig,
+    /// Keeping copies of builds.
+    #[serde(default)]
+    pub builds: BuildsConfig,
+    /// The generated table of contents.
+    #[serde(default)]
+    pub toc: TocConfig,
+    /// Options for `twee split`.
+    #[serde(default)]
+    pub split: SplitConfig,
+    /// Options for `twee lint`.
+    #[serde(default)]
+    pub lint: LintConfig,
+    /// Options for `twee stats`.
+    #[serde(default)]
+    pub stats: StatsConfig,
+    /// Options for `twee export dialogue`.
+    #[serde(default)]
+    pub dialogue: DialogueConfig,
+    /// Options for `twee characters`.
+    #[serde(default)]
+    pub characters: CharactersConfig,
+    /// Options for `twee add`.
+    #[serde(default)]
+    pub add: AddConfig,
+    /// Options for `twee sync`.
+    #[serde(default)]
+    pub sync: SyncConfig,
+    /// Options for `twee spellcheck`.
+    #[serde(default)]
+    pub spellcheck: SpellcheckConfig,
+    /// Options for `twee todo`.
+    #[serde(default)]
+    pub todo: TodoConfig,
+    /// The custom passage metadata the project uses.
+    #[serde(default)]
+    pub metadata: MetadataConfig,
+    /// The project directory the relative paths of the config are resolved against, empty for the current directory.
+    /// Set by [Project::load].
+    #[serde(skip)]
+    pub dir: PathBuf,
+}
+
+/// Makes a relative path of the config relative to the project directory instead. URLs are kept.
+fn resolve_path(dir: &Path, p: &mut String) {
+    if ! is_url(p) && Path::new(p).is_relative() {
+        *p = dir.join(&*p).to_string_lossy().to_string();
+    }
+}
+
+impl Config {
+    /// Resolves the relative paths of the config against the project directory, so the project can be built from
+    /// another directory.
+    pub fn resolve_paths(&mut self, dir: &Path) {
+        if dir.as_os_str().is_empty() {
+            return;
+        }
+        self.dir = dir.to_path_buf();
+        let single = [&mut self.output, &mut self.vendor, &mut self.cache, &mut self.package.output, &mut self.desktop.output,
+            &mut self.desktop.icon, &mut self.builds.dir, &mut self.split.output, &mut self.spellcheck.dictionary, &mut self.sync.library];
+        for p in single.into_iter().flatten() {
+            resolve_path(dir, p);
+        }
+        resolve_path(dir, &mut self.main);
+        let lists = [&mut self.style, &mut self.script, &mut self.formats, &mut self.asset_roots, &mut self.spellcheck.words];
+        for p in lists.into_iter().flatten() {
+            resolve_path(dir, p);
+        }
+        // Glob patterns get the directory escaped, so it matches literally.
+        for p in [&mut self.include, &mut self.package.assets, &mut self.package.files].into_iter().flatten() {
+            if ! is_url(p) && Path::new(p).is_relative() {
+                *p = glob_in(dir, p).to_string_lossy().to_string();
+            }
+        }
+        if self.asset_roots.is_empty() {
+            self.asset_roots.push(dir.to_string_lossy().to_string());
+        }
+    }
+}
+
+/// The `creator` config value: a custom name, `true` for twee-tools or `false` to leave it out.
+#[derive(Deserialize, JsonSchema)]
+#[serde(untagged)]
+pub enum Creator {
+    Enabled(bool),
+    Name(String),
+}
+
+/// A command line of `prebuild` or `postbuild`.
+#[derive(Clone, Debug)]
+pub enum CommandLine {
+    /// A program and its arguments, run without a shell.
+    Argv(Vec<String>),
+    /// A command line, run with `sh -c`, or `cmd /C` on Windows.
+    Shell(String),
+}
+
+/// A command of `prebuild` or `postbuild`.
+#[derive(Clone, Debug)]
+pub struct BuildCommand {
+    pub run: CommandLine,
+    /// The working directory, relative to the project directory.
+    pub cwd: Option<String>,
+}
+
+/// A `[[prebuild]]` or `[[postbuild]]` table, with either `argv` or `shell`.
+#[derive(Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct CommandTable {
+    /// A program and its arguments, run without a shell.
+    argv: Option<Vec<String>>,
+    /// A command line, run with `sh -c`, or `cmd /C` on Windows.
+    shell: Option<String>,
+    /// The working directory, relative to the project directory.
+    cwd: Option<String>,
+}
+
+/// Deserializes the `prebuild` and `postbuild` tables. A list of a program and its arguments, the form of older
+/// configs, is accepted as one command.
+fn build_commands<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<BuildCommand>, D::Error> {
+    let values = Vec::<toml::Value>::deserialize(deserializer)?;
+    if values.iter().all(toml::Value::is_str) {
+        let argv: Vec<String> = values.into_iter().filter_map(|v| v.as_str().map(str::to_string)).collect();
+        return Ok(if argv.is_empty() { vec![] } else { vec![BuildCommand { run: CommandLine::Argv(argv), cwd: None }] });
+    }
+    values.into_iter().map(|v| {
+        let table = CommandTable::deserialize(v).map_err(D::Error::custom)?;
+        let run = match (table.argv, table.shell) {
+            (Some(argv), None) if ! argv.is_empty() => CommandLine::Argv(argv),
+            (Some(_), None) => return Err(D::Error::custom("`argv` of a command is empty")),
+            (None, Some(shell)) => CommandLine::Shell(shell),
+            _ => return Err(D::Error::custom("a command needs either `argv` or `shell`")),
+        };
+        Ok(BuildCommand { run, cwd: table.cwd })
+    }).collect()
+}
+
+/// The `passage_order` config value: `"source"`, `"name"`, `"pid"` or `{ key = "..." }` to sort by a metadata key.
+#[derive(Deserialize, JsonSchema, Default, Clone)]
+#[serde(rename_all = "lowercase", deny_unknown_fields)]
+pub enum PassageOrderConfig {
+    #[default]
+    Source,
+    Name,
+    Pid,
+    Key(String),
+}
+
+impl From<&PassageOrderConfig> for PassageOrder {
+    fn from(order: &PassageOrderConfig) -> Self {
+        match order {
+            PassageOrderConfig::Source => PassageOrder::Source,
+            PassageOrderConfig::Name => PassageOrder::Name,
+            PassageOrderConfig::Pid => PassageOrder::Pid,
+            PassageOrderConfig::Key(key) => PassageOrder::Key(key.clone()),
+        }
+    }
+}
+
+/// The `[toc]` section of the config.
+#[derive(Deserialize, JsonSchema, Default)]
+#[serde(deny_unknown_fields)]
+pub struct TocConfig {
+    /// The tag of the passages to list. The table of contents is only generated if this is set.
+    pub tag: Option<String>,
+    /// The name of the generated passage. Defaults to `Contents`.
+    pub passage: Option<String>,
+    /// The order of the listed passages. Defaults to the order in the source.
+    #[serde(default)]
+    pub order: PassageOrderConfig,
+}
+
+/// The `duplicate_passages` config value.
+#[derive(Deserialize, JsonSchema, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum DuplicatePassages {
+    /// The passage from the file included last replaces the earlier one, with a warning naming both files.
+    #[default]
+    LastWins,
+    /// The build fails.
+    Error,
+}
+
+/// How a lint rule is treated. Problems found by `error` rules make `lint` fail.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Off,
+    Warn,
+    Error,
+}
+
+/// The `[lint]` section of the config.
+#[derive(Deserialize, JsonSchema, Default)]
+#[serde(deny_unknown_fields)]
+pub struct LintConfig {
+    /// The severity of rules by their code, overriding the default.
+    #[serde(default)]
+    pub rules: BTreeMap<String, Severity>,
+    /// How similar passages have to be for the `near-duplicate` rule, from 0 to 1. Defaults to 0.9.
+    pub similarity: Option<f64>,
+    /// The tag of passages that are meant to be endings, which the `dead-end` rule skips. Defaults to `ending`.
+    pub ending_tag: Option<String>,
+    /// The maximum number of characters of link text for the `long-link-text` rule. Defaults to 80.
+    pub max_link_text: Option<usize>,
+}
+
+/// The `[package]` section of the config.
+#[derive(Deserialize, JsonSchema, Default)]
+#[serde(deny_unknown_fields)]
+pub struct PackageConfig {
+    /// The zip file to create. Defaults to the name of the HTML file with .zip.
+    pub output: Option<String>,
+    /// Globs of the images, audio and other files to put in the zip.  
+    /// Defaults to all files referenced by the story that exist in the `asset_roots`.
+    #[serde(default)]
+    pub assets: Vec<String>,
+    /// Files like a license or readme to put in the zip.
+    #[serde(default)]
+    pub files: Vec<String>,
+}
+
+/// The `[itch]` section of the config.
+#[derive(Deserialize, JsonSchema, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ItchConfig {
+    /// The itch.io project, as `user/game`.
+    pub project: Option<String>,
+    /// The default channel to push to.
+    pub channel: Option<String>,
+    /// The butler executable. Defaults to `butler` from the PATH.
+    pub butler: Option<String>,
+}
+
+/// The `[desktop]` section of the config.
+#[derive(Deserialize, JsonSchema, Default)]
+#[serde(deny_unknown_fields)]
+pub struct DesktopConfig {
+    /// The directory to generate the Tauri project in. Defaults to `desktop`.
+    pub output: Option<String>,
+    /// The application identifier in reverse domain notation. Defaults to `com.example.<story name>`.
+    pub identifier: Option<String>,
+    /// The window title. Defaults to the story title.
+    pub title: Option<String>,
+    /// The application version.
+    pub version: Option<String>,
+    /// The window width.
+    pub width: Option<u32>,
+    /// The window height.
+    pub height: Option<u32>,
+    /// A square PNG image, at least 512x512 pixels, used as the application icon.
+    pub icon: Option<String>,
+}
+
+/// How kept builds are named.
+#[derive(Deserialize, JsonSchema, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum BuildName {
+    /// The UTC time of the build.
+    #[default]
+    Timestamp,
+    /// The abbreviated git commit, falling back to the time outside of git repositories.
+    Commit,
+}
+
+/// The `[builds]` section of the config.
+#[derive(Deserialize, JsonSchema, Default)]
+#[serde(deny_unknown_fields)]
+pub struct BuildsConfig {
+    /// Keeps a copy of each successful `twee build`.
+    #[serde(default)]
+    pub keep: bool,
+    /// The directory to keep the copies in. Defaults to `builds`.
+    pub dir: Option<String>,
+    /// How the copies are named. Defaults to the time of the build.
+    #[serde(default)]
+    pub name: BuildName,
+    /// The number of copies to keep. Older copies are removed. Defaults to 10.
+    pub retain: Option<usize>,
+}
+
+/// The `[split]` section of the config.
+#[derive(Deserialize, JsonSchema, Default)]
+#[serde(deny_unknown_fields)]
+pub struct SplitConfig {
+    /// The prefix of the tags the story is split by, like `chapter` for `chapter1`, `chapter2`. Defaults to `chapter`.
+    pub prefix: Option<String>,
+    /// The directory the parts are written to. Defaults to `parts`.
+    pub output: Option<String>,
+    /// The name of the passage links into another part lead to. `{part}` is replaced with the number of that part,
+    /// `{tag}` with its tag.
+    pub continue_passage: Option<String>,
+    /// The content of that passage, with the same replacements.
+    pub continue_text: Option<String>,
+}
+
+/// The `[stats]` section of the config.
+#[derive(Deserialize, JsonSchema, Default)]
+#[serde(deny_unknown_fields)]
+pub struct StatsConfig {
+    /// The targeted word count of the whole story.
+    pub target: Option<usize>,
+    /// The targeted word counts of the passages with a tag, like chapters or acts.
+    #[serde(default)]
+    pub targets: BTreeMap<String, usize>,
+}
+
+/// The `[dialogue]` section of the config.
+#[derive(Deserialize, JsonSchema, Default)]
+#[serde(deny_unknown_fields)]
+pub struct DialogueConfig {
+    /// A regex matching a line of dialogue in the prose of a passage, with the named groups `speaker` and `line`.
+    /// Defaults to lines like `Alice: Hello there.`
+    pub pattern: Option<String>,
+    /// Passages with a tag starting with this prefix are spoken by the character named by the rest of the tag, like
+    /// `speaker-Alice`.
+    pub tag_prefix: Option<String>,
+}
+
+/// The `[characters]` section of the config.
+#[derive(Deserialize, JsonSchema, Default)]
+#[serde(deny_unknown_fields)]
+pub struct CharactersConfig {
+    /// The names of the characters. The speakers found with the `[dialogue]` options are added to them.
+    #[serde(default)]
+    pub names: Vec<String>,
+    /// Other names a character is mentioned by, like nicknames.
+    #[serde(default)]
+    pub aliases: BTreeMap<String, Vec<String>>,
+}
+
+/// The `[add]` section of the config.
+#[derive(Deserialize, JsonSchema, Default)]
+#[serde(deny_unknown_fields)]
+pub struct AddConfig {
+    /// Passage templates for `twee add --template`, by name. `{name}` is replaced with the name of the new passage.
+    #[serde(default)]
+    pub templates: BTreeMap<String, String>,
+}
+
+/// The `[sync]` section of the config.
+#[derive(Deserialize, JsonSchema, Default)]
+#[serde(deny_unknown_fields)]
+pub struct SyncConfig {
+    /// The Stories directory of the Twine desktop app. Defaults to `Documents/Twine/Stories` in the home directory.
+    pub library: Option<String>,
+}
+
+/// The `[spellcheck]` section of the config.
+#[derive(Deserialize, JsonSchema, Default)]
+#[serde(deny_unknown_fields)]
+pub struct SpellcheckConfig {
+    /// The Hunspell dictionary to look for in the system dictionary directories. Defaults to `en_US`.
+    pub language: Option<String>,
+    /// The path of a Hunspell dictionary without the .aff and .dic extension, used instead of a system dictionary.
+    pub dictionary: Option<String>,
+    /// Project dictionaries: text files with one word per line that are also accepted.
+    #[serde(default)]
+    pub words: Vec<String>,
+    /// Words that are never reported, like character names.
+    #[serde(default)]
+    pub ignore: Vec<String>,
+}
+
+/// The `[todo]` section of the config.
+#[derive(Deserialize, JsonSchema, Default)]
+#[serde(deny_unknown_fields)]
+pub struct TodoConfig {
+    /// The markers of notes in passages. Defaults to `TODO` and `FIXME`.
+    pub markers: Option<Vec<String>>,
+    /// Also reports HTML comments (`<!-- ... -->`) as notes. Defaults to true.
+    pub comments: Option<bool>,
+}
+
+/// The `[metadata]` section of the config, a schema for custom passage metadata that is checked when building.
+#[derive(Deserialize, JsonSchema, Default)]
+#[serde(deny_unknown_fields)]
+pub struct MetadataConfig {
+    /// The types of the custom passage metadata keys. If any are declared, other keys are reported as unknown, except
+    /// the `position`, `size` and `pid` of Twine.
+    #[serde(default)]
+    pub keys: BTreeMap<String, MetadataType>,
+    /// The keys passages with a tag need to have, by tag.
+    #[serde(default)]
+    pub required: BTreeMap<String, Vec<String>>,
+}
+
+/// The type of a passage metadata value.
+#[derive(Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MetadataType {
+    String,
+    Number,
+    Boolean,
+    Array,
+    Object,
+    Any,
+}
+
+/// Reads the config.toml in the current directory.
+pub fn load_config() -> anyhow::Result<Config> {
+    load_config_in(Path::new(""))
+}
+
+/// Reads the config.toml in the project directory, resolving its paths against the directory.
+pub fn load_config_in(dir: &Path) -> anyhow::Result<Config> {
+    Ok(Project::load(dir.join("config.toml"))?.config)
+}
+
+/// Validates the config source and interpolates environment variables.
+pub fn config_from_source(source: &str) -> anyhow::Result<Config> {
+    let mut config = parse_config(source)?;
+    interpolate_config(&mut config)?;
+    Ok(config.try_into()?)
+}
+
+/// The config values environment variables are interpolated in: the output paths, the prebuild and postbuild
+/// commands and the paths of the included files. Other values, like regexes and passage templates, can contain `$`.
+const INTERPOLATED: &[&[&str]] = &[&["output"], &["main"], &["include"], &["style"], &["script"], &["prebuild"],
+    &["postbuild"], &["package", "output"], &["desktop", "output"], &["builds", "dir"], &["split", "output"]];
+
+/// Replaces environment variable references in the [INTERPOLATED] values of the config.
+fn interpolate_config(config: &mut toml::Value) -> Result<(), Error> {
+    for path in INTERPOLATED {
+        let mut value = Some(&mut *config);
+        for key in *path {
+            value = value.and_then(|v| v.get_mut(*key));
+        }
+        if let Some(v) = value {
+            interpolate_value(v)?;
+        }
+    }
+    Ok(())
+}
+
+/// Replaces environment variable references in all strings of the value.
+fn interpolate_value(v: &mut toml::Value) -> Result<(), Error> {
+    match v {
+        toml::Value::String(s) => *s = interpolate_env(s)?,
+        toml::Value::Array(a) => {
+            for v in a {
+                interpolate_value(v)?;
+            }
+        },
+        toml::Value::Table(t) => {
+            for (_, v) in t.iter_mut() {
+                interpolate_value(v)?;
+            }
+        },
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Replaces `${VAR}` and `${VAR:-fallback}` with the value of the environment variable. The fallback is used if the
+/// variable is unset or empty, and can contain references itself, like `${VAR:-${OTHER}}`. `$$` is a literal `$`.
+pub fn interpolate_env(s: &str) -> Result<String, Error> {
+    let mut res = String::new();
+    let mut rest = s;
+    while let Some(i) = rest.find('$') {
+        res += &rest[..i];
+        rest = &rest[i..];
+        if let Some(r) = rest.strip_prefix("$$") {
+            res.push('$');
+            rest = r;
+        } else if let Some(r) = rest.strip_prefix("${") {
+            let end = closing_brace(r).ok_or_else(|| Error::InvalidInterpolation(s.to_string()))?;
+            let (name, fallback) = match r[..end].split_once(":-") {
+                Some((name, fallback)) => (name, Some(fallback)),
+                None => (&r[..end], None),
+            };
+            match (std::env::var(name).ok().filter(|v| ! v.is_empty() || fallback.is_none()), fallback) {
+                (Some(v), _) => res += &v,
+                (None, Some(fallback)) => res += &interpolate_env(fallback)?,
+                (None, None) => return Err(Error::MissingEnvVar(name.to_string())),
+            }
+            rest = &r[end + 1..];
+        } else {
+            res.push('$');
+            rest = &rest[1..];
+        }
+    }
+    res += rest;
+    Ok(res)
+}
+
+/// The index of the `}` closing a `${`, skipping the braces of nested references.
+fn closing_brace(s: &str) -> Option<usize> {
+    let mut depth = 0;
+    for (i, c) in s.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' if depth == 0 => return Some(i),
+            '}' => depth -= 1,
+            _ => {}
+        }
+    }
+    None
+}
+
+/// The directory caches are kept in.
+pub fn cache_dir(config: &Config) -> PathBuf {
+    config.cache.as_ref().map(PathBuf::from).unwrap_or(config.dir.join(DEFAULT_CACHE_DIR))
+}
+
+
+/// The Levenshtein distance between two strings.
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut prev = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let cur = row[j + 1];
+            row[j + 1] = if ca == *cb { prev } else { 1 + prev.min(cur).min(row[j]) };
+            prev = cur;
+        }
+    }
+    row[b.len()]
+}
+
+/// Turns a toml error into a message with the line number and, for unknown keys, the closest known key.
+fn describe_error(source: &str, e: toml::de::Error) -> Error {
+    let line = e.span().map(|s| source[..s.start.min(source.len())].lines().count().max(1));
+    let message = e.message().trim();
+    let message = if let Some(rest) = message.strip_prefix("unknown field `") {
+        let (key, expected) = rest.split_once('`').unwrap_or((rest, ""));
+        let suggestion = expected.split('`').skip(1).step_by(2)
+            .map(|k| (edit_distance(key, k), k))
+            .filter(|(d, k)| *d <= 2.max(k.len() / 3))
+            .min();
+        match suggestion {
+            Some((_, k)) => format!("`{}` is not a known key, did you mean `{}`?", key, k),
+            None => format!("`{}` is not a known key", key),
+        }
+    } else {
+        message.to_string()
+    };
+    match line {
+        Some(line) => Error::InvalidConfig(format!("line {}: {}", line, message)),
+        None => Error::InvalidConfig(message),
+    }
+}
+
+/// Parses and validates a config.toml, keeping the values uninterpolated.
+pub fn parse_config(source: &str) -> Result<toml::Value, Error> {
+    let value = toml::from_str::<toml::Value>(source).map_err(|e| describe_error(source, e))?;
+    Config::deserialize(toml::de::Deserializer::new(source)).map_err(|e| describe_error(source, e))?;
+    Ok(value)
+}
+
+/// The JSON Schema of the config.toml, for editor autocompletion.
+pub fn config_schema() -> String {
+    serde_json::to_string_pretty(&schema_for!(Config)).unwrap()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn interpolate_set_var() {
+        std::env::set_var("TWEE_TEST_INTERPOLATE_SET", "value");
+        assert_eq!(interpolate_env("a ${TWEE_TEST_INTERPOLATE_SET} b").unwrap(), "a value b");
+        assert_eq!(interpolate_env("${TWEE_TEST_INTERPOLATE_SET:-fallback}").unwrap(), "value");
+    }
+    
+    #[test]
+    fn interpolate_missing_var() {
+        assert!(matches!(interpolate_env("${TWEE_TEST_INTERPOLATE_MISSING}"), Err(Error::MissingEnvVar(v)) if v == "TWEE_TEST_INTERPOLATE_MISSING"));
+        assert_eq!(interpolate_env("${TWEE_TEST_INTERPOLATE_MISSING:-fallback}").unwrap(), "fallback");
+        assert_eq!(interpolate_env("${TWEE_TEST_INTERPOLATE_MISSING:-}").unwrap(), "");
+    }
+    
+    #[test]
+    fn interpolate_empty_var() {
+        std::env::set_var("TWEE_TEST_INTERPOLATE_EMPTY", "");
+        assert_eq!(interpolate_env("${TWEE_TEST_INTERPOLATE_EMPTY}").unwrap(), "");
+        assert_eq!(interpolate_env("${TWEE_TEST_INTERPOLATE_EMPTY:-fallback}").unwrap(), "fallback");
+    }
+    
+    #[test]
+    fn interpolate_escaping() {
+        assert_eq!(interpolate_env("$$").unwrap(), "$");
+        assert_eq!(interpolate_env("$${TWEE_TEST_INTERPOLATE_MISSING}").unwrap(), "${TWEE_TEST_INTERPOLATE_MISSING}");
+        assert_eq!(interpolate_env("costs 5$ or $5").unwrap(), "costs 5$ or $5");
+        assert_eq!(interpolate_env("no references").unwrap(), "no references");
+    }
+    
+    #[test]
+    fn interpolate_config_values() {
+        std::env::set_var("TWEE_TEST_INTERPOLATE_CONFIG", "ci");
+        let config = config_from_source(concat!(
+            "output = \"${TWEE_TEST_INTERPOLATE_CONFIG}/story.html\"\nmain = \"main.twee\"\nstyle = []\nscript = []\n",
+            "include = [\"${TWEE_TEST_INTERPOLATE_CONFIG:-local}/*.twee\"]\n",
+            "[[prebuild]]\nshell = \"echo ${TWEE_TEST_INTERPOLATE_CONFIG} $$\"\n",
+            "[dialogue]\npattern = '^(?P<speaker>\\w+): (?P<line>.*)$$'\n",
+            "[add]\ntemplates.scene = \"${TWEE_TEST_INTERPOLATE_MISSING} $${x} {name}\"\n",
+            "[package]\noutput = \"${TWEE_TEST_INTERPOLATE_CONFIG}.zip\"\n",
+        )).unwrap();
+        assert_eq!(config.output.as_deref(), Some("ci/story.html"));
+        assert_eq!(config.include, vec!["ci/*.twee"]);
+        assert!(matches!(&config.prebuild[0].run, CommandLine::Shell(s) if s == "echo ci $"));
+        assert_eq!(config.package.output.as_deref(), Some("ci.zip"));
+        // Regexes and templates are used as written, without failing for unset variables or turning `$$` into `$`.
+        assert_eq!(config.dialogue.pattern.as_deref(), Some("^(?P<speaker>\\w+): (?P<line>.*)$$"));
+        assert_eq!(config.add.templates["scene"], "${TWEE_TEST_INTERPOLATE_MISSING} $${x} {name}");
+    }
+    
+    #[test]
+    fn interpolate_nested_braces() {
+        std::env::set_var("TWEE_TEST_INTERPOLATE_NESTED", "inner");
+        assert_eq!(interpolate_env("${TWEE_TEST_INTERPOLATE_MISSING:-${TWEE_TEST_INTERPOLATE_NESTED}}/x").unwrap(), "inner/x");
+        assert_eq!(interpolate_env("${TWEE_TEST_INTERPOLATE_MISSING:-{a}}").unwrap(), "{a}");
+        assert!(matches!(interpolate_env("${TWEE_TEST_INTERPOLATE_MISSING:-${TWEE_TEST_INTERPOLATE_MISSING}}"), Err(Error::MissingEnvVar(_))));
+        assert!(matches!(interpolate_env("${TWEE_TEST_INTERPOLATE_NESTED"), Err(Error::InvalidInterpolation(_))));
+        assert!(matches!(interpolate_env("${A:-${B}"), Err(Error::InvalidInterpolation(_))));
+    }
+    
+    #[test]
+    fn edit_distance_ascii() {
+        assert_eq!(edit_distance("", ""), 0);
+        assert_eq!(edit_distance("output", "output"), 0);
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("abc", ""), 3);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("ouptut", "output"), 2);
+        assert_eq!(edit_distance("outpu", "output"), 1);
+        assert_eq!(edit_distance("flaw", "lawn"), 2);
+    }
+    
+    #[test]
+    fn edit_distance_symmetric() {
+        for (a, b) in [("kitten", "sitting"), ("vendor", "vender"), ("", "x"), ("ab", "ba")] {
+            assert_eq!(edit_distance(a, b), edit_distance(b, a));
+        }
+    }
+    
+    #[test]
+    fn edit_distance_non_ascii() {
+        // Counts characters, not bytes.
+        assert_eq!(edit_distance("ü", "u"), 1);
+        assert_eq!(edit_distance("größe", "grösse"), 2);
+        assert_eq!(edit_distance("日本語", "日本"), 1);
+        assert_eq!(edit_distance("🦀", ""), 1);
+        assert_eq!(edit_distance("naïve", "naive"), 1);
+    }
+    
+    #[test]
+    fn unknown_key_suggestion() {
+        let Err(Error::InvalidConfig(message)) = parse_config("ouptut = \"a.html\"\n") else {
+            panic!("unknown key accepted");
+        };
+        assert!(message.contains("did you mean `output`?"), "{}", message);
+    }
+    
+    /// A minimal config with the given build commands.
+    fn commands(source: &str) -> Vec<BuildCommand> {
+        config_from_source(&format!("main = \"story.twee\"\nstyle = []\nscript = []\n{}", source)).unwrap().prebuild
+    }
+    
+    #[test]
+    fn build_commands_argv_list() {
+        assert!(commands("prebuild = []\n").is_empty());
+        let c = commands("prebuild = [\"make\", \"all\"]\n");
+        assert!(matches!(&c[..], [BuildCommand { run: CommandLine::Argv(argv), cwd: None }] if argv == &["make", "all"]));
+    }
+    
+    #[test]
+    fn build_commands_tables() {
+        let c = commands("[[prebuild]]\nargv = [\"scripts/gen.sh\"]\n\n[[prebuild]]\nshell = \"npm run generate\"\ncwd = \"assets\"\n");
+        assert!(matches!(&c[0], BuildCommand { run: CommandLine::Argv(argv), cwd: None } if argv == &["scripts/gen.sh"]));
+        assert!(matches!(&c[1], BuildCommand { run: CommandLine::Shell(line), cwd: Some(cwd) } if line == "npm run generate" && cwd == "assets"));
+        assert_eq!(c.len(), 2);
+    }
+    
+    #[test]
+    fn build_commands_invalid_tables() {
+        for (table, expected) in [
+            ("argv = [\"a\"]\nshell = \"a\"", "either `argv` or `shell`"),
+            ("cwd = \"a\"", "either `argv` or `shell`"),
+            ("argv = []", "`argv` of a command is empty"),
+            ("run = \"a\"", "`run` is not a known key"),
+        ] {
+            let source = format!("main = \"story.twee\"\nstyle = []\nscript = []\n[[prebuild]]\n{}\n", table);
+            let Err(Error::InvalidConfig(message)) = parse_config(&source) else {
+                panic!("invalid command accepted: {}", table);
+            };
+            assert!(message.contains(expected), "{}", message);
+        }
+    }
+}
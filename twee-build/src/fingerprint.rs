@@ -0,0 +1,82 @@
+use std::{path::Path, process::{Command, Stdio}, time::{SystemTime, UNIX_EPOCH}};
+
+use serde_json::Map;
+use twee_parser::{Passage, Story};
+
+use crate::{Config, Creator, Profile};
+
+/// Formats the current time as an ISO 8601 UTC timestamp, i.e. `2024-03-17T12:34:56Z`.
+pub fn utc_timestamp() -> String {
+    let secs = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let (days, rem) = (secs / 86400, secs % 86400);
+    // Converts days since the epoch into a civil date, see http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z", year, month, day, rem / 3600, rem % 3600 / 60, rem % 60)
+}
+
+/// Gets the abbreviated hash of the checked out git commit, if the project directory is in a git repository.
+pub fn git_commit(dir: &Path) -> Option<String> {
+    let mut c = Command::new("git");
+    if ! dir.as_os_str().is_empty() {
+        c.current_dir(dir);
+    }
+    let out = c.args(["rev-parse", "--short", "HEAD"]).stdin(Stdio::null()).stderr(Stdio::null()).output().ok()?;
+    if ! out.status.success() {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&out.stdout).trim().to_string())
+}
+
+/// Stamps the story metadata, and optionally a passage, with information identifying the build.
+pub fn fingerprint(config: &Config, profile: &Profile, story: &mut Story) {
+    if ! config.fingerprint {
+        return;
+    }
+    let mut info = vec![
+        ("build-date", utc_timestamp()),
+        ("build-tool", format!("twee-tools {}", env!("CARGO_PKG_VERSION"))),
+        ("build-profile", if profile.debug { "debug" } else { "release" }.to_string()),
+    ];
+    if let Some(commit) = git_commit(&config.dir) {
+        info.push(("build-commit", commit));
+    }
+    if let Some(name) = &config.fingerprint_passage {
+        story.passages.retain(|p| &p.name != name);
+        story.passages.push(Passage {
+            name: name.clone(),
+            tags: vec![],
+            meta: Map::new(),
+            content: info.iter().map(|(k, v)| format!("{}: {}", k, v)).collect::<Vec<String>>().join("\n"),
+        });
+    }
+    for (k, v) in info {
+        story.meta.insert(k.to_string(), v.into());
+    }
+}
+
+/// Sets the `creator` and `creator-version` of the story as configured, by default to twee-tools.
+pub fn attribute_creator(config: &Config, story: &mut Story) {
+    let (creator, default_version) = match &config.creator {
+        Some(Creator::Enabled(false)) => {
+            story.meta.remove("creator");
+            story.meta.remove("creator-version");
+            return;
+        },
+        Some(Creator::Name(name)) => (name.clone(), None),
+        Some(Creator::Enabled(true)) | None => ("twee-tools".to_string(), Some(env!("CARGO_PKG_VERSION").to_string())),
+    };
+    story.meta.insert("creator".to_string(), creator.into());
+    if let Some(version) = config.creator_version.clone().or(default_version) {
+        story.meta.insert("creator-version".to_string(), version.into());
+    } else {
+        story.meta.remove("creator-version");
+    }
+}
@@ -0,0 +1,210 @@
+use std::{fs::read_dir, path::PathBuf, sync::OnceLock, time::Instant};
+
+use serde_json::Value;
+use tracing::warn;
+use twee_parser::{FormatVersion, Story};
+
+use crate::{compressed_storydata_html, obfuscated_storydata_html, read_source, storydata_html, timings::record_timing, track_input, Config, Error};
+
+const DEFAULT_VENDOR_DIR: &str = "vendor";
+
+static FORMAT_HARLOWE: OnceLock<String> = OnceLock::new();
+static FORMAT_CHAPBOOK: OnceLock<String> = OnceLock::new();
+static FORMAT_SNOWMAN: OnceLock<String> = OnceLock::new();
+static FORMAT_SUGARCUBE: OnceLock<String> = OnceLock::new();
+
+/// The story formats bundled with twee-tools.
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+pub enum StoryFormat {
+    Harlowe,
+    Chapbook,
+    Snowman,
+    Sugarcube,
+}
+
+
+impl StoryFormat {
+    /// The name of the format as used in the StoryData.
+    pub fn format_name(&self) -> String {
+        match self {
+            StoryFormat::Harlowe => "Harlowe",
+            StoryFormat::Chapbook => "Chapbook",
+            StoryFormat::Snowman => "Snowman",
+            StoryFormat::Sugarcube => "SugarCube",
+        }.to_owned()
+    }
+
+    /// Gets the format by its name in the StoryData.
+    pub fn from_name(name: &str) -> anyhow::Result<Self> {
+        Ok(match name {
+            "Harlowe" => Self::Harlowe,
+            "Chapbook" => Self::Chapbook,
+            "Snowman" => Self::Snowman,
+            "SugarCube" => Self::Sugarcube,
+            _ => {
+                return Err(Error::UnknownStoryFormat(name.to_string()).into());
+            }
+        })
+    }
+
+    /// Gets the story format from the StoryData.
+    pub fn of_story(story: &Story) -> anyhow::Result<Self> {
+        if let Some(Value::String(s)) = story.meta.get("format") {
+            Self::from_name(s)
+        } else {
+            Err(Error::UnknownStoryFormat("".to_string()).into())
+        }
+    }
+
+    /// The version of the bundled format.
+    pub fn format_version(&self) -> String {
+        match self {
+            StoryFormat::Harlowe => "3.3.8",
+            StoryFormat::Chapbook => "1.2.3",
+            StoryFormat::Snowman => "2.0.2",
+            StoryFormat::Sugarcube => "2.36.1",
+        }.to_string()
+    }
+
+    /// The format.js of the bundled format, as JSON.
+    pub fn format_json(&self) -> &'static str {
+        match self {
+            StoryFormat::Harlowe => include_str!("../formats/harlowe-3.3.8.json"),
+            StoryFormat::Chapbook => include_str!("../formats/chapbook-1.2.3.json"),
+            StoryFormat::Snowman => include_str!("../formats/snowman-2.0.2.json"),
+            StoryFormat::Sugarcube => include_str!("../formats/sugarcube-2.36.1.json"),
+        }
+    }
+
+    /// The HTML source of the bundled format, with the `{{STORY_NAME}}` and `{{STORY_DATA}}` placeholders.
+    pub fn format_contents(&self) -> String {
+        let cell = match self {
+            StoryFormat::Harlowe => &FORMAT_HARLOWE,
+            StoryFormat::Chapbook => &FORMAT_CHAPBOOK,
+            StoryFormat::Snowman => &FORMAT_SNOWMAN,
+            StoryFormat::Sugarcube => &FORMAT_SUGARCUBE,
+        };
+        cell.get_or_init(|| serde_json::from_str::<Value>(self.format_json()).unwrap().as_object().unwrap().get("source").unwrap().as_str().unwrap().to_string()).clone()
+    }
+
+}
+
+/// The directory `twee vendor` copies story formats into.
+pub fn vendor_dir(config: &Config) -> PathBuf {
+    config.vendor.as_ref().map(PathBuf::from).unwrap_or(config.dir.join(DEFAULT_VENDOR_DIR))
+}
+
+/// The vendored copy of a version of a format.
+pub fn format_file(config: &Config, name: &str, version: &str) -> PathBuf {
+    vendor_dir(config).join("formats").join(format!("{}-{}.json", name.to_lowercase(), version))
+}
+
+/// The newest vendored version of the format, if any.
+pub fn vendored_format_version(config: &Config, format: StoryFormat) -> Option<String> {
+    let prefix = format!("{}-", format.format_name().to_lowercase());
+    read_dir(vendor_dir(config).join("formats")).ok()?.flatten()
+        .filter_map(|e| e.file_name().to_str()?.strip_prefix(&prefix)?.strip_suffix(".json").map(str::to_string))
+        .max_by_key(|v| FormatVersion::parse(v))
+}
+
+/// Reads the name, version and JSON of a story format from the source of its format.js at `file`, which passes the
+/// JSON to `window.storyFormat`. The JSON is in the format of the vendored files, so it can be written to
+/// [format_file].
+pub fn parse_format_js(file: &str, source: &str) -> anyhow::Result<(String, String, Value)> {
+    let json = match (source.find('{'), source.rfind('}')) {
+        (Some(start), Some(end)) if start < end => serde_json::from_str::<Value>(&source[start..=end]).ok(),
+        _ => None,
+    };
+    let field = |name: &str| json.as_ref().and_then(|j| j.get(name)).and_then(|v| v.as_str()).map(str::to_string);
+    match (field("name"), field("version"), field("source")) {
+        (Some(name), Some(version), Some(_)) => Ok((name, version, json.unwrap())),
+        _ => Err(Error::InvalidStoryFormat(file.to_string()).into()),
+    }
+}
+
+/// Looks for a vendored copy of the story format, preferring the version pinned in the StoryData over the bundled one.
+pub fn vendored_format(config: &Config, format: StoryFormat, story: &Story) -> anyhow::Result<Option<String>> {
+    let mut versions = vec![];
+    if let Some(Value::String(v)) = story.meta.get("format-version") {
+        versions.push(v.clone());
+    }
+    versions.push(format.format_version());
+    for v in versions {
+        let f = format_file(config, &format.format_name(), &v);
+        track_input(&f);
+        if f.exists() {
+            // Cached, as the formats are large and shared by the members of a workspace.
+            let json = serde_json::from_str::<Value>(&read_source(&f)?)?;
+            if let Some(source) = json.get("source").and_then(|s| s.as_str()) {
+                return Ok(Some(source.to_string()));
+            }
+            return Err(Error::InvalidStoryFormat(f.to_string_lossy().to_string()).into());
+        }
+    }
+    Ok(None)
+}
+
+/// Gets the story format source, preferring a vendored format.
+pub fn format_source(format: StoryFormat, story: &Story, config: &Config) -> anyhow::Result<String> {
+    if let Some(contents) = vendored_format(config, format, story)? {
+        Ok(contents)
+    } else {
+        let required = story.meta.get("format-version").and_then(|v| v.as_str()).and_then(FormatVersion::parse);
+        let bundled = FormatVersion::parse(&format.format_version()).unwrap();
+        if let Some(required) = required.filter(|r| ! bundled.is_compatible_with(r)) {
+            warn!(code = "format-version-incompatible", "The story needs {} {}, which isn't compatible with the bundled {}. Vendor a matching version, or change the format-version in the StoryData passage.", format.format_name(), required, bundled);
+        }
+        Ok(format.format_contents())
+    }
+}
+
+/// Sets the lang attribute of the html tag of the story format.
+pub fn set_lang(html: &str, lang: &str) -> String {
+    let Some(start) = html.find("<html") else {
+        return html.to_string();
+    };
+    let lang = lang.replace('&', "&amp;").replace('"', "&quot;");
+    format!("{}<html lang=\"{}\"{}", &html[..start], lang, &html[start + "<html".len()..])
+}
+
+/// Puts the story into the story format, compressing or obfuscating the passages if configured. Records how long
+/// serializing the story and wrapping it in the format took in the [build timings](crate::build_timings).
+pub fn build_html(format: StoryFormat, story: &Story, config: &Config) -> anyhow::Result<String> {
+    let start = Instant::now();
+    let html = if config.compress {
+        compressed_storydata_html(story, config)?
+    } else if config.obfuscate {
+        obfuscated_storydata_html(story, config)?
+    } else {
+        storydata_html(story, config)?
+    };
+    record_timing(if config.compress { "serialize (compressed)" } else if config.obfuscate { "serialize (obfuscated)" } else { "serialize" }, start.elapsed());
+    let start = Instant::now();
+    let contents = format_source(format, story, config)?;
+    let contents = match story.meta.get("lang") {
+        Some(Value::String(lang)) => set_lang(&contents, lang),
+        _ => contents,
+    };
+    let html = contents.replace("{{STORY_NAME}}", &story.title).replace("{{STORY_DATA}}", &html);
+    record_timing("format", start.elapsed());
+    Ok(html)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn format_js() {
+        let source = "window.storyFormat({\"name\":\"SugarCube\",\"version\":\"2.37.3\",\"source\":\"<html>{{STORY_DATA}}</html>\"});";
+        let (name, version, json) = parse_format_js("format.js", source).unwrap();
+        assert_eq!((name.as_str(), version.as_str()), ("SugarCube", "2.37.3"));
+        assert_eq!(json["source"], "<html>{{STORY_DATA}}</html>");
+        let (name, _, _) = parse_format_js("harlowe.json", StoryFormat::Harlowe.format_json()).unwrap();
+        assert_eq!(name, "Harlowe");
+        for invalid in ["", "window.storyFormat({\"name\":\"X\",\"version\":\"1.0.0\"});", "<html></html>"] {
+            assert_eq!(parse_format_js("format.js", invalid).unwrap_err().to_string(), Error::InvalidStoryFormat("format.js".to_string()).to_string());
+        }
+    }
+}
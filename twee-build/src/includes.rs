@@ -0,0 +1,168 @@
+use std::{cell::RefCell, path::{Path, PathBuf}, time::{Duration, Instant}};
+
+use crate::{is_url, Error};
+
+/// How a file got into the story.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IncludeKind {
+    /// The main twee file of the config.
+    Main,
+    /// A twee file from the `include` config value.
+    Config,
+    /// A twee file from the `include` list of a TweeTools passage.
+    Twee,
+    /// A Twine archive from the `include-archive` list of a TweeTools passage.
+    Archive,
+    /// A file included as the content of a passage, with the `include` metadata or a `twee-cmd` passage.
+    Passage,
+}
+
+/// A file in the [IncludeGraph].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IncludeEntry {
+    /// The path relative to the current directory, or the URL of a remote file.
+    pub path: String,
+    pub kind: IncludeKind,
+    /// The number of files up the chain of includes, 0 for the main file and the includes of the config.
+    pub depth: usize,
+    /// Whether the file was skipped, as it was already included elsewhere.
+    pub skipped: bool,
+    /// How long parsing the file and processing its includes took. Zero for skipped files and passage contents.
+    pub time: Duration,
+}
+
+/// The files included by a build, in the order they were included, each after the file including it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IncludeGraph {
+    pub entries: Vec<IncludeEntry>,
+    /// The canonical paths of the twee files and archives included so far.
+    included: Vec<PathBuf>,
+    /// The canonical paths of the files being processed, from the main file to the current one.
+    chain: Vec<PathBuf>,
+    /// The entries of the files in `chain`, with when they were entered.
+    open: Vec<(usize, Instant)>,
+}
+
+thread_local! {
+    /// The include graph of the last build on this thread.
+    static LAST_GRAPH: RefCell<Option<IncludeGraph>> = const { RefCell::new(None) };
+}
+
+/// The include graph of the last story built with [build_story](crate::build_story) on this thread, to explain where
+/// the passages came from.
+pub fn include_graph() -> Option<IncludeGraph> {
+    LAST_GRAPH.with_borrow(|g| g.clone())
+}
+
+pub(crate) fn set_include_graph(graph: IncludeGraph) {
+    LAST_GRAPH.set(Some(graph));
+}
+
+/// Identifies an included file, so it's only included once.
+fn include_key(p: &Path) -> anyhow::Result<PathBuf> {
+    if is_url(&p.to_string_lossy()) {
+        Ok(p.to_path_buf())
+    } else {
+        Ok(p.canonicalize()?)
+    }
+}
+
+/// The path relative to the current directory if it's inside it.
+pub(crate) fn display_path(p: &Path) -> String {
+    let relative = std::env::current_dir().ok().and_then(|d| p.strip_prefix(d).ok().map(Path::to_path_buf));
+    relative.unwrap_or(p.to_path_buf()).to_string_lossy().to_string()
+}
+
+impl IncludeGraph {
+    /// Starts including files into a story, which may include files in turn until [IncludeGraph::leave] is called.
+    /// Returns false if the file was already included, so it has to be skipped. Including a file that is currently
+    /// being processed by its `explicit` path is an include cycle, which is an error. Files matched by a glob pattern
+    /// are just skipped, as patterns like `*.twee` usually match the file they're in.
+    pub(crate) fn enter(&mut self, path: &Path, kind: IncludeKind, explicit: bool) -> anyhow::Result<bool> {
+        let key = include_key(path)?;
+        if let Some(i) = self.chain.iter().position(|k| *k == key).filter(|_| explicit) {
+            let chain: Vec<String> = self.chain[i..].iter().chain([&key]).map(|k| display_path(k)).collect();
+            return Err(Error::IncludeCycle(chain.join(" -> ")).into());
+        }
+        let skipped = self.included.contains(&key);
+        self.entries.push(IncludeEntry { path: display_path(&key), kind, depth: self.chain.len(), skipped, time: Duration::ZERO });
+        if skipped {
+            return Ok(false);
+        }
+        self.included.push(key.clone());
+        self.chain.push(key);
+        self.open.push((self.entries.len() - 1, Instant::now()));
+        Ok(true)
+    }
+
+    /// Finishes the file from the last [IncludeGraph::enter].
+    pub(crate) fn leave(&mut self) {
+        self.chain.pop();
+        if let Some((i, start)) = self.open.pop() {
+            self.entries[i].time = start.elapsed();
+        }
+    }
+
+    /// Records a file included as the content of a passage. These aren't processed further, so they can't form cycles.
+    pub(crate) fn content(&mut self, path: &Path) {
+        let path = include_key(path).unwrap_or(path.to_path_buf());
+        self.entries.push(IncludeEntry { path: display_path(&path), kind: IncludeKind::Passage, depth: self.chain.len(), skipped: false, time: Duration::ZERO });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    /// Creates empty twee files in the directory.
+    fn twee_files(dir: &Path, names: &[&str]) -> Vec<PathBuf> {
+        names.iter().map(|n| {
+            std::fs::write(dir.join(n), "").unwrap();
+            dir.join(n).canonicalize().unwrap()
+        }).collect()
+    }
+    
+    #[test]
+    fn two_file_cycle() {
+        let dir = tempfile::tempdir().unwrap();
+        let files = twee_files(dir.path(), &["a.twee", "b.twee"]);
+        let (a, b) = (&files[0], &files[1]);
+        let mut graph = IncludeGraph::default();
+        assert!(graph.enter(a, IncludeKind::Main, true).unwrap());
+        assert!(graph.enter(b, IncludeKind::Twee, true).unwrap());
+        let error = graph.enter(a, IncludeKind::Twee, true).unwrap_err().to_string();
+        assert_eq!(error, Error::IncludeCycle(format!("{} -> {} -> {}", display_path(a), display_path(b), display_path(a))).to_string());
+        // Matched by a glob pattern, the file is skipped instead.
+        assert!(! graph.enter(a, IncludeKind::Twee, false).unwrap());
+        assert!(graph.entries.last().unwrap().skipped);
+    }
+    
+    #[test]
+    fn self_include() {
+        let dir = tempfile::tempdir().unwrap();
+        let files = twee_files(dir.path(), &["a.twee"]);
+        let a = &files[0];
+        let mut graph = IncludeGraph::default();
+        assert!(graph.enter(a, IncludeKind::Main, true).unwrap());
+        let error = graph.enter(a, IncludeKind::Twee, true).unwrap_err().to_string();
+        assert_eq!(error, Error::IncludeCycle(format!("{} -> {}", display_path(a), display_path(a))).to_string());
+        assert!(! graph.enter(a, IncludeKind::Twee, false).unwrap());
+    }
+    
+    #[test]
+    fn included_twice_without_cycle() {
+        let dir = tempfile::tempdir().unwrap();
+        let files = twee_files(dir.path(), &["a.twee", "b.twee", "c.twee"]);
+        let (a, b, c) = (&files[0], &files[1], &files[2]);
+        let mut graph = IncludeGraph::default();
+        assert!(graph.enter(a, IncludeKind::Main, true).unwrap());
+        assert!(graph.enter(b, IncludeKind::Twee, true).unwrap());
+        graph.leave();
+        assert!(graph.enter(c, IncludeKind::Twee, true).unwrap());
+        // b was included before, but isn't being processed, so it's skipped.
+        assert!(! graph.enter(b, IncludeKind::Twee, true).unwrap());
+        graph.leave();
+        graph.leave();
+        assert_eq!(graph.entries.iter().map(|e| (e.depth, e.skipped)).collect::<Vec<_>>(), vec![(0, false), (1, false), (1, false), (2, true)]);
+    }
+}
@@ -0,0 +1,82 @@
+//! # twee_build
+//!
+//! The build pipeline of [twee-tools](https://github.com/tareksander/twine-rs/tree/main/twee-tools) as a library, for
+//! editors, servers and other tools that want to build Twine stories the same way.
+//! A [Project] is loaded from a config.toml and built into a [Build] with a [Profile]:
+//!
+//! ```no_run
+//! use twee_build::{Profile, Project};
+//!
+//! let project = Project::load("config.toml")?;
+//! let build = project.build(&Profile::default())?;
+//! std::fs::write(&build.out, build.html)?;
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+//!
+//! Relative paths in the config are resolved against the directory of the config.toml, so projects can be built from
+//! any directory.
+
+
+use thiserror::Error;
+mod config;
+pub use config::*;
+mod source;
+pub use source::*;
+mod output;
+pub use output::*;
+mod warning;
+pub use warning::*;
+mod build;
+pub use build::*;
+mod remote;
+pub use remote::*;
+mod format;
+pub use format::*;
+mod compress;
+pub use compress::*;
+mod obfuscate;
+pub use obfuscate::*;
+mod fingerprint;
+pub use fingerprint::*;
+mod project;
+pub use project::*;
+mod metadata;
+pub use metadata::*;
+mod includes;
+pub use includes::*;
+mod provenance;
+pub use provenance::*;
+mod timings;
+pub use timings::*;
+
+/// Errors of the build pipeline.
+#[derive(Error, Debug)]
+#[non_exhaustive]
+pub enum Error {
+    #[error("Could not open file: {0}")]
+    FileNotFound(String),
+    #[error("Unknown story format: {0}")]
+    UnknownStoryFormat(String),
+    #[error("Prebuild command exited with error")]
+    PrebuildError,
+    #[error("Postbuild command exited with error")]
+    PostbuildError,
+    #[error("Could not run {0} command {1}: {2}")]
+    CommandNotStarted(String, String, String),
+    #[error("Story format file has no source: {0}")]
+    InvalidStoryFormat(String),
+    #[error("Invalid config.toml: {0}")]
+    InvalidConfig(String),
+    #[error("Environment variable {0} is not set and has no default")]
+    MissingEnvVar(String),
+    #[error("Unterminated environment variable reference in config value: {0}")]
+    InvalidInterpolation(String),
+    #[error("Remote include {0} isn't cached, build once without --offline")]
+    RemoteNotCached(String),
+    #[error("Could not fetch {0}: {1}")]
+    RemoteFailed(String, String),
+    #[error("Include cycle: {0}")]
+    IncludeCycle(String),
+    #[error("Passage \"{0}\" is defined in both {1} and {2}")]
+    PassageConflict(String, String, String),
+}
@@ -0,0 +1,92 @@
+use serde_json::Value;
+use twee_parser::Story;
+
+use crate::{MetadataConfig, MetadataType};
+
+/// The passage metadata keys Twine uses.
+const TWINE_KEYS: &[&str] = &["position", "size", "pid"];
+
+impl MetadataType {
+    /// Whether the value has the type.
+    pub fn matches(&self, v: &Value) -> bool {
+        match self {
+            MetadataType::String => v.is_string(),
+            MetadataType::Number => v.is_number(),
+            MetadataType::Boolean => v.is_boolean(),
+            MetadataType::Array => v.is_array(),
+            MetadataType::Object => v.is_object(),
+            MetadataType::Any => true,
+        }
+    }
+}
+
+/// A passage metadata value that doesn't match the `[metadata]` schema of the config.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MetadataProblem {
+    pub passage: String,
+    pub message: String,
+}
+
+/// Checks the passage metadata against the schema: the types of the declared keys, keys that aren't declared, and
+/// the keys required for the tags of a passage.
+pub fn check_metadata(schema: &MetadataConfig, story: &Story) -> Vec<MetadataProblem> {
+    let mut problems = vec![];
+    for p in &story.passages {
+        let mut problem = |message: String| problems.push(MetadataProblem { passage: p.name.clone(), message });
+        for (k, v) in &p.meta {
+            match schema.keys.get(k) {
+                Some(t) if ! t.matches(v) => problem(format!("metadata \"{}\" should be of type {}, but is {}", k, format!("{:?}", t).to_lowercase(), v)),
+                None if ! schema.keys.is_empty() && ! TWINE_KEYS.contains(&k.as_str()) => problem(format!("metadata \"{}\" isn't declared in the [metadata] section of the config", k)),
+                _ => {},
+            }
+        }
+        for t in &p.tags {
+            for k in schema.required.get(t).into_iter().flatten() {
+                if ! p.meta.contains_key(k) {
+                    problem(format!("metadata \"{}\" is required for passages tagged \"{}\"", k, t));
+                }
+            }
+        }
+    }
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use twee_parser::parse_twee3;
+
+    use super::*;
+    
+    fn schema(source: &str) -> MetadataConfig {
+        toml::from_str(source).unwrap()
+    }
+    
+    fn problems(schema: &MetadataConfig, source: &str) -> Vec<(String, String)> {
+        check_metadata(schema, &parse_twee3(source).unwrap().0).into_iter().map(|p| (p.passage, p.message)).collect()
+    }
+    
+    #[test]
+    fn wrong_types() {
+        let schema = schema("keys.mood = \"string\"\nkeys.weight = \"number\"\nkeys.any = \"any\"\n");
+        let source = ":: A {\"mood\": 3, \"weight\": 2.5, \"any\": [1], \"position\": \"100,100\"}\n\n:: B {\"mood\": \"dark\", \"weight\": \"heavy\"}\n";
+        assert_eq!(problems(&schema, source), [
+            ("A".to_string(), "metadata \"mood\" should be of type string, but is 3".to_string()),
+            ("B".to_string(), "metadata \"weight\" should be of type number, but is \"heavy\"".to_string()),
+        ]);
+    }
+    
+    #[test]
+    fn missing_required_keys() {
+        let schema = schema("[required]\nscene = [\"location\", \"time\"]\n");
+        let source = ":: A [scene] {\"location\": \"forest\"}\n\n:: B {\"other\": 1}\n\n:: C [scene] {\"location\": \"town\", \"time\": \"noon\"}\n";
+        // Without declared keys, any key is allowed.
+        assert_eq!(problems(&schema, source), [("A".to_string(), "metadata \"time\" is required for passages tagged \"scene\"".to_string())]);
+    }
+    
+    #[test]
+    fn unknown_keys() {
+        let schema = schema("keys.mood = \"string\"\n");
+        let source = ":: A {\"mood\": \"calm\", \"moood\": \"calm\", \"size\": \"100,100\", \"pid\": \"1\"}\n";
+        assert_eq!(problems(&schema, source), [("A".to_string(), "metadata \"moood\" isn't declared in the [metadata] section of the config".to_string())]);
+    }
+}
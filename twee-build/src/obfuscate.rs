@@ -0,0 +1,81 @@
+use base64::Engine;
+use twee_parser::Story;
+
+use crate::{storydata_html, Config};
+
+const DEOBFUSCATE_SHIM: &str = include_str!("../templates/obfuscate/deobfuscate.js");
+
+/// XORs the text with the key and encodes it in base64.
+pub fn obfuscate(text: &str, key: &[u8]) -> String {
+    let bytes: Vec<u8> = text.bytes().zip(key.iter().cycle()).map(|(b, k)| b ^ k).collect();
+    base64::engine::general_purpose::STANDARD.encode(bytes)
+}
+
+/// Serializes the story into a &lt;tw-storydata&gt; tag with the passage text obfuscated, followed by a script that
+/// decodes it before the story format starts. The key is the IFID, or the title if there is none, so builds stay
+/// reproducible. This only keeps players from reading ahead in a text editor, it's not encryption.  
+/// The tw-storydata gets a `twee-obfuscated` attribute, as tools other than the bundled script can't read it.
+pub fn obfuscated_storydata_html(story: &Story, config: &Config) -> anyhow::Result<String> {
+    let mut story = story.clone();
+    let key = story.meta.get("ifid").and_then(|i| i.as_str()).filter(|i| ! i.is_empty()).unwrap_or(&story.title).as_bytes().to_vec();
+    for p in &mut story.passages {
+        if ! p.tags.iter().any(|t| t == "script" || t == "stylesheet") {
+            p.content = obfuscate(&p.content, &key);
+        }
+    }
+    story.meta.insert("twee-obfuscated".to_string(), "xor".into());
+    Ok(format!("{}<script>{}</script>", storydata_html(&story, config)?, DEOBFUSCATE_SHIM))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    const KEY: &[u8] = b"3F0F3B42-1C5B-4A4B-9C8B-2E3B4E1A9D2C";
+    
+    const TEXTS: [&str; 4] = [
+        "",
+        "Hello, world!",
+        "You go [[north->North]] or [[south->South]].\nYou go north, and then north again.",
+        "Grüße aus Köln, 日本語 and 😀 surrogate pairs 😀😀, longer than the key to wrap around it",
+    ];
+    
+    /// Reverses [obfuscate].
+    fn deobfuscate(text: &str, key: &[u8]) -> Option<String> {
+        let bytes = base64::engine::general_purpose::STANDARD.decode(text).ok()?;
+        String::from_utf8(bytes.into_iter().zip(key.iter().cycle()).map(|(b, k)| b ^ k).collect()).ok()
+    }
+    
+    #[test]
+    fn obfuscate_round_trip() {
+        for text in TEXTS {
+            let obfuscated = obfuscate(text, KEY);
+            assert!(text.is_empty() || obfuscated != text);
+            assert_eq!(deobfuscate(&obfuscated, KEY).as_deref(), Some(text));
+        }
+    }
+    
+    /// Runs the decoding script with node on passages obfuscated by [obfuscate]. Run with `cargo test -- --ignored`
+    /// where node is installed.
+    #[test]
+    #[ignore = "needs node"]
+    fn deobfuscation_script() {
+        let obfuscated: Vec<String> = TEXTS.iter().map(|t| obfuscate(t, KEY)).collect();
+        let script = format!(r#"
+            var passages = {}.map(function (t) {{ return {{ textContent: "\n" + t + "\n" }}; }});
+            var attributes = {{ ifid: {:?}, "twee-obfuscated": "xor" }};
+            var document = {{ querySelector: function () {{ return {{
+                getAttribute: function (name) {{ return attributes[name]; }},
+                querySelectorAll: function () {{ return passages; }},
+                removeAttribute: function (name) {{ delete attributes[name]; }},
+            }}; }} }};
+            {}
+            console.log(JSON.stringify({{ texts: passages.map(function (p) {{ return p.textContent; }}), attributes: Object.keys(attributes) }}));
+        "#, serde_json::to_string(&obfuscated).unwrap(), std::str::from_utf8(KEY).unwrap(), DEOBFUSCATE_SHIM);
+        let output = std::process::Command::new("node").arg("-e").arg(script).output().expect("node isn't installed");
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        let output: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+        assert_eq!(output["texts"], serde_json::json!(TEXTS));
+        assert_eq!(output["attributes"], serde_json::json!(["ifid"]));
+    }
+}
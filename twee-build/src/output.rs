@@ -0,0 +1,44 @@
+use std::{fs::{create_dir_all, remove_dir_all, remove_file, File}, io::Write, path::Path, sync::atomic::{AtomicBool, Ordering}};
+
+use tracing::info;
+
+/// Reports the files that would be written or removed instead of changing them, set by the `--dry-run` option of
+/// `twee`.
+pub static DRY_RUN: AtomicBool = AtomicBool::new(false);
+
+/// Whether files are only reported instead of written, see [DRY_RUN].
+pub fn dry_run() -> bool {
+    DRY_RUN.load(Ordering::Relaxed)
+}
+
+/// Writes a file, creating its parent directories, or only reports it in dry-run mode.
+pub fn write_output<P>(p: P, contents: &[u8]) -> anyhow::Result<()> where P: AsRef<Path> {
+    let p = p.as_ref();
+    if dry_run() {
+        info!("Would write {} ({} bytes)", p.to_string_lossy(), contents.len());
+        return Ok(());
+    }
+    if let Some(parent) = p.parent() {
+        if ! parent.as_os_str().is_empty() {
+            create_dir_all(parent)?;
+        }
+    }
+    File::create(p)?.write_all(contents)?;
+    Ok(())
+}
+
+/// Removes a file or directory, or only reports it in dry-run mode.
+pub fn remove_output<P>(p: P) -> anyhow::Result<()> where P: AsRef<Path> {
+    let p = p.as_ref();
+    if dry_run() {
+        info!("Would remove {}", p.to_string_lossy());
+        return Ok(());
+    }
+    info!("Removing {}", p.to_string_lossy());
+    if p.is_dir() {
+        remove_dir_all(p)?;
+    } else {
+        remove_file(p)?;
+    }
+    Ok(())
+}
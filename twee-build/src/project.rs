@@ -0,0 +1,156 @@
+use std::{io::{BufRead, BufReader, Read}, path::{Path, PathBuf}, process::Stdio};
+
+use tracing::info;
+use twee_parser::{parse_twee3, serde_json::Map, Story};
+
+use crate::{attribute_creator, build_html, build_story, config_from_source, dry_run, fingerprint, read_file, track_input, with_source_cache, BuildCommand, CommandLine, Config, Error, StoryFormat};
+
+/// How a story is built.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Profile {
+    /// Turns on the debug mode of the story format.
+    pub debug: bool,
+}
+
+impl Profile {
+    /// The profile of `twee build --debug`.
+    pub fn debug() -> Self {
+        Profile { debug: true }
+    }
+}
+
+/// A built story.
+pub struct Build {
+    /// The HTML file the story is meant to be written to.
+    pub out: PathBuf,
+    pub html: String,
+    pub story: Story,
+    pub format: StoryFormat,
+}
+
+/// A story project, described by its config.toml.  
+/// Each call to [Project::story] or [Project::build] reads the sources from disk again, so edits made between builds
+/// are picked up. To share the files read between multiple builds, like the members of a workspace, run them in a
+/// [SourceCache](crate::SourceCache) session.
+pub struct Project {
+    pub config: Config,
+}
+
+impl Project {
+    /// Reads and validates the config, interpolating environment variables. Relative paths in the config are resolved
+    /// against the directory of the config file.
+    pub fn load<P>(config: P) -> anyhow::Result<Project> where P: AsRef<Path> {
+        let config = config.as_ref();
+        if ! config.exists() {
+            return Err(Error::FileNotFound(config.to_string_lossy().to_string()).into());
+        }
+        track_input(config);
+        let mut project = Project::new(config_from_source(&read_file(config)?)?);
+        project.config.resolve_paths(config.parent().unwrap_or(Path::new("")));
+        Ok(project)
+    }
+
+    pub fn new(config: Config) -> Project {
+        Project { config }
+    }
+
+    /// Runs the `prebuild` commands of the config, if any. The story isn't built yet, so `OUTPUT` is where it will be
+    /// written, with the title from the main twee file as it is before the commands run. In dry-run mode, the commands
+    /// are only reported.
+    pub fn prebuild(&self, profile: &Profile) -> anyhow::Result<()> {
+        // The main file isn't cached, as prebuild commands may generate it.
+        let title = read_file(&self.config.main).ok().and_then(|s| parse_twee3(&s).ok()).map(|(s, _)| s.title).unwrap_or_default();
+        let story = Story { title: if title.is_empty() { "Story".to_string() } else { title }, passages: vec![], meta: Map::new() };
+        run_commands("prebuild", &self.config.prebuild, &self.config.dir, profile, &output_path(&self.config, &story), Error::PrebuildError)
+    }
+
+    /// Runs the `postbuild` commands of the config after the output was written to `out`, if any. In dry-run mode, the
+    /// commands are only reported.
+    pub fn postbuild(&self, profile: &Profile, out: &Path) -> anyhow::Result<()> {
+        run_commands("postbuild", &self.config.postbuild, &self.config.dir, profile, out, Error::PostbuildError)
+    }
+
+    /// Builds the story with everything added that only the final output has, like the fingerprint.
+    pub fn story(&self, profile: &Profile) -> anyhow::Result<Story> {
+        let mut story = with_source_cache(|| build_story(&self.config, profile.debug))?;
+        fingerprint(&self.config, profile, &mut story);
+        attribute_creator(&self.config, &mut story);
+        Ok(story)
+    }
+
+    /// Builds the story into HTML without writing it. The prebuild command isn't run, see [Project::prebuild].
+    pub fn build(&self, profile: &Profile) -> anyhow::Result<Build> {
+        with_source_cache(|| {
+            let story = self.story(profile)?;
+            let format = StoryFormat::of_story(&story)?;
+            let html = build_html(format, &story, &self.config)?;
+            Ok(Build { out: output_path(&self.config, &story), html, story, format })
+        })
+    }
+}
+
+/// Runs the commands of `prebuild` or `postbuild` in order in the project directory, with the project directory,
+/// profile and output path in the `PROJECT_DIR`, `PROFILE` and `OUTPUT` environment variables. Their output is logged line by line with the
+/// `stage` as the prefix. Returns the `failed` error if a command fails.
+fn run_commands(stage: &str, commands: &[BuildCommand], project_dir: &Path, profile: &Profile, out: &Path, failed: Error) -> anyhow::Result<()> {
+    let project_dir = std::path::absolute(if project_dir.as_os_str().is_empty() { Path::new(".") } else { project_dir })?;
+    for command in commands {
+        let mut c = match &command.run {
+            CommandLine::Argv(argv) if argv.is_empty() => continue,
+            CommandLine::Argv(argv) => {
+                let mut c = std::process::Command::new(&argv[0]);
+                c.args(&argv[1..]);
+                c
+            },
+            CommandLine::Shell(line) => {
+                let mut c = std::process::Command::new(if cfg!(windows) { "cmd" } else { "sh" });
+                c.args([if cfg!(windows) { "/C" } else { "-c" }, line]);
+                c
+            },
+        };
+        let shown = match &command.run {
+            CommandLine::Argv(argv) => argv.join(" "),
+            CommandLine::Shell(line) => line.clone(),
+        };
+        if dry_run() {
+            info!("Would run {} command: {}", stage, shown);
+            continue;
+        }
+        c.current_dir(project_dir.join(command.cwd.as_deref().unwrap_or("")))
+            .env("PROJECT_DIR", &project_dir)
+            .env("PROFILE", if profile.debug { "debug" } else { "release" })
+            .env("OUTPUT", std::path::absolute(out)?)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        info!("Running {} command: {}", stage, shown);
+        let mut child = c.spawn().map_err(|e| Error::CommandNotStarted(stage.to_string(), shown.clone(), e.to_string()))?;
+        let (stdout, stderr) = (child.stdout.take().unwrap(), child.stderr.take().unwrap());
+        std::thread::scope(|s| {
+            s.spawn(|| log_lines(stage, stdout));
+            s.spawn(|| log_lines(stage, stderr));
+        });
+        if ! child.wait()?.success() {
+            return Err(failed.into());
+        }
+    }
+    Ok(())
+}
+
+/// Logs the lines a command writes, prefixed with the stage it runs in.
+fn log_lines(stage: &str, output: impl Read) {
+    for line in BufReader::new(output).lines().map_while(Result::ok) {
+        info!("[{}] {}", stage, line);
+    }
+}
+
+/// The HTML file the story is built into.
+pub fn output_path(config: &Config, story: &Story) -> PathBuf {
+    if let Some(out) = &config.output {
+        PathBuf::from(out)
+    } else if config.dir.as_os_str().is_empty() {
+        PathBuf::from(".").join(story.title.clone() + ".html")
+    } else {
+        config.dir.join(story.title.clone() + ".html")
+    }
+}
@@ -0,0 +1,103 @@
+use std::{cell::RefCell, collections::BTreeMap, fmt::Display, path::Path};
+
+use serde::Serialize;
+use twee_parser::passage_spans;
+
+use crate::includes::display_path;
+
+/// Where a passage of a build comes from.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PassageSource {
+    /// The file relative to the current directory, or the URL of a remote include.
+    pub file: String,
+    /// The line of the passage header, starting at 1. None for passages from archives.
+    pub line: Option<usize>,
+}
+
+impl Display for PassageSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "{}:{}", self.file, line),
+            None => write!(f, "{}", self.file),
+        }
+    }
+}
+
+thread_local! {
+    /// The sources of the passages of the last build on this thread by passage name.
+    static SOURCES: RefCell<BTreeMap<String, PassageSource>> = const { RefCell::new(BTreeMap::new()) };
+}
+
+/// The sources of the passages of the last story built with [build_story](crate::build_story) on this thread, so
+/// diagnostics can point at the file a passage was written in instead of the assembled story. Passages generated by
+/// the build, like the table of contents, have no source.
+pub fn passage_sources() -> BTreeMap<String, PassageSource> {
+    SOURCES.with_borrow(|s| s.clone())
+}
+
+/// The source of a passage of the last build, see [passage_sources].
+pub fn passage_source(name: &str) -> Option<PassageSource> {
+    SOURCES.with_borrow(|s| s.get(name).cloned())
+}
+
+pub(crate) fn clear_passage_sources() {
+    SOURCES.with_borrow_mut(|s| s.clear());
+}
+
+/// The sources of the passages of a twee file. A passage duplicated in the file is at its first occurrence, and
+/// StoryTitle and StoryData at their last, like the parser keeps them.
+pub(crate) fn twee_sources(file: &Path, source: &str) -> BTreeMap<String, PassageSource> {
+    let file = display_path(&file.canonicalize().unwrap_or(file.to_path_buf()));
+    let mut found = BTreeMap::new();
+    for s in passage_spans(source) {
+        let line = source[..s.span.start].matches('\n').count() + 1;
+        let source = PassageSource { file: file.clone(), line: Some(line) };
+        if s.name == "StoryTitle" || s.name == "StoryData" {
+            found.insert(s.name, source);
+        } else {
+            found.entry(s.name).or_insert(source);
+        }
+    }
+    found
+}
+
+/// Records the passages of a twee file, keeping the passages already recorded from an earlier file.
+pub(crate) fn record_twee_sources(file: &Path, source: &str) {
+    let found = twee_sources(file, source);
+    SOURCES.with_borrow_mut(|sources| {
+        for (name, s) in found {
+            sources.entry(name).or_insert(s);
+        }
+    });
+}
+
+/// Replaces the source of a passage, when a later file overrides it.
+pub(crate) fn set_source(name: &str, source: PassageSource) {
+    SOURCES.with_borrow_mut(|s| s.insert(name.to_string(), source));
+}
+
+/// Records a passage from a file that isn't twee, like an archive or a script file, unless it's already recorded.
+pub(crate) fn record_source(name: &str, file: &Path, line: Option<usize>) {
+    let file = display_path(&file.canonicalize().unwrap_or(file.to_path_buf()));
+    SOURCES.with_borrow_mut(|s| {
+        s.entry(name.to_string()).or_insert(PassageSource { file, line });
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn duplicated_passage_sources() {
+        let source = ":: StoryTitle\nA\n\n:: Start\nfirst\n\n:: Other\n\n:: Start\nsecond\n\n:: StoryTitle\nB\n";
+        let sources = twee_sources(Path::new("story.twee"), source);
+        assert_eq!(sources["Start"], PassageSource { file: "story.twee".to_string(), line: Some(4) });
+        assert_eq!(sources["Other"].line, Some(7));
+        assert_eq!(sources["StoryTitle"].line, Some(12));
+        // The parser keeps the same occurrences.
+        let (story, _) = twee_parser::parse_twee3(source).unwrap();
+        assert_eq!(story.passage("Start").unwrap().content, "first");
+        assert_eq!(story.title, "B");
+    }
+}
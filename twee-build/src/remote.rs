@@ -0,0 +1,276 @@
+use std::{path::{Path, PathBuf}, sync::atomic::{AtomicBool, Ordering}, time::{SystemTime, UNIX_EPOCH}};
+
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+use twee_parser::stable_hash;
+use url::Url;
+
+use crate::{cache_dir, read_file, track_input, vendor_dir, write_output, Config, Error};
+
+/// Reads remote includes only from the cache, set by the `--offline` option of `twee`.
+pub static OFFLINE: AtomicBool = AtomicBool::new(false);
+
+/// Whether remote includes are only read from the cache, see [OFFLINE].
+pub fn offline() -> bool {
+    OFFLINE.load(Ordering::Relaxed)
+}
+
+/// Whether an include is an HTTP(S) URL instead of a path.
+pub fn is_url(s: &str) -> bool {
+    s.starts_with("http://") || s.starts_with("https://")
+}
+
+/// Resolves an include in a remote file relative to the URL of the file, like a link in a web page: `../` goes up a
+/// directory and `/` starts at the root of the server.
+pub fn join_url(base: &str, include: &str) -> String {
+    match Url::parse(base).and_then(|b| b.join(include)) {
+        Ok(url) => url.to_string(),
+        Err(_) => include.to_string(),
+    }
+}
+
+/// Where remote includes are read from: the copies made by `twee vendor`, which are preferred, and the cache.
+#[derive(Debug, Clone, Default)]
+pub struct RemoteDirs {
+    /// The vendor directory, see [vendor_dir].
+    pub vendor: PathBuf,
+    /// The cache directory, see [cache_dir].
+    pub cache: PathBuf,
+}
+
+impl RemoteDirs {
+    /// The directories of the config.
+    pub fn of(config: &Config) -> RemoteDirs {
+        RemoteDirs { vendor: vendor_dir(config), cache: cache_dir(config) }
+    }
+}
+
+/// What is known about a cached response.
+#[derive(Serialize, Deserialize)]
+struct CacheEntry {
+    url: String,
+    etag: Option<String>,
+    /// When the response becomes stale, in seconds since the epoch.
+    expires: u64,
+}
+
+/// The name of the cached files of a URL, a [stable_hash] so it's the same for every build of twee.
+pub(crate) fn cache_key(url: &str) -> String {
+    format!("{:016x}", stable_hash([url]))
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+/// The `max-age` of a Cache-Control header.
+fn max_age(cache_control: Option<&str>) -> u64 {
+    cache_control.into_iter().flat_map(|c| c.split(','))
+        .filter_map(|d| d.trim().strip_prefix("max-age=").and_then(|a| a.trim().parse().ok()))
+        .next().unwrap_or(0)
+}
+
+/// Fetches a remote include, using the copy in the `remote` directory of the cache while it's fresh according to
+/// the `max-age` of the response, and revalidating it with its ETag afterwards. If the server can't be reached, a
+/// stale copy is used with a warning. In offline mode, only the cache is used.
+pub fn fetch_remote(cache: &Path, url: &str) -> anyhow::Result<String> {
+    fetch_cached(cache, url, offline())
+}
+
+/// [fetch_remote] with the offline mode as a parameter instead of [OFFLINE].
+fn fetch_cached(cache: &Path, url: &str, offline: bool) -> anyhow::Result<String> {
+    let key = cache_key(url);
+    let (entry_path, body_path) = (cache.join("remote").join(key.clone() + ".json"), cache.join("remote").join(key + ".body"));
+    let cached = read_file(&entry_path).ok()
+        .and_then(|e| serde_json::from_str::<CacheEntry>(&e).ok())
+        .filter(|e| e.url == url)
+        .and_then(|e| read_file(&body_path).ok().map(|b| (e, b)));
+    match &cached {
+        Some((entry, body)) if offline || now() < entry.expires => return Ok(body.clone()),
+        None if offline => return Err(Error::RemoteNotCached(url.to_string()).into()),
+        _ => {},
+    }
+    let mut request = ureq::get(url);
+    if let Some(etag) = cached.as_ref().and_then(|(e, _)| e.etag.as_ref()) {
+        request = request.set("If-None-Match", etag);
+    }
+    let response = match request.call() {
+        Ok(response) => response,
+        Err(ureq::Error::Status(status, _)) => return Err(Error::RemoteFailed(url.to_string(), format!("HTTP status {}", status)).into()),
+        Err(e) => {
+            if let Some((_, body)) = cached {
+                warn!(code = "remote-stale", "Could not fetch {}, using the cached copy: {}", url, e);
+                return Ok(body);
+            }
+            return Err(Error::RemoteFailed(url.to_string(), e.to_string()).into());
+        },
+    };
+    let entry = CacheEntry {
+        url: url.to_string(),
+        etag: response.header("ETag").map(|e| e.to_string()),
+        expires: now() + max_age(response.header("Cache-Control")),
+    };
+    let body = match cached {
+        Some((_, body)) if response.status() == 304 => {
+            debug!("{} is unchanged", url);
+            body
+        },
+        _ => response.into_string()?,
+    };
+    write_output(&body_path, body.as_bytes())?;
+    write_output(&entry_path, serde_json::to_string(&entry)?.as_bytes())?;
+    Ok(body)
+}
+
+/// The URL of a vendored remote include, to tell apart URLs with the same [cache_key].
+#[derive(Serialize, Deserialize)]
+struct VendoredEntry {
+    url: String,
+}
+
+/// The files of the vendored copy of a URL, in the `remote` directory of the vendor directory.
+fn vendored_files(vendor: &Path, url: &str) -> (PathBuf, PathBuf) {
+    let key = cache_key(url);
+    (vendor.join("remote").join(key.clone() + ".json"), vendor.join("remote").join(key + ".body"))
+}
+
+/// Reads the copy of a remote include made by [vendor_remote], if there is one.
+pub fn vendored_remote(vendor: &Path, url: &str) -> Option<String> {
+    let (entry_path, body_path) = vendored_files(vendor, url);
+    track_input(&body_path);
+    read_file(&entry_path).ok()
+        .and_then(|e| serde_json::from_str::<VendoredEntry>(&e).ok())
+        .filter(|e| e.url == url)
+        .and_then(|_| read_file(&body_path).ok())
+}
+
+/// Reads a remote include, from the vendor directory if it was vendored and with [fetch_remote] otherwise.
+pub fn read_remote(dirs: &RemoteDirs, url: &str) -> anyhow::Result<String> {
+    match vendored_remote(&dirs.vendor, url) {
+        Some(body) => Ok(body),
+        None => fetch_remote(&dirs.cache, url),
+    }
+}
+
+/// Downloads a remote include with [fetch_remote] and copies it into the vendor directory, so builds read it from
+/// there instead of the network. Returns the vendored file.
+pub fn vendor_remote(dirs: &RemoteDirs, url: &str) -> anyhow::Result<PathBuf> {
+    let body = fetch_remote(&dirs.cache, url)?;
+    let (entry_path, body_path) = vendored_files(&dirs.vendor, url);
+    write_output(&body_path, body.as_bytes())?;
+    write_output(&entry_path, serde_json::to_string(&VendoredEntry { url: url.to_string() })?.as_bytes())?;
+    Ok(body_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::{Read, Write}, net::TcpListener, thread::JoinHandle};
+    
+    use super::*;
+    
+    /// Nothing listens on port 1, so requests to it fail to connect.
+    const UNREACHABLE: &str = "http://127.0.0.1:1/lib/part.twee";
+    
+    /// Puts a copy of the URL into the cache.
+    fn cache_entry(cache: &Path, url: &str, etag: Option<&str>, expires: u64, body: &str) {
+        let key = cache_key(url);
+        let entry = CacheEntry { url: url.to_string(), etag: etag.map(|e| e.to_string()), expires };
+        write_output(cache.join("remote").join(key.clone() + ".json"), serde_json::to_string(&entry).unwrap().as_bytes()).unwrap();
+        write_output(cache.join("remote").join(key + ".body"), body.as_bytes()).unwrap();
+    }
+    
+    fn read_entry(cache: &Path, url: &str) -> CacheEntry {
+        serde_json::from_str(&read_file(cache.join("remote").join(cache_key(url) + ".json")).unwrap()).unwrap()
+    }
+    
+    /// Answers one request with the response on a local port, returning the URL and the request.
+    fn serve(response: &'static str) -> (String, JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/lib/part.twee", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0; 1024];
+            while ! request.ends_with(b"\r\n\r\n") {
+                let n = stream.read(&mut buf).unwrap();
+                assert!(n > 0);
+                request.extend_from_slice(&buf[..n]);
+            }
+            stream.write_all(response.as_bytes()).unwrap();
+            String::from_utf8(request).unwrap()
+        });
+        (url, server)
+    }
+    
+    #[test]
+    fn max_ages() {
+        assert_eq!(max_age(None), 0);
+        assert_eq!(max_age(Some("no-cache")), 0);
+        assert_eq!(max_age(Some("max-age=60")), 60);
+        assert_eq!(max_age(Some("public, max-age=60, must-revalidate")), 60);
+        assert_eq!(max_age(Some("public, max-age=soon")), 0);
+    }
+    
+    #[test]
+    fn fresh_copies_are_used_without_requests() {
+        let cache = tempfile::tempdir().unwrap();
+        cache_entry(cache.path(), UNREACHABLE, None, u64::MAX, "cached");
+        assert_eq!(fetch_cached(cache.path(), UNREACHABLE, false).unwrap(), "cached");
+    }
+    
+    #[test]
+    fn offline_mode_uses_only_the_cache() {
+        let cache = tempfile::tempdir().unwrap();
+        let error = fetch_cached(cache.path(), UNREACHABLE, true).unwrap_err();
+        assert!(matches!(error.downcast::<Error>(), Ok(Error::RemoteNotCached(url)) if url == UNREACHABLE));
+        // Stale copies are used as they are.
+        cache_entry(cache.path(), UNREACHABLE, None, 0, "stale");
+        assert_eq!(fetch_cached(cache.path(), UNREACHABLE, true).unwrap(), "stale");
+    }
+    
+    #[test]
+    fn stale_copies_are_used_when_the_server_cant_be_reached() {
+        let cache = tempfile::tempdir().unwrap();
+        assert!(matches!(fetch_cached(cache.path(), UNREACHABLE, false).unwrap_err().downcast::<Error>(), Ok(Error::RemoteFailed(..))));
+        cache_entry(cache.path(), UNREACHABLE, None, 0, "stale");
+        assert_eq!(fetch_cached(cache.path(), UNREACHABLE, false).unwrap(), "stale");
+    }
+    
+    #[test]
+    fn downloads_are_cached_for_their_max_age() {
+        let cache = tempfile::tempdir().unwrap();
+        let (url, server) = serve("HTTP/1.1 200 OK\r\nCache-Control: max-age=600\r\nETag: \"v1\"\r\nContent-Length: 10\r\nConnection: close\r\n\r\ndownloaded");
+        let start = now();
+        assert_eq!(fetch_cached(cache.path(), &url, false).unwrap(), "downloaded");
+        server.join().unwrap();
+        let entry = read_entry(cache.path(), &url);
+        assert_eq!(entry.etag.as_deref(), Some("\"v1\""));
+        assert!(entry.expires >= start + 600 && entry.expires <= now() + 600);
+        // The server is gone, so this is read from the cache.
+        assert_eq!(fetch_cached(cache.path(), &url, false).unwrap(), "downloaded");
+    }
+    
+    #[test]
+    fn stale_copies_are_revalidated_with_their_etag() {
+        let cache = tempfile::tempdir().unwrap();
+        let (url, server) = serve("HTTP/1.1 304 Not Modified\r\nCache-Control: max-age=600\r\nETag: \"v1\"\r\nConnection: close\r\n\r\n");
+        cache_entry(cache.path(), &url, Some("\"v1\""), 0, "cached");
+        assert_eq!(fetch_cached(cache.path(), &url, false).unwrap(), "cached");
+        let request = server.join().unwrap().to_lowercase();
+        assert!(request.contains("if-none-match: \"v1\"\r\n"), "{}", request);
+        // The copy is fresh again.
+        assert!(read_entry(cache.path(), &url).expires > now());
+        assert_eq!(fetch_cached(cache.path(), &url, false).unwrap(), "cached");
+    }
+    
+    #[test]
+    fn join_urls() {
+        let base = "https://example.com/lib/story/main.twee?v=2";
+        assert_eq!(join_url(base, "part.twee"), "https://example.com/lib/story/part.twee");
+        assert_eq!(join_url(base, "./part.twee"), "https://example.com/lib/story/part.twee");
+        assert_eq!(join_url(base, "../common/widgets.twee"), "https://example.com/lib/common/widgets.twee");
+        assert_eq!(join_url(base, "/root.twee"), "https://example.com/root.twee");
+        assert_eq!(join_url(base, "https://cdn.example.org/x.js"), "https://cdn.example.org/x.js");
+        assert_eq!(join_url(base, "a b.twee"), "https://example.com/lib/story/a%20b.twee");
+    }
+}
@@ -0,0 +1,197 @@
+use std::{cell::RefCell, collections::{BTreeMap, BTreeSet}, fs::File, io::Read, ops::Range, path::{Path, PathBuf}, sync::{Arc, Mutex}};
+
+use glob::{MatchOptions, Pattern};
+use twee_parser::{passage_blocks, Passage};
+
+use crate::Error;
+
+/// Reads a file into a string.
+pub fn read_file<P>(p: P) -> anyhow::Result<String>  where P: AsRef<Path> {
+    let mut f = File::open(p)?;
+    let mut s = String::new();
+    f.read_to_string(&mut s)?;
+    Ok(s)  
+}
+
+/// Contents of the story source files and vendored story formats read in a build session, so files shared between
+/// workspace members are only read once, also when the members are built on multiple threads. Cloning it shares the
+/// contents.
+#[derive(Clone, Default)]
+pub struct SourceCache(Arc<Mutex<BTreeMap<PathBuf, String>>>);
+
+thread_local! {
+    /// The cache [read_source] uses on this thread, if a session is active.
+    static SESSION: RefCell<Option<SourceCache>> = const { RefCell::new(None) };
+}
+
+impl SourceCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    /// The twee source files read in the session.
+    pub fn twee_files(&self) -> Vec<PathBuf> {
+        self.0.lock().unwrap().keys().filter(|f| is_twee_file(f)).cloned().collect()
+    }
+    
+    /// Runs `f` with this cache used by [read_source] on the current thread.
+    pub fn enter<R>(&self, f: impl FnOnce() -> R) -> R {
+        let previous = SESSION.with(|s| s.replace(Some(self.clone())));
+        let res = f();
+        SESSION.with(|s| *s.borrow_mut() = previous);
+        res
+    }
+}
+
+/// Runs `f` in a new build session, unless the current thread already is in one, so every file is read from disk
+/// again by each build.
+pub fn with_source_cache<R>(f: impl FnOnce() -> R) -> R {
+    if SESSION.with(|s| s.borrow().is_some()) {
+        f()
+    } else {
+        SourceCache::new().enter(f)
+    }
+}
+
+/// Like [read_file], but in a build session the contents are cached until the session ends, see [SourceCache].
+pub fn read_source<P>(p: P) -> anyhow::Result<String>  where P: AsRef<Path> {
+    track_input(p.as_ref());
+    let path = p.as_ref().canonicalize().map_err(|_| Error::FileNotFound(p.as_ref().to_string_lossy().to_string()))?;
+    let Some(cache) = SESSION.with(|s| s.borrow().clone()) else {
+        return read_file(&path);
+    };
+    if let Some(s) = cache.0.lock().unwrap().get(&path) {
+        return Ok(s.clone());
+    }
+    let s = read_file(&path)?;
+    cache.0.lock().unwrap().insert(path, s.clone());
+    Ok(s)
+}
+
+/// Forgets the tracked inputs, so the next build collects them again.
+pub fn clear_build_inputs() {
+    INPUTS.lock().unwrap().clear();
+    INPUT_PATTERNS.lock().unwrap().clear();
+}
+
+/// Files read since the last [clear_build_inputs], like the story sources, the config and assets.
+static INPUTS: Mutex<BTreeSet<PathBuf>> = Mutex::new(BTreeSet::new());
+/// Absolute glob patterns expanded since the last [clear_build_inputs], so new matching files are noticed.
+static INPUT_PATTERNS: Mutex<BTreeSet<String>> = Mutex::new(BTreeSet::new());
+
+/// Records a file the build depends on, even if it doesn't exist yet.
+pub fn track_input(p: &Path) {
+    if let Ok(p) = absolute_path(p) {
+        INPUTS.lock().unwrap().insert(p);
+    }
+}
+
+/// The files and glob patterns the builds since the last [clear_build_inputs] depended on.
+pub struct BuildInputs {
+    pub files: BTreeSet<PathBuf>,
+    pub patterns: Vec<Pattern>,
+}
+
+impl BuildInputs {
+    /// The inputs of the builds since the last [clear_build_inputs].
+    pub fn collect() -> BuildInputs {
+        let files = INPUTS.lock().unwrap().clone();
+        let patterns = INPUT_PATTERNS.lock().unwrap().iter().filter_map(|p| Pattern::new(p).ok()).collect();
+        BuildInputs { files, patterns }
+    }
+    
+    /// The twee source files.
+    pub fn twee_files(&self) -> Vec<PathBuf> {
+        self.files.iter().filter(|f| is_twee_file(f)).cloned().collect()
+    }
+    
+    /// Whether a change to the path affects the build.
+    pub fn contains(&self, p: &Path) -> bool {
+        self.files.contains(p) || self.patterns.iter().any(|pattern| pattern.matches_path_with(p, GLOB_OPTIONS))
+    }
+    
+    /// The directories to watch: the parents of the files, and the literal prefixes of the patterns recursively.
+    /// Directories are watched instead of the files, so editors replacing the file on save don't break the watch.
+    pub fn watch_dirs(&self) -> BTreeMap<PathBuf, bool> {
+        let mut dirs = BTreeMap::new();
+        for f in &self.files {
+            if let Some(parent) = f.parent() {
+                dirs.entry(parent.to_path_buf()).or_insert(false);
+            }
+        }
+        for p in &self.patterns {
+            let path = Path::new(p.as_str());
+            let base: PathBuf = path.components()
+                .take_while(|c| ! c.as_os_str().to_string_lossy().contains(['*', '?', '[']))
+                .collect();
+            if base == path {
+                if let Some(parent) = path.parent() {
+                    dirs.entry(parent.to_path_buf()).or_insert(false);
+                }
+            } else {
+                dirs.insert(base, true);
+            }
+        }
+        dirs
+    }
+}
+
+fn is_twee_file(f: &Path) -> bool {
+    f.extension().is_some_and(|e| e == "twee" || e == "tw")
+}
+
+/// A passage in a twee source file.
+pub struct SourceBlock {
+    pub file: PathBuf,
+    /// The header line, including the newline.
+    pub header: Range<usize>,
+    /// The text after the header, up to the next passage, without trailing whitespace.
+    pub body: Range<usize>,
+    pub passage: Passage,
+}
+
+/// Finds the passages in twee files, like the [twee_files](BuildInputs::twee_files) read by the last build.
+pub fn source_blocks(files: &[PathBuf]) -> anyhow::Result<Vec<SourceBlock>> {
+    let mut blocks = vec![];
+    for f in files {
+        for b in passage_blocks(&read_source(f)?)? {
+            blocks.push(SourceBlock { file: f.clone(), header: b.header, body: b.body, passage: b.passage });
+        }
+    }
+    Ok(blocks)
+}
+
+const GLOB_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: true,
+};
+
+/// Joins a glob pattern to a directory, escaping the directory so characters like `[` in it match literally.
+pub fn glob_in(dir: &Path, pattern: &str) -> PathBuf {
+    if dir.as_os_str().is_empty() {
+        return PathBuf::from(pattern);
+    }
+    PathBuf::from(Pattern::escape(&dir.to_string_lossy())).join(pattern)
+}
+
+/// Expands a glob pattern relative to the directory, remembering it as an input of the build.
+pub fn glob(s: &str, parent: PathBuf) -> std::result::Result<Vec<PathBuf>, anyhow::Error> {
+    let mut res = vec![];
+    let pattern = glob_in(&parent, s);
+    if let Ok(p) = std::path::absolute(&parent) {
+        INPUT_PATTERNS.lock().unwrap().insert(glob_in(&p, s).to_string_lossy().to_string());
+    }
+    for r in glob::glob_with(&pattern.to_string_lossy(), GLOB_OPTIONS)?.flatten() {
+        res.push(r);
+    }
+    Ok(res)
+}
+
+/// Makes a path absolute, resolving symlinks if it exists.
+pub fn absolute_path(p: &Path) -> anyhow::Result<PathBuf> {
+    if let Ok(p) = p.canonicalize() {
+        return Ok(p);
+    }
+    Ok(std::path::absolute(p)?)
+}
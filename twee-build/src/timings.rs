@@ -0,0 +1,21 @@
+use std::{cell::RefCell, time::Duration};
+
+thread_local! {
+    /// The stages of the last build on this thread with how long they took.
+    static TIMINGS: RefCell<Vec<(String, Duration)>> = const { RefCell::new(vec![]) };
+}
+
+/// How long the stages of the last story built with [build_story](crate::build_story) on this thread took, in the order they ran:
+/// parsing the main file, the transforms like `includes`, validating, and serializing and wrapping the story in
+/// the story format if it was built into HTML.
+pub fn build_timings() -> Vec<(String, Duration)> {
+    TIMINGS.with_borrow(|t| t.clone())
+}
+
+pub(crate) fn clear_timings() {
+    TIMINGS.with_borrow_mut(|t| t.clear());
+}
+
+pub(crate) fn record_timing(stage: &str, time: Duration) {
+    TIMINGS.with_borrow_mut(|t| t.push((stage.to_string(), time)));
+}
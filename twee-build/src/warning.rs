@@ -0,0 +1,52 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use tracing::warn;
+use twee_parser::Warning;
+
+/// The number of warnings printed with [print_warning].
+pub static WARNING_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// A short code identifying the kind of warning in logs.
+pub fn warning_code(w: &Warning) -> &'static str {
+    match w {
+        Warning::StoryMetadataMalformed => "story-metadata-malformed",
+        Warning::StoryTitleMissing => "story-title-missing",
+        Warning::PassageMetadataMalformed(_) => "passage-metadata-malformed",
+        Warning::PassageTagsMalformed(_) => "passage-tags-malformed",
+        Warning::PassageDuplicated(_) => "passage-duplicated",
+        Warning::PassageNameMissing => "passage-name-missing",
+        Warning::PlaceholderUnresolved(_, _) => "placeholder-unresolved",
+        Warning::PassageNameUnlinkable(_, _) => "passage-name-unlinkable",
+        Warning::TagInvalid(_, _) => "tag-invalid",
+        Warning::TagReserved(_, _, _) => "tag-reserved",
+        Warning::StoryDataInvalid(_, _) => "storydata-invalid",
+        Warning::StartPassageMissing(_) => "start-passage-missing",
+        Warning::StartPassageGuessed(_) => "start-passage-guessed",
+        _ => "warning",
+    }
+}
+
+/// Logs a warning of the parser with its code.
+pub fn print_warning(w: Warning) {
+    WARNING_COUNT.fetch_add(1, Ordering::Relaxed);
+    let code = warning_code(&w);
+    warn!(code, "{}",
+    match w {
+        Warning::StoryMetadataMalformed => "Story metadata is not valid JSON and has been discarded.".to_owned(),
+        Warning::StoryTitleMissing => "Story title is missing.".to_owned(),
+        Warning::PassageMetadataMalformed(p) => format!("Passage \"{}\" metadata is not valid JSON and has been discarded.", p),
+        Warning::PassageTagsMalformed(p) => format!("Passage \"{}\" tags are not valid and have been discarded.", p),
+        Warning::PassageDuplicated(p) if p == "StoryTitle" || p == "StoryData" => format!("Passage \"{}\" is duplicated, using the last occurrence.", p),
+        Warning::PassageDuplicated(p) => format!("Passage \"{}\" is duplicated, using the first occurrence.", p),
+        Warning::PassageNameMissing => "Passage name is missing, passage has been discarded.".to_owned(),
+        Warning::PlaceholderUnresolved(p, k) => format!("Passage \"{}\" has no value for the placeholder {{{{{}}}}}.", p, k),
+        Warning::PassageNameUnlinkable(p, reason) => format!("Passage name \"{}\" contains {}, links to it won't work.", p, reason),
+        Warning::TagInvalid(p, t) => format!("Passage \"{}\" has the tag \"{}\", tags can't be empty or contain whitespace.", p, t),
+        Warning::TagReserved(p, t, reason) => format!("Passage \"{}\" has the tag \"{}\", which {}.", p, t, reason),
+        Warning::StoryDataInvalid(field, problem) => format!("StoryData field \"{}\": {}.", field, problem),
+        Warning::StartPassageMissing(Some(start)) => format!("The start passage \"{}\" doesn't exist.", start),
+        Warning::StartPassageMissing(None) => "The StoryData has no start passage and there is no passage named \"Start\".".to_owned(),
+        Warning::StartPassageGuessed(p) => format!("Using the first passage \"{}\" as the start passage.", p),
+        w => format!("{:?}", w),
+    });
+}
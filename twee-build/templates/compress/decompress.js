@@ -0,0 +1,66 @@
+// Decompresses the passage text compressed by twee-tools, before the story format reads it.
+// The format is compatible with LZString.compressToBase64 from lz-string.
+(function () {
+    var keys = "ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/=";
+    function decompress(input) {
+        var data = { val: keys.indexOf(input.charAt(0)), position: 32, index: 1 };
+        function read(n) {
+            var bits = 0;
+            for (var power = 1; power != 1 << n; power <<= 1) {
+                var b = data.val & data.position;
+                data.position >>= 1;
+                if (data.position == 0) {
+                    data.position = 32;
+                    data.val = keys.indexOf(input.charAt(data.index++));
+                }
+                bits |= (b > 0 ? 1 : 0) * power;
+            }
+            return bits;
+        }
+        var dictionary = [0, 1, 2], enlargeIn = 4, dictSize = 4, numBits = 3, result = [], w, c, entry;
+        switch (read(2)) {
+            case 0: c = String.fromCharCode(read(8)); break;
+            case 1: c = String.fromCharCode(read(16)); break;
+            default: return "";
+        }
+        dictionary[3] = w = c;
+        result.push(c);
+        while (data.index <= input.length) {
+            c = read(numBits);
+            if (c == 0 || c == 1) {
+                dictionary[dictSize++] = String.fromCharCode(read(c == 0 ? 8 : 16));
+                c = dictSize - 1;
+                enlargeIn--;
+            } else if (c == 2) {
+                return result.join("");
+            }
+            if (enlargeIn == 0) {
+                enlargeIn = Math.pow(2, numBits++);
+            }
+            if (dictionary[c]) {
+                entry = dictionary[c];
+            } else if (c == dictSize) {
+                entry = w + w.charAt(0);
+            } else {
+                return null;
+            }
+            result.push(entry);
+            dictionary[dictSize++] = w + entry.charAt(0);
+            enlargeIn--;
+            w = entry;
+            if (enlargeIn == 0) {
+                enlargeIn = Math.pow(2, numBits++);
+            }
+        }
+        return "";
+    }
+    var storydata = document.querySelector("tw-storydata[twee-compressed]");
+    if (! storydata) {
+        return;
+    }
+    var passages = storydata.querySelectorAll("tw-passagedata");
+    for (var i = 0; i < passages.length; i++) {
+        passages[i].textContent = decompress(passages[i].textContent.trim());
+    }
+    storydata.removeAttribute("twee-compressed");
+})();
@@ -0,0 +1,20 @@
+// Decodes the passage text obfuscated by twee-tools, before the story format reads it.
+// The text is UTF-8 XORed with the IFID of the story, in base64.
+(function () {
+    var storydata = document.querySelector("tw-storydata[twee-obfuscated]");
+    if (! storydata) {
+        return;
+    }
+    var key = new TextEncoder().encode(storydata.getAttribute("ifid") || storydata.getAttribute("name"));
+    var decoder = new TextDecoder();
+    var passages = storydata.querySelectorAll("tw-passagedata");
+    for (var i = 0; i < passages.length; i++) {
+        var data = atob(passages[i].textContent.trim());
+        var bytes = new Uint8Array(data.length);
+        for (var j = 0; j < data.length; j++) {
+            bytes[j] = data.charCodeAt(j) ^ key[j % key.length];
+        }
+        passages[i].textContent = decoder.decode(bytes);
+    }
+    storydata.removeAttribute("twee-obfuscated");
+})();
@@ -0,0 +1,71 @@
+use regex::Regex;
+
+use crate::{Passage, Story};
+
+/// A note like `TODO: ...` or `<!-- ... -->` left in a passage.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Annotation {
+    /// The name of the passage.
+    pub passage: String,
+    /// The line in the passage content the annotation starts at, starting at 1.
+    pub line: usize,
+    /// The marker, like `TODO`, or `note` for HTML comments.
+    pub marker: String,
+    /// The person the annotation is for, from `TODO(name)` or `TODO @name`.
+    pub assignee: Option<String>,
+    /// The text after the marker, with whitespace collapsed.
+    pub text: String,
+}
+
+fn collapse_whitespace(s: &str) -> String {
+    s.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+impl Passage {
+    /// Finds the annotations in the passage content: the markers, like `TODO` or `FIXME`, as whole words with the text
+    /// up to the end of the line, and with `comments` the HTML comments that don't contain a marker.
+    /// A marker can name an assignee as `TODO(name): text` or `TODO @name text`.
+    pub fn annotations(&self, markers: &[String], comments: bool) -> Vec<Annotation> {
+        let line_of = |i: usize| self.content[..i].matches('\n').count() + 1;
+        let mut annotations = vec![];
+        if ! markers.is_empty() {
+            let alternatives: Vec<String> = markers.iter().map(|m| regex::escape(m)).collect();
+            let marker = Regex::new(&format!(r"\b({})\b(?:\(([^)\n]*)\)|[ \t]+@(\S+))?:?([^\n]*)", alternatives.join("|"))).unwrap();
+            for c in marker.captures_iter(&self.content) {
+                // Comment ends are left out, as the annotation is usually inside one.
+                let text = c[4].trim().trim_end_matches("-->").trim_end_matches("*/");
+                annotations.push(Annotation {
+                    passage: self.name.clone(),
+                    line: line_of(c.get(0).unwrap().start()),
+                    marker: c[1].to_string(),
+                    assignee: c.get(2).or(c.get(3)).map(|a| a.as_str().trim().to_string()).filter(|a| ! a.is_empty()),
+                    text: collapse_whitespace(text),
+                });
+            }
+        }
+        if comments {
+            let comment = Regex::new(r"(?s)<!--(.*?)-->").unwrap();
+            for c in comment.captures_iter(&self.content) {
+                let start = c.get(0).unwrap().start();
+                if annotations.iter().any(|a| a.line >= line_of(start) && a.line <= line_of(c.get(0).unwrap().end())) {
+                    continue;
+                }
+                let text = collapse_whitespace(&c[1]);
+                let (assignee, text) = match text.strip_prefix('@').and_then(|t| t.split_once(' ')) {
+                    Some((a, t)) => (Some(a.trim_end_matches(':').to_string()), t.to_string()),
+                    None => (None, text),
+                };
+                annotations.push(Annotation { passage: self.name.clone(), line: line_of(start), marker: "note".to_string(), assignee, text });
+            }
+            annotations.sort_by_key(|a| a.line);
+        }
+        annotations
+    }
+}
+
+impl Story {
+    /// The annotations of all passages, see [Passage::annotations].
+    pub fn annotations(&self, markers: &[String], comments: bool) -> Vec<Annotation> {
+        self.passages.iter().flat_map(|p| p.annotations(markers, comments)).collect()
+    }
+}
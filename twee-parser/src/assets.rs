@@ -0,0 +1,64 @@
+use std::collections::HashMap;
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use regex::{Captures, Regex};
+
+use crate::Story;
+
+/// A file that was embedded as a base64 data URI, see [Story::extract_data_uris].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExtractedAsset {
+    /// The path the data URI was replaced with.
+    pub path: String,
+    /// The MIME type from the data URI.
+    pub mime: String,
+    /// The decoded file content.
+    pub data: Vec<u8>,
+}
+
+/// The file extension for a MIME type.
+fn extension(mime: &str) -> String {
+    match mime {
+        "image/jpeg" => "jpg",
+        "image/svg+xml" => "svg",
+        "image/x-icon" | "image/vnd.microsoft.icon" => "ico",
+        "audio/mpeg" => "mp3",
+        "audio/wav" | "audio/x-wav" | "audio/wave" => "wav",
+        "text/plain" => "txt",
+        "text/javascript" | "application/javascript" => "js",
+        "application/octet-stream" => "bin",
+        _ => {
+            let sub = mime.split('/').nth(1).unwrap_or("bin");
+            return sub.trim_start_matches("x-").chars().filter(|c| c.is_ascii_alphanumeric()).collect();
+        },
+    }.to_string()
+}
+
+impl Story {
+    /// Replaces the base64 data URIs in the passages with paths of files in `dir`, and returns the decoded files for
+    /// the caller to write. Makes decompiled stories with embedded images editable.  
+    /// The files are named `asset-1.png` and so on. The same data embedded multiple times becomes one file. Data URIs
+    /// that aren't valid base64 are left as they are.
+    pub fn extract_data_uris(&mut self, dir: &str) -> Vec<ExtractedAsset> {
+        let data_uri = Regex::new(r"data:([a-zA-Z0-9.+-]+/[a-zA-Z0-9.+-]+)(?:;[a-zA-Z0-9.+-]+=[a-zA-Z0-9.+-]+)*;base64,([A-Za-z0-9+/]+=*)").unwrap();
+        let dir = dir.trim_end_matches('/');
+        let mut assets: Vec<ExtractedAsset> = vec![];
+        let mut paths: HashMap<String, usize> = HashMap::new();
+        for p in &mut self.passages {
+            p.content = data_uri.replace_all(&p.content, |c: &Captures| {
+                if let Some(i) = paths.get(&c[2]) {
+                    return assets[*i].path.clone();
+                }
+                let Ok(data) = STANDARD.decode(&c[2]) else {
+                    return c[0].to_string();
+                };
+                let name = format!("asset-{}.{}", assets.len() + 1, extension(&c[1]));
+                let path = if dir.is_empty() { name } else { format!("{}/{}", dir, name) };
+                paths.insert(c[2].to_string(), assets.len());
+                assets.push(ExtractedAsset { path: path.clone(), mime: c[1].to_string(), data });
+                path
+            }).to_string();
+        }
+        assets
+    }
+}
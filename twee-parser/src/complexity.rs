@@ -0,0 +1,103 @@
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+
+use regex::Regex;
+
+use crate::{is_special_passage, Story};
+
+/// The structure of a passage, see [Story::complexity].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PassageComplexity {
+    pub name: String,
+    /// The number of distinct passages the passage links to.
+    pub choices: usize,
+    /// The number of passages linking to the passage.
+    pub incoming: usize,
+    /// 1 plus the number of decision points, which are the choices after the first one and conditionals like
+    /// `<<if>>`, `(if:)` or `[if ...]`.
+    pub complexity: usize,
+}
+
+/// The structural complexity of a story, see [Story::complexity].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Complexity {
+    /// The passages in story order.
+    pub passages: Vec<PassageComplexity>,
+    /// The cyclomatic complexity of the passages reachable from the start passage: links - passages + 2.
+    pub overall: usize,
+    /// Passages with at least twice the average number of links in and out, most connected first.
+    pub hubs: Vec<String>,
+    /// Passages every route from the start passage to an ending goes through, not counting the start passage and
+    /// the endings themselves.
+    pub bottlenecks: Vec<String>,
+}
+
+/// Hubs need at least this many links in and out.
+const MIN_HUB_LINKS: usize = 4;
+
+impl Story {
+    /// Analyses the link structure of the story. Special passages like scripts are left out.
+    pub fn complexity(&self) -> Complexity {
+        let conditionals = Regex::new(r"<<(?:if|elseif|case)\b|\((?:if|unless|else-if|elseif):|\[(?:if|unless|else if) ").unwrap();
+        let passages: Vec<_> = self.passages.iter().filter(|p| ! is_special_passage(p)).collect();
+        let mut links: BTreeMap<&str, BTreeSet<&str>> = BTreeMap::new();
+        for p in &passages {
+            let targets: BTreeSet<&str> = self.targets(p).into_iter()
+                .filter(|t| ! is_special_passage(t))
+                .map(|t| t.name.as_str()).collect();
+            links.insert(&p.name, targets);
+        }
+        let mut incoming: BTreeMap<&str, usize> = BTreeMap::new();
+        for targets in links.values() {
+            for t in targets {
+                *incoming.entry(t).or_default() += 1;
+            }
+        }
+        let result: Vec<PassageComplexity> = passages.iter().map(|p| {
+            let choices = links[p.name.as_str()].len();
+            PassageComplexity {
+                name: p.name.clone(),
+                choices,
+                incoming: incoming.get(p.name.as_str()).copied().unwrap_or(0),
+                complexity: 1 + choices.saturating_sub(1) + conditionals.find_iter(&p.content).count(),
+            }
+        }).collect();
+        
+        let start = self.start_passage().map(|p| p.name.as_str()).filter(|s| links.contains_key(s));
+        let reachable = |skip: Option<&str>| -> BTreeSet<&str> {
+            let mut seen = BTreeSet::new();
+            let mut queue: VecDeque<&str> = start.into_iter().collect();
+            while let Some(p) = queue.pop_front() {
+                if Some(p) == skip || ! seen.insert(p) {
+                    continue;
+                }
+                queue.extend(links[p].iter());
+            }
+            seen
+        };
+        let all = reachable(None);
+        let edges: usize = all.iter().map(|p| links[p].len()).sum();
+        let overall = (edges + 2).saturating_sub(all.len()).max(1);
+        
+        let degrees: Vec<(usize, &str)> = result.iter().map(|p| (p.choices + p.incoming, p.name.as_str())).collect();
+        let mean = degrees.iter().map(|(d, _)| *d).sum::<usize>() as f64 / degrees.len().max(1) as f64;
+        let mut hubs: Vec<(usize, &str)> = degrees.into_iter().filter(|(d, _)| *d >= MIN_HUB_LINKS && *d as f64 >= 2.0 * mean).collect();
+        hubs.sort_by_key(|h| std::cmp::Reverse(h.0));
+        let hubs = hubs.into_iter().map(|(_, p)| p.to_string()).collect();
+        
+        let endings: BTreeSet<&str> = all.iter().copied().filter(|p| links[p].is_empty()).collect();
+        let bottlenecks = if endings.is_empty() {
+            vec![]
+        } else {
+            all.iter().copied()
+                .filter(|p| Some(*p) != start && ! endings.contains(p))
+                .filter(|p| reachable(Some(p)).is_disjoint(&endings))
+                .map(|p| p.to_string()).collect()
+        };
+        Complexity {
+            passages: result,
+            overall,
+            hubs,
+            bottlenecks,
+        }
+    }
+}
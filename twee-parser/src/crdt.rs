@@ -0,0 +1,184 @@
+use std::{collections::BTreeSet, ops::Range};
+
+use serde::{Deserialize, Serialize};
+
+/// The identity of a character in a [CrdtText]: a Lamport timestamp and the replica that inserted it.
+/// Ids are ordered by counter, then replica, which decides the order of characters inserted concurrently at the same
+/// place.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct OpId {
+    pub counter: u64,
+    pub replica: u64,
+}
+
+/// An operation on a [CrdtText], to be sent to the other replicas.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum TextOp {
+    /// Inserts a character after another one, or at the start.
+    Insert { id: OpId, after: Option<OpId>, ch: char },
+    /// Deletes a character.
+    Delete { id: OpId },
+}
+
+#[derive(Debug, Clone)]
+struct Element {
+    id: OpId,
+    after: Option<OpId>,
+    ch: char,
+    deleted: bool,
+}
+
+/// Text that can be edited by multiple replicas at the same time, like the content of a passage edited by multiple
+/// users, and converges to the same text on all of them without conflicts.
+/// This is a replicated growable array: each edit returns [TextOp]s, which the other replicas [apply](CrdtText::apply)
+/// in any order. Operations that depend on ones that didn't arrive yet are held back until they do.
+#[derive(Debug, Clone)]
+pub struct CrdtText {
+    replica: u64,
+    clock: u64,
+    /// All characters ever inserted in document order, with deleted ones kept as tombstones.
+    elements: Vec<Element>,
+    pending: Vec<TextOp>,
+}
+
+impl CrdtText {
+    /// An empty text for a replica. Each replica editing the text needs its own id.
+    pub fn new(replica: u64) -> CrdtText {
+        CrdtText { replica, clock: 0, elements: vec![], pending: vec![] }
+    }
+
+    /// The id of the replica.
+    pub fn replica(&self) -> u64 {
+        self.replica
+    }
+
+    /// The current text.
+    pub fn text(&self) -> String {
+        self.elements.iter().filter(|e| ! e.deleted).map(|e| e.ch).collect()
+    }
+
+    /// The operations that recreate the current text on a new replica, including deleted characters other
+    /// replicas may still refer to.
+    pub fn ops(&self) -> Vec<TextOp> {
+        let mut ops: Vec<TextOp> = self.elements.iter().map(|e| TextOp::Insert { id: e.id, after: e.after, ch: e.ch }).collect();
+        ops.extend(self.elements.iter().filter(|e| e.deleted).map(|e| TextOp::Delete { id: e.id }));
+        ops
+    }
+
+    fn index_of(&self, id: OpId) -> Option<usize> {
+        self.elements.iter().position(|e| e.id == id)
+    }
+
+    /// The index in `elements` of the character at `index` in the text, or the length if it's past the end.
+    fn element_index(&self, index: usize) -> usize {
+        self.elements.iter().enumerate().filter(|(_, e)| ! e.deleted).nth(index).map(|(i, _)| i).unwrap_or(self.elements.len())
+    }
+
+    fn next_id(&mut self) -> OpId {
+        self.clock += 1;
+        OpId { counter: self.clock, replica: self.replica }
+    }
+
+    /// Inserts text before the character at `index`, counted in characters.
+    pub fn insert(&mut self, index: usize, text: &str) -> Vec<TextOp> {
+        let at = self.element_index(index);
+        // Inserting right after the previous character, even if characters deleted after it are in between.
+        let mut after = self.elements[..at].iter().rev().find(|e| ! e.deleted).map(|e| e.id);
+        let mut ops = vec![];
+        for ch in text.chars() {
+            let op = TextOp::Insert { id: self.next_id(), after, ch };
+            self.apply(&op);
+            if let TextOp::Insert { id, .. } = op {
+                after = Some(id);
+            }
+            ops.push(op);
+        }
+        ops
+    }
+
+    /// Deletes the characters in the range, counted in characters.
+    pub fn delete(&mut self, range: Range<usize>) -> Vec<TextOp> {
+        let ids: Vec<OpId> = self.elements.iter().filter(|e| ! e.deleted).skip(range.start).take(range.len()).map(|e| e.id).collect();
+        let ops: Vec<TextOp> = ids.into_iter().map(|id| TextOp::Delete { id }).collect();
+        for op in &ops {
+            self.apply(op);
+        }
+        ops
+    }
+
+    /// Changes the text to `text` by deleting and inserting the part between the common prefix and suffix, for
+    /// replicas that only see whole texts, like a file changed by an editor.
+    pub fn set_text(&mut self, text: &str) -> Vec<TextOp> {
+        let old: Vec<char> = self.text().chars().collect();
+        let new: Vec<char> = text.chars().collect();
+        let prefix = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+        let suffix = old[prefix..].iter().rev().zip(new[prefix..].iter().rev()).take_while(|(a, b)| a == b).count();
+        let mut ops = self.delete(prefix..(old.len() - suffix));
+        ops.extend(self.insert(prefix, &new[prefix..(new.len() - suffix)].iter().collect::<String>()));
+        ops
+    }
+
+    /// Applies an operation of this or another replica. Operations already applied are ignored, and ones that refer
+    /// to characters that aren't known yet are applied once they are.
+    pub fn apply(&mut self, op: &TextOp) {
+        if ! self.integrate(op) {
+            self.pending.push(op.clone());
+            return;
+        }
+        // Applying an operation can make pending ones applicable.
+        loop {
+            let before = self.pending.len();
+            let pending = std::mem::take(&mut self.pending);
+            for op in pending {
+                if ! self.integrate(&op) {
+                    self.pending.push(op);
+                }
+            }
+            if self.pending.len() == before {
+                break;
+            }
+        }
+    }
+
+    /// Applies an operation, returning false if it refers to a character that isn't known yet.
+    fn integrate(&mut self, op: &TextOp) -> bool {
+        match op {
+            TextOp::Insert { id, after, ch } => {
+                if self.index_of(*id).is_some() {
+                    return true;
+                }
+                let mut i = match after {
+                    Some(after) => match self.index_of(*after) {
+                        Some(i) => i + 1,
+                        None => return false,
+                    },
+                    None => 0,
+                };
+                // Characters inserted concurrently after the same one are ordered by id, newest first, and skipping
+                // them also skips the characters inserted after them.
+                let mut skipped = BTreeSet::new();
+                while i < self.elements.len() {
+                    let e = &self.elements[i];
+                    let in_skipped = e.after.is_some_and(|a| skipped.contains(&a));
+                    if e.after == *after && e.id > *id || in_skipped {
+                        skipped.insert(e.id);
+                        i += 1;
+                    } else {
+                        break;
+                    }
+                }
+                self.elements.insert(i, Element { id: *id, after: *after, ch: *ch, deleted: false });
+                self.clock = self.clock.max(id.counter);
+                true
+            },
+            TextOp::Delete { id } => match self.index_of(*id) {
+                Some(i) => {
+                    self.elements[i].deleted = true;
+                    true
+                },
+                None => false,
+            },
+        }
+    }
+}
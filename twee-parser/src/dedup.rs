@@ -0,0 +1,112 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Map;
+
+use crate::{Passage, Story};
+
+/// A passage that appears identically, with the same name, tags, metadata and content, in multiple stories.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SharedPassage {
+    pub passage: Passage,
+    /// The indices of the stories containing the passage.
+    pub stories: Vec<usize>,
+}
+
+impl SharedPassage {
+    /// The number of content bytes stored more than once.
+    pub fn duplicated_bytes(&self) -> usize {
+        self.passage.content.len() * (self.stories.len() - 1)
+    }
+}
+
+/// Finds the passages that appear identically in more than one of the stories, like widget or library passages
+/// copied between the stories of a collection. The passages are in the order they first appear.
+pub fn shared_passages(stories: &[Story]) -> Vec<SharedPassage> {
+    let mut shared: Vec<SharedPassage> = vec![];
+    // The indices in `shared` of the passages with a name and content, to only compare the tags and metadata of those.
+    let mut candidates: HashMap<(&str, &str), Vec<usize>> = HashMap::new();
+    for (i, s) in stories.iter().enumerate() {
+        for p in &s.passages {
+            let candidates = candidates.entry((&p.name, &p.content)).or_default();
+            match candidates.iter().find(|c| shared[**c].passage == *p) {
+                Some(c) => {
+                    if ! shared[*c].stories.contains(&i) {
+                        shared[*c].stories.push(i);
+                    }
+                },
+                None => {
+                    candidates.push(shared.len());
+                    shared.push(SharedPassage { passage: p.clone(), stories: vec![i] });
+                },
+            }
+        }
+    }
+    shared.retain(|sp| sp.stories.len() > 1);
+    shared
+}
+
+/// A passage of the library of [Deduplicated] that a story contained.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SharedRef {
+    /// The name of the passage in the library.
+    pub name: String,
+    /// The index of the passage in the passages of the original story.
+    pub position: usize,
+}
+
+/// Stories with the passages they share moved into a single library story, e.g. for storing a large collection in
+/// an archive with each shared passage only once. [Deduplicated::restore] gives back the original stories.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Deduplicated {
+    /// A story with one copy of each shared passage.
+    pub library: Story,
+    /// The stories without the shared passages, in the original order.
+    pub stories: Vec<Story>,
+    /// For each story, the library passages it contained, ordered by position. Can be serialized as a JSON manifest
+    /// next to the archive.
+    pub manifest: Vec<Vec<SharedRef>>,
+}
+
+impl Deduplicated {
+    /// Moves the passages that appear identically in more than one story into a library story with the title.
+    /// Passage names are unique in a story, so if different shared passages have the same name, only the first one
+    /// is moved into the library and the others stay in their stories.
+    pub fn new(stories: &[Story], library_title: &str) -> Deduplicated {
+        let mut library = Story { title: library_title.to_string(), passages: vec![], meta: Map::new() };
+        for sp in shared_passages(stories) {
+            if library.passage(&sp.passage.name).is_none() {
+                library.passages.push(sp.passage);
+            }
+        }
+        let mut deduplicated = vec![];
+        let mut manifest = vec![];
+        for s in stories {
+            let mut story = Story { title: s.title.clone(), passages: vec![], meta: s.meta.clone() };
+            let mut refs = vec![];
+            for (position, p) in s.passages.iter().enumerate() {
+                if library.passage(&p.name) == Some(p) {
+                    refs.push(SharedRef { name: p.name.clone(), position });
+                } else {
+                    story.passages.push(p.clone());
+                }
+            }
+            deduplicated.push(story);
+            manifest.push(refs);
+        }
+        Deduplicated { library, stories: deduplicated, manifest }
+    }
+
+    /// Puts the library passages back into the stories. Passages missing from the library are skipped.
+    pub fn restore(&self) -> Vec<Story> {
+        self.stories.iter().zip(&self.manifest).map(|(s, refs)| {
+            let mut story = s.clone();
+            for r in refs {
+                if let Some(p) = self.library.passage(&r.name) {
+                    story.passages.insert(r.position.min(story.passages.len()), p.clone());
+                }
+            }
+            story
+        }).collect()
+    }
+}
@@ -0,0 +1,50 @@
+use crate::{Passage, Story};
+
+const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+/// Continues a 64 bit FNV-1a hash with the parts, each followed by a 0 byte so moving text between parts changes the
+/// hash.
+fn fnv1a<'a>(mut hash: u64, parts: impl IntoIterator<Item = &'a str>) -> u64 {
+    for part in parts {
+        for b in part.bytes().chain([0]) {
+            hash ^= b as u64;
+            hash = hash.wrapping_mul(FNV_PRIME);
+        }
+    }
+    hash
+}
+
+/// A 64 bit FNV-1a hash of the parts, each followed by a 0 byte. Unlike the hashers of the standard library, it doesn't
+/// change between versions or platforms, so it can be stored or used in file names.
+pub fn stable_hash<'a>(parts: impl IntoIterator<Item = &'a str>) -> u64 {
+    fnv1a(FNV_OFFSET, parts)
+}
+
+impl Passage {
+    /// A hash of the name, tags and content of the passage, for finding out whether a passage changed.  
+    /// The hash is the 64 bit FNV-1a hash of the name, the tags in order and the content without trailing whitespace,
+    /// each followed by a 0 byte. It doesn't change between versions or platforms, so it can be stored.
+    /// Metadata like the position in the Twine editor isn't included, so moving passages around doesn't count as a
+    /// change.
+    pub fn content_hash(&self) -> u64 {
+        fnv1a(FNV_OFFSET, [self.name.as_str()].into_iter().chain(self.tags.iter().map(|t| t.as_str())).chain([self.content.trim_end()]))
+    }
+}
+
+impl Story {
+    /// A hash of the story, for finding out whether anything in it changed.  
+    /// The hash is the 64 bit FNV-1a hash of the title, the metadata as `key` and JSON value pairs sorted by key, and
+    /// the [content hashes](Passage::content_hash) of the passages sorted by name, as 16 lowercase hex digits, each
+    /// followed by a 0 byte. Like the content hash, it's stable and ignores passage metadata, and the order of the
+    /// passages doesn't matter.
+    pub fn fingerprint(&self) -> u64 {
+        let mut meta: Vec<(&String, String)> = self.meta.iter().map(|(k, v)| (k, v.to_string())).collect();
+        meta.sort();
+        let mut passages: Vec<(&str, String)> = self.passages.iter().map(|p| (p.name.as_str(), format!("{:016x}", p.content_hash()))).collect();
+        passages.sort();
+        let hash = fnv1a(FNV_OFFSET, [self.title.as_str()]);
+        let hash = fnv1a(hash, meta.iter().flat_map(|(k, v)| [k.as_str(), v.as_str()]));
+        fnv1a(hash, passages.iter().map(|(_, h)| h.as_str()))
+    }
+}
@@ -0,0 +1,204 @@
+use std::time::{Duration, Instant};
+
+use crate::{Passage, Story};
+
+/// A change recorded by [StoryHistory], with everything needed to revert it.
+#[derive(Debug, Clone)]
+enum Change {
+    Content { passage: String, old: String, new: String },
+    Rename { from: String, to: String },
+    Add { index: usize, passage: Passage },
+    Remove { index: usize, passage: Passage },
+    AddTag { passage: String, tag: String },
+    RemoveTag { passage: String, index: usize, tag: String },
+}
+
+impl Change {
+    fn apply(&self, story: &mut Story, undo: bool) {
+        let find = |story: &mut Story, name: &str| story.passages.iter().position(|p| p.name == name);
+        match (self, undo) {
+            (Change::Content { passage, old, new }, undo) => {
+                if let Some(i) = find(story, passage) {
+                    story.passages[i].content = if undo { old } else { new }.clone();
+                }
+            },
+            (Change::Rename { from, to }, undo) => {
+                let (from, to) = if undo { (to, from) } else { (from, to) };
+                if let Some(i) = find(story, from) {
+                    story.passages[i].name = to.clone();
+                }
+            },
+            (Change::Add { index, passage }, false) | (Change::Remove { index, passage }, true) => {
+                story.passages.insert(*index, passage.clone());
+            },
+            (Change::Add { index, .. }, true) | (Change::Remove { index, .. }, false) => {
+                story.passages.remove(*index);
+            },
+            (Change::AddTag { passage, tag }, false) => {
+                if let Some(i) = find(story, passage) {
+                    story.passages[i].tags.push(tag.clone());
+                }
+            },
+            (Change::AddTag { passage, .. }, true) => {
+                if let Some(i) = find(story, passage) {
+                    story.passages[i].tags.pop();
+                }
+            },
+            (Change::RemoveTag { passage, index, .. }, false) => {
+                if let Some(i) = find(story, passage) {
+                    story.passages[i].tags.remove(*index);
+                }
+            },
+            (Change::RemoveTag { passage, index, tag }, true) => {
+                if let Some(i) = find(story, passage) {
+                    story.passages[i].tags.insert(*index, tag.clone());
+                }
+            },
+        }
+    }
+}
+
+/// Undo and redo for changes to a [Story], for building editors.  
+/// Content edits of the same passage in quick succession, like typing, are combined into one step. The time window
+/// defaults to one second and can be changed with [StoryHistory::with_coalesce_window].
+pub struct StoryHistory {
+    story: Story,
+    undo: Vec<(Change, Instant)>,
+    redo: Vec<Change>,
+    window: Duration,
+    coalesce: bool,
+}
+
+impl StoryHistory {
+    /// Starts a history with the story as it is.
+    pub fn new(story: Story) -> Self {
+        Self { story, undo: vec![], redo: vec![], window: Duration::from_secs(1), coalesce: true }
+    }
+    
+    /// Sets the time within which content edits of the same passage are combined. [Duration::ZERO] turns it off.
+    pub fn with_coalesce_window(mut self, window: Duration) -> Self {
+        self.window = window;
+        self
+    }
+    
+    /// The current state of the story.
+    pub fn story(&self) -> &Story {
+        &self.story
+    }
+    
+    /// Unwraps the story.
+    pub fn into_inner(self) -> Story {
+        self.story
+    }
+    
+    /// Makes the next content edit a separate undo step, e.g. when the editor loses focus.
+    pub fn break_coalescing(&mut self) {
+        self.coalesce = false;
+    }
+    
+    fn record(&mut self, change: Change) {
+        change.apply(&mut self.story, false);
+        self.redo.clear();
+        let now = Instant::now();
+        if let (Change::Content { passage, new, .. }, true) = (&change, self.coalesce) {
+            if let Some((Change::Content { passage: last, new: last_new, .. }, at)) = self.undo.last_mut() {
+                if last == passage && now.duration_since(*at) < self.window {
+                    *last_new = new.clone();
+                    *at = now;
+                    return;
+                }
+            }
+        }
+        self.coalesce = true;
+        self.undo.push((change, now));
+    }
+    
+    /// Sets the content of a passage. Returns false if the passage doesn't exist.
+    pub fn set_content(&mut self, name: &str, content: &str) -> bool {
+        let Some(p) = self.story.passage(name) else {
+            return false;
+        };
+        if p.content != content {
+            self.record(Change::Content { passage: name.to_string(), old: p.content.clone(), new: content.to_string() });
+        }
+        true
+    }
+    
+    /// Renames a passage. Links to it aren't changed.  
+    /// Returns false if the passage doesn't exist or the new name is taken.
+    pub fn rename_passage(&mut self, from: &str, to: &str) -> bool {
+        if self.story.passage(from).is_none() || self.story.passage(to).is_some() {
+            return from == to && self.story.passage(from).is_some();
+        }
+        self.record(Change::Rename { from: from.to_string(), to: to.to_string() });
+        true
+    }
+    
+    /// Adds a passage at the end. Returns false if a passage with the name already exists.
+    pub fn add_passage(&mut self, passage: Passage) -> bool {
+        if self.story.passage(&passage.name).is_some() {
+            return false;
+        }
+        self.record(Change::Add { index: self.story.passages.len(), passage });
+        true
+    }
+    
+    /// Removes a passage. Returns false if the passage doesn't exist.
+    pub fn remove_passage(&mut self, name: &str) -> bool {
+        let Some(index) = self.story.passages.iter().position(|p| p.name == name) else {
+            return false;
+        };
+        self.record(Change::Remove { index, passage: self.story.passages[index].clone() });
+        true
+    }
+    
+    /// Adds a tag to a passage. Returns false if the passage doesn't exist or already has the tag.
+    pub fn add_tag(&mut self, name: &str, tag: &str) -> bool {
+        if self.story.passage(name).is_none_or(|p| p.tags.iter().any(|t| t == tag)) {
+            return false;
+        }
+        self.record(Change::AddTag { passage: name.to_string(), tag: tag.to_string() });
+        true
+    }
+    
+    /// Removes a tag from a passage. Returns false if the passage doesn't exist or doesn't have the tag.
+    pub fn remove_tag(&mut self, name: &str, tag: &str) -> bool {
+        let Some(index) = self.story.passage(name).and_then(|p| p.tags.iter().position(|t| t == tag)) else {
+            return false;
+        };
+        self.record(Change::RemoveTag { passage: name.to_string(), index, tag: tag.to_string() });
+        true
+    }
+    
+    /// Whether there is a change to undo.
+    pub fn can_undo(&self) -> bool {
+        ! self.undo.is_empty()
+    }
+    
+    /// Whether there is an undone change to redo.
+    pub fn can_redo(&self) -> bool {
+        ! self.redo.is_empty()
+    }
+    
+    /// Reverts the last change. Returns false if there is none.
+    pub fn undo(&mut self) -> bool {
+        let Some((change, _)) = self.undo.pop() else {
+            return false;
+        };
+        change.apply(&mut self.story, true);
+        self.redo.push(change);
+        self.coalesce = false;
+        true
+    }
+    
+    /// Applies the last undone change again. Returns false if there is none.
+    pub fn redo(&mut self) -> bool {
+        let Some(change) = self.redo.pop() else {
+            return false;
+        };
+        change.apply(&mut self.story, false);
+        self.undo.push((change, Instant::now()));
+        self.coalesce = false;
+        true
+    }
+}
@@ -1,8 +1,12 @@
+use std::{borrow::Cow, cmp::Ordering};
+
+use regex::{Captures, Regex};
+
 use crate::*;
 
 pub use xmltree::{Element, XMLNode, ParseError};
 
-pub use xmltree;
+pub use ::xmltree;
 
 fn search_storydata(e: &Element) -> Option<Element> {
     if e.name == "tw-storydata" {
@@ -13,26 +17,69 @@ fn search_storydata(e: &Element) -> Option<Element> {
             if e.name == "tw-storydata" {
                 return Some(e.clone());
             } else {
-                if let Some(e) = search_storydata(&e) {
+                if let Some(e) = search_storydata(e) {
                     return Some(e);
                 }
             }
         }
     }
-    return None;
+    None
+}
+
+/// Gives attributes without a value in the Twine tags, like the `hidden` of archives, an empty value, as XML needs one.
+fn fix_bare_attributes(source: &str) -> Cow<'_, str> {
+    let tag = Regex::new(r"<tw-(?:storydata|passagedata)\b[^>]*>").unwrap();
+    let attribute = Regex::new(r#"(\s+)([^\s=/>"']+)(\s*=\s*(?:"[^"]*"|'[^']*'|[^\s>]+))?"#).unwrap();
+    tag.replace_all(source, |c: &Captures| {
+        attribute.replace_all(&c[0], |a: &Captures| {
+            if a.get(3).is_some() || a[2].starts_with('<') {
+                a[0].to_string()
+            } else {
+                format!("{}{}=\"\"", &a[1], &a[2])
+            }
+        }).to_string()
+    })
 }
 
-/// Parses a Twine archive, a list of &lt;tw-storydata&gt; tags, into a list of [Story]s.
+/// An attribute of a Twine start tag like `<tw-storydata name="x">`, parsed like the tags of archives, so bare,
+/// single-quoted and newline-separated attributes work and entities are decoded.
+#[cfg(feature = "mmap")]
+pub(crate) fn start_tag_attribute(tag: &str, name: &str) -> Option<String> {
+    let element = Element::parse(fix_bare_attributes(&format!("{}</tw-storydata>", tag)).as_bytes()).ok()?;
+    element.attributes.get(name).cloned()
+}
+
+/// Collects the &lt;tw-storydata&gt; tags in the nodes, descending into wrapper elements. The text of wrappers like
+/// `<script type="text/html">` and `<template>` is parsed again if it contains story data.
+fn collect_storydata(nodes: &[XMLNode], found: &mut Vec<Element>) -> Result<(), Error> {
+    for n in nodes {
+        match n {
+            XMLNode::Element(e) if e.name == "tw-storydata" => found.push(e.clone()),
+            XMLNode::Element(e) => collect_storydata(&e.children, found)?,
+            XMLNode::Text(t) | XMLNode::CData(t) if t.contains("<tw-storydata") => {
+                collect_storydata(&Element::parse_all(fix_bare_attributes(t).as_bytes()).map_err(Error::HTMLParseError)?, found)?;
+            },
+            _ => {},
+        }
+    }
+    Ok(())
+}
+
+/// Parses a Twine archive, a list of &lt;tw-storydata&gt; tags, into a list of [Story]s.  
+/// The tags can also be wrapped in other elements, like `<script type="text/html">` or `<template>` in some
+/// exports and editor backups.
 pub fn parse_archive(source: &str) -> Result<Vec<(Story, Vec<Warning>)>, Error> {
-    let e = Element::parse_all(source.as_bytes()).map_err(|e| Error::HTMLParseError(e))?;
-    return e.into_iter().map(|e| e.as_element().ok_or(Error::HTMLStoryDataNotFound).and_then(|e| parse_element(e))).collect();
+    let nodes = Element::parse_all(fix_bare_attributes(source).as_bytes()).map_err(Error::HTMLParseError)?;
+    let mut found = vec![];
+    collect_storydata(&nodes, &mut found)?;
+    found.iter().map(parse_element).collect()
 }
 
 /// Parses a published Twine HTML file into a [Story], looking for a &lt;tw-storydata&gt; tag.
 pub fn parse_html(source: &str) -> Result<(Story, Vec<Warning>), Error> {
-    let e = Element::parse(source.as_bytes()).map_err(|e| Error::HTMLParseError(e))?;
+    let e = Element::parse(fix_bare_attributes(source).as_bytes()).map_err(Error::HTMLParseError)?;
     let storydata = search_storydata(&e).ok_or(Error::HTMLStoryDataNotFound)?;
-    return parse_element(&storydata);
+    parse_element(&storydata)
 }
 
 fn parse_element(storydata: &Element) -> Result<(Story, Vec<Warning>), Error> {
@@ -43,8 +90,8 @@ fn parse_element(storydata: &Element) -> Result<(Story, Vec<Warning>), Error> {
         c.as_element()
     }).collect::<Vec<&Element>>();
     elements.sort_by(|a, b| {
-        let a = a.attributes.get("pid").and_then(|p| u32::from_str_radix(p, 10).ok()).unwrap_or(u32::MAX);
-        let b = b.attributes.get("pid").and_then(|p| u32::from_str_radix(p, 10).ok()).unwrap_or(u32::MAX);
+        let a = a.attributes.get("pid").and_then(|p| p.parse::<u32>().ok()).unwrap_or(u32::MAX);
+        let b = b.attributes.get("pid").and_then(|p| p.parse::<u32>().ok()).unwrap_or(u32::MAX);
         a.cmp(&b)
     });
     for n in elements {
@@ -56,14 +103,12 @@ fn parse_element(storydata: &Element) -> Result<(Story, Vec<Warning>), Error> {
                 }
                 meta.remove("pid");
                 if let Some(name) = meta.remove("name") {
-                    let tags = meta.remove("tags").and_then(|tags| {
-                        Some(tags.as_str().unwrap().split_whitespace().map(|s| s.to_string()).collect())
-                    }).unwrap_or(vec![]);
+                    let tags = meta.remove("tags").map(|tags| tags.as_str().unwrap().split_whitespace().map(|s| s.to_string()).collect()).unwrap_or(vec![]);
                     let p = Passage {
                         name: name.as_str().unwrap().to_string(),
                         tags,
                         meta,
-                        content: n.get_text().unwrap().clone().to_string(),
+                        content: n.get_text().map(|t| t.to_string()).unwrap_or_default(),
                     };
                     passages.push(p);
                 }
@@ -119,7 +164,9 @@ fn parse_element(storydata: &Element) -> Result<(Story, Vec<Warning>), Error> {
         meta.insert(a.0.clone(), Value::String(a.1.clone()));
     }
     let mut title = "".to_string();
-    meta.remove("hidden");
+    if meta.contains_key("hidden") {
+        meta.insert("hidden".to_string(), Value::Bool(true));
+    }
     if let Some(t) = meta.remove("name") {
         title = t.as_str().unwrap().to_string();
     } else {
@@ -128,7 +175,7 @@ fn parse_element(storydata: &Element) -> Result<(Story, Vec<Warning>), Error> {
     if let Some(s) = meta.remove("startnode") {
         if let Some(start) = s.as_str() {
             let start = start.to_string();
-            if let Some(start) = storydata.children.iter().find(|c| c.as_element().and_then(|e| Some(e.attributes.get("pid") == Some(&start))).is_some()) {
+            if let Some(start) = storydata.children.iter().find(|c| c.as_element().is_some_and(|e| e.name == "tw-passagedata" && e.attributes.get("pid") == Some(&start))) {
                 if let Some(name) = start.as_element().and_then(|e| e.attributes.get("name")) {
                     meta.insert("start".to_string(), Value::String(name.clone()));
                 }
@@ -137,25 +184,118 @@ fn parse_element(storydata: &Element) -> Result<(Story, Vec<Warning>), Error> {
     }
     meta.insert("tag-colors".to_string(), Value::Object(tag_colors));
     
-    return Ok((Story {
+    Ok((Story {
         title,
         passages,
         meta,
-    }, warnings));
+    }, warnings))
+}
+
+/// What [serialize_html_with] does if the `start` metadata is missing or names a passage that doesn't exist.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum StartFallback {
+    /// Leaves out the startnode, like [serialize_html]. The story formats can't run the story then.
+    #[default]
+    Omit,
+    /// Uses the passage named "Start", the default of the Twee 3 specification.
+    Named,
+    /// Uses the passage named "Start", or else the first passage that isn't special, like scripts, with a
+    /// [Warning::StartPassageGuessed].
+    First,
+    /// Fails with [Error::StartPassageNotFound], unless the `start` metadata names a passage.
+    Error,
+}
+
+/// The order [serialize_html_with] writes the passages in. The pids are assigned in that order, starting at 1.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum PassageOrder {
+    /// The order of [Story::passages], which is the order they were parsed in.
+    #[default]
+    Source,
+    /// Sorted by name.
+    Name,
+    /// Sorted by a `pid` metadata value set in the twee source. Parsing HTML drops the pids and orders the passages by
+    /// them instead, so [PassageOrder::Source] keeps the pid order of a Twine story.
+    Pid,
+    /// Sorted by a metadata key. Values that are numbers or numeric strings are compared as numbers.
+    Key(String),
+}
+
+/// Options for [serialize_html_with].
+#[derive(Debug, Clone, Default)]
+pub struct HtmlOptions {
+    /// How the start passage is chosen if the `start` metadata doesn't name one.
+    pub start: StartFallback,
+    /// The order of the passages.
+    pub order: PassageOrder,
+}
+
+/// Compares metadata values, as numbers if both are numeric. Passages without the value come last.
+fn compare_meta(a: Option<&Value>, b: Option<&Value>) -> Ordering {
+    let number = |v: &Value| v.as_f64().or_else(|| v.as_str().and_then(|s| s.trim().parse::<f64>().ok()));
+    match (a, b) {
+        (None, None) => Ordering::Equal,
+        (None, Some(_)) => Ordering::Greater,
+        (Some(_), None) => Ordering::Less,
+        (Some(a), Some(b)) => match (number(a), number(b)) {
+            (Some(a), Some(b)) => a.total_cmp(&b),
+            _ => a.as_str().map(|s| s.to_string()).unwrap_or_else(|| a.to_string())
+                .cmp(&b.as_str().map(|s| s.to_string()).unwrap_or_else(|| b.to_string())),
+        },
+    }
+}
+
+impl PassageOrder {
+    /// Sorts the passages into the order. Sorting is stable, so passages that compare equal keep their order.
+    pub fn sort(&self, passages: &mut [&Passage]) {
+        match self {
+            PassageOrder::Source => {},
+            PassageOrder::Name => passages.sort_by(|a, b| a.name.cmp(&b.name)),
+            PassageOrder::Pid => passages.sort_by(|a, b| compare_meta(a.meta.get("pid"), b.meta.get("pid"))),
+            PassageOrder::Key(key) => passages.sort_by(|a, b| compare_meta(a.meta.get(key), b.meta.get(key))),
+        }
+    }
+}
+
+/// The name of the start passage for the startnode, according to the fallback.
+fn resolve_start(story: &Story, fallback: StartFallback, warnings: &mut Vec<Warning>) -> Result<Option<String>, Error> {
+    if let Some(start) = story.start_passage() {
+        return Ok(Some(start.name.clone()));
+    }
+    let named = story.passage("Start").map(|p| p.name.clone());
+    match fallback {
+        StartFallback::Omit => Ok(None),
+        StartFallback::Named => Ok(named),
+        StartFallback::First => Ok(named.or_else(|| {
+            let first = story.passages.iter().find(|p| ! is_special_passage(p))?.name.clone();
+            warnings.push(Warning::StartPassageGuessed(first.clone()));
+            Some(first)
+        })),
+        StartFallback::Error => Err(Error::StartPassageNotFound(story.meta.get("start").and_then(|s| s.as_str()).unwrap_or_default().to_string())),
+    }
 }
 
 /// Serializes a [Story] into a &lt;tw-storydata&gt; tag.
 pub fn serialize_html(story: &Story) -> Element {
+    serialize_html_with(story, &HtmlOptions::default()).unwrap().0
+}
+
+/// Serializes a [Story] into a &lt;tw-storydata&gt; tag, with options for a missing start passage and the passage order.
+pub fn serialize_html_with(story: &Story, options: &HtmlOptions) -> Result<(Element, Vec<Warning>), Error> {
+    let mut warnings = vec![];
+    let start = resolve_start(story, options.start, &mut warnings)?;
     let mut storydata = Element::new("tw-storydata");
     storydata.attributes.insert("name".to_string(), story.title.clone());
     
     let stylesheet = "stylesheet".to_string();
     let script = "script".to_string();
     let mut pid = 1;
-    for p in &story.passages {
+    let mut passages: Vec<&Passage> = story.passages.iter().collect();
+    options.order.sort(&mut passages);
+    for p in passages {
         let mut e;
         if p.tags.contains(&stylesheet) {
-            if let Some(e) = storydata.children.iter_mut().find(|e| e.as_element().and_then(|e| Some(e.name == "style")) == Some(true)) {
+            if let Some(e) = storydata.children.iter_mut().find(|e| e.as_element().map(|e| e.name == "style") == Some(true)) {
                 let e = e.as_mut_element().unwrap();
                 e.children.push(XMLNode::Text("\n".to_string()));
                 e.children.push(XMLNode::Text(p.content.clone()));
@@ -168,7 +308,7 @@ pub fn serialize_html(story: &Story) -> Element {
             e.children.push(XMLNode::Text(p.content.clone()));
         } else {
             if p.tags.contains(&script) {
-                if let Some(e) = storydata.children.iter_mut().find(|e| e.as_element().and_then(|e| Some(e.name == "script")) == Some(true)) {
+                if let Some(e) = storydata.children.iter_mut().find(|e| e.as_element().map(|e| e.name == "script") == Some(true)) {
                     let e = e.as_mut_element().unwrap();
                     e.children.push(XMLNode::Text("\n".to_string()));
                     e.children.push(XMLNode::Text(p.content.clone()));
@@ -185,7 +325,7 @@ pub fn serialize_html(story: &Story) -> Element {
                 pid += 1;
                 e.attributes.insert("name".to_string(), p.name.clone());
                 e.attributes.insert("tags".to_string(), p.tags.join(" "));
-                for m in &p.meta {
+                for m in p.meta.iter().filter(|m| m.0 != "pid") {
                     if let Some(v) = m.1.as_str() {
                         e.attributes.insert(m.0.clone(), v.to_string());
                     }
@@ -200,14 +340,7 @@ pub fn serialize_html(story: &Story) -> Element {
     
     for m in &story.meta {
         match m.0.as_str() {
-            "start" => {
-                if let Some(s) = m.1.as_str() {
-                    let s = s.to_string();
-                    if let Some(start) = storydata.children.iter().find(|c| c.as_element().and_then(|e| Some(e.attributes.get("name") == Some(&s))) == Some(true)) {
-                        storydata.attributes.insert("startnode".to_string(), start.as_element().unwrap().attributes.get("pid").unwrap().clone());
-                    }
-                }
-            },
+            "start" => {},
             "tag-colors" => {
                 if let Some(tags) = m.1.as_object() {
                     for t in tags {
@@ -220,6 +353,11 @@ pub fn serialize_html(story: &Story) -> Element {
                     }
                 }
             },
+            "hidden" => {
+                if m.1.as_bool() == Some(true) || m.1.is_string() {
+                    storydata.attributes.insert("hidden".to_string(), String::new());
+                }
+            },
             _ => {
                 if let Some(v) = &m.1.as_str() {
                     storydata.attributes.insert(m.0.clone(), v.to_string());
@@ -227,6 +365,12 @@ pub fn serialize_html(story: &Story) -> Element {
             }
         }
     }
-    return storydata;
+    if let Some(s) = start {
+        if let Some(start) = storydata.children.iter().filter_map(|c| c.as_element()).find(|e| e.name == "tw-passagedata" && e.attributes.get("name") == Some(&s)) {
+            let pid = start.attributes.get("pid").unwrap().clone();
+            storydata.attributes.insert("startnode".to_string(), pid);
+        }
+    }
+    Ok((storydata, warnings))
 }
 
@@ -1,34 +1,101 @@
 use serde_json::{Map, Value};
 
-use crate::{Error, Story, Warning};
-
-
+use crate::{Error, Passage, Story, Warning};
 
+/// Parses a [Story] from Twine JSON.
 pub fn parse_json(source: &str) -> Result<(Story, Vec<Warning>), Error> {
     let mut warnings = vec![];
-    let mut v = serde_json::from_str::<Map<String, Value>>(source).map_err(|e| Error::JSONParseError(e))?;
+    let mut v = serde_json::from_str::<Map<String, Value>>(source).map_err(Error::JSONParseError)?;
     let title = v.remove("name");
     if title.is_none() {
         warnings.push(Warning::StoryTitleMissing);
     }
-    let start = v.remove("start");
     let style = v.remove("style");
-    let srcipt = v.remove("script");
-    
+    let script = v.remove("script");
+    let mut passages = vec![];
+    if let Some(Value::Array(ps)) = v.remove("passages") {
+        for p in ps {
+            let Value::Object(mut p) = p else {
+                warnings.push(Warning::PassageNameMissing);
+                continue;
+            };
+            let name = match p.remove("name") {
+                Some(Value::String(name)) if ! name.is_empty() => name,
+                _ => {
+                    warnings.push(Warning::PassageNameMissing);
+                    continue;
+                }
+            };
+            if passages.iter().any(|o: &Passage| o.name == name) {
+                warnings.push(Warning::PassageDuplicated(name));
+                continue;
+            }
+            let tags = match p.remove("tags") {
+                Some(Value::Array(tags)) => tags.into_iter().filter_map(|t| t.as_str().map(|t| t.to_string())).collect(),
+                None => vec![],
+                _ => {
+                    warnings.push(Warning::PassageTagsMalformed(name.clone()));
+                    vec![]
+                }
+            };
+            let meta = match p.remove("metadata") {
+                Some(Value::Object(meta)) => meta,
+                None => Map::new(),
+                _ => {
+                    warnings.push(Warning::PassageMetadataMalformed(name.clone()));
+                    Map::new()
+                }
+            };
+            let content = p.remove("text").and_then(|t| t.as_str().map(|t| t.to_string())).unwrap_or_default();
+            passages.push(Passage { name, tags, meta, content });
+        }
+    }
+    if let Some(Value::String(s)) = style {
+        if ! s.is_empty() {
+            passages.push(Passage { name: "StoryStylesheet".to_string(), tags: vec!["stylesheet".to_string()], meta: Map::new(), content: s });
+        }
+    }
+    if let Some(Value::String(s)) = script {
+        if ! s.is_empty() {
+            passages.push(Passage { name: "StoryScript".to_string(), tags: vec!["script".to_string()], meta: Map::new(), content: s });
+        }
+    }
+
     Ok((Story {
         title: if let Some(title) = title {
-            serde_json::from_value::<String>(title.clone()).map_err(|e| Error::JSONParseError(e))?
+            serde_json::from_value::<String>(title.clone()).map_err(Error::JSONParseError)?
         } else {
             "".to_owned()
         },
-        passages: todo!(),
+        passages,
         meta: v
     }, warnings))
 }
 
-
-
-
-
-
-
+/// Serializes a [Story] into Twine JSON.  
+/// The text of passages tagged `script` or `stylesheet` goes into the `script` and `style` fields.
+pub fn serialize_json(story: &Story) -> String {
+    let mut v = story.meta.clone();
+    v.insert("name".to_string(), story.title.clone().into());
+    let mut style = vec![];
+    let mut script = vec![];
+    let mut passages = vec![];
+    for p in &story.passages {
+        if p.tags.iter().any(|t| t == "stylesheet") {
+            style.push(p.content.as_str());
+        } else if p.tags.iter().any(|t| t == "script") {
+            script.push(p.content.as_str());
+        } else {
+            let mut o = Map::new();
+            o.insert("name".to_string(), p.name.clone().into());
+            o.insert("tags".to_string(), p.tags.clone().into());
+            o.insert("metadata".to_string(), p.meta.clone().into());
+            o.insert("text".to_string(), p.content.clone().into());
+            passages.push(Value::Object(o));
+        }
+    }
+    v.insert("passages".to_string(), passages.into());
+    v.insert("style".to_string(), style.join("\n").into());
+    v.insert("script".to_string(), script.join("\n").into());
+    serde_json::to_string_pretty(&v).unwrap()
+}
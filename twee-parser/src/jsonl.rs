@@ -0,0 +1,84 @@
+use std::io::{BufRead, Lines, Write};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::{Error, Passage, Story};
+
+#[derive(Serialize)]
+struct JsonlLink<'a> {
+    text: &'a str,
+    target: &'a str,
+}
+
+#[derive(Serialize)]
+struct JsonlPassageRef<'a> {
+    name: &'a str,
+    tags: &'a [String],
+    meta: &'a Map<String, Value>,
+    content: &'a str,
+    links: Vec<JsonlLink<'a>>,
+}
+
+#[derive(Deserialize)]
+struct JsonlPassage {
+    name: String,
+    #[serde(default)]
+    tags: Vec<String>,
+    #[serde(default)]
+    meta: Map<String, Value>,
+    #[serde(default)]
+    content: String,
+}
+
+/// Writes the passages of a [Story] as JSON Lines: one object per line with the `name`, `tags`, `meta`, `content` and
+/// the `links` of a passage as objects with `text` and `target`.
+/// The title and metadata of the story aren't written.
+pub fn export_jsonl<W: Write>(story: &Story, mut writer: W) -> std::io::Result<()> {
+    for p in &story.passages {
+        let links = p.links();
+        let line = JsonlPassageRef {
+            name: &p.name,
+            tags: &p.tags,
+            meta: &p.meta,
+            content: &p.content,
+            links: links.iter().map(|l| JsonlLink { text: &l.text, target: &l.target }).collect(),
+        };
+        serde_json::to_writer(&mut writer, &line)?;
+        writer.write_all(b"\n")?;
+    }
+    writer.flush()
+}
+
+/// An iterator over the passages of JSON Lines, see [import_jsonl].
+pub struct JsonlPassages<R> {
+    lines: Lines<R>,
+    line: usize,
+}
+
+impl<R: BufRead> Iterator for JsonlPassages<R> {
+    type Item = Result<Passage, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            self.line += 1;
+            let line = match self.lines.next()? {
+                Ok(line) => line,
+                Err(e) => return Some(Err(Error::IOError(e))),
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+            return Some(serde_json::from_str::<JsonlPassage>(&line)
+                .map(|p| Passage { name: p.name, tags: p.tags, meta: p.meta, content: p.content })
+                .map_err(|e| Error::JSONLinesParseError(self.line, e)));
+        }
+    }
+}
+
+/// Reads passages from JSON Lines as written by [export_jsonl], one line at a time. The `links` are ignored, as they
+/// come from the content. Blank lines are skipped, and a malformed line is an error for that line only, so the
+/// iteration can go on.
+pub fn import_jsonl<R: BufRead>(reader: R) -> JsonlPassages<R> {
+    JsonlPassages { lines: reader.lines(), line: 0 }
+}
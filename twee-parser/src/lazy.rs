@@ -0,0 +1,102 @@
+use std::borrow::Cow;
+
+use regex::RegexBuilder;
+use serde_json::{Map, Value};
+
+use crate::{links::links_in, parse_twee3, passage_spans, Error, Link, Passage, Story, Warning};
+
+/// A passage of a [LazyStory].
+#[derive(Debug, Clone, PartialEq)]
+pub struct LazyPassage<'a> {
+    /// The name of the passage.
+    pub name: String,
+    /// The passage tags.
+    pub tags: Vec<String>,
+    /// The passage metadata.
+    pub meta: Map<String, Value>,
+    /// The text content of the passage, borrowed from the source unless lines starting with `::` had to be unescaped.
+    pub content: Cow<'a, str>,
+}
+
+impl<'a> LazyPassage<'a> {
+    /// Extracts the `[[...]]` links like [Passage::links], without copying the content.
+    pub fn links(&self) -> Vec<Link> {
+        links_in(&self.content)
+    }
+
+    /// Copies the passage into a [Passage].
+    pub fn to_passage(&self) -> Passage {
+        Passage { name: self.name.clone(), tags: self.tags.clone(), meta: self.meta.clone(), content: self.content.to_string() }
+    }
+}
+
+/// A [Story] parsed from Twee3 that borrows the content of the passages from the source instead of copying it, so tools
+/// that only need the names, tags and links of huge stories don't keep a second copy of every passage.  
+/// The source has to outlive the story, it can be e.g. a memory-mapped file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LazyStory<'a> {
+    /// The name of the story.
+    pub title: String,
+    /// The list of [LazyPassage]s.
+    pub passages: Vec<LazyPassage<'a>>,
+    /// The metadata.
+    pub meta: Map<String, Value>,
+}
+
+impl<'a> LazyStory<'a> {
+    /// Gets the passage with the name.
+    pub fn passage(&self, name: &str) -> Option<&LazyPassage<'a>> {
+        self.passages.iter().find(|p| p.name == name)
+    }
+
+    /// Copies the story into a [Story], with the same passages as [parse_twee3] would return.
+    pub fn to_story(&self) -> Story {
+        Story { title: self.title.clone(), passages: self.passages.iter().map(LazyPassage::to_passage).collect(), meta: self.meta.clone() }
+    }
+}
+
+/// Parses Twee3 into a [LazyStory], with the same warnings as [parse_twee3].
+pub fn parse_twee3_lazy(source: &str) -> Result<(LazyStory<'_>, Vec<Warning>), Error> {
+    let mut story = LazyStory { title: String::new(), passages: vec![], meta: Map::new() };
+    let mut warnings = vec![];
+    let mut has_meta = false;
+    let passage_escape = RegexBuilder::new("^\\\\::").multi_line(true).build().unwrap();
+    for span in passage_spans(source) {
+        let text = &source[span.span];
+        let header_end = text.find('\n').map(|i| i + 1).unwrap_or(text.len());
+        let content = text[header_end..].trim_end();
+        if span.name == "StoryTitle" || span.name == "StoryData" {
+            // The special passages are small and parsed with their content.
+            let (part, w) = parse_twee3(text)?;
+            warnings.extend(w.into_iter().filter(|w| ! matches!(w, Warning::StoryTitleMissing)));
+            if span.name == "StoryTitle" {
+                if ! story.title.is_empty() {
+                    warnings.push(Warning::PassageDuplicated("StoryTitle".to_string()));
+                }
+                story.title = part.title;
+            } else {
+                if has_meta {
+                    warnings.push(Warning::PassageDuplicated("StoryData".to_string()));
+                }
+                has_meta = true;
+                story.meta = part.meta;
+            }
+            continue;
+        }
+        // Parsing only the header line gives the name, tags and metadata with the same warnings as the full parser.
+        let (part, w) = parse_twee3(&text[..header_end])?;
+        warnings.extend(w.into_iter().filter(|w| ! matches!(w, Warning::StoryTitleMissing)));
+        let Some(p) = part.passages.into_iter().next() else {
+            continue;
+        };
+        if story.passage(&p.name).is_some() {
+            warnings.push(Warning::PassageDuplicated(p.name));
+            continue;
+        }
+        story.passages.push(LazyPassage { name: p.name, tags: p.tags, meta: p.meta, content: passage_escape.replace_all(content, "::") });
+    }
+    if story.title.is_empty() {
+        warnings.push(Warning::StoryTitleMissing);
+    }
+    Ok((story, warnings))
+}
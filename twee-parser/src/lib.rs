@@ -9,7 +9,7 @@ pub use serde_json;
 use serde_json::{Value, Map};
 
 /// An in-memory representation of a Twine story.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Story {
     /// The name of the story.
     pub title: String,
@@ -23,7 +23,7 @@ pub struct Story {
 }
 
 /// Representation of a passage in a [Story].
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Passage {
     /// The name of the passage.
     pub name: String,
@@ -37,6 +37,7 @@ pub struct Passage {
 
 /// Possible parsing errors.
 #[derive(Error, Debug)]
+#[non_exhaustive]
 pub enum Error {
     /// The xmltree library couldn't parse the data, or it doesn't have the right format.
     #[error("Could not parse HTML: {0}")]
@@ -46,14 +47,35 @@ pub enum Error {
     #[error("No tw-storydata tag found in HTML")]
     #[cfg(feature = "html")]
     HTMLStoryDataNotFound,
+    /// No start passage was found with [StartFallback::Error].  
+    /// The argument is the name from the `start` metadata, which is empty if there is none.
+    #[error("Start passage not found: \"{0}\"")]
+    #[cfg(feature = "html")]
+    StartPassageNotFound(String),
     /// Error while parsing a JSON story.
     #[error("Could not deserialize JSON story: {0}")]
     JSONParseError(serde_json::Error),
+    /// Error while parsing a line of JSON Lines.  
+    /// The arguments are the line number, starting at 1, and the error.
+    #[error("Could not deserialize passage in line {0}: {1}")]
+    JSONLinesParseError(usize, serde_json::Error),
+    /// Error while reading.
+    #[error("Could not read: {0}")]
+    IOError(std::io::Error),
+    /// The range of an edit passed to [Story::reparse_passage_at] is outside the source or doesn't start and end at
+    /// character boundaries.  
+    /// The arguments are the start and end of the range.
+    #[error("Invalid edit range {0}..{1}")]
+    InvalidEditRange(usize, usize),
+    /// A &lt;tw-storydata&gt; tag of a `MappedArchive` isn't valid UTF-8.
+    #[error("Story data isn't valid UTF-8: {0}")]
+    InvalidUtf8(std::str::Utf8Error),
 }
 
 /// Possible warnings during parsing.  
 /// Per specification, the parser is quite generous and generates many things as warnings instead of errors.
 #[derive(Debug, Clone)]
+#[non_exhaustive]
 pub enum Warning {
     /// The story metadata wasn't a valid JSON object.
     StoryMetadataMalformed,
@@ -70,28 +92,776 @@ pub enum Warning {
     PassageDuplicated(String),
     /// A passage is missing it's name.
     PassageNameMissing,
+    /// [Story::render] had no value for a placeholder.  
+    /// The arguments are the passage name and the placeholder key.
+    PlaceholderUnresolved(String, String),
+    /// The passage name contains link syntax like `->` or `|`, or leading or trailing whitespace, so links to it break.  
+    /// The arguments are the passage name and the problem.
+    PassageNameUnlinkable(String, String),
+    /// A tag is empty or contains whitespace.  
+    /// The arguments are the passage name and the tag.
+    TagInvalid(String, String),
+    /// A tag looks like it accidentally collides with a tag that has a special meaning in the story format.  
+    /// The arguments are the passage name, the tag and why it looks accidental.
+    TagReserved(String, String, String),
+    /// A StoryData field has a value the story formats or Twine don't understand, like a `format-version` that's not a
+    /// version.  
+    /// The arguments are the field and the problem.
+    StoryDataInvalid(String, String),
+    /// The `start` metadata names a passage that doesn't exist, or is missing and there is no passage named "Start".  
+    /// The argument is the name from the `start` metadata, if there is one.
+    StartPassageMissing(Option<String>),
+    /// `StartFallback::First` picked the first passage as the start passage.  
+    /// The argument is the passage name.
+    StartPassageGuessed(String),
 }
 
 use thiserror::Error;
 mod twee3;
 pub use twee3::*;
+mod lazy;
+pub use lazy::*;
 mod json;
 pub use json::*;
+mod jsonl;
+pub use jsonl::*;
+mod sql;
+pub use sql::*;
+mod links;
+pub use links::*;
+mod navigation;
+pub use navigation::*;
+mod template;
+pub use template::*;
+mod transform;
+pub use transform::*;
+mod tracked;
+pub use tracked::*;
+mod history;
+pub use history::*;
+mod normalize;
+pub use normalize::*;
+mod validate;
+pub use validate::{is_special_passage, unlinkable};
+mod meta;
+mod assets;
+pub use assets::*;
+mod similar;
+mod dedup;
+pub use dedup::*;
+mod hash;
+pub use hash::stable_hash;
+mod split;
+pub use split::*;
+mod variables;
+mod annotations;
+pub use annotations::*;
+mod paths;
+pub use paths::*;
+mod complexity;
+pub use complexity::*;
+mod replace;
+pub use replace::*;
+mod tags;
+mod version;
+pub use version::*;
 
 #[cfg(feature = "html")]
 mod html;
 #[cfg(feature = "html")]
 pub use html::*;
-#[cfg(feature = "html")]
+#[cfg(feature = "shared")]
+mod shared;
+#[cfg(feature = "shared")]
+pub use shared::*;
+#[cfg(feature = "mmap")]
+mod mmap;
+#[cfg(feature = "mmap")]
+pub use mmap::*;
+#[cfg(feature = "crdt")]
+mod crdt;
+#[cfg(feature = "crdt")]
+pub use crdt::*;
 
 
 #[cfg(test)]
 mod tests {
+    use std::borrow::Cow;
+    
     use super::*;
     
     #[test]
     fn parse_twee() {
         let story = parse_twee3(include_str!("../test-data/Test Story.twee")).unwrap();
-        assert!(story.1.len() == 0, "{:?}", story.1);
+        assert!(story.1.is_empty(), "{:?}", story.1);
+    }
+    
+    #[test]
+    fn passage_tags() {
+        let story = parse_twee3(":: StoryTitle\nT\n\n:: A [x y]\ntext\n").unwrap().0;
+        assert_eq!(story.passages[0].tags, vec!["x".to_string(), "y".to_string()]);
+    }
+    
+    #[test]
+    fn passage_metadata() {
+        let story = parse_twee3(":: StoryTitle\nT\n\n:: A {\"position\":\"100,200\"}\ntext\n").unwrap().0;
+        let twee = serialize_twee3(&story);
+        assert!(twee.contains("\n:: A {\"position\":\"100,200\"}\n"), "{}", twee);
+        assert_eq!(parse_twee3(&twee).unwrap().0.passages[0].meta, story.passages[0].meta);
+    }
+    
+    #[test]
+    #[cfg(feature = "html")]
+    fn start_node() {
+        let source = "<tw-storydata name=\"T\" startnode=\"2\"><tw-passagedata pid=\"1\" name=\"A\">a</tw-passagedata><tw-passagedata pid=\"2\" name=\"B\">b</tw-passagedata></tw-storydata>";
+        let story = parse_archive(source).unwrap().remove(0).0;
+        assert_eq!(story.meta.get("start").and_then(|s| s.as_str()), Some("B"));
+    }
+    
+    #[test]
+    fn json_round_trip() {
+        let story = parse_twee3(":: StoryTitle\nT\n\n:: StoryData\n{\"ifid\": \"X\"}\n\n:: A [x]\ntext\n\n:: S [script]\nlet a;\n").unwrap().0;
+        let parsed = parse_json(&serialize_json(&story)).unwrap();
+        assert!(parsed.1.is_empty(), "{:?}", parsed.1);
+        let parsed = parsed.0;
+        assert_eq!(parsed.title, "T");
+        assert_eq!(parsed.meta.get("ifid").and_then(|i| i.as_str()), Some("X"));
+        assert_eq!(parsed.passages.len(), 2);
+        assert_eq!(parsed.passages[0].tags, vec!["x".to_string()]);
+        assert_eq!(parsed.passages[1].content, "let a;");
+    }
+    
+    #[test]
+    fn jsonl_round_trip() {
+        let story = parse_twee3(":: StoryTitle\nT\n\n:: A [x] {\"position\":\"100,100\"}\nGo [[on->B]]\n\n:: B\nline 1\nline 2\n").unwrap().0;
+        let mut out = vec![];
+        export_jsonl(&story, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(out.lines().count(), 2);
+        assert!(out.starts_with("{\"name\":\"A\",\"tags\":[\"x\"],\"meta\":{\"position\":\"100,100\"},\"content\":\"Go [[on->B]]\",\"links\":[{\"text\":\"on\",\"target\":\"B\"}]}\n"), "{}", out);
+        let passages: Vec<Passage> = import_jsonl((out + "\n{\"tags\": []}\n").as_bytes()).collect::<Vec<_>>().into_iter().take(2).map(|p| p.unwrap()).collect();
+        assert_eq!(passages, story.passages);
+        let errors: Vec<_> = import_jsonl("{\"name\": \"A\"}\n\nnot json\n".as_bytes()).collect();
+        assert!(matches!(&errors[..], [Ok(_), Err(Error::JSONLinesParseError(3, _))]), "{:?}", errors);
+    }
+    
+    #[test]
+    fn sql() {
+        let a = parse_twee3(":: StoryTitle\nA\n\n:: StoryData\n{\"ifid\": \"X\"}\n\n:: Start [x y]\nIt's [[B]] or [[C]]\n\n:: B\nend\n").unwrap().0;
+        let b = parse_twee3(":: StoryTitle\nB\n\n:: Start\n[[Start]]\n").unwrap().0;
+        let sql = serialize_sql(&[a, b]);
+        assert!(sql.starts_with("BEGIN;\nDROP TABLE IF EXISTS links;\n") && sql.ends_with("COMMIT;\n"));
+        assert!(sql.contains("INSERT INTO stories VALUES (1, 'A', 'X');\nINSERT INTO story_meta VALUES (1, 'ifid', '\"X\"');\n"), "{}", sql);
+        assert!(sql.contains("INSERT INTO passages VALUES (1, 1, 0, 'Start', 'It''s [[B]] or [[C]]');\n"), "{}", sql);
+        assert!(sql.contains("INSERT INTO passage_tags VALUES (1, 1, 'y');\n"), "{}", sql);
+        assert!(sql.contains("INSERT INTO links VALUES (1, 0, 'B', 'B', 2);\nINSERT INTO links VALUES (1, 1, 'C', 'C', NULL);\n"), "{}", sql);
+        assert!(sql.contains("INSERT INTO stories VALUES (2, 'B', NULL);\nINSERT INTO passages VALUES (3, 2, 0, 'Start', '[[Start]]');\nINSERT INTO links VALUES (3, 0, 'Start', 'Start', 3);\n"), "{}", sql);
+    }
+    
+    #[test]
+    fn retain_passages() {
+        let source = ":: StoryTitle\nT\n\n:: StoryData\n{\"start\": \"A\"}\n\n:: A\n[[B]] [[next->C]]\n\n:: B [ch2]\n[[D]]\n\n:: C\nend\n\n:: D\n[[C]]\n\n:: S [script]\nlet a;\n";
+        let mut story = parse_twee3(source).unwrap().0;
+        let retained = story.retain_passages(|p| ! p.tags.contains(&"ch2".to_string()), CleanupMode::Report);
+        assert_eq!(retained.removed, vec!["B".to_string()]);
+        assert_eq!(retained.dangling.len(), 1);
+        assert_eq!(story.passage("A").unwrap().content, "[[B]] [[next->C]]");
+        let mut story = parse_twee3(source).unwrap().0;
+        let retained = story.retain_passages(|p| ! p.tags.contains(&"ch2".to_string()), CleanupMode::Prune);
+        assert_eq!(retained.removed, vec!["B".to_string(), "D".to_string()]);
+        assert_eq!(story.passage("A").unwrap().content, "B [[next->C]]");
+        assert!(story.passage("C").is_some() && story.passage("S").is_some());
+    }
+    
+    #[test]
+    fn render() {
+        let story = parse_twee3(":: StoryTitle\nT\n\n:: A\n{{item.price}} gold, {{ item.name }}, {{missing}}, \\{{item.price}}\n").unwrap().0;
+        let data = serde_json::json!({"item": {"price": 5, "name": "Sword"}});
+        let (rendered, warnings) = story.render(&data);
+        assert_eq!(rendered.passages[0].content, "5 gold, Sword, {{missing}}, {{item.price}}");
+        assert!(matches!(&warnings[..], [Warning::PlaceholderUnresolved(p, k)] if p == "A" && k == "missing"));
+    }
+    
+    #[test]
+    fn pipeline() {
+        let mut story = parse_twee3(":: StoryTitle\nT\n\n:: A\ntext\n").unwrap().0;
+        let upper = |s: &mut Story| -> Result<Report, TransformError> {
+            s.passages[0].content = s.passages[0].content.to_uppercase();
+            Ok(Report::default())
+        };
+        let fail = |_: &mut Story| -> Result<Report, TransformError> { Err("failed".into()) };
+        let e = Pipeline::new().then(fail).then(upper).run(&mut story).unwrap_err();
+        assert_eq!(e.errors.len(), 1);
+        assert_eq!(story.passages[0].content, "TEXT");
+        // Failed transforms are timed as well.
+        assert_eq!(e.report.timings.len(), 2);
+    }
+    
+    #[test]
+    fn tracked_story() {
+        use std::{cell::RefCell, rc::Rc};
+        let story = parse_twee3(":: StoryTitle\nT\n\n:: A\ntext\n").unwrap().0;
+        let mut tracked = TrackedStory::new(story);
+        let events = Rc::new(RefCell::new(vec![]));
+        let e = events.clone();
+        tracked.subscribe(move |ev| e.borrow_mut().push(ev.clone()));
+        assert!(tracked.set_content("A", "text"));
+        assert!(tracked.rename_passage("A", "B"));
+        assert!(tracked.set_content("B", "new"));
+        assert!(! tracked.set_content("A", "new"));
+        assert_eq!(*events.borrow(), vec![
+            StoryEvent::PassageRenamed { from: "A".to_string(), to: "B".to_string() },
+            StoryEvent::ContentChanged("B".to_string()),
+        ]);
+        assert_eq!(tracked.changed_since(1).len(), 1);
+        let mut cache = PassageCache::new();
+        assert_eq!(tracked.serialize_twee3(&mut cache), serialize_twee3(tracked.story()));
+        tracked.set_content("B", "newer");
+        assert_eq!(tracked.serialize_twee3(&mut cache), serialize_twee3(tracked.story()));
+    }
+    
+    #[test]
+    fn passage_cache_reuse() {
+        let mut tracked = TrackedStory::new(parse_twee3(":: StoryTitle\nT\n\n:: A\none\n\n:: B\ntwo\n").unwrap().0);
+        let mut cache = PassageCache::new();
+        let calls = std::cell::Cell::new(0);
+        // Passages serializing to nothing are cached too.
+        let empty = |_: &Passage| {
+            calls.set(calls.get() + 1);
+            String::new()
+        };
+        for _ in 0..2 {
+            for p in tracked.story().passages.clone() {
+                cache.get_or_insert_with(&tracked, &p, empty);
+            }
+        }
+        assert_eq!(calls.get(), 2);
+        tracked.set_content("A", "changed");
+        for p in tracked.story().passages.clone() {
+            cache.get_or_insert_with(&tracked, &p, empty);
+        }
+        assert_eq!(calls.get(), 3);
+    }
+    
+    #[test]
+    fn reparse_passage_at() {
+        let source = ":: StoryTitle\nT\n\n:: A\none\n\n:: B\ntwo\n\n:: C\nthree\n";
+        let mut story = parse_twee3(source).unwrap().0;
+        let at = source.find("two").unwrap();
+        let (source, warnings) = story.reparse_passage_at(source, at..(at + 3), "2\n\n:: B2 [x]\nmore").unwrap();
+        assert!(warnings.is_empty(), "{:?}", warnings);
+        let names: Vec<&str> = story.passages.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, vec!["A", "B", "B2", "C"]);
+        assert_eq!(story.passage("B").unwrap().content, "2");
+        let full = parse_twee3(&source).unwrap().0;
+        assert_eq!(full.passages.iter().map(|p| &p.content).collect::<Vec<_>>(), story.passages.iter().map(|p| &p.content).collect::<Vec<_>>());
+        let at = source.find(":: A").unwrap();
+        story.reparse_passage_at(&source, at..(at + 4), ":: Renamed").unwrap();
+        assert_eq!(story.passages[0].name, "Renamed");
+        assert_eq!(story.title, "T");
+    }
+    
+    #[test]
+    fn reparse_passage_at_invalid_range() {
+        let source = ":: StoryTitle\nT\n\n:: Ä\nöne\n";
+        let mut story = parse_twee3(source).unwrap().0;
+        let at = source.find("öne").unwrap();
+        for edit in [at + 1..at + 2, source.len()..source.len() + 1, at + 2..at] {
+            assert!(matches!(story.reparse_passage_at(source, edit.clone(), "x"), Err(Error::InvalidEditRange(_, _))), "{:?}", edit);
+        }
+        assert_eq!(story.passage("Ä").unwrap().content, "öne");
+        let (edited, _) = story.reparse_passage_at(source, source.len()..source.len(), "more\n").unwrap();
+        assert!(edited.ends_with("öne\nmore\n"));
+    }
+    
+    #[test]
+    #[cfg(feature = "mmap")]
+    fn mapped_archive() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/test-data/Test Archive.html");
+        // SAFETY: The test data isn't changed while the tests run.
+        let archive = unsafe { MappedArchive::open(path) }.unwrap();
+        let blocks: Vec<StorydataBlock> = archive.stories().collect();
+        assert_eq!(blocks.iter().map(|b| b.attribute("name").unwrap()).collect::<Vec<_>>(), vec!["Example 1", "Example 2"]);
+        let parsed: Vec<Story> = parse_archive(&std::fs::read_to_string(path).unwrap()).unwrap().into_iter().map(|(s, _)| s).collect();
+        assert_eq!(blocks[1].parse().unwrap().0, parsed[1]);
+        for (block, story) in blocks.iter().zip(&parsed) {
+            let (lazy, _) = block.parse_lazy().unwrap();
+            assert_eq!(&lazy.to_story(), story);
+            // Contents without entities are borrowed from the mapping.
+            assert!(lazy.passages.iter().any(|p| matches!(p.content, Cow::Borrowed(_))));
+        }
+        
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("attributes.html");
+        std::fs::write(&path, "<tw-storydata hidden\n\tname='A &amp; \"B\"'\nifid=\"X\" options=\"a>b\"><tw-passagedata pid=\"1\" name=\"Start\">x</tw-passagedata><tw-passagedata pid=\"2\" name=\"B\" tags>&lt;&lt;set $a to 1&gt;&gt;</tw-passagedata><script>s</script></tw-storydata>").unwrap();
+        // SAFETY: The file is only removed after the archive is dropped.
+        let archive = unsafe { MappedArchive::open(&path) }.unwrap();
+        let block = archive.stories().next().unwrap();
+        assert_eq!(block.attribute("name").as_deref(), Some("A & \"B\""));
+        assert_eq!(block.attribute("ifid").as_deref(), Some("X"));
+        assert_eq!(block.attribute("hidden").as_deref(), Some(""));
+        assert_eq!(block.attribute("format"), None);
+        let (lazy, _) = block.parse_lazy().unwrap();
+        assert_eq!(lazy.to_story(), block.parse().unwrap().0);
+        assert!(matches!(lazy.passage("Start").unwrap().content, Cow::Borrowed("x")));
+        assert_eq!(lazy.passage("B").unwrap().content, "<<set $a to 1>>");
+        assert_eq!(lazy.passage("StoryScript").unwrap().content, "s");
+        drop(archive);
+    }
+    
+    #[test]
+    #[cfg(feature = "crdt")]
+    fn crdt_text() {
+        let mut a = CrdtText::new(1);
+        let mut ops = a.insert(0, "Hello world");
+        let mut b = CrdtText::new(2);
+        // Out of order, the inserts wait for the characters they come after.
+        for op in ops.iter().rev() {
+            b.apply(op);
+        }
+        assert_eq!(b.text(), "Hello world");
+        // Concurrent edits at the same place and in different places.
+        let from_a = [a.insert(5, ", dear"), a.delete(0..1)].concat();
+        let from_b = [b.insert(5, " there"), b.set_text(&b.text().replace("world", "World!"))].concat();
+        for op in &from_b {
+            a.apply(op);
+        }
+        for op in &from_a {
+            b.apply(op);
+        }
+        assert_eq!(a.text(), b.text());
+        assert_eq!(a.text(), "ello there, dear World!");
+        // A new replica catches up from the operations of an existing one.
+        ops = a.ops();
+        let mut c = CrdtText::new(3);
+        for op in &ops {
+            c.apply(op);
+        }
+        assert_eq!(c.text(), a.text());
+    }
+    
+    #[test]
+    #[cfg(feature = "shared")]
+    fn shared_story() {
+        let story: SharedStory = parse_twee3(":: StoryTitle\nT\n\n:: A\none\n\n:: B\ntwo\n").unwrap().0.into();
+        let mut edited = story.clone();
+        edited.passage_mut("A").unwrap().content = "changed".to_string();
+        assert_eq!(story.passage("A").unwrap().content, "one");
+        assert!(! story.shares_passage(&edited, "A"));
+        assert!(story.shares_passage(&edited, "B"));
+        assert_eq!(Story::from(edited).passages[0].content, "changed");
+    }
+    
+    #[test]
+    fn story_history() {
+        let story = parse_twee3(":: StoryTitle\nT\n\n:: A\none\n").unwrap().0;
+        let mut history = StoryHistory::new(story).with_coalesce_window(std::time::Duration::from_secs(60));
+        history.set_content("A", "one t");
+        history.set_content("A", "one tw");
+        history.set_content("A", "one two");
+        history.rename_passage("A", "B");
+        history.add_tag("B", "x");
+        assert!(history.undo() && history.undo());
+        assert_eq!(history.story().passages[0].name, "A");
+        assert!(history.undo());
+        assert_eq!(history.story().passages[0].content, "one");
+        assert!(! history.undo());
+        assert!(history.redo());
+        assert_eq!(history.story().passages[0].content, "one two");
+        history.remove_passage("A");
+        assert!(! history.can_redo());
+        assert!(history.undo());
+        assert_eq!(history.story().passages[0].content, "one two");
+    }
+    
+    #[test]
+    fn semantic_eq() {
+        let a = parse_twee3(":: StoryTitle\nT\n\n:: A [x y]\none  \r\ntwo\n\n:: B\nthree\n").unwrap().0;
+        let b = parse_twee3(":: StoryTitle\nT\n\n:: B\nthree\n\n\n:: A [y x]\none\ntwo\n").unwrap().0;
+        let c = parse_twee3(":: StoryTitle\nT\n\n:: B\nthree\n\n:: A [y x]\none two\n").unwrap().0;
+        assert!(super::semantic_eq(&a, &b));
+        assert!(! super::semantic_eq(&a, &c));
+    }
+    
+    #[test]
+    fn validate_names() {
+        let story = parse_twee3(":: StoryTitle\nT\n\n:: A->B\none\n\n:: Start\ntwo\n").unwrap().0;
+        let warnings = story.validate();
+        assert!(matches!(&warnings[..], [Warning::PassageNameUnlinkable(n, _)] if n == "A->B"), "{:?}", warnings);
+    }
+    
+    #[test]
+    fn validate_tags() {
+        let story = parse_twee3(":: StoryTitle\nT\n\n:: StoryData\n{\"format\": \"SugarCube\"}\n\n:: A [Widget]\none\n\n:: B [widget]\ntwo\n\n:: C [widget]\n<<widget \"x\">><</widget>>\n\n:: Start\nthree\n").unwrap().0;
+        let warnings = story.validate();
+        assert_eq!(warnings.len(), 2, "{:?}", warnings);
+        assert!(warnings.iter().all(|w| matches!(w, Warning::TagReserved(..))));
+    }
+    
+    #[test]
+    fn validate_story_data() {
+        let story = parse_twee3(":: StoryTitle\nT\n\n:: StoryData\n{\"format\": \"Harlowe\", \"format-version\": \"4.0.0\", \"zoom\": \"big\", \"tag-colors\": {\"a\": \"green\", \"b\": \"pink\"}}\n").unwrap().0;
+        let fields: Vec<String> = story.validate().into_iter().filter_map(|w| match w {
+            Warning::StoryDataInvalid(field, _) => Some(field),
+            _ => None,
+        }).collect();
+        assert_eq!(fields, vec!["format-version", "zoom", "tag-colors"]);
+    }
+    
+    #[test]
+    fn format_version() {
+        let v = |s: &str| FormatVersion::parse(s).unwrap();
+        assert!(v("3.3.9").is_compatible_with(&v("3.3.8")));
+        assert!(! v("3.3.8").is_compatible_with(&v("3.3.9")));
+        assert!(! v("4.0.0").is_compatible_with(&v("3.3.8")));
+        assert!(v("4.0.0-beta.1") < v("4.0.0"));
+        assert_eq!(v("4.0.0-beta.1").to_string(), "4.0.0-beta.1");
+        assert_eq!(v("3.3"), v("3.3.0"));
+        assert_eq!(v("3.3").to_string(), "3.3.0");
+        assert_eq!(v("4.0-beta.1"), v("4.0.0-beta.1"));
+        assert!(v("3.3.1").is_compatible_with(&v("3.3")));
+        assert!(FormatVersion::parse("3").is_none());
+        assert!(FormatVersion::parse("3.3.1.1").is_none());
+        assert!(FormatVersion::parse("3.x").is_none());
+    }
+    
+    #[test]
+    #[cfg(feature = "html")]
+    fn wrapped_archive() {
+        let storydata = "<tw-storydata name=\"T\" startnode=\"1\" hidden><tw-passagedata pid=\"1\" name=\"A\">text</tw-passagedata><tw-passagedata pid=\"2\" name=\"B\"></tw-passagedata></tw-storydata>";
+        let escaped = storydata.replace('<', "&lt;").replace('>', "&gt;");
+        let source = format!("<!-- backup -->\n<template>{}</template>\n<script type=\"text/html\">{}</script>\n", storydata, escaped);
+        let stories = parse_archive(&source).unwrap();
+        assert_eq!(stories.len(), 2);
+        assert!(stories.iter().all(|(s, _)| s.title == "T" && s.passages[0].content == "text"));
+    }
+    
+    #[test]
+    #[cfg(feature = "html")]
+    fn html_round_trip() {
+        let source = "<tw-storydata name=\"T\" startnode=\"2\" options=\"debug\" custom=\"x\" hidden><tw-passagedata pid=\"1\" name=\"A\" position=\"100,200\">a</tw-passagedata><tw-passagedata pid=\"2\" name=\"B\">b</tw-passagedata></tw-storydata>";
+        let story = parse_archive(source).unwrap().remove(0).0;
+        assert!(story.hidden());
+        assert_eq!(story.options(), vec!["debug".to_string()]);
+        assert_eq!(story.meta.get("start").and_then(|s| s.as_str()), Some("B"));
+        let story = parse_twee3(&serialize_twee3(&story)).unwrap();
+        assert!(story.1.is_empty(), "{:?}", story.1);
+        let e = serialize_html(&story.0);
+        assert!(e.attributes.contains_key("hidden"));
+        assert_eq!(e.attributes.get("custom").map(|s| s.as_str()), Some("x"));
+        assert_eq!(e.attributes.get("startnode").map(|s| s.as_str()), Some("2"));
+        let a = e.children.iter().filter_map(|c| c.as_element()).find(|e| e.attributes.get("name").map(|s| s.as_str()) == Some("A")).unwrap();
+        assert_eq!(a.attributes.get("position").map(|s| s.as_str()), Some("100,200"));
+    }
+    
+    #[test]
+    #[cfg(feature = "html")]
+    fn start_fallback() {
+        let story = parse_twee3(":: StoryTitle\nT\n\n:: Init [script]\nlet a;\n\n:: First\na\n\n:: Second\nb\n").unwrap().0;
+        assert!(matches!(&story.validate()[..], [Warning::StartPassageMissing(None)]));
+        assert!(! serialize_html(&story).attributes.contains_key("startnode"));
+        let options = |start| HtmlOptions { start, ..Default::default() };
+        let (e, warnings) = serialize_html_with(&story, &options(StartFallback::First)).unwrap();
+        assert_eq!(e.attributes.get("startnode").map(|s| s.as_str()), Some("1"));
+        assert!(matches!(&warnings[..], [Warning::StartPassageGuessed(p)] if p == "First"));
+        assert!(serialize_html_with(&story, &options(StartFallback::Error)).is_err());
+    }
+    
+    #[test]
+    #[cfg(feature = "html")]
+    fn passage_order() {
+        let story = parse_twee3(":: StoryTitle\nT\n\n:: StoryData\n{\"start\": \"B\"}\n\n:: C {\"order\": \"10\"}\nc\n\n:: B {\"order\": \"2\"}\nb\n\n:: A\na\n").unwrap().0;
+        let names = |order| {
+            let (e, _) = serialize_html_with(&story, &HtmlOptions { order, ..Default::default() }).unwrap();
+            let passages: Vec<(String, String)> = e.children.iter().filter_map(|c| c.as_element())
+                .map(|e| (e.attributes["name"].clone(), e.attributes["pid"].clone())).collect();
+            (passages, e.attributes["startnode"].clone())
+        };
+        let pairs = |names: &[&str]| names.iter().enumerate().map(|(i, n)| (n.to_string(), (i + 1).to_string())).collect::<Vec<_>>();
+        assert_eq!(names(PassageOrder::Source), (pairs(&["C", "B", "A"]), "2".to_string()));
+        assert_eq!(names(PassageOrder::Name), (pairs(&["A", "B", "C"]), "2".to_string()));
+        assert_eq!(names(PassageOrder::Key("order".to_string())), (pairs(&["B", "C", "A"]), "1".to_string()));
+    }
+    
+    #[test]
+    fn extract_data_uris() {
+        let mut story = parse_twee3(":: StoryTitle\nT\n\n:: A\n<img src=\"data:image/png;base64,aGVsbG8=\"> <img src=\"data:image/png;base64,aGVsbG8=\">\n\n:: S [stylesheet]\nbody { background: url(data:image/svg+xml;charset=utf-8;base64,PHN2Zy8+) }\n").unwrap().0;
+        let assets = story.extract_data_uris("assets/");
+        assert_eq!(assets.len(), 2);
+        assert_eq!(assets[0].data, b"hello");
+        assert_eq!(assets[1].path, "assets/asset-2.svg");
+        assert_eq!(story.passages[0].content, "<img src=\"assets/asset-1.png\"> <img src=\"assets/asset-1.png\">");
+    }
+    
+    #[test]
+    fn split_by_tag() {
+        let story = parse_twee3(":: StoryTitle\nT\n\n:: StoryData\n{\"start\": \"Intro\"}\n\n:: StoryInit\ninit\n\n:: Intro [chapter1]\n[[Next]] [[Later]]\n\n:: Next [chapter1]\n[[Later]] [[Go->End]]\n\n:: Later [chapter2]\n[[Intro]]\n\n:: End [chapter2]\nend\n").unwrap().0;
+        let parts = story.split_by_tag("chapter", &SplitOptions::default());
+        assert_eq!(parts.len(), 2);
+        let (one, two) = (&parts[0].story, &parts[1].story);
+        assert_eq!(one.title, "T (chapter1)");
+        let names = |s: &Story| s.passages.iter().map(|p| p.name.clone()).collect::<Vec<_>>();
+        assert_eq!(names(one), vec!["StoryInit", "Intro", "Next", "Continue in part 2"]);
+        assert_eq!(one.passage("Next").unwrap().content, "[[Later->Continue in part 2]] [[Go->Continue in part 2]]");
+        assert_eq!(one.passage("Continue in part 2").unwrap().content, "To be continued in part 2.");
+        assert_eq!(names(two), vec!["StoryInit", "Later", "End", "Continue in part 1"]);
+        assert_eq!(one.start_passage().unwrap().name, "Intro");
+        assert_eq!(two.start_passage().unwrap().name, "Later");
+    }
+    
+    #[test]
+    fn split_by_tag_macros() {
+        let story = parse_twee3(":: StoryTitle\nT\n\n:: StoryData\n{\"format\": \"Harlowe\", \"start\": \"A\"}\n\n:: A [chapter1]\n(link-goto: \"Onward\", \"B\") (go-to: 'B') (go-to: $next) (display: \"B\")\n\n:: B [chapter2]\nend\n").unwrap().0;
+        let parts = story.split_by_tag("chapter", &SplitOptions { continue_passage: "Part \"{part}\"".to_string(), ..SplitOptions::default() });
+        assert_eq!(parts[0].story.passage("A").unwrap().content, "(link-goto: \"Onward\", \"Part \\\"2\\\"\") (go-to: 'Part \"2\"') (go-to: $next) (display: \"B\")");
+        assert!(parts[0].story.passage("Part \"2\"").is_some());
+        let story = parse_twee3(":: StoryData\n{\"format\": \"SugarCube\"}\n\n:: A [chapter1]\n<<link \"Go\" \"B\">><</link>> <<goto [[B]]>> <<goto \"B\">>\n\n:: B [chapter2]\nend\n").unwrap().0;
+        let parts = story.split_by_tag("chapter", &SplitOptions::default());
+        assert_eq!(parts[0].story.passage("A").unwrap().content, "<<link \"Go\" \"Continue in part 2\">><</link>> <<goto [[B->Continue in part 2]]>> <<goto \"Continue in part 2\">>");
+    }
+    
+    #[test]
+    fn variables() {
+        let story = parse_twee3(":: StoryTitle\nT\n\n:: StoryInit\n<<set $gold to 0>><<set _i to 1>>\n\n:: Shop\nYou have $gold coins. (set: $has_sword to true)\n\n:: Script [script]\nState.variables.gold += V.bonus; $(document).on();\n").unwrap().0;
+        let vars = story.variables();
+        assert_eq!(vars.keys().collect::<Vec<_>>(), vec!["bonus", "gold", "has_sword"]);
+        assert_eq!(vars["gold"], vec!["StoryInit", "Shop", "Script"]);
+    }
+    
+    #[test]
+    fn paths_to_endings() {
+        let story = parse_twee3(":: StoryTitle\nT\n\n:: StoryData\n{\"start\": \"Start\"}\n\n:: Start\n[[Left]] [[Right]]\n\n:: Left\n[[Start]] [[Good end]]\n\n:: Right\n[[Good end]] [[Bad end]] [[Right]]\n\n:: Good end\nYou win.\n\n:: Bad end\nYou lose.\n\n:: Secret end\nUnreachable.\n").unwrap().0;
+        let found = story.paths_to_endings(10, 100);
+        assert_eq!(found.paths["Good end"], vec![vec!["Start", "Left", "Good end"], vec!["Start", "Right", "Good end"]]);
+        assert_eq!(found.paths["Bad end"], vec![vec!["Start", "Right", "Bad end"]]);
+        assert_eq!(found.unreached, vec!["Secret end"]);
+        assert!(! found.truncated);
+        let limited = story.paths_to_endings(2, 100);
+        assert!(limited.paths.is_empty() && limited.truncated);
+        assert_eq!(story.paths_to_endings(10, 1).paths.len(), 1);
+    }
+    
+    #[test]
+    fn paths_to_endings_dense_cycles() {
+        // Every passage links to every other one and none is an ending, so there are about 20! routes to try.
+        let names: Vec<String> = (0..20).map(|i| format!("P{}", i)).collect();
+        let mut source = ":: StoryTitle\nT\n\n:: StoryData\n{\"start\": \"P0\"}\n\n".to_string();
+        for n in &names {
+            source += &format!(":: {}\n{}\n\n", n, names.iter().map(|t| format!("[[{}]]", t)).collect::<String>());
+        }
+        let story = parse_twee3(&source).unwrap().0;
+        let found = story.paths_to_endings(20, 100);
+        assert!(found.paths.is_empty() && found.truncated);
+    }
+    
+    #[test]
+    fn tags() {
+        let mut story = parse_twee3(":: StoryTitle\nT\n\n:: StoryData\n{\"tag-colors\": {\"ch1\": \"red\"}}\n\n:: A [ch1 intro]\na\n\n:: B [intro chapter1 ch1]\nb\n\n:: C\nc\n").unwrap().0;
+        assert_eq!(story.rename_tag("ch1", "chapter1"), vec!["A", "B"]);
+        assert_eq!(story.passage("A").unwrap().tags, vec!["chapter1", "intro"]);
+        assert_eq!(story.passage("B").unwrap().tags, vec!["intro", "chapter1"]);
+        assert_eq!(story.meta["tag-colors"], serde_json::json!({"chapter1": "red"}));
+        let c = &mut story.passages[2];
+        assert!(c.add_tag("end"));
+        assert!(! c.add_tag("end"));
+        assert!(c.remove_tag("end"));
+        assert!(! c.remove_tag("end"));
+    }
+    
+    #[test]
+    fn replace() {
+        let mut story = parse_twee3(":: StoryTitle\nT\n\n:: Start\n<<set $gold to 1>> [[Old Mill]]\n\n:: Old Mill\n$gold coins\n\n:: Mill\nA mill.\n").unwrap().0;
+        let regex = regex::Regex::new(r"\$gold\b").unwrap();
+        assert_eq!(story.replace(&regex, "$$coins", &ReplaceOptions::default()), vec!["Start", "Old Mill"]);
+        assert_eq!(story.passage("Old Mill").unwrap().content, "$coins coins");
+        let regex = regex::Regex::new(r"Old (Mill)").unwrap();
+        assert_eq!(story.replace(&regex, "$1", &ReplaceOptions { names: true }), vec!["Start"]);
+        assert_eq!(story.passage("Start").unwrap().content, "<<set $coins to 1>> [[Mill]]");
+        assert!(story.passage("Old Mill").is_some());
+        let regex = regex::Regex::new(r"Old Mill").unwrap();
+        assert_eq!(story.replace(&regex, "Windmill", &ReplaceOptions { names: true }), vec!["Windmill"]);
+    }
+    
+    #[test]
+    fn complexity() {
+        let story = parse_twee3(":: StoryTitle\nT\n\n:: StoryData\n{\"start\": \"Start\"}\n\n:: Start\n[[Hall]]\n\n:: Hall\n<<if $key>>[[Vault]]<</if>> [[Kitchen]] [[Garden]] [[Start]]\n\n:: Vault\n[[Hall]] [[Gate]]\n\n:: Kitchen\n[[Hall]] [[Gate]]\n\n:: Garden\n[[Hall]] [[Gate]]\n\n:: Gate\n[[End]]\n\n:: End\nThe end.\n").unwrap().0;
+        let c = story.complexity();
+        let hall = c.passages.iter().find(|p| p.name == "Hall").unwrap();
+        assert_eq!((hall.choices, hall.incoming, hall.complexity), (4, 4, 5));
+        // 13 links between 8 passages
+        assert_eq!(c.overall, 7);
+        assert_eq!(c.hubs, vec!["Hall"]);
+        assert_eq!(c.bottlenecks, vec!["Gate", "Hall"]);
+    }
+    
+    #[test]
+    fn near_duplicates() {
+        let story = parse_twee3(":: StoryTitle\nT\n\n:: A\nThe guard blocks the door and says you shall not pass.\n\n:: B\nAn entirely different text about a dragon.\n\n:: C\nThe guard blocks the door and says: you shall not pass today!\n\n:: D\nthe guard blocks the door and says you shall not pass.\n").unwrap().0;
+        assert_eq!(story.near_duplicates(0.8), vec![vec!["A".to_string(), "C".to_string(), "D".to_string()]]);
+        assert_eq!(story.near_duplicates(1.0), vec![vec!["A".to_string(), "D".to_string()]]);
+    }
+    
+    #[test]
+    fn links() {
+        let p = Passage {
+            name: "A".to_string(),
+            tags: vec![],
+            meta: Map::new(),
+            content: "[[B]] [[go->C]] [[D<-back]] [[x|E][$y to 1]] [[a->b->F]]".to_string(),
+        };
+        let links: Vec<(String, String)> = p.links().into_iter().map(|l| (l.text, l.target)).collect();
+        assert_eq!(links, vec![
+            ("B".to_string(), "B".to_string()),
+            ("go".to_string(), "C".to_string()),
+            ("back".to_string(), "D".to_string()),
+            ("x".to_string(), "E".to_string()),
+            ("a->b".to_string(), "F".to_string()),
+        ]);
+    }
+    
+    #[test]
+    fn lazy_twee3() {
+        let source = ":: StoryTitle\nT\n\n:: StoryData\n{\"start\": \"A\"}\n\n:: A [t] {\"position\": \"1,1\"}\n[[B]] and [[go->C]]\n\n:: B\nText\n\n:: A\nDuplicate\n\n:: C [open\n";
+        let (lazy, warnings) = parse_twee3_lazy(source).unwrap();
+        assert_eq!(lazy.to_story(), parse_twee3(source).unwrap().0);
+        assert_eq!(format!("{:?}", warnings), format!("{:?}", parse_twee3(source).unwrap().1));
+        let a = lazy.passage("A").unwrap();
+        assert_eq!(a.content, "[[B]] and [[go->C]]");
+        assert_eq!(a.links().into_iter().map(|l| l.target).collect::<Vec<_>>(), vec!["B", "C"]);
+        assert!(matches!(a.content, Cow::Borrowed(_)));
+    }
+    
+    #[test]
+    fn lazy_twee3_escaped_headers() {
+        let source = ":: StoryTitle\nT\n\n:: A\n\\:: not a header\ntext \\:: in a line\n\\::\n\n:: B\nb\n";
+        let (lazy, _) = parse_twee3_lazy(source).unwrap();
+        let story = parse_twee3(source).unwrap().0;
+        assert_eq!(lazy.to_story(), story);
+        assert_eq!(story.passage("A").unwrap().content, ":: not a header\ntext \\:: in a line\n::");
+        assert_eq!(lazy.passage("A").unwrap().content, story.passage("A").unwrap().content);
+        assert!(matches!(lazy.passage("B").unwrap().content, Cow::Borrowed("b")));
+    }
+    
+    #[test]
+    fn deduplicate() {
+        let a = parse_twee3(":: StoryTitle\nA\n\n:: Start\nA story\n\n:: Widgets [widget]\n<<widget \"w\">>W<</widget>>\n\n:: End\nThe end\n").unwrap().0;
+        let b = parse_twee3(":: StoryTitle\nB\n\n:: Widgets [widget]\n<<widget \"w\">>W<</widget>>\n\n:: Start\nB story\n\n:: End\nThe end\n").unwrap().0;
+        let c = parse_twee3(":: StoryTitle\nC\n\n:: Start\nC story\n\n:: End\nDifferent\n").unwrap().0;
+        let stories = vec![a, b, c];
+        let shared = shared_passages(&stories);
+        assert_eq!(shared.iter().map(|s| (s.passage.name.as_str(), s.stories.clone())).collect::<Vec<_>>(), vec![("Widgets", vec![0, 1]), ("End", vec![0, 1])]);
+        assert_eq!(shared[1].duplicated_bytes(), 7);
+        let deduplicated = Deduplicated::new(&stories, "Library");
+        assert_eq!(deduplicated.library.passages.len(), 2);
+        assert_eq!(deduplicated.stories[0].passages.len(), 1);
+        assert_eq!(deduplicated.stories[2].passages.len(), 2);
+        assert_eq!(deduplicated.restore(), stories);
+    }
+    
+    #[test]
+    fn content_hash() {
+        let story = parse_twee3(":: StoryTitle\nT\n\n:: StoryData\n{\"ifid\": \"X\"}\n\n:: A [t] {\"position\": \"1,1\"}\nText\n\n:: B\nMore\n").unwrap().0;
+        let a = story.passage("A").unwrap();
+        // The values are stored by sync, so they must not change.
+        assert_eq!(Passage { name: String::new(), tags: vec![], meta: Map::new(), content: String::new() }.content_hash(), 0x08328807b4eb6fed);
+        let mut moved = a.clone();
+        moved.meta.clear();
+        moved.content += "\n\n";
+        assert_eq!(moved.content_hash(), a.content_hash());
+        moved.tags.clear();
+        assert_ne!(moved.content_hash(), a.content_hash());
+        let mut reordered = story.clone();
+        reordered.passages.reverse();
+        assert_eq!(reordered.fingerprint(), story.fingerprint());
+        reordered.passages[0].content += "!";
+        assert_ne!(reordered.fingerprint(), story.fingerprint());
+    }
+    
+    #[test]
+    fn annotations() {
+        let story = parse_twee3(":: StoryTitle\nT\n\n:: A\nText\nTODO(ann): describe the room\n<!-- FIXME @bob broken link -->\n<!--\n  @cy: check\n  the tone\n-->\nTODOS aren't markers\n").unwrap().0;
+        let markers = vec!["TODO".to_string(), "FIXME".to_string()];
+        let found: Vec<(usize, String, Option<String>, String)> = story.annotations(&markers, true).into_iter().map(|a| (a.line, a.marker, a.assignee, a.text)).collect();
+        assert_eq!(found, vec![
+            (2, "TODO".to_string(), Some("ann".to_string()), "describe the room".to_string()),
+            (3, "FIXME".to_string(), Some("bob".to_string()), "broken link".to_string()),
+            (4, "note".to_string(), Some("cy".to_string()), "check the tone".to_string()),
+        ]);
+        assert_eq!(story.annotations(&markers, false).len(), 2);
+    }
+    
+    #[test]
+    fn harlowe_navigation() {
+        let story = parse_twee3(":: StoryData\n{\"format\": \"Harlowe\", \"start\": \"A\"}\n\n:: A\n(link-goto: \"Open, the door\", \"B\")[[C]](Go-To: $next)\n(click-goto: ?door, 'D')(set: $x to (a: 1, 2))\n\n:: B\n(redirect: \"A\")\n\n:: C\nEnd\n\n:: D\n(goto: \"Nowhere\" + $n)\n").unwrap().0;
+        let a = story.passage("A").unwrap();
+        let nav: Vec<(String, Option<String>, String, bool)> = story.navigation(a).into_iter().map(|n| (n.target, n.text, n.via, n.dynamic)).collect();
+        assert_eq!(nav, vec![
+            ("B".to_string(), Some("Open, the door".to_string()), "link-goto".to_string(), false),
+            ("C".to_string(), Some("C".to_string()), "link".to_string(), false),
+            ("$next".to_string(), None, "Go-To".to_string(), true),
+            ("D".to_string(), None, "click-goto".to_string(), false),
+        ]);
+        assert_eq!(&a.content[story.navigation(a)[0].span.clone()], "(link-goto: \"Open, the door\", \"B\")");
+        assert_eq!(story.targets(a).iter().map(|p| p.name.as_str()).collect::<Vec<_>>(), vec!["B", "C", "D"]);
+        // Other formats only have links, and passages with dynamic targets aren't endings.
+        assert_eq!(a.navigation("SugarCube").len(), 1);
+        assert_eq!(story.endings().iter().map(|p| p.name.as_str()).collect::<Vec<_>>(), vec!["C"]);
+    }
+    
+    #[test]
+    fn sugarcube_navigation() {
+        let story = parse_twee3(":: StoryData\n{\"format\": \"SugarCube\", \"start\": \"A\"}\n\n:: A\n<<link \"Open >> door\" \"B\">><</link>>\n<<button 'Go' $next>><</button>>\n<<link [[C]]>><</link>><<goto \"D\">>\n<<link \"Act\">><<set $x to 1>><</link>>\n<<run Engine.play(\"E\", true)>>\n\n:: B\n<<goto `$dest`>>\n").unwrap().0;
+        let a = story.passage("A").unwrap();
+        let nav: Vec<(String, Option<String>, String, bool)> = story.navigation(a).into_iter().map(|n| (n.target, n.text, n.via, n.dynamic)).collect();
+        assert_eq!(nav, vec![
+            ("B".to_string(), Some("Open >> door".to_string()), "link".to_string(), false),
+            ("$next".to_string(), Some("Go".to_string()), "button".to_string(), true),
+            ("C".to_string(), Some("C".to_string()), "link".to_string(), false),
+            ("D".to_string(), None, "goto".to_string(), false),
+            ("E".to_string(), None, "Engine.play".to_string(), false),
+        ]);
+        assert_eq!(&a.content[story.navigation(a)[0].span.clone()], "<<link \"Open >> door\" \"B\">>");
+        let b = story.passage("B").unwrap();
+        assert!(story.navigation(b)[0].dynamic);
+    }
+    
+    #[test]
+    fn chapbook_navigation() {
+        let story = parse_twee3(":: StoryData\n{\"format\": \"Chapbook\", \"start\": \"A\"}\n\n:: A\n{embed passage: 'Header'}\n{link to: 'B', label: 'Onward, {friend}'}\n{reveal link: 'Look', passage: 'Detail'}{reveal link: 'Hm', text: 'Nothing'}\n{link to: next}\n\n:: B\n{embed passage named: \"Header\"}\n\n:: Header\nTitle\n\n:: Detail\nA view\n").unwrap().0;
+        let a = story.passage("A").unwrap();
+        let nav: Vec<(String, Option<String>, String, bool, bool)> = story.navigation(a).into_iter().map(|n| (n.target, n.text, n.via, n.dynamic, n.embed)).collect();
+        assert_eq!(nav, vec![
+            ("Header".to_string(), None, "embed passage".to_string(), false, true),
+            ("B".to_string(), Some("Onward, {friend}".to_string()), "link to".to_string(), false, false),
+            ("Detail".to_string(), Some("Look".to_string()), "reveal link".to_string(), false, true),
+            ("next".to_string(), None, "link to".to_string(), true, false),
+        ]);
+        assert_eq!(story.targets(a).iter().map(|p| p.name.as_str()).collect::<Vec<_>>(), vec!["B"]);
+        // Embedding a passage doesn't lead anywhere.
+        assert_eq!(story.endings().iter().map(|p| p.name.as_str()).collect::<Vec<_>>(), vec!["B", "Header", "Detail"]);
+    }
+    
+    #[test]
+    fn passage_blocks_round_trip() {
+        let source = ":: StoryTitle\nT\n\n:: A [x] {\"position\":\"1,2\"}\ntext\n\\:: not a header\n\n\n:: A\nagain\n:: B\n";
+        let blocks = passage_blocks(source).unwrap();
+        assert_eq!(blocks.iter().map(|b| b.passage.name.as_str()).collect::<Vec<_>>(), vec!["A", "A", "B"]);
+        let a = &blocks[0];
+        assert_eq!(&source[a.header.clone()], ":: A [x] {\"position\":\"1,2\"}\n");
+        assert_eq!(&source[a.body.clone()], "text\n\\:: not a header");
+        assert_eq!(a.passage.content, "text\n:: not a header");
+        assert_eq!(passage_header(&a.passage.name, &a.passage.tags, &a.passage.meta), source[a.header.clone()]);
+        assert_eq!(escape_content(&a.passage.content), source[a.body.clone()]);
+        assert_eq!(blocks[2].body, source.len()..source.len());
+    }
+    
+    #[test]
+    fn passage_header_escapes() {
+        let header = passage_header("a [b] {c}", &["t\\".to_string()], &Map::new());
+        assert_eq!(header, ":: a \\[b\\] \\{c\\} [t\\\\]\n");
+        let (story, _) = parse_twee3(&format!("{}text\n", header)).unwrap();
+        assert_eq!(story.passages[0].name, "a [b] {c}");
+        assert_eq!(story.passages[0].tags, vec!["t\\".to_string()]);
+        assert_eq!(escape_content("::x\n::y\n\n"), "\\::x\n\\::y");
     }
 }
@@ -0,0 +1,139 @@
+use std::{collections::{BTreeSet, VecDeque}, ops::Range};
+
+use regex::Regex;
+
+use crate::{Passage, Story};
+
+/// A link from a passage to another passage.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Link {
+    /// The text displayed for the link.
+    pub text: String,
+    /// The name of the linked passage.
+    pub target: String,
+    /// The byte range of the link in the passage content.
+    pub span: Range<usize>,
+}
+
+/// Parses the inside of a `[[...]]` link into the displayed text and the target.  
+/// Supports the `text|target`, `text->target` and `target<-text` forms, and ignores SugarCube setters (`[[text|target][$x to 1]]`).
+pub fn parse_link(inner: &str) -> (String, String) {
+    let inner = inner.split("][").next().unwrap();
+    if let Some(i) = inner.rfind("->") {
+        return (inner[..i].to_string(), inner[(i + 2)..].to_string());
+    }
+    if let Some(i) = inner.find("<-") {
+        return (inner[(i + 2)..].to_string(), inner[..i].to_string());
+    }
+    if let Some(i) = inner.find('|') {
+        return (inner[..i].to_string(), inner[(i + 1)..].to_string());
+    }
+    (inner.to_string(), inner.to_string())
+}
+
+/// What [Story::retain_passages] does with links to removed passages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CleanupMode {
+    /// Leaves the links as they are, only reporting them.
+    Report,
+    /// Replaces the links with their text.
+    Unlink,
+    /// Replaces the links with their text, and also removes passages that were only reachable through removed passages.
+    Prune,
+}
+
+/// A link to a passage removed by [Story::retain_passages].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DanglingLink {
+    /// The name of the passage containing the link.
+    pub passage: String,
+    /// The link, with the span in the content before it was rewritten.
+    pub link: Link,
+}
+
+/// The changes made by [Story::retain_passages].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Retained {
+    /// The names of the removed passages.
+    pub removed: Vec<String>,
+    /// The links to removed passages in the remaining passages.
+    pub dangling: Vec<DanglingLink>,
+}
+
+/// Extracts the `[[...]]` links from passage content.
+pub(crate) fn links_in(content: &str) -> Vec<Link> {
+    let link = Regex::new(r"\[\[((?:[^\]]|\][^\]])+)\]\]").unwrap();
+    link.captures_iter(content).map(|c| {
+        let (text, target) = parse_link(&c[1]);
+        Link { text, target, span: c.get(0).unwrap().range() }
+    }).collect()
+}
+
+impl Passage {
+    /// Extracts the `[[...]]` links from the passage content.
+    pub fn links(&self) -> Vec<Link> {
+        links_in(&self.content)
+    }
+}
+
+impl Story {
+    /// Gets the passage with the name.
+    pub fn passage(&self, name: &str) -> Option<&Passage> {
+        self.passages.iter().find(|p| p.name == name)
+    }
+    
+    /// Gets the passage named by the `start` metadata.
+    pub fn start_passage(&self) -> Option<&Passage> {
+        self.meta.get("start").and_then(|s| s.as_str()).and_then(|s| self.passage(s))
+    }
+    
+    /// The names of the passages reachable through links and navigation macros from the start passage, including the
+    /// embedded passages.
+    fn reachable(&self) -> BTreeSet<String> {
+        let mut reachable = BTreeSet::new();
+        let mut queue: VecDeque<&Passage> = self.start_passage().into_iter().collect();
+        while let Some(p) = queue.pop_front() {
+            if ! reachable.insert(p.name.clone()) {
+                continue;
+            }
+            queue.extend(self.navigation(p).iter().filter(|n| ! n.dynamic).filter_map(|n| self.passage(&n.target)));
+        }
+        reachable
+    }
+    
+    /// Removes the passages for which the predicate returns false, and handles the links to them according to `mode`.  
+    /// With [CleanupMode::Prune], passages reachable from the start passage before, but not after removing the passages,
+    /// are removed as well. Passages that weren't reachable through links before, like scripts, are kept.
+    pub fn retain_passages(&mut self, mut predicate: impl FnMut(&Passage) -> bool, mode: CleanupMode) -> Retained {
+        let before = if mode == CleanupMode::Prune { self.reachable() } else { BTreeSet::new() };
+        let mut removed = BTreeSet::new();
+        self.passages.retain(|p| {
+            let keep = predicate(p);
+            if ! keep {
+                removed.insert(p.name.clone());
+            }
+            keep
+        });
+        if mode == CleanupMode::Prune {
+            let after = self.reachable();
+            self.passages.retain(|p| {
+                let keep = ! before.contains(&p.name) || after.contains(&p.name);
+                if ! keep {
+                    removed.insert(p.name.clone());
+                }
+                keep
+            });
+        }
+        let mut dangling = vec![];
+        for p in &mut self.passages {
+            let links: Vec<Link> = p.links().into_iter().filter(|l| removed.contains(&l.target)).collect();
+            if mode != CleanupMode::Report {
+                for l in links.iter().rev() {
+                    p.content.replace_range(l.span.clone(), &l.text);
+                }
+            }
+            dangling.extend(links.into_iter().map(|link| DanglingLink { passage: p.name.clone(), link }));
+        }
+        Retained { removed: removed.into_iter().collect(), dangling }
+    }
+}
@@ -0,0 +1,48 @@
+use serde_json::Value;
+
+use crate::Story;
+
+/// Typed access to the StoryData fields Twine and the story formats use. All other fields are kept as they are in
+/// [Story::meta].
+impl Story {
+    /// Whether the story is hidden in the Twine story library, from the `hidden` attribute of archives.
+    pub fn hidden(&self) -> bool {
+        match self.meta.get("hidden") {
+            Some(Value::Bool(b)) => *b,
+            Some(Value::String(_)) => true,
+            _ => false,
+        }
+    }
+    
+    /// Sets whether the story is hidden in the Twine story library.
+    pub fn set_hidden(&mut self, hidden: bool) {
+        if hidden {
+            self.meta.insert("hidden".to_string(), Value::Bool(true));
+        } else {
+            self.meta.remove("hidden");
+        }
+    }
+    
+    /// The story format options, like `debug`, from the space separated `options` field.
+    pub fn options(&self) -> Vec<String> {
+        self.meta.get("options").and_then(|o| o.as_str()).map(|o| o.split_whitespace().map(|o| o.to_string()).collect()).unwrap_or_default()
+    }
+    
+    /// Sets the story format options, removing the field if there are none.
+    pub fn set_options(&mut self, options: &[String]) {
+        if options.is_empty() {
+            self.meta.remove("options");
+        } else {
+            self.meta.insert("options".to_string(), options.join(" ").into());
+        }
+    }
+    
+    /// The zoom level of the story map in the Twine editor, which is stored as a number or a string.
+    pub fn zoom(&self) -> Option<f64> {
+        match self.meta.get("zoom")? {
+            Value::Number(n) => n.as_f64(),
+            Value::String(s) => s.trim().parse().ok(),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,210 @@
+use std::{borrow::Cow, fs::File, path::Path};
+
+use crate::{html::start_tag_attribute, parse_archive, Element, Error, LazyPassage, LazyStory, Story, Warning};
+
+/// The bytes of a file, mapped into memory where supported and read otherwise.
+struct Mapping {
+    #[cfg(unix)]
+    ptr: *mut libc::c_void,
+    #[cfg(unix)]
+    len: usize,
+    #[cfg(not(unix))]
+    bytes: Vec<u8>,
+}
+
+impl Mapping {
+    #[cfg(unix)]
+    fn new(file: File) -> std::io::Result<Mapping> {
+        use std::os::fd::AsRawFd;
+        let len = usize::try_from(file.metadata()?.len()).map_err(|_| std::io::Error::new(std::io::ErrorKind::OutOfMemory, "file too large to map"))?;
+        if len == 0 {
+            return Ok(Mapping { ptr: std::ptr::null_mut(), len });
+        }
+        // SAFETY: The file is open, and the mapping is read-only and private. It is unmapped on drop.
+        let ptr = unsafe { libc::mmap(std::ptr::null_mut(), len, libc::PROT_READ, libc::MAP_PRIVATE, file.as_raw_fd(), 0) };
+        if ptr == libc::MAP_FAILED {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(Mapping { ptr, len })
+    }
+
+    #[cfg(not(unix))]
+    fn new(mut file: File) -> std::io::Result<Mapping> {
+        use std::io::Read;
+        let mut bytes = vec![];
+        file.read_to_end(&mut bytes)?;
+        Ok(Mapping { bytes })
+    }
+
+    #[cfg(unix)]
+    fn bytes(&self) -> &[u8] {
+        if self.len == 0 {
+            return &[];
+        }
+        // SAFETY: The mapping is valid for len bytes until it is dropped.
+        unsafe { std::slice::from_raw_parts(self.ptr as *const u8, self.len) }
+    }
+
+    #[cfg(not(unix))]
+    fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        if self.len != 0 {
+            // SAFETY: ptr and len are from a successful mmap that wasn't unmapped yet.
+            unsafe { libc::munmap(self.ptr, self.len) };
+        }
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+    let mut i = from;
+    while i + needle.len() <= haystack.len() {
+        let next = haystack[i..].iter().position(|b| *b == needle[0])? + i;
+        if haystack[next..].starts_with(needle) {
+            return Some(next);
+        }
+        i = next + 1;
+    }
+    None
+}
+
+/// The index of the `>` that ends the tag at the start of the bytes, skipping quoted attribute values.
+fn tag_end(bytes: &[u8]) -> Option<usize> {
+    let mut quote = None;
+    bytes.iter().position(|b| match (quote, *b) {
+        (None, b'"' | b'\'') => {
+            quote = Some(*b);
+            false
+        },
+        (Some(q), b) if q == b => {
+            quote = None;
+            false
+        },
+        (None, b'>') => true,
+        _ => false,
+    })
+}
+
+/// The text of passage data as the XML parser reads it. Text without entities, markup or carriage returns is the same
+/// as the source and is borrowed.
+fn passage_text(raw: &str) -> Cow<'_, str> {
+    if raw.contains(['&', '<', '\r']) || (! raw.is_empty() && raw.trim().is_empty()) {
+        let text = Element::parse(format!("<x>{}</x>", raw).as_bytes()).ok().and_then(|e| e.get_text().map(|t| t.to_string()));
+        Cow::Owned(text.unwrap_or_default())
+    } else {
+        Cow::Borrowed(raw)
+    }
+}
+
+/// A Twine archive file mapped into memory, for archives too large to read at once.
+/// The &lt;tw-storydata&gt; tags are found without parsing the file, and each story is only parsed when asked for,
+/// so only the stories in use take memory beyond the mapping. On platforms other than Unix, the file is read instead.
+pub struct MappedArchive {
+    mapping: Mapping,
+}
+
+impl MappedArchive {
+    /// Maps the file.
+    ///
+    /// # Safety
+    ///
+    /// The file must not be changed or truncated, by this or another process, while the archive and the blocks
+    /// borrowed from it are in use. The bytes are read directly from the file, so changes would break the guarantee
+    /// that they don't change while borrowed, and reading past the end of a truncated file raises `SIGBUS`.
+    pub unsafe fn open<P: AsRef<Path>>(path: P) -> std::io::Result<MappedArchive> {
+        Ok(MappedArchive { mapping: Mapping::new(File::open(path)?)? })
+    }
+
+    /// The contents of the file.
+    pub fn bytes(&self) -> &[u8] {
+        self.mapping.bytes()
+    }
+
+    /// The &lt;tw-storydata&gt; tags in the file, in order.
+    pub fn stories(&self) -> StorydataBlocks<'_> {
+        StorydataBlocks { bytes: self.bytes(), at: 0 }
+    }
+}
+
+/// An iterator over the &lt;tw-storydata&gt; tags of a [MappedArchive].
+pub struct StorydataBlocks<'a> {
+    bytes: &'a [u8],
+    at: usize,
+}
+
+impl<'a> Iterator for StorydataBlocks<'a> {
+    type Item = StorydataBlock<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        const END: &[u8] = b"</tw-storydata>";
+        let start = find(self.bytes, b"<tw-storydata", self.at)?;
+        let end = find(self.bytes, END, start).map(|e| e + END.len()).unwrap_or(self.bytes.len());
+        self.at = end;
+        Some(StorydataBlock { bytes: &self.bytes[start..end] })
+    }
+}
+
+/// A &lt;tw-storydata&gt; tag in a [MappedArchive] that hasn't been parsed yet.
+pub struct StorydataBlock<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> StorydataBlock<'a> {
+    /// The source of the tag.
+    pub fn source(&self) -> Result<&'a str, Error> {
+        std::str::from_utf8(self.bytes).map_err(Error::InvalidUtf8)
+    }
+
+    /// An attribute of the &lt;tw-storydata&gt; tag, like `name` or `ifid`, read without parsing the story.
+    pub fn attribute(&self, name: &str) -> Option<String> {
+        start_tag_attribute(std::str::from_utf8(&self.bytes[..=tag_end(self.bytes)?]).ok()?, name)
+    }
+
+    /// Parses the story.
+    pub fn parse(&self) -> Result<(Story, Vec<Warning>), Error> {
+        parse_archive(self.source()?)?.into_iter().next().ok_or(Error::HTMLStoryDataNotFound)
+    }
+
+    /// Parses the story into a [LazyStory] that borrows the passage contents from the mapping, so only the names,
+    /// tags and metadata of the passages are copied. Contents with entities like `&amp;lt;` are decoded into a copy.
+    pub fn parse_lazy(&self) -> Result<(LazyStory<'a>, Vec<Warning>), Error> {
+        const START: &str = "<tw-passagedata";
+        const END: &str = "</tw-passagedata>";
+        let source = self.source()?;
+        // The tags are parsed without the passage contents, which are marked with their index in the source instead.
+        let mut skeleton = String::new();
+        let mut contents = vec![];
+        let mut at = 0;
+        while let Some(start) = source[at..].find(START).map(|s| s + at) {
+            let open_end = start + tag_end(&self.bytes[start..]).ok_or(Error::HTMLStoryDataNotFound)?;
+            skeleton += &source[at..start + START.len()];
+            skeleton += &format!(" twee-lazy-content=\"{}\"", contents.len());
+            skeleton += &source[start + START.len()..=open_end];
+            if source[..open_end].ends_with('/') {
+                contents.push("");
+                at = open_end + 1;
+                continue;
+            }
+            let close = source[open_end..].find(END).map(|e| e + open_end).unwrap_or(source.len());
+            contents.push(&source[open_end + 1..close]);
+            at = close;
+        }
+        skeleton += &source[at..];
+        let (story, warnings) = parse_archive(&skeleton)?.into_iter().next().ok_or(Error::HTMLStoryDataNotFound)?;
+        let passages = story.passages.into_iter().map(|mut p| {
+            let content = match p.meta.remove("twee-lazy-content").and_then(|i| i.as_str().and_then(|i| i.parse::<usize>().ok())) {
+                // The story script and stylesheet elements are appended to passages with their names.
+                Some(i) if ! p.content.is_empty() => Cow::Owned(passage_text(contents[i]).into_owned() + &p.content),
+                Some(i) => passage_text(contents[i]),
+                None => Cow::Owned(p.content),
+            };
+            LazyPassage { name: p.name, tags: p.tags, meta: p.meta, content }
+        }).collect();
+        Ok((LazyStory { title: story.title, passages, meta: story.meta }, warnings))
+    }
+}
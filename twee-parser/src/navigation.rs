@@ -0,0 +1,279 @@
+use std::ops::Range;
+
+use regex::Regex;
+
+use crate::{Passage, Story};
+
+/// A way to go from a passage to another one: a `[[...]]` link, or a macro of the story format like `(goto:)` or `<<goto>>`.
+/// Macros that show another passage inside the passage, like Chapbook's `{embed passage:}`, are navigation too, as
+/// embeds.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Navigation {
+    /// The name of the target passage, or the expression computing it if the target is dynamic.
+    pub target: String,
+    /// The text shown for the link, if it's a link with a fixed text.
+    pub text: Option<String>,
+    /// What navigates: `link` for `[[...]]` links, or the name of the macro.
+    pub via: String,
+    /// The byte range of the link or macro in the passage content.
+    pub span: Range<usize>,
+    /// The byte range of the target argument of a macro in the passage content, including the quotes. None for
+    /// `[[...]]` links and dynamic targets.
+    pub target_span: Option<Range<usize>>,
+    /// Whether the target is an expression, like `(goto: $next)`, so it's only known when playing.
+    pub dynamic: bool,
+    /// Whether the target is shown inside the passage instead of going to it.
+    pub embed: bool,
+}
+
+/// Splits the arguments of a macro call at the top-level commas, starting after the opening of the call, up to the
+/// `close` character that ends it. Strings and nested brackets are skipped. Returns the trimmed arguments and the
+/// index after the end of the call.
+pub(crate) fn macro_args(s: &str, close: char) -> Option<(Vec<&str>, usize)> {
+    let mut args = vec![];
+    let mut depth = 0;
+    let mut quote: Option<char> = None;
+    let mut escape = false;
+    let mut start = 0;
+    for (i, c) in s.char_indices() {
+        if let Some(q) = quote {
+            if escape {
+                escape = false;
+            } else if c == '\\' {
+                escape = true;
+            } else if c == q {
+                quote = None;
+            }
+            continue;
+        }
+        match c {
+            '"' | '\'' | '`' => quote = Some(c),
+            '(' | '[' | '{' => depth += 1,
+            c if c == close && depth == 0 => {
+                args.push(s[start..i].trim());
+                args.retain(|a| ! a.is_empty());
+                return Some((args, i + c.len_utf8()));
+            },
+            ')' | ']' | '}' => depth -= 1,
+            ',' if depth == 0 => {
+                args.push(s[start..i].trim());
+                start = i + 1;
+            },
+            _ => {},
+        }
+    }
+    None
+}
+
+/// Splits the arguments of a SugarCube macro at the whitespace, starting after the macro name, up to the `>>` that
+/// ends it. Strings, `[[...]]` links and backquoted expressions are single arguments. Returns the arguments and the
+/// index after the end of the macro.
+fn sugarcube_args(s: &str) -> Option<(Vec<&str>, usize)> {
+    let mut args = vec![];
+    let mut i = 0;
+    loop {
+        i += s[i..].len() - s[i..].trim_start().len();
+        let rest = &s[i..];
+        if rest.starts_with(">>") {
+            return Some((args, i + 2));
+        }
+        let len = if rest.starts_with("[[") {
+            rest.find("]]")? + 2
+        } else if let Some(q) = rest.chars().next().filter(|c| ['"', '\'', '`'].contains(c)) {
+            let mut escape = false;
+            1 + rest[1..].char_indices().find(|(_, c)| {
+                let end = ! escape && *c == q;
+                escape = ! escape && *c == '\\';
+                end
+            })?.0 + 1
+        } else {
+            rest.find(char::is_whitespace).unwrap_or(rest.len()).min(rest.find(">>").unwrap_or(rest.len()))
+        };
+        if len == 0 {
+            return None;
+        }
+        args.push(&rest[..len]);
+        i += len;
+    }
+}
+
+/// The value of a string literal, or None if the argument is an expression.
+pub(crate) fn string_literal(arg: &str) -> Option<String> {
+    let q = arg.chars().next().filter(|c| ['"', '\''].contains(c))?;
+    let inner = arg.strip_prefix(q)?.strip_suffix(q)?;
+    let mut value = String::new();
+    let mut escape = false;
+    for c in inner.chars() {
+        if escape {
+            escape = false;
+            value.push(c);
+        } else if c == '\\' {
+            escape = true;
+        } else if c == q {
+            // An unescaped quote means the argument is an expression like `"a" + "b"`.
+            return None;
+        } else {
+            value.push(c);
+        }
+    }
+    Some(value)
+}
+
+/// A navigation to the target argument of a macro, with the text argument if there is one. The arguments are slices
+/// of `content`.
+fn macro_navigation(content: &str, via: &str, span: Range<usize>, target: &str, text: Option<&str>) -> Navigation {
+    let literal = string_literal(target);
+    let start = target.as_ptr() as usize - content.as_ptr() as usize;
+    Navigation {
+        target_span: literal.as_ref().map(|_| start..(start + target.len())),
+        dynamic: literal.is_none(),
+        target: literal.unwrap_or(target.to_string()),
+        text: text.and_then(string_literal),
+        via: via.to_string(),
+        span,
+        embed: false,
+    }
+}
+
+/// The SugarCube macros that go to a passage: `<<link>>` and `<<button>>` with a passage argument, `<<goto>>`, and
+/// `Engine.play()`. Arguments in the `[[...]]` link syntax are already found as links.
+fn sugarcube_navigation(content: &str) -> Vec<Navigation> {
+    let call = Regex::new(r"<<(link|button|goto)\s|\bEngine\.play\(").unwrap();
+    let mut nav = vec![];
+    for c in call.captures_iter(content) {
+        let start = c.get(0).unwrap().start();
+        let after = c.get(0).unwrap().end();
+        let Some(name) = c.get(1) else {
+            if let Some((args, end)) = macro_args(&content[after..], ')') {
+                if let Some(target) = args.first() {
+                    nav.push(macro_navigation(content, "Engine.play", start..(after + end), target, None));
+                }
+            }
+            continue;
+        };
+        let Some((args, end)) = sugarcube_args(&content[after..]) else {
+            continue;
+        };
+        let span = start..(after + end);
+        match (name.as_str(), args.as_slice()) {
+            (_, args) if args.iter().any(|a| a.starts_with("[[")) => {},
+            ("link" | "button", [text, target, ..]) => nav.push(macro_navigation(content, name.as_str(), span, target, Some(text))),
+            ("goto", [target, ..]) => nav.push(macro_navigation(content, "goto", span, target, None)),
+            _ => {},
+        }
+    }
+    nav
+}
+
+/// The Chapbook inserts that refer to a passage: `{link to:}`, and the embeds `{embed passage:}`, `{embed passage named:}`
+/// and `{reveal link:}` with a `passage`.
+fn chapbook_navigation(content: &str) -> Vec<Navigation> {
+    let insert = Regex::new(r"\{(link to|embed passage named|embed passage|reveal link)\s*:").unwrap();
+    let mut nav = vec![];
+    for c in insert.captures_iter(content) {
+        let after = c.get(0).unwrap().end();
+        let Some((args, end)) = macro_args(&content[after..], '}') else {
+            continue;
+        };
+        let span = c.get(0).unwrap().start()..(after + end);
+        // The properties after the first argument are written as `name: value`.
+        let property = |name: &str| args.iter().skip(1).find_map(|a| a.strip_prefix(name)?.trim_start().strip_prefix(':').map(str::trim));
+        match (&c[1], args.first()) {
+            ("link to", Some(target)) => nav.push(macro_navigation(content, "link to", span, target, property("label"))),
+            ("reveal link", Some(text)) => if let Some(target) = property("passage") {
+                nav.push(Navigation { embed: true, ..macro_navigation(content, "reveal link", span, target, Some(text)) });
+            },
+            (via, Some(target)) => nav.push(Navigation { embed: true, ..macro_navigation(content, via, span, target, None) }),
+            _ => {},
+        }
+    }
+    nav
+}
+
+/// The Harlowe macros that go to a passage: `(go-to:)`, `(redirect:)`, `(link-goto:)`, `(link-reveal-goto:)`,
+/// `(click-goto:)`, `(mouseover-goto:)` and `(mouseout-goto:)`. Harlowe ignores the case, `-` and `_` in macro names.
+fn harlowe_navigation(content: &str) -> Vec<Navigation> {
+    let call = Regex::new(r"\(([A-Za-z][\w-]*):").unwrap();
+    let mut nav = vec![];
+    for c in call.captures_iter(content) {
+        let name: String = c[1].chars().filter(|c| *c != '-' && *c != '_').collect::<String>().to_lowercase();
+        let start = c.get(0).unwrap().start();
+        let Some((args, end)) = macro_args(&content[c.get(0).unwrap().end()..], ')') else {
+            continue;
+        };
+        let span = start..(c.get(0).unwrap().end() + end);
+        match (name.as_str(), args.as_slice()) {
+            ("goto" | "redirect", [target, ..]) => nav.push(macro_navigation(content, &c[1], span, target, None)),
+            ("linkgoto" | "linkrevealgoto", [text, target, ..]) => nav.push(macro_navigation(content, &c[1], span, target, Some(text))),
+            ("linkgoto", [target]) => nav.push(macro_navigation(content, &c[1], span, target, Some(target))),
+            ("clickgoto" | "mouseovergoto" | "mouseoutgoto", [_, target, ..]) => nav.push(macro_navigation(content, &c[1], span, target, None)),
+            _ => {},
+        }
+    }
+    nav
+}
+
+impl Navigation {
+    /// The text to replace the [span](Navigation::span) of the navigation with to lead to another passage instead,
+    /// keeping the rest of the macro. Links are written as `[[text->target]]`. Returns None for dynamic targets.
+    pub fn retarget(&self, content: &str, target: &str) -> Option<String> {
+        if self.dynamic {
+            return None;
+        }
+        let Some(target_span) = &self.target_span else {
+            return Some(format!("[[{}->{}]]", self.text.as_deref().unwrap_or(&self.target), target));
+        };
+        let quote = content[target_span.clone()].chars().next()?;
+        let literal = format!("{}{}{}", quote, target.replace('\\', "\\\\").replace(quote, &format!("\\{}", quote)), quote);
+        let mut text = content[self.span.clone()].to_string();
+        text.replace_range((target_span.start - self.span.start)..(target_span.end - self.span.start), &literal);
+        Some(text)
+    }
+}
+
+impl Passage {
+    /// The ways to go from this passage to other passages: the `[[...]]` links, and the navigation macros of the story
+    /// format, in the order they appear.
+    /// For Harlowe, these are `(go-to:)`, `(redirect:)`, `(link-goto:)`, `(link-reveal-goto:)`, `(click-goto:)`,
+    /// `(mouseover-goto:)` and `(mouseout-goto:)`, for SugarCube `<<link>>` and `<<button>>` with a passage, `<<goto>>`
+    /// and `Engine.play()`, and for Chapbook `{link to:}` and the embeds `{embed passage:}` and `{reveal link:}`.
+    pub fn navigation(&self, format: &str) -> Vec<Navigation> {
+        let mut nav: Vec<Navigation> = self.links().into_iter().map(|l| Navigation {
+            target: l.target,
+            text: Some(l.text),
+            via: "link".to_string(),
+            span: l.span,
+            target_span: None,
+            dynamic: false,
+            embed: false,
+        }).collect();
+        if format.eq_ignore_ascii_case("harlowe") {
+            nav.extend(harlowe_navigation(&self.content));
+        } else if format.eq_ignore_ascii_case("sugarcube") {
+            nav.extend(sugarcube_navigation(&self.content));
+        } else if format.eq_ignore_ascii_case("chapbook") {
+            nav.extend(chapbook_navigation(&self.content));
+        }
+        nav.sort_by_key(|n| n.span.start);
+        nav
+    }
+}
+
+impl Story {
+    /// The [navigation](Passage::navigation) of a passage, with the story format of the story.
+    pub fn navigation(&self, p: &Passage) -> Vec<Navigation> {
+        p.navigation(self.meta.get("format").and_then(|f| f.as_str()).unwrap_or(""))
+    }
+
+    /// The passages a passage leads to through [navigation](Story::navigation) with a fixed target, each once, in
+    /// the order they first appear. Embedded passages aren't included, as players stay in the passage.
+    pub fn targets(&self, p: &Passage) -> Vec<&Passage> {
+        let mut targets: Vec<&Passage> = vec![];
+        for t in self.navigation(p).iter().filter(|n| ! n.dynamic && ! n.embed).filter_map(|n| self.passage(&n.target)) {
+            if ! targets.iter().any(|p| p.name == t.name) {
+                targets.push(t);
+            }
+        }
+        targets
+    }
+}
@@ -0,0 +1,51 @@
+use crate::Story;
+
+/// What [Story::normalize] changes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NormalizeOptions {
+    /// Sorts the passages by name.
+    pub sort_passages: bool,
+    /// Sorts the tags of each passage and removes duplicates.
+    pub sort_tags: bool,
+    /// Converts `\r\n` line endings to `\n`, removes trailing whitespace from lines and trims the title.
+    pub whitespace: bool,
+}
+
+impl Default for NormalizeOptions {
+    fn default() -> Self {
+        Self { sort_passages: true, sort_tags: true, whitespace: true }
+    }
+}
+
+impl Story {
+    /// Brings the story into a canonical form, so differences that don't matter to the story formats disappear.  
+    /// Metadata keys are always sorted, as the metadata maps are ordered by key.
+    pub fn normalize(&mut self, options: &NormalizeOptions) {
+        if options.sort_passages {
+            self.passages.sort_by(|a, b| a.name.cmp(&b.name));
+        }
+        if options.whitespace {
+            self.title = self.title.trim().to_string();
+        }
+        for p in &mut self.passages {
+            if options.sort_tags {
+                p.tags.sort();
+                p.tags.dedup();
+            }
+            if options.whitespace {
+                p.content = p.content.replace("\r\n", "\n").lines().map(|l| l.trim_end()).collect::<Vec<_>>().join("\n").trim_end().to_string();
+            }
+        }
+    }
+}
+
+/// Whether the stories are the same apart from passage order, tag order and whitespace, e.g. to tell whether a story
+/// actually changed or was only serialized again.
+pub fn semantic_eq(a: &Story, b: &Story) -> bool {
+    let normalize = |s: &Story| {
+        let mut s = s.clone();
+        s.normalize(&NormalizeOptions::default());
+        s
+    };
+    normalize(a) == normalize(b)
+}
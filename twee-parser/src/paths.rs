@@ -0,0 +1,86 @@
+use std::collections::BTreeMap;
+
+use crate::{is_special_passage, Passage, Story};
+
+/// The routes found by [Story::paths_to_endings].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EndingPaths {
+    /// The routes to each reached ending by its name. A route lists the passages from the start passage to the ending.
+    pub paths: BTreeMap<String, Vec<Vec<String>>>,
+    /// The endings no route was found to within the limits.
+    pub unreached: Vec<String>,
+    /// Whether the search hit `max_depth` or `max_paths`, so there may be more routes.
+    pub truncated: bool,
+}
+
+impl Story {
+    /// The endings of the story: passages that aren't special, like scripts, and don't [navigate](Story::navigation) to
+    /// another passage. Passages navigating to a dynamic target aren't endings, as they may go anywhere.
+    pub fn endings(&self) -> Vec<&Passage> {
+        self.passages.iter().filter(|p| ! is_special_passage(p) && ! self.navigation(p).iter().any(|n| ! n.embed && (n.dynamic || self.passage(&n.target).is_some()))).collect()
+    }
+    
+    /// Finds the distinct routes through links and navigation macros from the start passage to each of the [endings](Story::endings).  
+    /// Routes don't visit a passage twice, so cycles like "back" links are followed only once. Routes longer than
+    /// `max_depth` passages are cut off, and the search stops after `max_paths` routes, as the number of routes grows
+    /// exponentially with the number of choices. As routes that loop back without reaching an ending grow just as fast,
+    /// the search also stops after visiting `max_depth * max_paths` passages.
+    pub fn paths_to_endings(&self, max_depth: usize, max_paths: usize) -> EndingPaths {
+        let links: BTreeMap<&str, Vec<&Passage>> = self.passages.iter().map(|p| (p.name.as_str(), self.targets(p))).collect();
+        let visits = max_depth.saturating_mul(max_paths);
+        let mut search = PathSearch { links, max_depth, max_paths, visits, found: 0, result: EndingPaths::default() };
+        if let Some(start) = self.start_passage() {
+            search.visit(&mut vec![start]);
+        }
+        let mut result = search.result;
+        result.unreached = self.endings().iter().filter(|e| ! result.paths.contains_key(&e.name)).map(|e| e.name.clone()).collect();
+        result
+    }
+}
+
+/// The state of the depth-first search of [Story::paths_to_endings].
+struct PathSearch<'a> {
+    /// The passages each passage links to.
+    links: BTreeMap<&'a str, Vec<&'a Passage>>,
+    max_depth: usize,
+    max_paths: usize,
+    /// How many more passages may be visited.
+    visits: usize,
+    found: usize,
+    result: EndingPaths,
+}
+
+impl<'a> PathSearch<'a> {
+    fn visit(&mut self, route: &mut Vec<&'a Passage>) {
+        if self.visits == 0 {
+            self.result.truncated = true;
+            return;
+        }
+        self.visits -= 1;
+        let current = *route.last().unwrap();
+        let targets = self.links[current.name.as_str()].clone();
+        if targets.is_empty() {
+            if ! is_special_passage(current) {
+                self.result.paths.entry(current.name.clone()).or_default().push(route.iter().map(|p| p.name.clone()).collect());
+                self.found += 1;
+            }
+            return;
+        }
+        if route.len() >= self.max_depth {
+            self.result.truncated = true;
+            return;
+        }
+        for t in targets {
+            if self.found >= self.max_paths || self.visits == 0 {
+                self.result.truncated = true;
+                return;
+            }
+            if route.iter().any(|p| p.name == t.name) {
+                continue;
+            }
+            route.push(t);
+            self.visit(route);
+            route.pop();
+        }
+    }
+}
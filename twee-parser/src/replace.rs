@@ -0,0 +1,48 @@
+use regex::Regex;
+
+use crate::{Passage, Story};
+
+/// Where [Story::replace] replaces, besides the passage text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ReplaceOptions {
+    /// Also replaces in passage names. Renaming a passage into the name of another one is skipped.
+    pub names: bool,
+}
+
+impl Passage {
+    /// Replaces all matches of the regex in the passage text. `$1` or `$name` in the replacement refer to capture
+    /// groups, like in [Regex::replace_all]. Returns whether the text changed.
+    pub fn replace(&mut self, regex: &Regex, replacement: &str) -> bool {
+        let replaced = regex.replace_all(&self.content, replacement);
+        if replaced == self.content {
+            return false;
+        }
+        self.content = replaced.into_owned();
+        true
+    }
+}
+
+impl Story {
+    /// Replaces all matches of the regex in the text of all passages, and in the names with [ReplaceOptions::names].
+    /// Links are changed like any other text, so a regex matching a passage name in both renames the passage and
+    /// updates the links to it.  
+    /// Returns the names of the changed passages, after renaming.
+    pub fn replace(&mut self, regex: &Regex, replacement: &str, options: &ReplaceOptions) -> Vec<String> {
+        let mut changed = vec![];
+        for i in 0..self.passages.len() {
+            let mut renamed = false;
+            if options.names {
+                let name = regex.replace_all(&self.passages[i].name, replacement).into_owned();
+                if name != self.passages[i].name && self.passage(&name).is_none() {
+                    self.passages[i].name = name;
+                    renamed = true;
+                }
+            }
+            let p = &mut self.passages[i];
+            if p.replace(regex, replacement) || renamed {
+                changed.push(p.name.clone());
+            }
+        }
+        changed
+    }
+}
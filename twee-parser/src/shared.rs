@@ -0,0 +1,102 @@
+use std::sync::Arc;
+
+use serde_json::{Map, Value};
+
+use crate::{Passage, Story};
+
+/// A [Story] where cloning is O(1), for keeping many versions of a story around, like undo history or per-request
+/// snapshots in a server.  
+/// Versions share the passages they have in common, mutating a passage only copies that passage and the list of
+/// passage pointers.
+#[derive(Debug, Clone, Default)]
+pub struct SharedStory {
+    title: Arc<String>,
+    meta: Arc<Map<String, Value>>,
+    passages: Arc<Vec<Arc<Passage>>>,
+}
+
+impl SharedStory {
+    /// The name of the story.
+    pub fn title(&self) -> &str {
+        &self.title
+    }
+    
+    /// Sets the name of the story.
+    pub fn set_title(&mut self, title: &str) {
+        self.title = Arc::new(title.to_string());
+    }
+    
+    /// The story metadata.
+    pub fn meta(&self) -> &Map<String, Value> {
+        &self.meta
+    }
+    
+    /// The story metadata for changing it, copying it if it's shared.
+    pub fn meta_mut(&mut self) -> &mut Map<String, Value> {
+        Arc::make_mut(&mut self.meta)
+    }
+    
+    /// The passages.
+    pub fn passages(&self) -> impl Iterator<Item = &Passage> {
+        self.passages.iter().map(|p| p.as_ref())
+    }
+    
+    /// Gets the passage with the name.
+    pub fn passage(&self, name: &str) -> Option<&Passage> {
+        self.passages().find(|p| p.name == name)
+    }
+    
+    /// Gets the passage with the name for changing it, copying it if it's shared.
+    pub fn passage_mut(&mut self, name: &str) -> Option<&mut Passage> {
+        let i = self.passages.iter().position(|p| p.name == name)?;
+        Some(Arc::make_mut(&mut Arc::make_mut(&mut self.passages)[i]))
+    }
+    
+    /// Adds a passage at the end.
+    pub fn push_passage(&mut self, passage: Passage) {
+        Arc::make_mut(&mut self.passages).push(Arc::new(passage));
+    }
+    
+    /// Removes the passage with the name.
+    pub fn remove_passage(&mut self, name: &str) -> Option<Passage> {
+        let i = self.passages.iter().position(|p| p.name == name)?;
+        let p = Arc::make_mut(&mut self.passages).remove(i);
+        Some(Arc::unwrap_or_clone(p))
+    }
+    
+    /// Whether both versions share the passage with the name, which means it's unchanged between them.
+    /// Cheaper than comparing the passages.
+    pub fn shares_passage(&self, other: &SharedStory, name: &str) -> bool {
+        let find = |s: &SharedStory| s.passages.iter().find(|p| p.name == name).cloned();
+        matches!((find(self), find(other)), (Some(a), Some(b)) if Arc::ptr_eq(&a, &b))
+    }
+    
+    /// Copies the story into a [Story].
+    pub fn to_story(&self) -> Story {
+        Story {
+            title: self.title.to_string(),
+            passages: self.passages().cloned().collect(),
+            meta: self.meta.as_ref().clone(),
+        }
+    }
+}
+
+impl From<Story> for SharedStory {
+    fn from(story: Story) -> Self {
+        Self {
+            title: Arc::new(story.title),
+            meta: Arc::new(story.meta),
+            passages: Arc::new(story.passages.into_iter().map(Arc::new).collect()),
+        }
+    }
+}
+
+impl From<SharedStory> for Story {
+    fn from(story: SharedStory) -> Self {
+        Story {
+            title: Arc::unwrap_or_clone(story.title),
+            meta: Arc::unwrap_or_clone(story.meta),
+            passages: Arc::unwrap_or_clone(story.passages).into_iter().map(Arc::unwrap_or_clone).collect(),
+        }
+    }
+}
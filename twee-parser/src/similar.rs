@@ -0,0 +1,62 @@
+use std::collections::HashSet;
+
+use crate::Story;
+
+/// The pairs of consecutive words in the text, lowercased and without surrounding punctuation. Texts with a single word give that word.
+fn shingles(text: &str) -> HashSet<String> {
+    let words: Vec<String> = text.split_whitespace().map(|w| w.trim_matches(|c: char| ! c.is_alphanumeric()).to_lowercase())
+        .filter(|w| ! w.is_empty()).collect();
+    if words.len() < 2 {
+        return words.into_iter().collect();
+    }
+    words.windows(2).map(|w| w.join(" ")).collect()
+}
+
+/// The Jaccard similarity of two sets, from 0 for nothing in common to 1 for equal sets.
+fn jaccard(a: &HashSet<String>, b: &HashSet<String>) -> f64 {
+    let common = a.intersection(b).count();
+    common as f64 / (a.len() + b.len() - common) as f64
+}
+
+fn root(parents: &mut [usize], i: usize) -> usize {
+    let mut r = i;
+    while parents[r] != r {
+        r = parents[r];
+    }
+    parents[i] = r;
+    r
+}
+
+impl Story {
+    /// Finds groups of passages with identical or similar content, e.g. copy-pasted boilerplate.  
+    /// The similarity compares the pairs of consecutive words of the passages, ignoring case and whitespace, and
+    /// ranges from 0 to 1. Passages are grouped if they are at least `threshold` similar to another passage of the
+    /// group. Empty passages, scripts and stylesheets are ignored. Returns the passage names of each group with more
+    /// than one passage, in story order.
+    pub fn near_duplicates(&self, threshold: f64) -> Vec<Vec<String>> {
+        let passages: Vec<_> = self.passages.iter()
+            .filter(|p| ! p.content.trim().is_empty() && ! p.tags.iter().any(|t| t == "script" || t == "stylesheet"))
+            .map(|p| (p, shingles(&p.content)))
+            .collect();
+        let mut parents: Vec<usize> = (0..passages.len()).collect();
+        for i in 0..passages.len() {
+            for j in (i + 1)..passages.len() {
+                let (a, b) = (&passages[i].1, &passages[j].1);
+                // The similarity can't be higher than the ratio of the sizes.
+                if (a.len().min(b.len()) as f64) < threshold * a.len().max(b.len()) as f64 {
+                    continue;
+                }
+                if jaccard(a, b) >= threshold {
+                    let (ri, rj) = (root(&mut parents, i), root(&mut parents, j));
+                    parents[rj.max(ri)] = ri.min(rj);
+                }
+            }
+        }
+        let mut groups: Vec<Vec<String>> = vec![vec![]; passages.len()];
+        for (i, (p, _)) in passages.iter().enumerate() {
+            let r = root(&mut parents, i);
+            groups[r].push(p.name.clone());
+        }
+        groups.into_iter().filter(|g| g.len() > 1).collect()
+    }
+}
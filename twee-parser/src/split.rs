@@ -0,0 +1,84 @@
+use crate::{Passage, Story};
+
+/// Options for [Story::split_by_tag].
+#[derive(Debug, Clone)]
+pub struct SplitOptions {
+    /// The name of the passage that links into another part lead to instead. `{part}` is replaced with the number
+    /// of that part, `{tag}` with its tag.
+    pub continue_passage: String,
+    /// The content of that passage, with the same replacements.
+    pub continue_text: String,
+}
+
+impl Default for SplitOptions {
+    fn default() -> Self {
+        Self {
+            continue_passage: "Continue in part {part}".to_string(),
+            continue_text: "To be continued in part {part}.".to_string(),
+        }
+    }
+}
+
+/// A part of a story split by [Story::split_by_tag].
+#[derive(Debug, Clone)]
+pub struct StoryPart {
+    /// The tag of the passages in the part.
+    pub tag: String,
+    /// The story of the part: the passages with the tag and the untagged passages, with the metadata of the whole
+    /// story, including its IFID.
+    pub story: Story,
+}
+
+/// Replaces `{part}` and `{tag}` in a [SplitOptions] template.
+fn fill(template: &str, part: usize, tag: &str) -> String {
+    template.replace("{part}", &part.to_string()).replace("{tag}", tag)
+}
+
+impl Story {
+    /// Splits the story into one story per tag starting with `tag_prefix`, like `chapter1` and `chapter2` for `chapter`,
+    /// e.g. for episodic releases. The parts are numbered from 1 in the order their tags first appear.  
+    /// A passage goes into the part of its first such tag. Passages without one, like StoryInit and scripts, are put
+    /// into every part. Links and navigation macros like `(go-to:)` or `<<goto>>` to passages of another part lead to a
+    /// passage made from [SplitOptions] instead. Dynamic targets and passages embedded from another part are kept.  
+    /// Each part starts at the start passage if it has it, or else at its first tagged passage. The titles get the tag
+    /// appended, and the rest of the metadata is copied, so the parts should be given new IFIDs.
+    pub fn split_by_tag(&self, tag_prefix: &str, options: &SplitOptions) -> Vec<StoryPart> {
+        let part_tag = |p: &Passage| p.tags.iter().find(|t| t.starts_with(tag_prefix)).cloned();
+        let mut tags: Vec<String> = vec![];
+        for t in self.passages.iter().filter_map(part_tag) {
+            if ! tags.contains(&t) {
+                tags.push(t);
+            }
+        }
+        let start = self.start_passage().map(|p| p.name.clone());
+        tags.iter().map(|tag| {
+            let mut passages = vec![];
+            let mut continues: Vec<Passage> = vec![];
+            for p in self.passages.iter().filter(|p| part_tag(p).is_none_or(|t| t == *tag)) {
+                let mut p = p.clone();
+                for n in self.navigation(&p).iter().rev().filter(|n| ! n.embed) {
+                    let Some(target) = self.passage(&n.target).and_then(part_tag).filter(|t| t != tag) else {
+                        continue;
+                    };
+                    let part = tags.iter().position(|t| *t == target).unwrap() + 1;
+                    let name = fill(&options.continue_passage, part, &target);
+                    let Some(text) = n.retarget(&p.content, &name) else {
+                        continue;
+                    };
+                    p.content.replace_range(n.span.clone(), &text);
+                    if ! continues.iter().any(|c| c.name == name) {
+                        continues.push(Passage { name, tags: vec![], meta: Default::default(), content: fill(&options.continue_text, part, &target) });
+                    }
+                }
+                passages.push(p);
+            }
+            passages.extend(continues);
+            let mut story = Story { title: format!("{} ({})", self.title, tag), passages, meta: self.meta.clone() };
+            let first = story.passages.iter().find(|p| part_tag(p).is_some()).map(|p| p.name.clone());
+            if let Some(start) = start.clone().filter(|s| story.passage(s).is_some()).or(first) {
+                story.meta.insert("start".to_string(), start.into());
+            }
+            StoryPart { tag: tag.clone(), story }
+        }).collect()
+    }
+}
@@ -0,0 +1,63 @@
+use crate::Story;
+
+/// The tables [serialize_sql] creates. Metadata values are stored as JSON text.
+pub const SQL_SCHEMA: &str = "CREATE TABLE stories (id INTEGER PRIMARY KEY, title TEXT NOT NULL, ifid TEXT);
+CREATE TABLE story_meta (story_id INTEGER NOT NULL REFERENCES stories(id), key TEXT NOT NULL, value TEXT NOT NULL);
+CREATE TABLE passages (id INTEGER PRIMARY KEY, story_id INTEGER NOT NULL REFERENCES stories(id), position INTEGER NOT NULL, name TEXT NOT NULL, content TEXT NOT NULL);
+CREATE TABLE passage_tags (passage_id INTEGER NOT NULL REFERENCES passages(id), position INTEGER NOT NULL, tag TEXT NOT NULL);
+CREATE TABLE passage_meta (passage_id INTEGER NOT NULL REFERENCES passages(id), key TEXT NOT NULL, value TEXT NOT NULL);
+CREATE TABLE links (passage_id INTEGER NOT NULL REFERENCES passages(id), position INTEGER NOT NULL, text TEXT NOT NULL, target TEXT NOT NULL, target_id INTEGER REFERENCES passages(id));
+";
+
+/// A query for a database written by [serialize_sql] that returns a row for each story, as Twine JSON that
+/// [parse_json](crate::parse_json) reconstructs the [Story] from.
+pub const SQL_STORIES_QUERY: &str = "SELECT json_patch(
+    (SELECT json_group_object(key, json(value)) FROM story_meta WHERE story_id = s.id),
+    json_object('name', s.title, 'passages', (SELECT json_group_array(json_object(
+        'name', p.name,
+        'tags', (SELECT json_group_array(tag) FROM (SELECT tag FROM passage_tags WHERE passage_id = p.id ORDER BY position)),
+        'metadata', (SELECT json_group_object(key, json(value)) FROM passage_meta WHERE passage_id = p.id),
+        'text', p.content
+    )) FROM (SELECT * FROM passages WHERE story_id = s.id ORDER BY position) p))
+) FROM stories s ORDER BY s.id;
+";
+
+fn quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "''"))
+}
+
+/// Serializes stories into an SQL script for SQLite that replaces the tables of [SQL_SCHEMA] with the stories, their
+/// passages, tags, metadata and links, for analysis with SQL.
+/// Links to passages of the same story have their `target_id` set.
+pub fn serialize_sql(stories: &[Story]) -> String {
+    let mut sql = String::from("BEGIN;\n");
+    for table in ["links", "passage_meta", "passage_tags", "passages", "story_meta", "stories"] {
+        sql += &format!("DROP TABLE IF EXISTS {};\n", table);
+    }
+    sql += SQL_SCHEMA;
+    let mut passage_id = 0;
+    for (story_id, story) in stories.iter().enumerate() {
+        let story_id = story_id + 1;
+        let ifid = story.meta.get("ifid").and_then(|i| i.as_str()).map(quote).unwrap_or("NULL".to_string());
+        sql += &format!("INSERT INTO stories VALUES ({}, {}, {});\n", story_id, quote(&story.title), ifid);
+        for (k, v) in &story.meta {
+            sql += &format!("INSERT INTO story_meta VALUES ({}, {}, {});\n", story_id, quote(k), quote(&v.to_string()));
+        }
+        let first_id = passage_id + 1;
+        for (position, p) in story.passages.iter().enumerate() {
+            passage_id += 1;
+            sql += &format!("INSERT INTO passages VALUES ({}, {}, {}, {}, {});\n", passage_id, story_id, position, quote(&p.name), quote(&p.content));
+            for (position, t) in p.tags.iter().enumerate() {
+                sql += &format!("INSERT INTO passage_tags VALUES ({}, {}, {});\n", passage_id, position, quote(t));
+            }
+            for (k, v) in &p.meta {
+                sql += &format!("INSERT INTO passage_meta VALUES ({}, {}, {});\n", passage_id, quote(k), quote(&v.to_string()));
+            }
+            for (position, l) in p.links().iter().enumerate() {
+                let target_id = story.passages.iter().position(|t| t.name == l.target).map(|i| (first_id + i).to_string()).unwrap_or("NULL".to_string());
+                sql += &format!("INSERT INTO links VALUES ({}, {}, {}, {}, {});\n", passage_id, position, quote(&l.text), quote(&l.target), target_id);
+            }
+        }
+    }
+    sql + "COMMIT;\n"
+}
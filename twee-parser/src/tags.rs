@@ -0,0 +1,49 @@
+use crate::{Passage, Story};
+
+impl Passage {
+    /// Adds a tag at the end. Returns false if the passage already has it.
+    pub fn add_tag(&mut self, tag: &str) -> bool {
+        if self.tags.iter().any(|t| t == tag) {
+            return false;
+        }
+        self.tags.push(tag.to_string());
+        true
+    }
+    
+    /// Removes a tag. Returns false if the passage doesn't have it.
+    pub fn remove_tag(&mut self, tag: &str) -> bool {
+        let len = self.tags.len();
+        self.tags.retain(|t| t != tag);
+        self.tags.len() != len
+    }
+    
+    /// Renames a tag, keeping its position. If the passage already has the new tag, the old one is only removed.
+    /// Returns false if the passage doesn't have the tag.
+    pub fn rename_tag(&mut self, from: &str, to: &str) -> bool {
+        let Some(i) = self.tags.iter().position(|t| t == from) else {
+            return false;
+        };
+        if from == to {
+            return true;
+        }
+        if self.tags.iter().any(|t| t == to) {
+            self.tags.remove(i);
+        } else {
+            self.tags[i] = to.to_string();
+        }
+        true
+    }
+}
+
+impl Story {
+    /// Renames a tag in all passages and in the `tag-colors` of the StoryData. Returns the names of the changed
+    /// passages.
+    pub fn rename_tag(&mut self, from: &str, to: &str) -> Vec<String> {
+        if let Some(colors) = self.meta.get_mut("tag-colors").and_then(|c| c.as_object_mut()) {
+            if let Some(color) = colors.remove(from) {
+                colors.entry(to).or_insert(color);
+            }
+        }
+        self.passages.iter_mut().filter_map(|p| p.rename_tag(from, to).then(|| p.name.clone())).collect()
+    }
+}
@@ -0,0 +1,74 @@
+use std::collections::{BTreeMap, HashMap};
+
+use regex::{Captures, Regex};
+use serde_json::Value;
+
+use crate::{Story, Warning};
+
+/// Supplies the values for `{{key}}` placeholders in passage content, see [Story::render].
+pub trait Resolver {
+    /// Gets the value for a placeholder key, or [None] if the key is unknown.
+    fn resolve(&self, key: &str) -> Option<String>;
+}
+
+impl<F: Fn(&str) -> Option<String>> Resolver for F {
+    fn resolve(&self, key: &str) -> Option<String> {
+        self(key)
+    }
+}
+
+impl Resolver for BTreeMap<String, String> {
+    fn resolve(&self, key: &str) -> Option<String> {
+        self.get(key).cloned()
+    }
+}
+
+impl Resolver for HashMap<String, String> {
+    fn resolve(&self, key: &str) -> Option<String> {
+        self.get(key).cloned()
+    }
+}
+
+/// Resolves dotted keys like `item.price` by walking into objects, and arrays for numeric parts.  
+/// Strings are inserted as they are, other values as JSON.
+impl Resolver for Value {
+    fn resolve(&self, key: &str) -> Option<String> {
+        let mut v = self;
+        for part in key.split('.') {
+            v = match v {
+                Value::Object(o) => o.get(part)?,
+                Value::Array(a) => a.get(part.parse::<usize>().ok()?)?,
+                _ => return None,
+            };
+        }
+        match v {
+            Value::String(s) => Some(s.clone()),
+            Value::Null => None,
+            v => Some(v.to_string()),
+        }
+    }
+}
+
+impl Story {
+    /// Creates a copy of the story with the `{{key}}` placeholders in the passage content replaced by the values
+    /// from the resolver.  
+    /// Keys consist of letters, digits, `_`, `-` and `.`, and may be surrounded by spaces. Placeholders the resolver has no
+    /// value for are left as they are and reported as [Warning::PlaceholderUnresolved]. `\{{` produces a literal `{{`.
+    pub fn render(&self, resolver: &impl Resolver) -> (Story, Vec<Warning>) {
+        let placeholder = Regex::new(r"\\\{\{|\{\{\s*([A-Za-z0-9_.\-]+)\s*\}\}").unwrap();
+        let mut warnings = vec![];
+        let mut story = self.clone();
+        for p in &mut story.passages {
+            p.content = placeholder.replace_all(&p.content, |c: &Captures| {
+                let Some(key) = c.get(1) else {
+                    return "{{".to_string();
+                };
+                resolver.resolve(key.as_str()).unwrap_or_else(|| {
+                    warnings.push(Warning::PlaceholderUnresolved(p.name.clone(), key.as_str().to_string()));
+                    c[0].to_string()
+                })
+            }).to_string();
+        }
+        (story, warnings)
+    }
+}
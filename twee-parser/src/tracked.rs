@@ -0,0 +1,251 @@
+use std::collections::{hash_map::Entry, HashMap};
+
+use serde_json::Value;
+
+use crate::{serialize_twee3_header, serialize_twee3_passage, Passage, Story};
+
+/// A change to a [TrackedStory].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StoryEvent {
+    /// A passage was added. The argument is the passage name.
+    PassageAdded(String),
+    /// A passage was removed. The argument is the passage name.
+    PassageRemoved(String),
+    /// A passage was renamed.
+    PassageRenamed {
+        /// The old name.
+        from: String,
+        /// The new name.
+        to: String,
+    },
+    /// The content of a passage changed. The argument is the passage name.
+    ContentChanged(String),
+    /// The tags of a passage changed. The argument is the passage name.
+    TagsChanged(String),
+    /// A metadata entry changed or was removed.
+    MetaChanged {
+        /// The passage name, or [None] for the story metadata.
+        passage: Option<String>,
+        /// The metadata key.
+        key: String,
+    },
+    /// The story title changed.
+    TitleChanged,
+}
+
+type Observer = Box<dyn FnMut(&StoryEvent)>;
+
+/// A [Story] that notifies observers about changes made through its methods, so e.g. an editor UI or a search index
+/// can update without comparing whole stories.  
+/// The methods only emit events for actual changes, setting a value to what it already is does nothing.  
+/// Every change increases the [revision](TrackedStory::revision) of the story and of the changed passage, which lets
+/// serializers skip unchanged passages, see [PassageCache].
+pub struct TrackedStory {
+    story: Story,
+    observers: Vec<Observer>,
+    revision: u64,
+    passage_revisions: HashMap<String, u64>,
+}
+
+impl TrackedStory {
+    /// Wraps a story.
+    pub fn new(story: Story) -> Self {
+        Self { story, observers: vec![], revision: 0, passage_revisions: HashMap::new() }
+    }
+    
+    /// The wrapped story.
+    pub fn story(&self) -> &Story {
+        &self.story
+    }
+    
+    /// Unwraps the story.
+    pub fn into_inner(self) -> Story {
+        self.story
+    }
+    
+    /// Calls the observer with every following change.
+    pub fn subscribe(&mut self, observer: impl FnMut(&StoryEvent) + 'static) {
+        self.observers.push(Box::new(observer));
+    }
+    
+    /// The number of changes made so far.
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+    
+    /// The revision at which the passage was last changed, 0 if it wasn't changed since the story was wrapped.
+    pub fn passage_revision(&self, name: &str) -> Option<u64> {
+        self.story.passage(name)?;
+        Some(self.passage_revisions.get(name).copied().unwrap_or(0))
+    }
+    
+    /// The passages changed after the revision, e.g. to send only those to a browser.
+    pub fn changed_since(&self, revision: u64) -> Vec<&Passage> {
+        self.story.passages.iter().filter(|p| self.passage_revisions.get(&p.name).is_some_and(|r| *r > revision)).collect()
+    }
+    
+    /// Serializes the story into Twee3 like [serialize_twee3](crate::serialize_twee3), reusing the cached text of
+    /// passages that didn't change since the last call with the cache.
+    pub fn serialize_twee3(&self, cache: &mut PassageCache) -> String {
+        let mut res = serialize_twee3_header(&self.story);
+        for p in &self.story.passages {
+            res += cache.get_or_insert_with(self, p, serialize_twee3_passage);
+        }
+        res
+    }
+    
+    fn emit(&mut self, event: StoryEvent) {
+        self.revision += 1;
+        match &event {
+            StoryEvent::PassageAdded(name) | StoryEvent::ContentChanged(name) | StoryEvent::TagsChanged(name)
+            | StoryEvent::MetaChanged { passage: Some(name), .. } => {
+                self.passage_revisions.insert(name.clone(), self.revision);
+            },
+            StoryEvent::PassageRemoved(name) => {
+                self.passage_revisions.remove(name);
+            },
+            StoryEvent::PassageRenamed { from, to } => {
+                self.passage_revisions.remove(from);
+                self.passage_revisions.insert(to.clone(), self.revision);
+            },
+            StoryEvent::MetaChanged { passage: None, .. } | StoryEvent::TitleChanged => {},
+        }
+        for o in &mut self.observers {
+            o(&event);
+        }
+    }
+    
+    fn passage_mut(&mut self, name: &str) -> Option<&mut Passage> {
+        self.story.passages.iter_mut().find(|p| p.name == name)
+    }
+    
+    /// Sets the story title.
+    pub fn set_title(&mut self, title: &str) {
+        if self.story.title != title {
+            self.story.title = title.to_string();
+            self.emit(StoryEvent::TitleChanged);
+        }
+    }
+    
+    /// Sets a story metadata entry, or removes it with [None].
+    pub fn set_meta(&mut self, key: &str, value: Option<Value>) {
+        let changed = match value {
+            Some(v) => self.story.meta.insert(key.to_string(), v.clone()).as_ref() != Some(&v),
+            None => self.story.meta.remove(key).is_some(),
+        };
+        if changed {
+            self.emit(StoryEvent::MetaChanged { passage: None, key: key.to_string() });
+        }
+    }
+    
+    /// Adds a passage. Returns false without adding it if a passage with the name already exists.
+    pub fn add_passage(&mut self, passage: Passage) -> bool {
+        if self.story.passage(&passage.name).is_some() {
+            return false;
+        }
+        let name = passage.name.clone();
+        self.story.passages.push(passage);
+        self.emit(StoryEvent::PassageAdded(name));
+        true
+    }
+    
+    /// Removes the passage with the name.
+    pub fn remove_passage(&mut self, name: &str) -> Option<Passage> {
+        let i = self.story.passages.iter().position(|p| p.name == name)?;
+        let p = self.story.passages.remove(i);
+        self.emit(StoryEvent::PassageRemoved(p.name.clone()));
+        Some(p)
+    }
+    
+    /// Renames a passage. Links to it aren't changed.  
+    /// Returns false if the passage doesn't exist or the new name is taken.
+    pub fn rename_passage(&mut self, from: &str, to: &str) -> bool {
+        if from == to {
+            return self.story.passage(from).is_some();
+        }
+        if self.story.passage(to).is_some() {
+            return false;
+        }
+        let Some(p) = self.passage_mut(from) else {
+            return false;
+        };
+        p.name = to.to_string();
+        self.emit(StoryEvent::PassageRenamed { from: from.to_string(), to: to.to_string() });
+        true
+    }
+    
+    /// Sets the content of a passage. Returns false if the passage doesn't exist.
+    pub fn set_content(&mut self, name: &str, content: &str) -> bool {
+        let Some(p) = self.passage_mut(name) else {
+            return false;
+        };
+        if p.content != content {
+            p.content = content.to_string();
+            self.emit(StoryEvent::ContentChanged(name.to_string()));
+        }
+        true
+    }
+    
+    /// Sets the tags of a passage. Returns false if the passage doesn't exist.
+    pub fn set_tags(&mut self, name: &str, tags: Vec<String>) -> bool {
+        let Some(p) = self.passage_mut(name) else {
+            return false;
+        };
+        if p.tags != tags {
+            p.tags = tags;
+            self.emit(StoryEvent::TagsChanged(name.to_string()));
+        }
+        true
+    }
+    
+    /// Sets a metadata entry of a passage, or removes it with [None]. Returns false if the passage doesn't exist.
+    pub fn set_passage_meta(&mut self, name: &str, key: &str, value: Option<Value>) -> bool {
+        let Some(p) = self.passage_mut(name) else {
+            return false;
+        };
+        let changed = match value {
+            Some(v) => p.meta.insert(key.to_string(), v.clone()).as_ref() != Some(&v),
+            None => p.meta.remove(key).is_some(),
+        };
+        if changed {
+            self.emit(StoryEvent::MetaChanged { passage: Some(name.to_string()), key: key.to_string() });
+        }
+        true
+    }
+}
+
+/// Per-passage serialization results of a [TrackedStory], kept until the passage changes.  
+/// A cache should only be used with one story. Only [TrackedStory::serialize_twee3] uses the cache, serializing the
+/// story to HTML or a Twine archive always serializes every passage.
+#[derive(Debug, Clone, Default)]
+pub struct PassageCache {
+    entries: HashMap<String, CachedPassage>,
+}
+
+/// The serialized text of a passage, with the revision of the passage it was serialized at.
+#[derive(Debug, Clone)]
+struct CachedPassage {
+    revision: u64,
+    text: String,
+}
+
+impl PassageCache {
+    /// Creates an empty cache.
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    /// Gets the cached text of the passage, serializing it with `serialize` if it changed since it was cached.
+    pub fn get_or_insert_with(&mut self, story: &TrackedStory, passage: &Passage, serialize: impl FnOnce(&Passage) -> String) -> &str {
+        let revision = story.passage_revision(&passage.name).unwrap_or(story.revision());
+        let cached = match self.entries.entry(passage.name.clone()) {
+            Entry::Occupied(e) if e.get().revision == revision => e.into_mut(),
+            Entry::Occupied(mut e) => {
+                e.insert(CachedPassage { revision, text: serialize(passage) });
+                e.into_mut()
+            },
+            Entry::Vacant(e) => e.insert(CachedPassage { revision, text: serialize(passage) }),
+        };
+        &cached.text
+    }
+}
@@ -0,0 +1,106 @@
+use std::time::{Duration, Instant};
+
+use thiserror::Error;
+
+use crate::{Story, Warning};
+
+/// The error type of [StoryTransform]s.
+pub type TransformError = Box<dyn std::error::Error + Send + Sync>;
+
+/// What a [StoryTransform] has to report besides changing the story.
+#[derive(Debug, Clone, Default)]
+#[non_exhaustive]
+pub struct Report {
+    /// Problems that didn't stop the transform.
+    pub warnings: Vec<Warning>,
+    /// How long the transforms took by their name, in the order they ran. Filled in by [Pipeline::run].
+    pub timings: Vec<(String, Duration)>,
+}
+
+impl Report {
+    /// Adds the warnings and timings of another report.
+    pub fn extend(&mut self, other: Report) {
+        self.warnings.extend(other.warnings);
+        self.timings.extend(other.timings);
+    }
+}
+
+/// A step that changes a [Story], like resolving includes or attaching scripts. Steps are composed with a [Pipeline].  
+/// Closures taking a `&mut Story` and returning a `Result<Report, TransformError>` are transforms too.
+pub trait StoryTransform {
+    /// Identifies the transform in errors and in [Pipeline::insert_before]. Defaults to the type name.
+    fn name(&self) -> &str {
+        std::any::type_name::<Self>()
+    }
+    
+    /// Applies the transform to the story.
+    fn transform(&self, story: &mut Story) -> Result<Report, TransformError>;
+}
+
+impl<F: Fn(&mut Story) -> Result<Report, TransformError>> StoryTransform for F {
+    fn transform(&self, story: &mut Story) -> Result<Report, TransformError> {
+        self(story)
+    }
+}
+
+/// The transforms of a [Pipeline] that failed.
+#[derive(Error, Debug)]
+#[error("{}", .errors.iter().map(|(name, e)| format!("{}: {}", name, e)).collect::<Vec<_>>().join("\n"))]
+pub struct PipelineError {
+    /// The names of the failed transforms with their errors, in the order they ran.
+    pub errors: Vec<(String, TransformError)>,
+    /// The reports of the transforms that succeeded.
+    pub report: Report,
+}
+
+/// Runs [StoryTransform]s on a story in order.
+#[derive(Default)]
+pub struct Pipeline {
+    transforms: Vec<Box<dyn StoryTransform>>,
+}
+
+impl Pipeline {
+    /// Creates an empty pipeline.
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    /// Adds a transform that runs after the ones already added.
+    pub fn then(mut self, transform: impl StoryTransform + 'static) -> Self {
+        self.transforms.push(Box::new(transform));
+        self
+    }
+    
+    /// Adds a transform that runs before the transform with the name, or last if there is none.
+    pub fn insert_before(mut self, name: &str, transform: impl StoryTransform + 'static) -> Self {
+        let i = self.transforms.iter().position(|t| t.name() == name).unwrap_or(self.transforms.len());
+        self.transforms.insert(i, Box::new(transform));
+        self
+    }
+    
+    /// The names of the transforms in the order they run.
+    pub fn names(&self) -> Vec<&str> {
+        self.transforms.iter().map(|t| t.name()).collect()
+    }
+    
+    /// Runs all transforms in order and combines their reports.  
+    /// A failing transform doesn't stop the pipeline, so all errors are reported at once, but the story may be left
+    /// partially transformed.
+    pub fn run(&self, story: &mut Story) -> Result<Report, PipelineError> {
+        let mut report = Report::default();
+        let mut errors = vec![];
+        for t in &self.transforms {
+            let start = Instant::now();
+            match t.transform(story) {
+                Ok(r) => report.extend(r),
+                Err(e) => errors.push((t.name().to_string(), e)),
+            }
+            report.timings.push((t.name().to_string(), start.elapsed()));
+        }
+        if errors.is_empty() {
+            Ok(report)
+        } else {
+            Err(PipelineError { errors, report })
+        }
+    }
+}
@@ -1,3 +1,5 @@
+use std::ops::Range;
+
 use regex::RegexBuilder;
 
 use crate::*;
@@ -12,7 +14,7 @@ enum PassageState {
 /// Parses Twee3 into a [Story].
 pub fn parse_twee3(source: &str) -> Result<(Story, Vec<Warning>), Error> {
     let passage_start = RegexBuilder::new("^::[^\n]*\n").multi_line(true).build().unwrap();
-    let passage_escape = RegexBuilder::new("^\\::").multi_line(true).build().unwrap();
+    let passage_escape = RegexBuilder::new("^\\\\::").multi_line(true).build().unwrap();
     let mut warnings = vec![];
     let mut passages: Vec<Passage> = Vec::new();
     let mut start = 0;
@@ -21,13 +23,14 @@ pub fn parse_twee3(source: &str) -> Result<(Story, Vec<Warning>), Error> {
     let mut meta: &str = "{}";
     let mut title = String::new();
     let mut story_meta = None;
-    fn handle_passage(warnings: &mut Vec<Warning>, title: &mut String, story_meta: &mut Option<Map<String, Value>>, passages: &mut Vec<Passage>, name: &str, content: &str, tags: &Vec<String>, meta: &str) {
-        if name.len() == 0 {
+    #[allow(clippy::too_many_arguments)]
+    fn handle_passage(warnings: &mut Vec<Warning>, title: &mut String, story_meta: &mut Option<Map<String, Value>>, passages: &mut Vec<Passage>, name: &str, content: &str, tags: &[String], meta: &str) {
+        if name.is_empty() {
             warnings.push(Warning::PassageNameMissing);
         } else {
             match name {
                 "StoryTitle" => {
-                    if title.len() != 0 {
+                    if !title.is_empty() {
                         warnings.push(Warning::PassageDuplicated("StoryTitle".to_string()));
                     }
                     *title = content.trim().to_string();
@@ -36,7 +39,7 @@ pub fn parse_twee3(source: &str) -> Result<(Story, Vec<Warning>), Error> {
                     if story_meta.is_some() {
                         warnings.push(Warning::PassageDuplicated("StoryData".to_string()));
                     }
-                    *story_meta = if let Ok(v) = serde_json::from_str(&content) {
+                    *story_meta = if let Ok(v) = serde_json::from_str(content) {
                         let v: Value = v;
                         match v {
                             Value::Object(o) => {
@@ -77,7 +80,7 @@ pub fn parse_twee3(source: &str) -> Result<(Story, Vec<Warning>), Error> {
                             warnings.push(Warning::PassageMetadataMalformed(name.to_string()));
                             Map::new()
                         };
-                        passages.push(Passage { name: name.to_string(), tags: tags.clone(), meta, content: content.trim_end().to_string()});
+                        passages.push(Passage { name: name.to_string(), tags: tags.to_vec(), meta, content: content.trim_end().to_string()});
                     }
                 }
             }
@@ -141,6 +144,7 @@ pub fn parse_twee3(source: &str) -> Result<(Story, Vec<Warning>), Error> {
                     if c == ']' {
                         if ! tag.is_empty() {
                             tags.push(tag.iter().collect());
+                            tag.clear();
                         }
                         state = PassageState::Between;
                         continue;
@@ -171,7 +175,7 @@ pub fn parse_twee3(source: &str) -> Result<(Story, Vec<Warning>), Error> {
         if ! tag.is_empty() {
             tags.push(tag.iter().collect());
         }
-        if meta.trim().len() == 0 {
+        if meta.trim().is_empty() {
             meta = "{}";
         }
         start = a.end();
@@ -186,25 +190,33 @@ pub fn parse_twee3(source: &str) -> Result<(Story, Vec<Warning>), Error> {
     if title.is_empty() {
         warnings.push(Warning::StoryTitleMissing);
     }
-    return Ok((Story {
+    Ok((Story {
         title,
         passages,
         meta: story_meta.unwrap_or(Map::new()),
-    }, warnings));
+    }, warnings))
 }
 
 
 /// Serializes a [Story] into Twee3.
 pub fn serialize_twee3(story: &Story) -> String {
-    let passage_escape = RegexBuilder::new("^::").multi_line(true).build().unwrap();
-    let escape = |t: &String| {
-        t.replace("\\", "\\\\")
-        .replace("[", "\\[")
-        .replace("]", "\\]")
-        .replace("{", "\\{")
-        .replace("}", "\\}")
-    };
-    
+    let mut res = serialize_twee3_header(story);
+    for p in &story.passages {
+        res += &serialize_twee3_passage(p);
+    }
+    res
+}
+
+fn escape(t: &str) -> String {
+    t.replace("\\", "\\\\")
+    .replace("[", "\\[")
+    .replace("]", "\\]")
+    .replace("{", "\\{")
+    .replace("}", "\\}")
+}
+
+/// Serializes the StoryTitle and StoryData passages of a [Story] into Twee3.
+pub fn serialize_twee3_header(story: &Story) -> String {
     let mut res: Vec<char> = Vec::new();
     res.extend(":: StoryTitle\n".chars());
     res.extend(escape(&story.title).chars());
@@ -212,26 +224,156 @@ pub fn serialize_twee3(story: &Story) -> String {
     res.extend("\n\n:: StoryData\n".chars());
     res.extend(serde_json::to_string_pretty(&story.meta).unwrap().chars());
     res.extend("\n\n".chars());
-    
-    for p in &story.passages {
-        res.extend("\n:: ".chars());
-        res.extend(escape(&p.name).chars());
-        if ! p.tags.is_empty() {
-            res.extend(" [".chars());
-            res.extend(p.tags.iter().map(escape).collect::<Vec<String>>().join(" ").chars());
-            res.push(']');
+    res.into_iter().collect()
+}
+
+/// Serializes a single [Passage] into Twee3, as it appears in the output of [serialize_twee3].
+pub fn serialize_twee3_passage(p: &Passage) -> String {
+    let passage_escape = RegexBuilder::new("^::").multi_line(true).build().unwrap();
+    let mut res: Vec<char> = Vec::new();
+    res.push('\n');
+    res.extend(passage_header(&p.name, &p.tags, &p.meta).chars());
+    let content = passage_escape.replace_all(&p.content, "\\::");
+    res.extend(content.chars());
+    res.push('\n');
+    res.into_iter().collect()
+}
+
+/// The Twee3 header line of a passage, including the newline.
+pub fn passage_header(name: &str, tags: &[String], meta: &Map<String, Value>) -> String {
+    let mut h = format!(":: {}", escape(name));
+    if ! tags.is_empty() {
+        h += &format!(" [{}]", tags.iter().map(|t| escape(t)).collect::<Vec<_>>().join(" "));
+    }
+    if ! meta.is_empty() {
+        h += &format!(" {}", serde_json::to_string(meta).unwrap());
+    }
+    h + "\n"
+}
+
+/// Escapes passage content for Twee3 by escaping lines starting with `::`, without trailing whitespace, which isn't
+/// part of the passage when parsing it.
+pub fn escape_content(content: &str) -> String {
+    RegexBuilder::new("^::").multi_line(true).build().unwrap().replace_all(content.trim_end(), "\\::").to_string()
+}
+
+/// The location of a passage in Twee3 source.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PassageSpan {
+    /// The passage name, including StoryTitle and StoryData.
+    pub name: String,
+    /// The byte range from the start of the header line up to the next header or the end of the source.
+    pub span: Range<usize>,
+}
+
+/// A passage in Twee3 source, with the ranges of its header and body, so it can be changed without touching the rest
+/// of the source.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PassageBlock {
+    /// The byte range of the header line, including the newline.
+    pub header: Range<usize>,
+    /// The byte range of the text after the header, up to the next passage, without trailing whitespace.
+    pub body: Range<usize>,
+    pub passage: Passage,
+}
+
+/// Parses the passages of Twee3 source into [PassageBlock]s, in the order they appear. StoryTitle and StoryData are
+/// skipped, and unlike with [parse_twee3], all copies of duplicated passages are returned.
+pub fn passage_blocks(source: &str) -> Result<Vec<PassageBlock>, Error> {
+    let header = RegexBuilder::new("^::[^\n]*(?:\n|\\z)").multi_line(true).build().unwrap();
+    let headers: Vec<Range<usize>> = header.find_iter(source).map(|m| m.range()).collect();
+    let mut blocks = vec![];
+    for (i, h) in headers.iter().enumerate() {
+        let end = headers.get(i + 1).map(|n| n.start).unwrap_or(source.len());
+        let body = h.end..(h.end + source[h.end..end].trim_end().len());
+        let Some(passage) = parse_twee3(&source[h.start..end])?.0.passages.into_iter().next() else {
+            continue;
+        };
+        blocks.push(PassageBlock { header: h.clone(), body, passage });
+    }
+    Ok(blocks)
+}
+
+/// The unescaped name from a passage header line.
+fn header_name(header: &str) -> String {
+    let mut name = String::new();
+    let mut escape = false;
+    for c in header.trim_start_matches("::").chars() {
+        if escape {
+            escape = false;
+            name.push(c);
+            continue;
         }
-        if ! p.meta.is_empty() {
-            res.extend(" {".chars());
-            res.extend(serde_json::to_string(&p.meta).unwrap().chars());
-            res.push('}');
+        match c {
+            '\\' => escape = true,
+            '[' | '{' | '\r' | '\n' => break,
+            c => name.push(c),
         }
-        res.push('\n');
-        let content = passage_escape.replace_all(&p.content, "\\::");
-        res.extend(content.chars());
-        res.push('\n');
     }
-    return res.into_iter().collect();
+    name.trim().to_string()
 }
 
+/// Finds the passages in Twee3 source, in the order they appear.
+pub fn passage_spans(source: &str) -> Vec<PassageSpan> {
+    let passage_start = RegexBuilder::new("^::[^\n]*\n").multi_line(true).build().unwrap();
+    let headers: Vec<_> = passage_start.find_iter(source).collect();
+    headers.iter().enumerate().map(|(i, h)| PassageSpan {
+        name: header_name(h.as_str()),
+        span: h.start()..headers.get(i + 1).map(|n| n.start()).unwrap_or(source.len()),
+    }).collect()
+}
 
+impl Story {
+    /// Applies an edit to the Twee3 source this story was parsed from, and only reparses the passages touched by it
+    /// instead of the whole source. Returns the edited source and the warnings for the reparsed passages.  
+    /// `edit` is the byte range in `source` replaced by `new_text`. If the edit changes where passages start in a way
+    /// that can't be handled locally, like removing the first header, the whole source is reparsed.  
+    /// Returns [Error::InvalidEditRange] if `edit` isn't a valid range of `source`.
+    pub fn reparse_passage_at(&mut self, source: &str, edit: Range<usize>, new_text: &str) -> Result<(String, Vec<Warning>), Error> {
+        if source.get(edit.clone()).is_none() {
+            return Err(Error::InvalidEditRange(edit.start, edit.end));
+        }
+        let mut edited = source.to_string();
+        edited.replace_range(edit.clone(), new_text);
+        let spans = passage_spans(source);
+        let touched: Vec<&PassageSpan> = spans.iter().filter(|s| s.span.start <= edit.end && edit.start <= s.span.end).collect();
+        let is_boundary = |i: usize| i == edited.len() || (edited[i..].starts_with("::") && (i == 0 || edited[..i].ends_with('\n')));
+        let duplicated = touched.iter().any(|t| spans.iter().filter(|s| s.name == t.name).count() > 1);
+        let (Some(first), Some(last), false) = (touched.first(), touched.last(), duplicated) else {
+            return self.reparse_all(edited);
+        };
+        let start = first.span.start;
+        let end = (last.span.end + edited.len()).checked_sub(source.len()).filter(|e| *e >= start);
+        let Some(end) = end.filter(|e| edit.start >= start && is_boundary(start) && is_boundary(*e)) else {
+            return self.reparse_all(edited);
+        };
+        let (part, warnings) = parse_twee3(&edited[start..end])?;
+        let mut warnings: Vec<Warning> = warnings.into_iter().filter(|w| ! matches!(w, Warning::StoryTitleMissing)).collect();
+        let region: Vec<String> = passage_spans(&edited[start..end]).into_iter().map(|s| s.name).collect();
+        for name in touched.iter().map(|s| &s.name).chain(&region) {
+            match name.as_str() {
+                "StoryTitle" => self.title = part.title.clone(),
+                "StoryData" => self.meta = part.meta.clone(),
+                _ => {},
+            }
+        }
+        let index = self.passages.iter().position(|p| touched.iter().any(|s| s.name == p.name)).unwrap_or(self.passages.len());
+        self.passages.retain(|p| ! touched.iter().any(|s| s.name == p.name));
+        let mut added = vec![];
+        for p in part.passages {
+            if self.passage(&p.name).is_some() {
+                warnings.push(Warning::PassageDuplicated(p.name));
+            } else {
+                added.push(p);
+            }
+        }
+        self.passages.splice(index..index, added);
+        Ok((edited, warnings))
+    }
+    
+    fn reparse_all(&mut self, edited: String) -> Result<(String, Vec<Warning>), Error> {
+        let (story, warnings) = parse_twee3(&edited)?;
+        *self = story;
+        Ok((edited, warnings))
+    }
+}
@@ -0,0 +1,125 @@
+use serde_json::Value;
+
+use crate::{FormatVersion, Passage, Story, Warning};
+
+/// Character sequences that end or split a `[[...]]` link, so a passage name containing them can't be linked reliably.
+const LINK_SYNTAX: &[&str] = &["[[", "]]", "->", "<-", "|", "[", "]"];
+
+/// Tags with a special meaning, for all formats and per story format.
+const SPECIAL_TAGS: &[(&str, &[&str])] = &[
+    ("", &["script", "stylesheet", "Twine.private"]),
+    ("sugarcube", &["widget", "init", "nobr", "bookmark", "Twine.audio", "Twine.image", "Twine.video", "Twine.vtt"]),
+    ("harlowe", &["header", "footer", "startup", "debug-header", "debug-footer", "debug-startup"]),
+];
+
+/// The special tags of the story format, and those of all formats.
+fn special_tags(format: &str) -> impl Iterator<Item = &'static str> + '_ {
+    SPECIAL_TAGS.iter().filter(move |(f, _)| f.is_empty() || f.eq_ignore_ascii_case(format)).flat_map(|(_, tags)| tags.iter().copied())
+}
+
+/// Passages the story formats use by their name instead of showing them.
+const SPECIAL_PASSAGES: &[&str] = &["StoryInit", "StoryCaption", "StoryMenu", "StoryBanner", "StorySubtitle", "StoryAuthor",
+    "StoryInterface", "StoryShare", "PassageReady", "PassageDone", "PassageHeader", "PassageFooter"];
+
+/// Whether the passage is used by the story formats for something else than being shown, like scripts.
+pub fn is_special_passage(p: &Passage) -> bool {
+    SPECIAL_PASSAGES.contains(&p.name.as_str()) || p.tags.iter().any(|t| SPECIAL_TAGS.iter().any(|(_, tags)| tags.contains(&t.as_str())))
+}
+
+/// Why a tag probably has its special meaning by accident, if it does.
+fn reserved_collision(p: &Passage, tag: &str, format: &str) -> Option<String> {
+    if let Some(special) = special_tags(format).find(|s| *s != tag && s.eq_ignore_ascii_case(tag)) {
+        return Some(format!("differs from the special tag \"{}\" only in case", special));
+    }
+    if tag == "widget" && format.eq_ignore_ascii_case("sugarcube") && ! p.content.contains("<<widget") {
+        return Some("makes the passage a widget passage, but it defines no widgets".to_string());
+    }
+    if (tag == "script" || tag == "stylesheet") && p.content.contains("[[") {
+        return Some(format!("makes the passage part of the story {}, but it contains links", if tag == "script" { "JavaScript" } else { "CSS" }));
+    }
+    None
+}
+
+/// The major versions of the story formats that have been released.
+const FORMAT_MAJOR_VERSIONS: &[(&str, &[u64])] = &[
+    ("harlowe", &[1, 2, 3]),
+    ("sugarcube", &[1, 2]),
+    ("chapbook", &[1, 2]),
+    ("snowman", &[1, 2]),
+    ("paperthin", &[1]),
+];
+
+/// The tag colors the Twine editor supports.
+const TAG_COLORS: &[&str] = &["red", "orange", "yellow", "green", "blue", "purple"];
+
+/// Problems with the StoryData fields, as the field and the problem.
+fn story_data_problems(story: &Story) -> Vec<(String, String)> {
+    let mut problems = vec![];
+    let format = story.meta.get("format").and_then(|f| f.as_str()).unwrap_or_default();
+    if let Some(v) = story.meta.get("format-version") {
+        match v.as_str().map(|v| (v, FormatVersion::parse(v))) {
+            Some((_, Some(FormatVersion { major, .. }))) => {
+                let known = FORMAT_MAJOR_VERSIONS.iter().find(|(f, _)| f.eq_ignore_ascii_case(format));
+                if let Some((_, majors)) = known.filter(|(_, majors)| ! majors.contains(&major)) {
+                    problems.push(("format-version".to_string(), format!("{} has no major version {}, known are {}", format, major,
+                        majors.iter().map(|m| m.to_string()).collect::<Vec<_>>().join(", "))));
+                }
+            },
+            Some((v, None)) => problems.push(("format-version".to_string(), format!("\"{}\" is not a version like 1.2.3", v))),
+            None => problems.push(("format-version".to_string(), "is not a string".to_string())),
+        }
+    }
+    if let Some(zoom) = story.meta.get("zoom") {
+        if ! zoom.is_number() && zoom.as_str().is_none_or(|z| z.trim().parse::<f64>().is_err()) {
+            problems.push(("zoom".to_string(), format!("{} is not a number", zoom)));
+        }
+    }
+    match story.meta.get("tag-colors") {
+        Some(Value::Object(colors)) => {
+            for (tag, color) in colors {
+                if color.as_str().is_none_or(|c| ! TAG_COLORS.contains(&c)) {
+                    problems.push(("tag-colors".to_string(), format!("the color {} of the tag \"{}\" is not one of {}", color, tag, TAG_COLORS.join(", "))));
+                }
+            }
+        },
+        Some(_) => problems.push(("tag-colors".to_string(), "is not an object of tags and colors".to_string())),
+        None => {},
+    }
+    problems
+}
+
+/// Why a passage name can't be linked reliably, if it can't.
+pub fn unlinkable(name: &str) -> Option<String> {
+    if name.trim() != name {
+        return Some("leading or trailing whitespace".to_string());
+    }
+    LINK_SYNTAX.iter().find(|s| name.contains(*s)).map(|s| format!("\"{}\"", s))
+}
+
+impl Story {
+    /// Checks the story for problems that don't stop parsing or serializing, but break the story in the browser.
+    pub fn validate(&self) -> Vec<Warning> {
+        let mut warnings: Vec<Warning> = story_data_problems(self).into_iter().map(|(field, problem)| Warning::StoryDataInvalid(field, problem)).collect();
+        let format = self.meta.get("format").and_then(|f| f.as_str()).unwrap_or_default();
+        if self.start_passage().is_none() {
+            match self.meta.get("start").and_then(|s| s.as_str()) {
+                Some(start) => warnings.push(Warning::StartPassageMissing(Some(start.to_string()))),
+                None if self.passage("Start").is_none() => warnings.push(Warning::StartPassageMissing(None)),
+                None => {},
+            }
+        }
+        for p in &self.passages {
+            if let Some(reason) = unlinkable(&p.name) {
+                warnings.push(Warning::PassageNameUnlinkable(p.name.clone(), reason));
+            }
+            for t in &p.tags {
+                if t.is_empty() || t.contains(char::is_whitespace) {
+                    warnings.push(Warning::TagInvalid(p.name.clone(), t.clone()));
+                } else if let Some(reason) = reserved_collision(p, t, format) {
+                    warnings.push(Warning::TagReserved(p.name.clone(), t.clone(), reason));
+                }
+            }
+        }
+        warnings
+    }
+}
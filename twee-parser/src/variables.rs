@@ -0,0 +1,30 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use regex::Regex;
+
+use crate::{Passage, Story};
+
+impl Passage {
+    /// The story variables used in the passage, like `$gold` in SugarCube and Harlowe, or `State.variables.gold` and
+    /// `V.gold` in SugarCube JavaScript. Temporary variables like `_i` aren't included, as they aren't saved.
+    pub fn variables(&self) -> BTreeSet<String> {
+        let patterns = [
+            Regex::new(r"\$([A-Za-z_][A-Za-z0-9_]*)").unwrap(),
+            Regex::new(r"\b(?:State\.variables|V)\.([A-Za-z_$][A-Za-z0-9_$]*)").unwrap(),
+        ];
+        patterns.iter().flat_map(|p| p.captures_iter(&self.content).map(|c| c[1].to_string()).collect::<Vec<_>>()).collect()
+    }
+}
+
+impl Story {
+    /// The story variables used in the story, with the names of the passages using them. See [Passage::variables].
+    pub fn variables(&self) -> BTreeMap<String, Vec<String>> {
+        let mut vars: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        for p in &self.passages {
+            for v in p.variables() {
+                vars.entry(v).or_default().push(p.name.clone());
+            }
+        }
+        vars
+    }
+}
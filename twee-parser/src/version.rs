@@ -0,0 +1,68 @@
+use std::{cmp::Ordering, fmt::Display};
+
+/// A semantic version of a story format, like `2.36.1` or `4.0.0-beta.1`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct FormatVersion {
+    /// Incremented for incompatible changes.
+    pub major: u64,
+    /// Incremented for new features.
+    pub minor: u64,
+    /// Incremented for fixes.
+    pub patch: u64,
+    /// The pre-release part after a `-`, like `beta.1`.
+    pub pre: Option<String>,
+}
+
+impl FormatVersion {
+    /// Parses a `major.minor.patch` version with an optional `-pre-release` and ignored `+build` part. The patch version
+    /// can be left out, like in `3.3`, and defaults to 0.
+    pub fn parse(v: &str) -> Option<Self> {
+        let v = v.trim();
+        let v = v.split('+').next()?;
+        let (core, pre) = match v.split_once('-') {
+            Some((core, pre)) if ! pre.is_empty() => (core, Some(pre.to_string())),
+            Some(_) => return None,
+            None => (v, None),
+        };
+        let parts: Vec<u64> = core.split('.').map(|p| p.parse().ok()).collect::<Option<_>>()?;
+        match parts[..] {
+            [major, minor, patch] => Some(Self { major, minor, patch, pre }),
+            [major, minor] => Some(Self { major, minor, patch: 0, pre }),
+            _ => None,
+        }
+    }
+    
+    /// Whether a story made for the `required` version works with this version: the major versions are the same, and
+    /// this version is the same or newer. For major version 0, the minor versions have to be the same too.
+    pub fn is_compatible_with(&self, required: &FormatVersion) -> bool {
+        self.major == required.major && (self.major != 0 || self.minor == required.minor) && self >= required
+    }
+}
+
+impl PartialOrd for FormatVersion {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Pre-releases come before the release, and are compared as strings.
+impl Ord for FormatVersion {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.major, self.minor, self.patch).cmp(&(other.major, other.minor, other.patch)).then_with(|| match (&self.pre, &other.pre) {
+            (None, None) => Ordering::Equal,
+            (None, Some(_)) => Ordering::Greater,
+            (Some(_), None) => Ordering::Less,
+            (Some(a), Some(b)) => a.cmp(b),
+        })
+    }
+}
+
+impl Display for FormatVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)?;
+        if let Some(pre) = &self.pre {
+            write!(f, "-{}", pre)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,116 @@
+use std::{collections::BTreeMap, ops::Range, path::PathBuf};
+
+use serde_json::Map;
+use tracing::{info, warn};
+use twee_build::{build_story, load_config, read_source, source_blocks, write_output, BuildInputs, SourceBlock};
+use twee_parser::{passage_header, escape_content, unlinkable};
+
+use crate::{build::Error, tag::is_valid_tag};
+
+/// The file a new passage goes into: the file of the passage linking to it, else the file with the most passages
+/// sharing a tag with it, else the main twee file.
+fn target_file(blocks: &[SourceBlock], tags: &[String], from: Option<&SourceBlock>, main: PathBuf) -> PathBuf {
+    if let Some(from) = from {
+        return from.file.clone();
+    }
+    let mut counts: BTreeMap<&PathBuf, usize> = BTreeMap::new();
+    for b in blocks.iter().filter(|b| b.passage.tags.iter().any(|t| tags.contains(t))) {
+        *counts.entry(&b.file).or_default() += 1;
+    }
+    counts.into_iter().max_by_key(|(_, c)| *c).map(|(f, _)| f.clone()).unwrap_or(main)
+}
+
+/// Appends a new passage to the source of its file, and a link to it to the end of the passage `from`, which has to
+/// be in the same file. The link is left out with a warning if the name can't be linked.
+fn add_to_source(source: &str, name: &str, tags: &[String], content: &str, from: Option<&SourceBlock>) -> String {
+    let mut source = source.to_string();
+    // Edits as (range, replacement), applied from the end so the ranges stay valid.
+    let mut edits: Vec<(Range<usize>, String)> = vec![];
+    let end = source.trim_end().len();
+    edits.push((end..source.len(), format!("\n\n{}{}\n", passage_header(name, tags, &Map::new()), escape_content(content))));
+    if let Some(from) = from {
+        match unlinkable(name) {
+            Some(reason) => warn!(code = "add-unlinkable", "The name can't be linked because of {}, link \"{}\" from \"{}\" by hand", reason, name, from.passage.name),
+            None => edits.push((from.body.end..from.body.end, format!("\n[[{}]]", name))),
+        }
+    }
+    edits.sort_by_key(|(r, _)| std::cmp::Reverse(r.start));
+    for (r, replacement) in edits {
+        source.replace_range(r, &replacement);
+    }
+    source
+}
+
+/// Appends a new passage to the twee files of the project in the current directory, optionally with the content of a
+/// template and linked from the end of another passage.
+pub(crate) fn add(name: &str, tags: Vec<String>, template: Option<String>, from: Option<String>) -> anyhow::Result<()> {
+    if let Some(t) = tags.iter().find(|t| ! is_valid_tag(t)) {
+        return Err(Error::InvalidTag(t.clone()).into());
+    }
+    let config = load_config()?;
+    let story = build_story(&config, false)?;
+    if story.passage(name).is_some() {
+        return Err(Error::PassageExists(name.to_string()).into());
+    }
+    let content = match template {
+        Some(t) => config.add.templates.get(&t).ok_or(Error::TemplateNotFound(t.clone()))?.replace("{name}", name),
+        None => String::new(),
+    };
+    let blocks = source_blocks(&BuildInputs::collect().twee_files())?;
+    let from = match from {
+        Some(f) => Some(blocks.iter().find(|b| b.passage.name == f).ok_or(Error::PassageNotFound(f))?),
+        None => None,
+    };
+    // The passage linking to the new one is always in the same file.
+    let file = target_file(&blocks, &tags, from, PathBuf::from(&config.main).canonicalize()?);
+    let source = add_to_source(&read_source(&file)?, name, &tags, &content, from);
+    write_output(&file, source.as_bytes())?;
+    info!("Added passage \"{}\" to {}", name, file.to_string_lossy());
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    
+    use super::*;
+    
+    fn blocks(dir: &Path, files: &[(&str, &str)]) -> Vec<SourceBlock> {
+        let paths: Vec<PathBuf> = files.iter().map(|(name, source)| {
+            std::fs::write(dir.join(name), source).unwrap();
+            dir.join(name)
+        }).collect();
+        source_blocks(&paths).unwrap()
+    }
+    
+    #[test]
+    fn target_files() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let blocks = blocks(dir, &[
+            ("main.twee", ":: Start\n[[Forest]]\n"),
+            ("forest.twee", ":: Forest [outside]\nTrees\n\n:: Clearing [outside]\nGrass\n"),
+            ("town.twee", ":: Town [outside town]\nHouses\n"),
+        ]);
+        let main = dir.join("main.twee");
+        let tags = |t: &[&str]| t.iter().map(|t| t.to_string()).collect::<Vec<_>>();
+        assert_eq!(target_file(&blocks, &tags(&["outside"]), None, main.clone()), dir.join("forest.twee"));
+        assert_eq!(target_file(&blocks, &tags(&["town"]), None, main.clone()), dir.join("town.twee"));
+        assert_eq!(target_file(&blocks, &tags(&["inside"]), None, main.clone()), main);
+        // The file of the linking passage comes first.
+        assert_eq!(target_file(&blocks, &tags(&["outside"]), Some(&blocks[0]), main.clone()), main);
+    }
+    
+    #[test]
+    fn link_from_last_passage() {
+        let source = ":: Start\nHello\n\n:: Forest\nTrees\n\n";
+        let dir = tempfile::tempdir().unwrap();
+        let blocks = blocks(dir.path(), &[("main.twee", source)]);
+        let forest = blocks.iter().find(|b| b.passage.name == "Forest");
+        assert_eq!(add_to_source(source, "Clearing", &["outside".to_string()], "Grass", forest),
+            ":: Start\nHello\n\n:: Forest\nTrees\n[[Clearing]]\n\n:: Clearing [outside]\nGrass\n");
+        // Names that can't be linked are added without the link.
+        assert_eq!(add_to_source(source, "A -> B", &[], "", forest), ":: Start\nHello\n\n:: Forest\nTrees\n\n:: A -> B\n\n");
+    }
+}
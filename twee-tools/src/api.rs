@@ -0,0 +1,458 @@
+use std::{collections::BTreeMap, io::{BufRead, BufReader, Read, Write}, net::{TcpListener, TcpStream}, path::{Path, PathBuf}, sync::{Arc, Mutex}, time::Duration};
+
+use percent_encoding::percent_decode_str;
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use tracing::{debug, error, info, warn};
+use twee_build::{build_story, clear_build_inputs, load_config, read_source, source_blocks, write_output, BuildInputs};
+use twee_parser::{escape_content, passage_header, Passage, TextOp};
+
+use crate::{build::{BuildOptions, Error}, build_all, lint::{lint_story, severity}, list::passage_entries, on_changes, collab::Documents, search::display_path, tag::is_valid_tag, websocket::Feed};
+
+/// Request bodies larger than this are refused.
+const MAX_BODY: usize = 16 * 1024 * 1024;
+
+/// Held while reading or building the project, as requests and rebuilds in watch mode run on different threads but
+/// share the source cache and the current directory.
+static BUSY: Mutex<()> = Mutex::new(());
+
+/// How long a client may take to send its request or read the response.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+struct Request {
+    method: String,
+    path: String,
+    host: Option<String>,
+    origin: Option<String>,
+    content_type: Option<String>,
+    /// The `Sec-WebSocket-Key` of a request to upgrade to a WebSocket.
+    websocket_key: Option<String>,
+    body: Vec<u8>,
+}
+
+struct Response {
+    status: u16,
+    body: Value,
+}
+
+impl Response {
+    fn ok(body: Value) -> Response {
+        Response { status: 200, body }
+    }
+
+    fn error(status: u16, message: impl ToString) -> Response {
+        Response { status, body: json!({ "error": message.to_string() }) }
+    }
+}
+
+/// The changes of `PUT /passages/{name}`. Fields that are left out stay as they are.
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct PassageUpdate {
+    content: Option<String>,
+    tags: Option<Vec<String>>,
+    meta: Option<Map<String, Value>>,
+}
+
+fn read_request(stream: &mut TcpStream) -> anyhow::Result<Option<Request>> {
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(path)) = (parts.next(), parts.next()) else {
+        return Ok(None);
+    };
+    let (method, path) = (method.to_string(), path.to_string());
+    let mut length = 0;
+    let mut host = None;
+    let mut origin = None;
+    let mut content_type = None;
+    let mut websocket_key = None;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+        if let Some((k, v)) = header.split_once(':') {
+            if k.trim().eq_ignore_ascii_case("content-length") {
+                length = v.trim().parse()?;
+            } else if k.trim().eq_ignore_ascii_case("host") {
+                host = Some(v.trim().to_string());
+            } else if k.trim().eq_ignore_ascii_case("origin") {
+                origin = Some(v.trim().to_string());
+            } else if k.trim().eq_ignore_ascii_case("content-type") {
+                content_type = Some(v.trim().to_string());
+            } else if k.trim().eq_ignore_ascii_case("sec-websocket-key") {
+                websocket_key = Some(v.trim().to_string());
+            }
+        }
+    }
+    if length > MAX_BODY {
+        return Err(anyhow::anyhow!("Request body too large"));
+    }
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body)?;
+    Ok(Some(Request { method, path, host, origin, content_type, websocket_key, body }))
+}
+
+fn write_response(stream: &mut TcpStream, response: &Response, allow_origin: Option<&str>) -> std::io::Result<()> {
+    let reason = match response.status {
+        200 => "OK",
+        204 => "No Content",
+        400 => "Bad Request",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        415 => "Unsupported Media Type",
+        _ => "Internal Server Error",
+    };
+    let body = if response.status == 204 { String::new() } else { serde_json::to_string_pretty(&response.body).unwrap() };
+    let mut head = format!("HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n", response.status, reason, body.len());
+    if let Some(origin) = allow_origin {
+        head += &format!("Access-Control-Allow-Origin: {}\r\nAccess-Control-Allow-Methods: GET, PUT, POST\r\nAccess-Control-Allow-Headers: Content-Type\r\n", origin);
+    }
+    stream.write_all((head + "\r\n" + &body).as_bytes())?;
+    stream.flush()
+}
+
+/// A passage with its metadata, content, links and twee file.
+fn get_passage(name: &str) -> anyhow::Result<Response> {
+    let story = build_story(&load_config()?, false)?;
+    let Some(p) = story.passage(name) else {
+        return Ok(Response::error(404, Error::PassageNotFound(name.to_string())));
+    };
+    let file = source_blocks(&BuildInputs::collect().twee_files())?.into_iter().find(|b| b.passage.name == name).map(|b| display_path(&b.file));
+    Ok(Response::ok(json!({
+        "name": p.name,
+        "tags": p.tags,
+        "meta": p.meta,
+        "content": p.content,
+        "links": p.links().iter().map(|l| json!({ "text": l.text, "target": l.target })).collect::<Vec<_>>(),
+        "file": file,
+    })))
+}
+
+/// The passage as it is in its twee file, without changes by the build. None if the build generates it.
+fn source_passage(name: &str) -> anyhow::Result<Option<Passage>> {
+    Ok(source_blocks(&BuildInputs::collect().twee_files())?.into_iter().find(|b| b.passage.name == name).map(|b| b.passage))
+}
+
+/// Changes a passage in the twee file it is in.
+fn update_passage(name: &str, update: PassageUpdate) -> anyhow::Result<Response> {
+    if let Some(t) = update.tags.iter().flatten().find(|t| ! is_valid_tag(t)) {
+        return Ok(Response::error(400, Error::InvalidTag(t.clone())));
+    }
+    let story = build_story(&load_config()?, false)?;
+    if story.passage(name).is_none() {
+        return Ok(Response::error(404, Error::PassageNotFound(name.to_string())));
+    }
+    let blocks = source_blocks(&BuildInputs::collect().twee_files())?;
+    let Some(b) = blocks.iter().find(|b| b.passage.name == name) else {
+        return Ok(Response::error(409, format!("Passage \"{}\" is generated by the build and can't be changed", name)));
+    };
+    let mut source = read_source(&b.file)?;
+    let header = passage_header(name, update.tags.as_ref().unwrap_or(&b.passage.tags), update.meta.as_ref().unwrap_or(&b.passage.meta));
+    let content = match &update.content {
+        Some(content) => escape_content(content),
+        None => source[b.body.clone()].to_string(),
+    };
+    source.replace_range(b.header.start..b.body.end, &(header + &content));
+    write_output(&b.file, source.as_bytes())?;
+    info!("Changed passage \"{}\" in {}", name, display_path(&b.file));
+    get_passage(name)
+}
+
+/// The lint problems with their severity.
+fn lint() -> anyhow::Result<Value> {
+    let config = load_config()?;
+    let story = build_story(&config, false)?;
+    Ok(lint_story(&config, &story).iter().map(|l| json!({
+        "rule": l.rule,
+        "passage": l.passage,
+        "message": l.message,
+        "severity": severity(&config, l.rule),
+    })).collect())
+}
+
+/// Builds the story, or all stories of the workspace, and sends the result to the clients of the feed.
+fn build(feed: &Feed) -> anyhow::Result<Vec<PathBuf>> {
+    match build_all(&BuildOptions::default()) {
+        Ok(outputs) => {
+            feed.broadcast(&json!({ "type": "build", "ok": true, "outputs": outputs }));
+            Ok(outputs)
+        },
+        Err(e) => {
+            feed.broadcast(&json!({ "type": "build", "ok": false, "error": format!("{:#}", e) }));
+            Err(e)
+        },
+    }
+}
+
+/// The passages of the project in the current directory by name, or none for a workspace or a story that doesn't build.
+fn passages() -> BTreeMap<String, Passage> {
+    if ! Path::new("config.toml").exists() {
+        return BTreeMap::new();
+    }
+    load_config().and_then(|c| build_story(&c, false)).map(|s| s.passages.into_iter().map(|p| (p.name.clone(), p)).collect()).unwrap_or_default()
+}
+
+/// Rebuilds on changes to the sources and sends the passages that changed, the build result and the lint problems
+/// to the clients of the feed.
+fn watch_events(feed: &Feed) -> anyhow::Result<()> {
+    let (mut before, out) = {
+        let _busy = BUSY.lock().unwrap();
+        (passages(), build(feed).unwrap_or_default())
+    };
+    on_changes(out.clone(), || {
+        let _busy = BUSY.lock().unwrap();
+        clear_build_inputs();
+        let after = passages();
+        let added: Vec<&String> = after.keys().filter(|n| ! before.contains_key(*n)).collect();
+        let removed: Vec<&String> = before.keys().filter(|n| ! after.contains_key(*n)).collect();
+        let changed: Vec<&String> = after.iter().filter(|(n, p)| before.get(*n).is_some_and(|b| b != *p)).map(|(n, _)| n).collect();
+        if ! (added.is_empty() && removed.is_empty() && changed.is_empty()) {
+            feed.broadcast(&json!({ "type": "passages", "added": added, "changed": changed, "removed": removed }));
+        }
+        before = after;
+        let out = match build(feed) {
+            Ok(out) => out,
+            Err(e) => {
+                error!("Build failed: {:#}", e);
+                out.clone()
+            },
+        };
+        if Path::new("config.toml").exists() {
+            match lint() {
+                Ok(problems) => feed.broadcast(&json!({ "type": "lint", "problems": problems })),
+                Err(e) => debug!("Could not lint: {:#}", e),
+            }
+        }
+        out
+    })
+}
+
+/// The passages as nodes and the links and navigation macros between them as edges. Edges with a dynamic target have
+/// the expression as `to`.
+fn graph() -> anyhow::Result<Response> {
+    let story = build_story(&load_config()?, false)?;
+    let edges: Vec<Value> = story.passages.iter().flat_map(|p| story.navigation(p).into_iter().map(|n| json!({
+        "from": p.name,
+        "to": n.target,
+        "text": n.text,
+        "via": n.via,
+        "dynamic": n.dynamic,
+        "embed": n.embed,
+        "missing": ! n.dynamic && story.passage(&n.target).is_none(),
+    }))).collect();
+    Ok(Response::ok(json!({
+        "start": story.start_passage().map(|p| p.name.clone()),
+        "nodes": story.passages.iter().map(|p| json!({ "name": p.name, "tags": p.tags })).collect::<Vec<_>>(),
+        "edges": edges,
+    })))
+}
+
+/// Joins editing a passage together, or applies the operations of a client to it, see [Documents].
+fn passage_ops(name: &str, method: &str, body: &[u8], feed: &Feed, documents: &Documents) -> anyhow::Result<Response> {
+    let ops: Vec<TextOp> = match method {
+        "GET" => vec![],
+        "POST" => match serde_json::from_slice(body) {
+            Ok(ops) => ops,
+            Err(e) => return Ok(Response::error(400, e)),
+        },
+        _ => return Ok(Response::error(405, "Use GET or POST")),
+    };
+    build_story(&load_config()?, false)?;
+    let Some(p) = source_passage(name)? else {
+        return Ok(Response::error(404, Error::PassageNotFound(name.to_string())));
+    };
+    if method == "GET" {
+        let (replica, ops, outside) = documents.join(name, &p.content);
+        if ! outside.is_empty() {
+            feed.broadcast(&json!({ "type": "ops", "passage": name, "ops": outside }));
+        }
+        return Ok(Response::ok(json!({ "replica": replica, "ops": ops })));
+    }
+    let (content, outside) = documents.apply(name, &p.content, &ops);
+    feed.broadcast(&json!({ "type": "ops", "passage": name, "ops": ([outside, ops].concat()) }));
+    if content != p.content {
+        update_passage(name, PassageUpdate { content: Some(content.clone()), ..Default::default() })?;
+    }
+    Ok(Response::ok(json!({ "content": content })))
+}
+
+/// Refuses requests for another host, so web pages can't reach the API by making their domain resolve to 127.0.0.1,
+/// and changes that aren't sent as JSON, which web pages can't send to another origin without asking first.
+fn refuse(request: &Request, port: u16) -> Option<Response> {
+    if ! request.host.as_deref().is_some_and(|h| h == format!("127.0.0.1:{}", port) || h == format!("localhost:{}", port)) {
+        return Some(Response::error(403, format!("Use the API at 127.0.0.1:{} or localhost:{}", port, port)));
+    }
+    let json = request.content_type.as_deref().is_some_and(|t| t.split(';').next().unwrap_or_default().trim().eq_ignore_ascii_case("application/json"));
+    if ["PUT", "POST"].contains(&request.method.as_str()) && ! json {
+        return Some(Response::error(415, "Send changes with Content-Type: application/json"));
+    }
+    None
+}
+
+/// Whether a web page from the origin may connect to the WebSocket: pages on localhost and the allowed origin. Requests
+/// without an origin don't come from web pages.
+fn allowed_origin(origin: Option<&str>, allow_origin: Option<&str>) -> bool {
+    let Some(origin) = origin else {
+        return true;
+    };
+    let host = origin.strip_prefix("http://").or(origin.strip_prefix("https://")).unwrap_or_default();
+    let host = host.rsplit_once(':').filter(|(_, port)| port.parse::<u16>().is_ok()).map(|(host, _)| host).unwrap_or(host);
+    ["localhost", "127.0.0.1", "[::1]"].contains(&host) || allow_origin == Some(origin)
+}
+
+fn route(request: &Request, feed: &Feed, documents: &Documents) -> anyhow::Result<Response> {
+    // The files of the story may have changed since the last request, so only the inputs of this request's builds
+    // are used to find the passages in the sources.
+    clear_build_inputs();
+    let path = request.path.split('?').next().unwrap_or_default();
+    let method = request.method.as_str();
+    if let Some(name) = path.strip_prefix("/passages/").and_then(|p| p.strip_suffix("/ops")) {
+        let name = percent_decode_str(name).decode_utf8()?;
+        return passage_ops(&name, method, &request.body, feed, documents);
+    }
+    if let Some(name) = path.strip_prefix("/passages/") {
+        let name = percent_decode_str(name).decode_utf8()?;
+        return match method {
+            "GET" => get_passage(&name),
+            "PUT" => {
+                let update = match serde_json::from_slice(&request.body) {
+                    Ok(update) => update,
+                    Err(e) => return Ok(Response::error(400, e)),
+                };
+                let response = update_passage(&name, update)?;
+                if response.status == 200 {
+                    feed.broadcast(&json!({ "type": "passages", "added": [], "changed": [name], "removed": [] }));
+                }
+                Ok(response)
+            },
+            _ => Ok(Response::error(405, "Use GET or PUT")),
+        };
+    }
+    match (method, path) {
+        ("GET", "/passages") => Ok(Response::ok(serde_json::to_value(passage_entries()?)?)),
+        ("POST", "/build") => Ok(Response::ok(json!({ "outputs": build(feed)? }))),
+        ("GET", "/lint") => Ok(Response::ok(lint()?)),
+        ("GET", "/graph") => graph(),
+        ("GET", "/events") => Ok(Response::error(400, "Connect to /events with a WebSocket")),
+        (_, "/passages" | "/build" | "/lint" | "/graph" | "/events") => Ok(Response::error(405, format!("{} isn't supported for {}", method, path))),
+        _ => Ok(Response::error(404, format!("Unknown endpoint {}", path))),
+    }
+}
+
+/// Serves a JSON API for the project in the current directory on localhost, answering one request at a time.
+/// `allow_origin` is sent as `Access-Control-Allow-Origin`, so web frontends on that origin can use the API.
+/// Clients of the WebSocket at `/events` get changes made through the API, and with `watch`, the changes to the
+/// sources with the build results and lint problems.
+pub(crate) fn api(port: u16, allow_origin: Option<String>, watch: bool) -> anyhow::Result<()> {
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    let port = listener.local_addr()?.port();
+    info!("Serving the API at http://127.0.0.1:{}", port);
+    let feed = Arc::new(Feed::default());
+    let documents = Documents::default();
+    if watch {
+        let feed = feed.clone();
+        std::thread::spawn(move || {
+            if let Err(e) = watch_events(&feed) {
+                error!("Watching stopped: {:#}", e);
+            }
+        });
+    }
+    for stream in listener.incoming() {
+        let mut stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                debug!("Could not accept connection: {}", e);
+                continue;
+            },
+        };
+        // Requests are answered one at a time, so a client that stops sending or reading must not block the others.
+        if let Err(e) = stream.set_read_timeout(Some(TIMEOUT)).and(stream.set_write_timeout(Some(TIMEOUT))) {
+            debug!("Could not set the timeouts of the connection: {}", e);
+            continue;
+        }
+        let response = match read_request(&mut stream) {
+            Ok(None) => continue,
+            Ok(Some(request)) if request.method == "OPTIONS" => Response { status: 204, body: Value::Null },
+            Ok(Some(request)) => match (refuse(&request, port), &request.websocket_key) {
+                (Some(refused), _) => refused,
+                (None, Some(_)) if request.path == "/events" && ! allowed_origin(request.origin.as_deref(), allow_origin.as_deref()) => {
+                    Response::error(403, format!("Web pages from {} can't connect to /events", request.origin.as_deref().unwrap_or_default()))
+                },
+                (None, Some(key)) if request.path == "/events" => {
+                    if let Err(e) = feed.accept(stream, key) {
+                        debug!("Could not accept event client: {}", e);
+                    }
+                    continue;
+                },
+                (None, _) => {
+                    debug!("{} {}", request.method, request.path);
+                    let _busy = BUSY.lock().unwrap();
+                    route(&request, &feed, &documents).unwrap_or_else(|e| {
+                        warn!(code = "api-error", "{} {}: {:#}", request.method, request.path, e);
+                        Response::error(500, format!("{:#}", e))
+                    })
+                },
+            },
+            Err(e) => Response::error(400, e),
+        };
+        if let Err(e) = write_response(&mut stream, &response, allow_origin.as_deref()) {
+            debug!("Could not send response: {}", e);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn request(method: &str, host: &str, content_type: Option<&str>) -> Request {
+        Request {
+            method: method.to_string(),
+            path: "/build".to_string(),
+            host: Some(host.to_string()),
+            origin: None,
+            content_type: content_type.map(str::to_string),
+            websocket_key: None,
+            body: vec![],
+        }
+    }
+    
+    #[test]
+    fn refused_hosts() {
+        assert!(refuse(&request("GET", "127.0.0.1:8080", None), 8080).is_none());
+        assert!(refuse(&request("GET", "localhost:8080", None), 8080).is_none());
+        for host in ["evil.example:8080", "127.0.0.1:8081", "localhost", "127.0.0.1.evil.example:8080"] {
+            assert_eq!(refuse(&request("GET", host, None), 8080).map(|r| r.status), Some(403));
+        }
+        assert_eq!(refuse(&Request { host: None, ..request("GET", "", None) }, 8080).map(|r| r.status), Some(403));
+    }
+    
+    #[test]
+    fn changes_need_json() {
+        assert!(refuse(&request("POST", "localhost:8080", Some("application/json")), 8080).is_none());
+        assert!(refuse(&request("PUT", "localhost:8080", Some("Application/JSON; charset=utf-8")), 8080).is_none());
+        for content_type in [None, Some("text/plain"), Some("application/x-www-form-urlencoded"), Some("multipart/form-data")] {
+            assert_eq!(refuse(&request("POST", "localhost:8080", content_type), 8080).map(|r| r.status), Some(415));
+        }
+    }
+    
+    #[test]
+    fn event_origins() {
+        assert!(allowed_origin(None, None));
+        for origin in ["http://localhost:3000", "http://127.0.0.1:8080", "https://localhost", "http://[::1]:8080"] {
+            assert!(allowed_origin(Some(origin), None), "{}", origin);
+        }
+        for origin in ["https://evil.example", "http://localhost.evil.example", "http://127.0.0.1.evil.example:80", "null"] {
+            assert!(! allowed_origin(Some(origin), None), "{}", origin);
+        }
+        assert!(allowed_origin(Some("https://editor.example"), Some("https://editor.example")));
+    }
+}
@@ -0,0 +1,31 @@
+use std::{ffi::OsStr, path::Path, process::{Command, Stdio}};
+
+use crate::build::Error;
+
+/// Opens the file in the default browser.
+pub(crate) fn open_in_browser(path: &Path) -> anyhow::Result<()> {
+    open_url(path.canonicalize()?.as_os_str())
+}
+
+/// Opens the URL, or a file, in the default browser.
+pub(crate) fn open_url(url: &OsStr) -> anyhow::Result<()> {
+    let mut c = if cfg!(target_os = "windows") {
+        let mut c = Command::new("cmd");
+        c.args(["/C", "start", ""]).arg(url);
+        c
+    } else if cfg!(target_os = "macos") {
+        let mut c = Command::new("open");
+        c.arg(url);
+        c
+    } else {
+        let mut c = Command::new("xdg-open");
+        c.arg(url);
+        c
+    };
+    let status = c.stdin(Stdio::null()).stdout(Stdio::null()).stderr(Stdio::null()).status()
+        .map_err(|e| Error::CommandFailed(format!("Could not open browser: {}", e)))?;
+    if ! status.success() {
+        return Err(Error::CommandFailed(format!("Could not open browser for {}", url.to_string_lossy())).into());
+    }
+    Ok(())
+}
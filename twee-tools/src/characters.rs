@@ -0,0 +1,100 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use regex::Regex;
+use serde::Serialize;
+use tracing::warn;
+use twee_build::{build_story, load_config, Config};
+use twee_parser::Story;
+
+use crate::{dialogue::dialogue_lines, spellcheck::prose, stats::is_prose};
+
+/// The JSON output of `characters`.
+#[derive(Serialize)]
+pub(crate) struct CharacterReport {
+    /// The passages each character appears in.
+    pub passages: BTreeMap<String, Vec<String>>,
+    /// The number of passages each pair of characters appears in together.
+    pub cooccurrence: BTreeMap<String, BTreeMap<String, usize>>,
+}
+
+/// Finds the passages each character speaks in or is mentioned in, by name or alias.
+pub(crate) fn character_report(story: &Story, config: &Config) -> anyhow::Result<CharacterReport> {
+    let mut appearances: BTreeMap<String, BTreeSet<&str>> = config.characters.names.iter().map(|n| (n.clone(), BTreeSet::new())).collect();
+    for l in dialogue_lines(story, &config.dialogue)? {
+        if let Some(p) = story.passage(&l.passage) {
+            appearances.entry(l.speaker).or_default().insert(&p.name);
+        }
+    }
+    let format = story.meta.get("format").and_then(|f| f.as_str()).unwrap_or("");
+    let names: Vec<String> = appearances.keys().cloned().collect();
+    for name in names {
+        let mentions = std::iter::once(&name).chain(config.characters.aliases.get(&name).into_iter().flatten())
+            .map(|n| regex::escape(n)).collect::<Vec<_>>().join("|");
+        let mentions = Regex::new(&format!(r"\b(?:{})\b", mentions)).unwrap();
+        let found = appearances.get_mut(&name).unwrap();
+        for p in story.passages.iter().filter(|p| is_prose(p)) {
+            if mentions.is_match(&prose(p, format)) {
+                found.insert(&p.name);
+            }
+        }
+    }
+    let mut cooccurrence: BTreeMap<String, BTreeMap<String, usize>> = BTreeMap::new();
+    for (a, pa) in &appearances {
+        for (b, pb) in &appearances {
+            if a != b {
+                cooccurrence.entry(a.clone()).or_default().insert(b.clone(), pa.intersection(pb).count());
+            }
+        }
+    }
+    let passages = appearances.into_iter().map(|(c, p)| {
+        // In story order.
+        let p = story.passages.iter().filter(|s| p.contains(s.name.as_str())).map(|s| s.name.clone()).collect();
+        (c, p)
+    }).collect();
+    Ok(CharacterReport { passages, cooccurrence })
+}
+
+/// Prints the passages each character of the story in the current directory appears in, and how often the
+/// characters appear together.
+pub(crate) fn characters(json: bool) -> anyhow::Result<()> {
+    let config = load_config()?;
+    let story = build_story(&config, false)?;
+    let report = character_report(&story, &config)?;
+    if report.passages.is_empty() {
+        warn!(code = "characters-none", "Found no characters, list them in the [characters] section of the config.toml");
+    }
+    for (c, passages) in &report.passages {
+        if passages.is_empty() {
+            warn!(code = "character-unused", "Character \"{}\" doesn't appear in any passage", c);
+        }
+    }
+    if json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        return Ok(());
+    }
+    println!("{:<20} {:>8}  Passages", "Character", "Count");
+    for (c, passages) in &report.passages {
+        println!("{:<20} {:>8}  {}", c, passages.len(), passages.join(", "));
+    }
+    if report.passages.len() > 1 {
+        println!();
+        let width = report.passages.keys().map(|c| c.chars().count()).max().unwrap_or(0).max(3);
+        print!("{:<width$}", "", width = width);
+        for c in report.passages.keys() {
+            print!(" {:>width$}", c, width = width);
+        }
+        println!();
+        for a in report.passages.keys() {
+            print!("{:<width$}", a, width = width);
+            for b in report.passages.keys() {
+                let count = report.cooccurrence.get(a).and_then(|m| m.get(b));
+                match count {
+                    Some(count) => print!(" {:>width$}", count, width = width),
+                    None => print!(" {:>width$}", "-", width = width),
+                }
+            }
+            println!();
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,98 @@
+use std::{io::Read, path::Path, process::Command, sync::atomic::Ordering};
+
+use tracing::{error, info};
+use twee_build::{build_story, load_config_in, print_warning, WARNING_COUNT};
+use twee_parser::{parse_twee3, Warning};
+
+use crate::build::Error;
+
+/// Parses and validates the story in the project directory, with its includes, without writing anything. Returns the
+/// number of problems, where a story that can't be built is one.
+pub(crate) fn check_project(dir: &Path) -> usize {
+    let before = WARNING_COUNT.load(Ordering::Relaxed);
+    let failed = match load_config_in(dir).and_then(|config| build_story(&config, false)) {
+        Ok(_) => 0,
+        Err(e) => {
+            error!("{:#}", e);
+            1
+        },
+    };
+    WARNING_COUNT.load(Ordering::Relaxed) - before + failed
+}
+
+/// Parses and validates a single twee file. Files without a StoryData passage are treated as fragments of a story, so
+/// a missing title or start passage isn't a problem. Returns the number of problems.
+fn check_source(name: &str, source: &str) -> usize {
+    let (story, warnings) = match parse_twee3(source) {
+        Ok(r) => r,
+        Err(e) => {
+            error!("{}: {}", name, e);
+            return 1;
+        },
+    };
+    let fragment = story.meta.is_empty();
+    let problems: Vec<Warning> = warnings.into_iter().chain(story.validate())
+        .filter(|w| ! (fragment && matches!(w, Warning::StoryTitleMissing | Warning::StartPassageMissing(_))))
+        .collect();
+    if ! problems.is_empty() {
+        info!("In {}:", name);
+    }
+    let count = problems.len();
+    for w in problems {
+        print_warning(w);
+    }
+    count
+}
+
+/// The twee files staged in Git, with their staged contents.
+fn staged_files() -> anyhow::Result<Vec<(String, String)>> {
+    let git = |args: &[&str]| -> anyhow::Result<String> {
+        let out = Command::new("git").args(args).output()?;
+        if ! out.status.success() {
+            return Err(Error::CommandFailed(format!("git {}: {}", args.join(" "), String::from_utf8_lossy(&out.stderr).trim())).into());
+        }
+        Ok(String::from_utf8(out.stdout)?)
+    };
+    let names = git(&["diff", "--cached", "--name-only", "--diff-filter=ACMR", "-z"])?;
+    names.split('\0').filter(|n| n.ends_with(".twee") || n.ends_with(".tw"))
+        .map(|n| Ok((n.to_string(), git(&["show", &format!(":{}", n)])?)))
+        .collect()
+}
+
+/// Parses and validates twee files on their own: the given paths, where `-` reads from stdin, or with `staged` the
+/// versions of the twee files staged in Git. Returns the number of problems.
+pub(crate) fn check_files(paths: &[String], staged: bool) -> anyhow::Result<usize> {
+    let mut files = vec![];
+    if staged {
+        files.extend(staged_files()?);
+    }
+    for p in paths {
+        if p == "-" {
+            let mut source = String::new();
+            std::io::stdin().read_to_string(&mut source)?;
+            files.push(("stdin".to_string(), source));
+        } else {
+            files.push((p.clone(), std::fs::read_to_string(p).map_err(|_| Error::FileNotFound(p.clone()))?));
+        }
+    }
+    Ok(files.iter().map(|(name, source)| check_source(name, source)).sum())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn fragments() {
+        assert_eq!(check_source("fragment.twee", ":: Room\nA room. [[Hall]]\n\n:: Hall\nA hall.\n"), 0);
+        // A story needs a title and a start passage.
+        assert_eq!(check_source("story.twee", ":: StoryData\n{\"format\": \"Harlowe\"}\n\n:: Room\nA room.\n"), 2);
+    }
+    
+    #[test]
+    fn invalid_files() {
+        assert_eq!(check_source("broken.twee", ":: Room {\"position\": \n"), 1);
+        assert_eq!(check_source("data.twee", ":: StoryTitle\nStory\n\n:: StoryData\n{\"format\": \"Harlowe\", \"format-version\": \"9.0.0\", \"start\": \"Room\"}\n\n:: Room\nA room.\n"), 1);
+    }
+}
@@ -0,0 +1,34 @@
+use std::path::Path;
+
+use tracing::info;
+use twee_build::{build_story, load_config, write_output};
+use twee_parser::Story;
+
+use crate::{csv::csv_row, spellcheck::prose, stats::{is_prose, words}};
+
+/// The choices of the story as CSV, with a row for each link and for each passage without links.
+pub(crate) fn choices_csv(story: &Story) -> String {
+    let format = story.meta.get("format").and_then(|f| f.as_str()).unwrap_or("");
+    let mut csv = csv_row(&["passage", "choice", "target", "tags", "words"]);
+    for p in story.passages.iter().filter(|p| is_prose(p)) {
+        let tags = p.tags.join(" ");
+        let words = words(&prose(p, format)).len().to_string();
+        let links = p.links();
+        if links.is_empty() {
+            csv += &csv_row(&[p.name.as_str(), "", "", &tags, &words]);
+        }
+        for l in links {
+            csv += &csv_row(&[p.name.as_str(), &l.text, &l.target, &tags, &words]);
+        }
+    }
+    csv
+}
+
+/// Writes the choices of the story in the current directory into a CSV file for reviewing them in a spreadsheet.
+pub(crate) fn export_choices(out: &Path) -> anyhow::Result<()> {
+    let config = load_config()?;
+    let story = build_story(&config, false)?;
+    write_output(out, choices_csv(&story).as_bytes())?;
+    info!("Wrote the choices to {}", out.to_string_lossy());
+    Ok(())
+}
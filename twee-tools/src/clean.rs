@@ -0,0 +1,39 @@
+use std::path::{Path, PathBuf};
+
+use tracing::warn;
+use twee_build::{build_story, cache_dir, load_config_in, output_path, remove_output, vendor_dir};
+
+/// Removes everything twee-tools generated for the project in the directory: the built HTML, package zip, exported
+/// desktop project, caches and vendored formats.  
+pub(crate) fn clean(dir: &Path) -> anyhow::Result<()> {
+    let config = load_config_in(dir)?;
+    let mut paths = vec![];
+    match build_story(&config, false) {
+        Ok(story) => {
+            let html = output_path(&config, &story);
+            paths.push(config.package.output.as_ref().map(PathBuf::from).unwrap_or(html.with_extension("zip")));
+            paths.push(crate::reload::reload_path(&html));
+            paths.push(html);
+        },
+        Err(e) => {
+            warn!(code = "output-unknown", "Could not determine the output file: {}", e);
+            if let Some(out) = &config.output {
+                paths.push(crate::reload::reload_path(&PathBuf::from(out)));
+                paths.push(PathBuf::from(out));
+            }
+        }
+    }
+    let desktop = config.desktop.output.as_ref().map(PathBuf::from).unwrap_or(config.dir.join("desktop"));
+    if desktop.join("src-tauri").join("tauri.conf.json").exists() {
+        paths.push(desktop);
+    }
+    paths.push(cache_dir(&config));
+    paths.push(vendor_dir(&config));
+    for p in paths {
+        if ! p.exists() {
+            continue;
+        }
+        remove_output(&p)?;
+    }
+    Ok(())
+}
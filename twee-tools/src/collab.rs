@@ -0,0 +1,45 @@
+use std::{collections::BTreeMap, sync::{atomic::{AtomicU64, Ordering}, Mutex}};
+
+use twee_parser::{CrdtText, TextOp};
+
+/// The passages edited together through the API, as texts that merge concurrent edits. The server is replica 0 and
+/// makes the changes to the twee files from outside the API, each client that joins gets its own replica.
+/// The documents only live as long as the server, clients have to join again after a restart.
+#[derive(Default)]
+pub(crate) struct Documents {
+    texts: Mutex<BTreeMap<String, CrdtText>>,
+    replicas: AtomicU64,
+}
+
+/// Brings the document of a passage up to date with its content in the twee file, returning the operations for the
+/// changes made outside the API.
+fn sync(texts: &mut BTreeMap<String, CrdtText>, name: &str, content: &str) -> Vec<TextOp> {
+    let text = texts.entry(name.to_string()).or_insert(CrdtText::new(0));
+    if text.text() == content {
+        return vec![];
+    }
+    text.set_text(content)
+}
+
+impl Documents {
+    /// A new replica for a client that starts editing a passage, with the operations that recreate the passage.
+    /// Also returns the operations for the changes made outside the API, for the other clients.
+    pub(crate) fn join(&self, name: &str, content: &str) -> (u64, Vec<TextOp>, Vec<TextOp>) {
+        let mut texts = self.texts.lock().unwrap();
+        let outside = sync(&mut texts, name, content);
+        let replica = self.replicas.fetch_add(1, Ordering::Relaxed) + 1;
+        (replica, texts[name].ops(), outside)
+    }
+
+    /// Applies the operations of a client to a passage and returns the new content, and the operations for the
+    /// changes made outside the API.
+    pub(crate) fn apply(&self, name: &str, content: &str, ops: &[TextOp]) -> (String, Vec<TextOp>) {
+        let mut texts = self.texts.lock().unwrap();
+        let outside = sync(&mut texts, name, content);
+        let text = texts.get_mut(name).unwrap();
+        for op in ops {
+            text.apply(op);
+        }
+        (text.text(), outside)
+    }
+}
@@ -0,0 +1,94 @@
+use std::path::Path;
+
+use tracing::{error, info, warn};
+use twee_build::{build_story, load_config, print_warning, read_file};
+use twee_parser::{is_special_passage, parse_twee3, Story};
+
+use crate::{build::Error, import::read_stories};
+
+/// A change between two versions of a story that affects saves of the old version.
+pub(crate) struct CompatIssue {
+    pub code: &'static str,
+    /// Whether the change likely breaks loading or playing old saves.
+    pub breaking: bool,
+    pub message: String,
+}
+
+/// Compares the passages and story variables of two versions of a story.  
+/// Saves store the names of the visited passages and the values of the story variables, so removed or renamed
+/// passages and variables the new version uses without the old one setting them break old saves. StoryInit isn't
+/// run again when loading a save.
+pub(crate) fn compat_issues(old: &Story, new: &Story) -> Vec<CompatIssue> {
+    let mut issues = vec![];
+    let added: Vec<_> = new.passages.iter().filter(|p| ! is_special_passage(p) && old.passage(&p.name).is_none()).collect();
+    for p in old.passages.iter().filter(|p| ! is_special_passage(p) && new.passage(&p.name).is_none()) {
+        let renamed = added.iter().find(|a| ! p.content.trim().is_empty() && a.content.trim() == p.content.trim());
+        issues.push(match renamed {
+            Some(r) => CompatIssue {
+                code: "compat-passage-renamed",
+                breaking: true,
+                message: format!("Passage \"{}\" was renamed to \"{}\", saves in or through it can't be restored", p.name, r.name),
+            },
+            None => CompatIssue {
+                code: "compat-passage-removed",
+                breaking: true,
+                message: format!("Passage \"{}\" was removed, saves in or through it can't be restored", p.name),
+            },
+        });
+    }
+    let (old_vars, new_vars) = (old.variables(), new.variables());
+    for v in old_vars.keys().filter(|v| ! new_vars.contains_key(*v)) {
+        issues.push(CompatIssue {
+            code: "compat-variable-removed",
+            breaking: false,
+            message: format!("${} isn't used anymore, old saves still have it", v),
+        });
+    }
+    for (v, passages) in new_vars.iter().filter(|(v, _)| ! old_vars.contains_key(*v)) {
+        issues.push(CompatIssue {
+            code: "compat-variable-added",
+            breaking: true,
+            message: format!("${} is new and isn't set in old saves, but is used in {}", v,
+                passages.iter().map(|p| format!("\"{}\"", p)).collect::<Vec<_>>().join(", ")),
+        });
+    }
+    issues
+}
+
+/// Reads a story from an HTML file, an archive or a twee file.
+fn read_story(p: &Path) -> anyhow::Result<Story> {
+    if ! p.is_file() {
+        return Err(Error::FileNotFound(p.to_string_lossy().to_string()).into());
+    }
+    if p.extension().is_some_and(|e| e == "twee" || e == "tw") {
+        let (story, warnings) = parse_twee3(&read_file(p)?)?;
+        for w in warnings {
+            print_warning(w);
+        }
+        return Ok(story);
+    }
+    read_stories(p)?.into_iter().next().ok_or(Error::FileNotFound(p.to_string_lossy().to_string()).into())
+}
+
+/// Reports the changes from the old to the new version that affect saves, returning the number of breaking ones.
+/// Without a new version, the story in the current directory is built.
+pub(crate) fn compat(old: &Path, new: Option<&Path>) -> anyhow::Result<usize> {
+    let old = read_story(old)?;
+    let new = match new {
+        Some(new) => read_story(new)?,
+        None => build_story(&load_config()?, false)?,
+    };
+    let issues = compat_issues(&old, &new);
+    for i in &issues {
+        if i.breaking {
+            error!(code = i.code, "{}", i.message);
+        } else {
+            warn!(code = i.code, "{}", i.message);
+        }
+    }
+    let breaking = issues.iter().filter(|i| i.breaking).count();
+    if breaking == 0 {
+        info!("No changes found that break old saves");
+    }
+    Ok(breaking)
+}
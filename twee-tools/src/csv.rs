@@ -0,0 +1,12 @@
+/// Formats a CSV row, quoting fields with commas, quotes or line breaks.
+pub(crate) fn csv_row<S: AsRef<str>>(fields: &[S]) -> String {
+    let fields: Vec<String> = fields.iter().map(|f| {
+        let f = f.as_ref();
+        if f.contains([',', '"', '\n', '\r']) {
+            format!("\"{}\"", f.replace('"', "\"\""))
+        } else {
+            f.to_string()
+        }
+    }).collect();
+    fields.join(",") + "\r\n"
+}
@@ -0,0 +1,105 @@
+use std::path::{Path, PathBuf};
+
+use clap::ValueEnum;
+use regex::Regex;
+use tracing::{info, warn};
+use twee_build::{build_story, load_config, write_output, DialogueConfig};
+use twee_parser::Story;
+
+use crate::{build::Error, csv::csv_row, spellcheck::prose, stats::is_prose};
+
+/// The default convention for dialogue: lines starting with the name of the speaker and a colon.
+const DEFAULT_PATTERN: &str = r"^\s*(?P<speaker>[A-Z][\w .'-]{0,30}):\s+(?P<line>\S.*)$";
+
+/// The file format of the dialogue script.
+#[derive(Debug, Clone, Copy, Default, ValueEnum)]
+pub(crate) enum DialogueFormat {
+    /// A row per line, grouped by character.
+    #[default]
+    Csv,
+    /// A Fountain screenplay with a section per character.
+    Fountain,
+}
+
+/// A line of dialogue.
+pub(crate) struct DialogueLine {
+    pub speaker: String,
+    pub passage: String,
+    /// The line number in the passage, starting at 1.
+    pub line: usize,
+    pub text: String,
+}
+
+/// Collects the lines of dialogue of the story, grouped by speaker and in story order for each speaker.
+pub(crate) fn dialogue_lines(story: &Story, config: &DialogueConfig) -> anyhow::Result<Vec<DialogueLine>> {
+    let pattern = Regex::new(config.pattern.as_deref().unwrap_or(DEFAULT_PATTERN))
+        .map_err(|e| Error::InvalidConfig(format!("dialogue.pattern: {}", e)))?;
+    for group in ["speaker", "line"] {
+        if ! pattern.capture_names().any(|n| n == Some(group)) {
+            return Err(Error::InvalidConfig(format!("dialogue.pattern has no group named \"{}\"", group)).into());
+        }
+    }
+    let whitespace = Regex::new(r"\s+").unwrap();
+    let format = story.meta.get("format").and_then(|f| f.as_str()).unwrap_or("");
+    let mut lines = vec![];
+    for p in story.passages.iter().filter(|p| is_prose(p)) {
+        let tag_speaker = config.tag_prefix.as_deref().and_then(|prefix| p.tags.iter().find_map(|t| t.strip_prefix(prefix)))
+            .filter(|s| ! s.is_empty());
+        for (i, text) in prose(p, format).lines().enumerate() {
+            let (speaker, text) = match (pattern.captures(text), tag_speaker) {
+                (Some(c), _) => (c["speaker"].trim().to_string(), c["line"].to_string()),
+                (None, Some(speaker)) => (speaker.to_string(), text.to_string()),
+                (None, None) => continue,
+            };
+            let text = whitespace.replace_all(text.trim(), " ").to_string();
+            if ! text.is_empty() {
+                lines.push(DialogueLine { speaker, passage: p.name.clone(), line: i + 1, text });
+            }
+        }
+    }
+    lines.sort_by(|a, b| a.speaker.cmp(&b.speaker));
+    Ok(lines)
+}
+
+/// The dialogue as CSV, with a row for each line.
+pub(crate) fn dialogue_csv(lines: &[DialogueLine]) -> String {
+    let mut csv = csv_row(&["character", "passage", "line", "text"]);
+    for l in lines {
+        csv += &csv_row(&[l.speaker.as_str(), &l.passage, &l.line.to_string(), &l.text]);
+    }
+    csv
+}
+
+/// The dialogue as a Fountain screenplay, with a section for each character and the passage of each line as a
+/// synopsis.
+pub(crate) fn dialogue_fountain(title: &str, lines: &[DialogueLine]) -> String {
+    let mut fountain = format!("Title: {}\nCredit: Dialogue script\n\n", title);
+    let mut speaker = None;
+    for l in lines {
+        if speaker != Some(&l.speaker) {
+            fountain += &format!("# {}\n\n", l.speaker);
+            speaker = Some(&l.speaker);
+        }
+        // The @ forces a character, even if the name isn't all uppercase letters.
+        fountain += &format!("= {}, line {}\n@{}\n{}\n\n", l.passage, l.line, l.speaker.to_uppercase(), l.text);
+    }
+    fountain
+}
+
+/// Writes the dialogue of the story in the current directory into a script for voice-over recording or reviewing
+/// the dialogue.
+pub(crate) fn export_dialogue(out: Option<PathBuf>, format: DialogueFormat) -> anyhow::Result<()> {
+    let config = load_config()?;
+    let story = build_story(&config, false)?;
+    let lines = dialogue_lines(&story, &config.dialogue)?;
+    if lines.is_empty() {
+        warn!(code = "dialogue-none", "Found no lines of dialogue, check the pattern and tag prefix in the [dialogue] section of the config.toml");
+    }
+    let (out, script) = match format {
+        DialogueFormat::Csv => (out.unwrap_or("dialogue.csv".into()), dialogue_csv(&lines)),
+        DialogueFormat::Fountain => (out.unwrap_or("dialogue.fountain".into()), dialogue_fountain(&story.title, &lines)),
+    };
+    write_output(&out, script.as_bytes())?;
+    info!("Wrote {} line(s) of dialogue to {}", lines.len(), Path::new(&out).to_string_lossy());
+    Ok(())
+}
@@ -0,0 +1,240 @@
+use std::ops::Range;
+
+/// Lines of context around changes.
+const CONTEXT: usize = 3;
+
+/// Lines replaced by an edit.
+struct Change {
+    /// The replaced lines of the old text.
+    old: Range<usize>,
+    added: Vec<String>,
+}
+
+/// The start of each line of a text, by byte offset.
+fn line_starts(text: &str) -> Vec<usize> {
+    std::iter::once(0).chain(text.match_indices('\n').map(|(i, _)| i + 1)).collect()
+}
+
+/// Formats edits of a text, as sorted and non-overlapping (byte range, replacement) pairs, as a unified diff.
+pub(crate) fn unified_diff(path: &str, old: &str, edits: &[(Range<usize>, String)]) -> String {
+    let starts = line_starts(old);
+    let lines: Vec<&str> = old.split('\n').collect();
+    let line_of = |byte: usize| starts.partition_point(|s| *s <= byte) - 1;
+    let mut changes = vec![];
+    for (r, replacement) in edits {
+        let first = line_of(r.start);
+        let last = line_of(r.end);
+        let end = starts.get(last + 1).map(|s| s - 1).unwrap_or(old.len());
+        let new = format!("{}{}{}", &old[starts[first]..r.start], replacement, &old[r.end..end]);
+        let new: Vec<&str> = new.split('\n').collect();
+        let old_lines = &lines[first..=last];
+        let prefix = old_lines.iter().zip(&new).take_while(|(a, b)| a == b).count();
+        let suffix = old_lines[prefix..].iter().rev().zip(new[prefix..].iter().rev()).take_while(|(a, b)| a == b).count();
+        if prefix == old_lines.len() && prefix == new.len() {
+            continue;
+        }
+        changes.push(Change {
+            old: (first + prefix)..(last + 1 - suffix),
+            added: new[prefix..(new.len() - suffix)].iter().map(|l| l.to_string()).collect(),
+        });
+    }
+    if changes.is_empty() {
+        return String::new();
+    }
+    // Without the empty line after a trailing newline.
+    let count = if old.ends_with('\n') { lines.len() - 1 } else { lines.len() };
+    let mut diff = format!("--- a/{}\n+++ b/{}\n", path, path);
+    // The difference in line numbers between the old and new text before the current hunk.
+    let mut offset: isize = 0;
+    let mut i = 0;
+    while i < changes.len() {
+        let mut j = i + 1;
+        while j < changes.len() && changes[j].old.start <= changes[j - 1].old.end + 2 * CONTEXT {
+            j += 1;
+        }
+        let start = changes[i].old.start.saturating_sub(CONTEXT);
+        let end = (changes[j - 1].old.end + CONTEXT).min(count).max(changes[j - 1].old.end);
+        let mut body = String::new();
+        let mut cursor = start;
+        let mut delta: isize = 0;
+        for c in &changes[i..j] {
+            for l in &lines[cursor..c.old.start] {
+                body += &format!(" {}\n", l);
+            }
+            for l in &lines[c.old.clone()] {
+                body += &format!("-{}\n", l);
+            }
+            for l in &c.added {
+                body += &format!("+{}\n", l);
+            }
+            delta += c.added.len() as isize - c.old.len() as isize;
+            cursor = c.old.end;
+        }
+        for l in &lines[cursor..end] {
+            body += &format!(" {}\n", l);
+        }
+        let old_len = end - start;
+        let new_len = (old_len as isize + delta) as usize;
+        let new_start = (start as isize + offset) as usize;
+        // Empty ranges are numbered by the line before them.
+        let number = |start: usize, len: usize| if len == 0 { start } else { start + 1 };
+        diff += &format!("@@ -{},{} +{},{} @@\n{}", number(start, old_len), old_len, number(new_start, new_len), new_len, body);
+        offset += delta;
+        i = j;
+    }
+    diff
+}
+
+/// For each line of `a`, the line of `b` it is matched with in a longest common subsequence.
+fn lcs_matches(a: &[&str], b: &[&str]) -> Vec<Option<usize>> {
+    // lengths[i][j] is the length of the LCS of a[i..] and b[j..].
+    let mut lengths = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lengths[i][j] = if a[i] == b[j] { lengths[i + 1][j + 1] + 1 } else { lengths[i + 1][j].max(lengths[i][j + 1]) };
+        }
+    }
+    let mut matches = vec![None; a.len()];
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        if a[i] == b[j] {
+            matches[i] = Some(j);
+            i += 1;
+            j += 1;
+        } else if lengths[i + 1][j] >= lengths[i][j + 1] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    matches
+}
+
+/// Merges the changes from `base` to `ours` and from `base` to `theirs` line by line. Lines changed differently on
+/// both sides are a conflict and get Git's conflict markers. Returns the merged text and the number of conflicts.
+pub(crate) fn merge3(base: &str, ours: &str, theirs: &str) -> (String, usize) {
+    let o: Vec<&str> = base.split_inclusive('\n').collect();
+    let a: Vec<&str> = ours.split_inclusive('\n').collect();
+    let b: Vec<&str> = theirs.split_inclusive('\n').collect();
+    let ma = lcs_matches(&o, &a);
+    let mb = lcs_matches(&o, &b);
+    let mut merged = String::new();
+    let mut conflicts = 0;
+    let (mut io, mut ia, mut ib) = (0, 0, 0);
+    loop {
+        // The next base line unchanged on both sides, or the end of all three.
+        let stable = (io..o.len()).find_map(|i| Some((i, ma[i]?, mb[i]?)));
+        let (so, sa, sb) = stable.unwrap_or((o.len(), a.len(), b.len()));
+        let (co, ca, cb) = (o[io..so].concat(), a[ia..sa].concat(), b[ib..sb].concat());
+        if ca == cb || cb == co {
+            merged += &ca;
+        } else if ca == co {
+            merged += &cb;
+        } else {
+            conflicts += 1;
+            let line = |s: &str| if s.is_empty() || s.ends_with('\n') { s.to_string() } else { format!("{}\n", s) };
+            merged += &format!("<<<<<<< ours\n{}=======\n{}>>>>>>> theirs\n", line(&ca), line(&cb));
+        }
+        if stable.is_none() {
+            break;
+        }
+        merged += o[so];
+        (io, ia, ib) = (so + 1, sa + 1, sb + 1);
+    }
+    (merged, conflicts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    /// The lines 1 to `n`, each with a newline.
+    fn numbered(n: usize) -> String {
+        (1..=n).map(|i| format!("{}\n", i)).collect()
+    }
+    
+    /// An edit replacing the whole line with the content.
+    fn replace_line(text: &str, line: &str, replacement: &str) -> (Range<usize>, String) {
+        let start = text.find(&format!("\n{}\n", line)).unwrap() + 1;
+        (start..(start + line.len()), replacement.to_string())
+    }
+    
+    #[test]
+    fn diff_hunk_with_context() {
+        let old = numbered(10);
+        let diff = unified_diff("story.twee", &old, &[replace_line(&old, "5", "five")]);
+        assert_eq!(diff, "--- a/story.twee\n+++ b/story.twee\n@@ -2,7 +2,7 @@\n 2\n 3\n 4\n-5\n+five\n 6\n 7\n 8\n");
+    }
+    
+    #[test]
+    fn diff_context_at_the_edges() {
+        let old = numbered(4);
+        let diff = unified_diff("a", &old, &[(0..1, "one".to_string())]);
+        assert_eq!(diff, "--- a/a\n+++ b/a\n@@ -1,4 +1,4 @@\n-1\n+one\n 2\n 3\n 4\n");
+        let diff = unified_diff("a", &old, &[replace_line(&old, "4", "four")]);
+        assert_eq!(diff, "--- a/a\n+++ b/a\n@@ -1,4 +1,4 @@\n 1\n 2\n 3\n-4\n+four\n");
+    }
+    
+    #[test]
+    fn diff_hunks() {
+        let old = numbered(30);
+        // Changes at most 6 lines apart share a hunk.
+        let diff = unified_diff("a", &old, &[replace_line(&old, "5", "five"), replace_line(&old, "11", "eleven")]);
+        assert_eq!(diff.matches("@@ -").count(), 1);
+        assert!(diff.starts_with("--- a/a\n+++ b/a\n@@ -2,13 +2,13 @@\n"));
+        // Further apart, they get their own hunks, numbered after the lines added by the hunks before them.
+        let diff = unified_diff("a", &old, &[replace_line(&old, "5", "five\nand a half"), replace_line(&old, "20", "twenty")]);
+        assert_eq!(diff, "--- a/a\n+++ b/a\n\
+            @@ -2,7 +2,8 @@\n 2\n 3\n 4\n-5\n+five\n+and a half\n 6\n 7\n 8\n\
+            @@ -17,7 +18,7 @@\n 17\n 18\n 19\n-20\n+twenty\n 21\n 22\n 23\n");
+    }
+    
+    #[test]
+    fn diff_added_and_removed_lines() {
+        let old = "a\nb\nc\n";
+        assert_eq!(unified_diff("a", old, &[(2..4, String::new())]), "--- a/a\n+++ b/a\n@@ -1,3 +1,2 @@\n a\n-b\n c\n");
+        assert_eq!(unified_diff("a", old, &[(2..2, "new\n".to_string())]), "--- a/a\n+++ b/a\n@@ -1,3 +1,4 @@\n a\n+new\n b\n c\n");
+    }
+    
+    #[test]
+    fn diff_without_changes() {
+        assert_eq!(unified_diff("a", "a\nb\n", &[(0..1, "a".to_string())]), "");
+        assert_eq!(unified_diff("a", "a\nb\n", &[]), "");
+    }
+    
+    #[test]
+    fn lcs() {
+        assert_eq!(lcs_matches(&["a", "b", "c", "d"], &["a", "c", "x", "d"]), vec![Some(0), None, Some(1), Some(3)]);
+        assert_eq!(lcs_matches(&["a", "b"], &[]), vec![None, None]);
+        assert_eq!(lcs_matches(&[], &["a"]), vec![]);
+    }
+    
+    #[test]
+    fn merge_clean() {
+        let base = "1\n2\n3\n4\n5\n";
+        assert_eq!(merge3(base, "one\n2\n3\n4\n5\n", "1\n2\n3\n4\nfive\n"), ("one\n2\n3\n4\nfive\n".to_string(), 0));
+        assert_eq!(merge3(base, "1\n2\nnew\n3\n4\n5\n", "1\n2\n3\n4\n"), ("1\n2\nnew\n3\n4\n".to_string(), 0));
+        // The same change on both sides isn't a conflict.
+        assert_eq!(merge3(base, "1\n2\nthree\n4\n5\n", "1\n2\nthree\n4\n5\n"), ("1\n2\nthree\n4\n5\n".to_string(), 0));
+    }
+    
+    #[test]
+    fn merge_conflict() {
+        let (merged, conflicts) = merge3("1\n2\n3\n", "1\nours\n3\n", "1\ntheirs\n3\n");
+        assert_eq!(merged, "1\n<<<<<<< ours\nours\n=======\ntheirs\n>>>>>>> theirs\n3\n");
+        assert_eq!(conflicts, 1);
+        // A line without a newline at the end still gets the markers on their own lines.
+        let (merged, conflicts) = merge3("1\n2", "1\nours", "1\ntheirs");
+        assert_eq!(merged, "1\n<<<<<<< ours\nours\n=======\ntheirs\n>>>>>>> theirs\n");
+        assert_eq!(conflicts, 1);
+    }
+    
+    #[test]
+    fn merge_delete_and_modify() {
+        let (merged, conflicts) = merge3("1\n2\n3\n", "1\n3\n", "1\nchanged\n3\n");
+        assert_eq!(merged, "1\n<<<<<<< ours\n=======\nchanged\n>>>>>>> theirs\n3\n");
+        assert_eq!(conflicts, 1);
+        // Deleting the same line on both sides is fine.
+        assert_eq!(merge3("1\n2\n3\n", "1\n3\n", "1\n3\n"), ("1\n3\n".to_string(), 0));
+    }
+}
@@ -0,0 +1,142 @@
+use std::{collections::{BTreeMap, BTreeSet, VecDeque}, io::{Cursor, Write}, path::PathBuf};
+
+use serde_json::Value;
+use tracing::info;
+use twee_build::{build_story, load_config, output_path, utc_timestamp, write_output};
+use twee_parser::{Passage, Story};
+use zip::{write::FileOptions, CompressionMethod, ZipWriter};
+
+use crate::{site::{escape_html, is_shown, prose_paragraphs, Span}, spellcheck::prose, stats::words};
+
+const DOCX_CONTENT_TYPES: &str = include_str!("../templates/docx/content_types.xml");
+const DOCX_RELS: &str = include_str!("../templates/docx/rels.xml");
+const DOCX_DOCUMENT_RELS: &str = include_str!("../templates/docx/document.xml.rels");
+const DOCX_CORE: &str = include_str!("../templates/docx/core.xml");
+const DOCX_STYLES: &str = include_str!("../templates/docx/styles.xml");
+
+const W_NS: &str = "http://schemas.openxmlformats.org/wordprocessingml/2006/main";
+
+/// The passages shown to players in reading order: the passages reachable from the start passage breadth-first, then
+/// the unreachable ones in source order.
+pub(crate) fn linearize(story: &Story) -> Vec<&Passage> {
+    let mut order = vec![];
+    let mut seen = BTreeSet::new();
+    let mut queue: VecDeque<&Passage> = story.start_passage().or(story.passage("Start")).into_iter().collect();
+    while let Some(p) = queue.pop_front() {
+        if ! is_shown(p) || ! seen.insert(p.name.clone()) {
+            continue;
+        }
+        order.push(p);
+        queue.extend(story.targets(p));
+    }
+    order.extend(story.passages.iter().filter(|p| is_shown(p) && ! seen.contains(&p.name)));
+    order
+}
+
+fn run(text: &str, properties: &str) -> String {
+    format!("<w:r>{}<w:t xml:space=\"preserve\">{}</w:t></w:r>", properties, escape_html(text))
+}
+
+fn paragraph(style: Option<&str>, content: &str) -> String {
+    match style {
+        Some(style) => format!("<w:p><w:pPr><w:pStyle w:val=\"{}\"/></w:pPr>{}</w:p>", style, content),
+        None => format!("<w:p>{}</w:p>", content),
+    }
+}
+
+/// The front page with the title and the metadata of the story.
+fn front_page(story: &Story, format: &str) -> String {
+    let mut xml = paragraph(Some("Title"), &run(&story.title, ""));
+    let passages = linearize(story);
+    let mut fields: Vec<(String, String)> = story.meta.iter().filter_map(|(k, v)| match v {
+        Value::String(s) => Some((k.clone(), s.clone())),
+        Value::Number(_) | Value::Bool(_) => Some((k.clone(), v.to_string())),
+        _ => None,
+    }).collect();
+    fields.push(("passages".to_string(), passages.len().to_string()));
+    fields.push(("words".to_string(), passages.iter().map(|p| words(&prose(p, format)).len()).sum::<usize>().to_string()));
+    fields.push(("exported".to_string(), utc_timestamp()));
+    for (k, v) in fields {
+        xml += &paragraph(None, &(run(&format!("{}: ", k), "<w:rPr><w:b/></w:rPr>") + &run(&v, "")));
+    }
+    xml + "<w:p><w:r><w:br w:type=\"page\"/></w:r></w:p>"
+}
+
+/// Renders the story into the document.xml and footnotes.xml of a Word document. Each passage gets a heading with a
+/// bookmark, and each link a footnote with a cross-reference to the heading of its target.
+fn docx_parts(story: &Story) -> (String, String) {
+    let format = story.meta.get("format").and_then(|f| f.as_str()).unwrap_or("");
+    let passages = linearize(story);
+    let bookmarks: BTreeMap<&str, usize> = passages.iter().enumerate().map(|(i, p)| (p.name.as_str(), i)).collect();
+    let mut body = front_page(story, format);
+    let mut footnotes = String::new();
+    let mut footnote_id = 0;
+    for (i, p) in passages.iter().enumerate() {
+        body += &paragraph(Some("Heading1"), &format!("<w:bookmarkStart w:id=\"{0}\" w:name=\"passage{0}\"/>{1}<w:bookmarkEnd w:id=\"{0}\"/>", i, run(&p.name, "")));
+        if ! p.tags.is_empty() {
+            body += &paragraph(Some("Tags"), &run(&p.tags.join(" "), ""));
+        }
+        for lines in prose_paragraphs(p, format) {
+            let mut content = String::new();
+            for (j, spans) in lines.iter().enumerate() {
+                if j > 0 {
+                    content += "<w:r><w:br/></w:r>";
+                }
+                for s in spans {
+                    match s {
+                        Span::Text(t) => content += &run(t, ""),
+                        Span::Link(l) => {
+                            footnote_id += 1;
+                            content += &run(&l.text, "<w:rPr><w:u w:val=\"single\"/></w:rPr>");
+                            content += &format!("<w:r><w:rPr><w:rStyle w:val=\"FootnoteReference\"/></w:rPr><w:footnoteReference w:id=\"{}\"/></w:r>", footnote_id);
+                            let target = match bookmarks.get(l.target.as_str()) {
+                                Some(b) => format!("<w:hyperlink w:anchor=\"passage{}\">{}</w:hyperlink>", b, run(&l.target, "<w:rPr><w:rStyle w:val=\"Hyperlink\"/></w:rPr>")),
+                                None => run(&format!("{} (missing)", l.target), ""),
+                            };
+                            footnotes += &format!("<w:footnote w:id=\"{}\">{}</w:footnote>", footnote_id, paragraph(Some("FootnoteText"),
+                                &format!("<w:r><w:rPr><w:rStyle w:val=\"FootnoteReference\"/></w:rPr><w:footnoteRef/></w:r>{}{}", run(" Goes to ", ""), target)));
+                        },
+                    }
+                }
+            }
+            body += &paragraph(None, &content);
+        }
+    }
+    let document = format!("<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n<w:document xmlns:w=\"{}\"><w:body>{}<w:sectPr><w:pgMar w:top=\"1440\" w:right=\"1440\" w:bottom=\"1440\" w:left=\"1440\" w:header=\"720\" w:footer=\"720\" w:gutter=\"0\"/></w:sectPr></w:body></w:document>", W_NS, body);
+    let footnotes = format!("<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n<w:footnotes xmlns:w=\"{}\"><w:footnote w:type=\"separator\" w:id=\"-1\"><w:p><w:r><w:separator/></w:r></w:p></w:footnote><w:footnote w:type=\"continuationSeparator\" w:id=\"0\"><w:p><w:r><w:continuationSeparator/></w:r></w:p></w:footnote>{}</w:footnotes>", W_NS, footnotes);
+    (document, footnotes)
+}
+
+/// Writes the story in the current directory in reading order into a Word document for editors, with a front page
+/// with the metadata and the links as footnotes.
+pub(crate) fn export_docx(out: Option<PathBuf>) -> anyhow::Result<()> {
+    let config = load_config()?;
+    let story = build_story(&config, false)?;
+    let out = out.unwrap_or(output_path(&config, &story).with_extension("docx"));
+    let (document, footnotes) = docx_parts(&story);
+    let mut properties = String::new();
+    if let Some(ifid) = story.meta.get("ifid").and_then(|i| i.as_str()) {
+        properties += &format!("<dc:identifier>{}</dc:identifier>\n", escape_html(ifid));
+    }
+    if let Some(lang) = story.meta.get("lang").and_then(|l| l.as_str()) {
+        properties += &format!("<dc:language>{}</dc:language>\n", escape_html(lang));
+    }
+    let core = DOCX_CORE.replace("{{TITLE}}", &escape_html(&story.title)).replace("{{PROPERTIES}}", &properties).replace("{{DATE}}", &utc_timestamp());
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+    for (name, contents) in [
+        ("[Content_Types].xml", DOCX_CONTENT_TYPES),
+        ("_rels/.rels", DOCX_RELS),
+        ("docProps/core.xml", &core),
+        ("word/_rels/document.xml.rels", DOCX_DOCUMENT_RELS),
+        ("word/styles.xml", DOCX_STYLES),
+        ("word/document.xml", &document),
+        ("word/footnotes.xml", &footnotes),
+    ] {
+        zip.start_file(name, options)?;
+        zip.write_all(contents.as_bytes())?;
+    }
+    write_output(&out, &zip.finish()?.into_inner())?;
+    info!("Wrote {}", out.to_string_lossy());
+    Ok(())
+}
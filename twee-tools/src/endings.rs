@@ -0,0 +1,46 @@
+use serde::Serialize;
+use tracing::warn;
+use twee_build::{build_story, load_config};
+
+/// The JSON output of `endings`.
+#[derive(Serialize)]
+struct EndingsReport {
+    paths: std::collections::BTreeMap<String, Vec<Vec<String>>>,
+    unreached: Vec<String>,
+    truncated: bool,
+}
+
+/// Prints the routes from the start passage to each ending of the story in the current directory, and warns about
+/// endings that can't be reached.
+pub(crate) fn endings(max_depth: usize, max_paths: usize, routes: bool, json: bool) -> anyhow::Result<()> {
+    let config = load_config()?;
+    let story = build_story(&config, false)?;
+    let found = story.paths_to_endings(max_depth, max_paths);
+    for e in &found.unreached {
+        warn!(code = "ending-unreachable", "Ending \"{}\" can't be reached from the start passage", e);
+    }
+    if found.truncated {
+        warn!(code = "endings-truncated", "Stopped searching at {} passages per route or {} routes, there may be more routes. Use --max-depth and --max-paths to search further.", max_depth, max_paths);
+    }
+    if json {
+        let report = EndingsReport { paths: found.paths, unreached: found.unreached, truncated: found.truncated };
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        return Ok(());
+    }
+    println!("{:<30} {:>8} {:>8} {:>8}", "Ending", "Routes", "Shortest", "Longest");
+    for (ending, paths) in &found.paths {
+        let shortest = paths.iter().map(|p| p.len()).min().unwrap_or(0);
+        let longest = paths.iter().map(|p| p.len()).max().unwrap_or(0);
+        println!("{:<30} {:>8} {:>8} {:>8}", ending, paths.len(), shortest, longest);
+    }
+    if routes {
+        for (ending, paths) in &found.paths {
+            println!();
+            println!("{}:", ending);
+            for p in paths {
+                println!("  {}", p.join(" -> "));
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,65 @@
+use std::path::{Path, PathBuf};
+
+use serde_json::Value;
+use tracing::{info, warn};
+use twee_build::{load_config, write_output, Build};
+
+use crate::{build::BuildOptions, package::package_files};
+
+const DESKTOP_CARGO: &str = include_str!("../templates/desktop/Cargo.toml.template");
+const DESKTOP_BUILD: &str = include_str!("../templates/desktop/build.rs.template");
+const DESKTOP_MAIN: &str = include_str!("../templates/desktop/main.rs.template");
+const DESKTOP_TAURI_CONF: &str = include_str!("../templates/desktop/tauri.conf.json.template");
+const DESKTOP_CAPABILITIES: &str = include_str!("../templates/desktop/capabilities.json.template");
+const DESKTOP_SAVE_SHIM: &str = include_str!("../templates/desktop/save-shim.js");
+
+/// Inserts a script tag as early as possible into the head of the HTML document.
+pub(crate) fn inject_script(html: &str, script: &str) -> String {
+    let tag = format!("<script>{}</script>", script);
+    if let Some(i) = html.find("<head>") {
+        let i = i + "<head>".len();
+        format!("{}{}{}", &html[..i], tag, &html[i..])
+    } else {
+        tag + html
+    }
+}
+
+/// Builds the story and generates a Tauri project around it, which can be built into desktop applications.
+pub(crate) fn export_desktop(options: &BuildOptions) -> anyhow::Result<PathBuf> {
+    let Build { html, story, .. } = crate::build(options)?;
+    let config = load_config()?;
+    let desktop = &config.desktop;
+    let out = desktop.output.as_ref().map(PathBuf::from).unwrap_or(config.dir.join("desktop"));
+    let slug: String = story.title.to_lowercase().chars().map(|c| if c.is_ascii_alphanumeric() { c } else { '-' }).collect::<String>()
+        .split('-').filter(|s| ! s.is_empty()).collect::<Vec<&str>>().join("-");
+    let slug = if slug.is_empty() || slug.starts_with(|c: char| c.is_ascii_digit()) { format!("story-{}", slug) } else { slug };
+    let title = desktop.title.clone().unwrap_or(story.title.clone());
+    let version = desktop.version.clone().unwrap_or("0.1.0".to_string());
+    let identifier = desktop.identifier.clone().unwrap_or(format!("com.example.{}", slug.replace('-', "")));
+    let icons: Vec<Value> = if desktop.icon.is_some() { vec!["icons/icon.png".into()] } else { vec![] };
+    
+    write_output(out.join("dist").join("index.html"), inject_script(&html, DESKTOP_SAVE_SHIM).as_bytes())?;
+    for (f, name) in package_files(&config, &story)? {
+        write_output(out.join("dist").join(name), &std::fs::read(f)?)?;
+    }
+    let tauri = out.join("src-tauri");
+    write_output(tauri.join("Cargo.toml"), DESKTOP_CARGO.replace("{{CRATE_NAME}}", &slug).replace("{{VERSION}}", &version).as_bytes())?;
+    write_output(tauri.join("build.rs"), DESKTOP_BUILD.as_bytes())?;
+    write_output(tauri.join("src").join("main.rs"), DESKTOP_MAIN.as_bytes())?;
+    write_output(tauri.join("capabilities").join("default.json"), DESKTOP_CAPABILITIES.as_bytes())?;
+    write_output(tauri.join("tauri.conf.json"), DESKTOP_TAURI_CONF
+        .replace("{{TITLE}}", &Value::from(title).to_string())
+        .replace("{{VERSION}}", &version)
+        .replace("{{IDENTIFIER}}", &Value::from(identifier).to_string())
+        .replace("{{WIDTH}}", &desktop.width.unwrap_or(1024).to_string())
+        .replace("{{HEIGHT}}", &desktop.height.unwrap_or(768).to_string())
+        .replace("{{ICONS}}", &Value::from(icons).to_string())
+        .as_bytes())?;
+    if let Some(icon) = &desktop.icon {
+        write_output(tauri.join("icons").join("icon.png"), &std::fs::read(icon)?)?;
+        info!("Run `cargo tauri icon {}` in {} to generate the icons for all platforms.", Path::new(icon).canonicalize()?.to_string_lossy(), tauri.to_string_lossy());
+    } else {
+        warn!(code = "no-icon", "No icon configured, set desktop.icon in config.toml or run `cargo tauri icon` in {}.", tauri.to_string_lossy());
+    }
+    Ok(out)
+}
@@ -0,0 +1,64 @@
+use std::{fs::read_dir, path::{Path, PathBuf}};
+
+use regex::Regex;
+use tracing::info;
+use twee_build::{git_commit, remove_output, utc_timestamp, write_output, BuildName, Config};
+
+const DEFAULT_BUILDS_DIR: &str = "builds";
+const DEFAULT_RETAIN: usize = 10;
+
+/// Whether a file name is a copy of the output kept by [keep_build], named after the timestamp or the commit. Other
+/// files starting with the name of the output, like the builds of a story named with a suffix, aren't copies.
+fn is_kept_build(name: &str, stem: &str) -> bool {
+    Regex::new(&format!(r"^{}-(?:\d{{4}}-\d{{2}}-\d{{2}}T\d{{2}}-\d{{2}}-\d{{2}}Z|[0-9a-f]{{7,40}})\.html$", regex::escape(stem))).unwrap().is_match(name)
+}
+
+/// Keeps a copy of the built HTML in the builds directory and removes the oldest copies beyond the retention limit.
+pub(crate) fn keep_build(config: &Config, out: &Path, html: &str) -> anyhow::Result<()> {
+    let dir = config.builds.dir.as_ref().map(PathBuf::from).unwrap_or(config.dir.join(DEFAULT_BUILDS_DIR));
+    let stem = out.file_stem().unwrap_or_default().to_string_lossy().to_string();
+    let id = match config.builds.name {
+        BuildName::Commit => git_commit(&config.dir),
+        BuildName::Timestamp => None,
+    }.unwrap_or_else(|| utc_timestamp().replace(':', "-"));
+    let copy = dir.join(format!("{}-{}.html", stem, id));
+    write_output(&copy, html.as_bytes())?;
+    info!("Kept build as {}", copy.to_string_lossy());
+    let mut copies: Vec<(std::time::SystemTime, PathBuf)> = read_dir(&dir).map(|d| d.flatten()
+        .filter(|e| e.file_name().to_str().is_some_and(|n| is_kept_build(n, &stem)))
+        .filter_map(|e| Some((e.metadata().ok()?.modified().ok()?, e.path())))
+        .collect()).unwrap_or_default();
+    copies.sort();
+    let retain = config.builds.retain.unwrap_or(DEFAULT_RETAIN).max(1);
+    if copies.len() > retain {
+        for (_, p) in &copies[..copies.len() - retain] {
+            remove_output(p)?;
+        }
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn kept_build_names() {
+        assert!(is_kept_build("Story-2024-05-01T12-30-00Z.html", "Story"));
+        assert!(is_kept_build("Story-1a2b3c4.html", "Story"));
+        assert!(is_kept_build("My Story (v2)-1a2b3c4.html", "My Story (v2)"));
+    }
+    
+    #[test]
+    fn other_files_are_not_kept_builds() {
+        assert!(! is_kept_build("Story-demo.html", "Story"));
+        assert!(! is_kept_build("Story-cafe.html", "Story"));
+        assert!(! is_kept_build("Story-demo-2024-05-01T12-30-00Z.html", "Story"));
+        assert!(! is_kept_build("Story-1a2b3c4.html.bak", "Story"));
+        assert!(! is_kept_build("Story.html", "Story"));
+        assert!(! is_kept_build("Other-1a2b3c4.html", "Story"));
+        assert!(! is_kept_build("Story-1a2b3c4.html", "Sto"));
+        assert!(! is_kept_build("Storyx-1a2b3c4.html", "Story."));
+    }
+}
@@ -0,0 +1,120 @@
+use std::{collections::BTreeMap, path::PathBuf};
+
+use rand::{RngCore, SeedableRng};
+use tracing::{info, warn};
+use twee_build::{absolute_path, load_config_in, read_file, write_output};
+use twee_parser::{parse_twee3, stable_hash, Story};
+
+use crate::{upgrade::set_story_data, workspace::Workspace};
+
+/// Generates a random IFID, which is an uppercase UUID.
+pub(crate) fn gen_ifid() -> String {
+    let mut r = rand::rngs::StdRng::from_entropy();
+    let mut uuid: [u8; 16] = [0; 16];
+    r.fill_bytes(&mut uuid[..]);
+    // Version 4, variant 1
+    uuid[6] = (uuid[6] & 0x0F) | 0x40;
+    uuid[8] = (uuid[8] & 0x3F) | 0x80;
+    format_uuid(&uuid)
+}
+
+/// Derives an IFID from the IFID of a story and a name, like the tag of a part of the split story, so the same part
+/// gets the same IFID on every build. It's a version 8 UUID made from stable hashes of both.
+pub(crate) fn derived_ifid(parent: &str, name: &str) -> String {
+    let mut uuid: [u8; 16] = [0; 16];
+    uuid[..8].copy_from_slice(&stable_hash([parent, name]).to_be_bytes());
+    uuid[8..].copy_from_slice(&stable_hash(["ifid", parent, name]).to_be_bytes());
+    // Version 8, variant 1
+    uuid[6] = (uuid[6] & 0x0F) | 0x80;
+    uuid[8] = (uuid[8] & 0x3F) | 0x80;
+    format_uuid(&uuid)
+}
+
+/// Formats the bytes of a UUID as uppercase hex groups.
+fn format_uuid(uuid: &[u8; 16]) -> String {
+    fn hex_bytes(b: &[u8]) -> String {
+        b.iter().map(|b| format!("{:02X}", b)).collect()
+    }
+    format!("{}-{}-{}-{}-{}", hex_bytes(&uuid[0..4]), hex_bytes(&uuid[4..6]), hex_bytes(&uuid[6..8]), hex_bytes(&uuid[8..10]), hex_bytes(&uuid[10..16]))
+}
+
+/// Whether the text is an IFID: a UUID with uppercase letters, as Twine generates them.
+pub(crate) fn is_valid_ifid(ifid: &str) -> bool {
+    let groups: Vec<&str> = ifid.split('-').collect();
+    groups.iter().map(|g| g.len()).eq([8, 4, 4, 4, 12]) && groups.iter().all(|g| g.chars().all(|c| c.is_ascii_digit() || ('A'..='F').contains(&c)))
+}
+
+/// The IFID of the story, uppercased for comparison.
+pub(crate) fn story_ifid(story: &Story) -> Option<String> {
+    story.meta.get("ifid").and_then(|i| i.as_str()).map(|i| i.trim().to_uppercase()).filter(|i| ! i.is_empty())
+}
+
+/// Finds the entries sharing an IFID with an earlier entry, returning their indices and the earlier entry's index.
+pub(crate) fn duplicate_ifids(ifids: &[Option<String>]) -> Vec<(usize, usize)> {
+    let mut seen: BTreeMap<&str, usize> = BTreeMap::new();
+    let mut res = vec![];
+    for (i, ifid) in ifids.iter().enumerate() {
+        if let Some(ifid) = ifid {
+            if let Some(first) = seen.get(ifid.as_str()) {
+                res.push((i, *first));
+            } else {
+                seen.insert(ifid, i);
+            }
+        }
+    }
+    res
+}
+
+/// Warns about workspace members sharing an IFID. With `fix`, all but the first member get a new IFID in the
+/// StoryData of their main twee file.  
+/// Only the StoryData in the main twee file is checked, which is read directly instead of building the story, so
+/// it runs before the prebuild commands without caching the sources the builds read afterwards. Members whose
+/// config or main file can't be read are skipped, their builds report the error.
+pub(crate) fn check_workspace_ifids(workspace: &Workspace, fix: bool) -> anyhow::Result<()> {
+    let members = workspace.member_dirs()?;
+    let stories: Vec<(Option<String>, PathBuf)> = members.iter().map(|dir| {
+        let Ok(config) = load_config_in(dir) else {
+            return (None, PathBuf::new());
+        };
+        let main = PathBuf::from(&config.main);
+        let story = read_file(&main).ok().and_then(|s| parse_twee3(&s).ok()).map(|(s, _)| s);
+        (story.as_ref().and_then(story_ifid), absolute_path(&main).unwrap_or(main))
+    }).collect();
+    let ifids: Vec<Option<String>> = stories.iter().map(|(i, _)| i.clone()).collect();
+    let duplicates = duplicate_ifids(&ifids);
+    for (i, first) in &duplicates {
+        let (m, f) = (members[*i].to_string_lossy(), members[*first].to_string_lossy());
+        let ifid = ifids[*i].as_deref().unwrap_or_default();
+        if ! fix {
+            warn!(code = "duplicate-ifid", "Workspace member {} has the same IFID {} as {}. Run with --fix-ifids to give it a new one.", m, ifid, f);
+            continue;
+        }
+        let main = &stories[*i].1;
+        match set_story_data(&read_file(main)?, "ifid", &gen_ifid()) {
+            Some(source) => {
+                info!("Gave workspace member {} a new IFID, it had the same as {}", m, f);
+                write_output(main, source.as_bytes())?;
+            },
+            None => warn!(code = "duplicate-ifid", "Workspace member {} has the same IFID {} as {}, but its StoryData isn't in {} and can't be fixed automatically.", m, ifid, f, main.to_string_lossy()),
+        }
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn derived_ifids() {
+        let parent = "3C6D5A07-1F5E-4C9B-9D0A-2B7E4F8A1C3D";
+        let one = derived_ifid(parent, "chapter1");
+        assert!(is_valid_ifid(&one), "{}", one);
+        assert_eq!(&one[14..15], "8");
+        assert_eq!(one, derived_ifid(parent, "chapter1"));
+        assert_ne!(one, derived_ifid(parent, "chapter2"));
+        assert_ne!(one, derived_ifid("8E2F7B1A-0C4D-4E6F-A1B2-C3D4E5F60718", "chapter1"));
+        assert!(is_valid_ifid(&gen_ifid()));
+    }
+}
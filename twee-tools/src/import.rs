@@ -0,0 +1,119 @@
+use std::{collections::BTreeSet, fs::read_dir, path::{Path, PathBuf}};
+
+use tracing::{info, warn};
+use twee_build::{print_warning, read_file, write_output};
+use twee_parser::{parse_archive, parse_html, serialize_twee3, Story};
+
+use crate::{build::Error, ifid::story_ifid, DEFAULT_CONFIG};
+
+/// Whether the file is a story or archive of the Twine editor, including its backups.
+fn is_twine_file(p: &Path) -> bool {
+    let name = p.file_name().unwrap_or_default().to_string_lossy().to_lowercase();
+    name.ends_with(".html") || name.ends_with(".htm") || name.ends_with(".html.bak")
+}
+
+/// Reads the stories of a file, which can be an archive, a file of the Twine story library or a published story.
+pub(crate) fn read_stories(p: &Path) -> anyhow::Result<Vec<Story>> {
+    let source = read_file(p)?;
+    let stories = match parse_archive(&source) {
+        Ok(stories) if ! stories.is_empty() => stories,
+        _ => match parse_html(&source) {
+            Ok(story) => vec![story],
+            // The story format code in published stories often isn't valid XML, so only parse the story data.
+            Err(e) => match (source.find("<tw-storydata"), source.rfind("</tw-storydata>")) {
+                (Some(start), Some(end)) if start < end => parse_archive(&source[start..end + "</tw-storydata>".len()])?,
+                _ => return Err(e.into()),
+            },
+        },
+    };
+    Ok(stories.into_iter().map(|(story, warnings)| {
+        for w in warnings {
+            print_warning(w);
+        }
+        story
+    }).collect())
+}
+
+/// A directory name for the story title.
+pub(crate) fn project_dir_name(title: &str) -> String {
+    let name: String = title.chars().map(|c| if "/\\:*?\"<>|".contains(c) || c.is_control() { '_' } else { c }).collect();
+    let name = name.trim().trim_matches('.').to_string();
+    if name.is_empty() { "story".to_string() } else { name }
+}
+
+/// A directory in `out` for a new project for the story, which is neither in `dirs` nor has a config.toml.
+pub(crate) fn new_project_dir(out: &Path, title: &str, dirs: &mut BTreeSet<String>) -> PathBuf {
+    let mut name = project_dir_name(title);
+    let mut i = 1;
+    while dirs.contains(&name) || out.join(&name).join("config.toml").exists() {
+        i += 1;
+        name = format!("{} {}", project_dir_name(title), i);
+    }
+    dirs.insert(name.clone());
+    out.join(&name)
+}
+
+/// Writes a story as a twee project, with the story script and stylesheet in story.js and story.css.
+pub(crate) fn write_project(dir: &Path, mut story: Story) -> anyhow::Result<()> {
+    let mut js = String::new();
+    let mut css = String::new();
+    story.passages.retain(|p| {
+        if p.name == "StoryScript" && p.tags.iter().any(|t| t == "script") {
+            js += &p.content;
+            false
+        } else if p.name == "StoryStylesheet" && p.tags.iter().any(|t| t == "stylesheet") {
+            css += &p.content;
+            false
+        } else {
+            true
+        }
+    });
+    write_output(dir.join("story.twee"), serialize_twee3(&story).as_bytes())?;
+    write_output(dir.join("story.js"), js.as_bytes())?;
+    write_output(dir.join("story.css"), css.as_bytes())?;
+    write_output(dir.join("config.toml"), DEFAULT_CONFIG.as_bytes())?;
+    Ok(())
+}
+
+/// Imports the stories from a Twine story library folder, a single story file or an archive into a twee project
+/// each, in subdirectories of `out` named after the stories.  
+/// Stories with the same IFID, e.g. from backups, are only imported once, preferring files that aren't backups.
+pub(crate) fn import_twine(path: &Path, out: &Path) -> anyhow::Result<()> {
+    let files: Vec<PathBuf> = if path.is_dir() {
+        let mut files: Vec<PathBuf> = read_dir(path)?.flatten().map(|e| e.path()).filter(|p| p.is_file() && is_twine_file(p)).collect();
+        // Backups last, so the current version of a story is imported.
+        files.sort_by_key(|p| (p.to_string_lossy().to_lowercase().ends_with(".bak"), p.clone()));
+        files
+    } else if path.is_file() {
+        vec![path.to_path_buf()]
+    } else {
+        return Err(Error::FileNotFound(path.to_string_lossy().to_string()).into());
+    };
+    let mut seen = BTreeSet::new();
+    let mut dirs = BTreeSet::new();
+    let (mut imported, mut skipped) = (0, 0);
+    for f in files {
+        let stories = match read_stories(&f) {
+            Ok(stories) => stories,
+            Err(e) => {
+                warn!(code = "import-skipped", "Skipped {}, it's not a Twine story: {}", f.to_string_lossy(), e);
+                skipped += 1;
+                continue;
+            }
+        };
+        for story in stories {
+            let key = story_ifid(&story).unwrap_or(story.title.clone());
+            if ! seen.insert(key.clone()) {
+                info!("Skipped \"{}\" from {}, it was already imported", story.title, f.to_string_lossy());
+                skipped += 1;
+                continue;
+            }
+            let dir = new_project_dir(out, &story.title, &mut dirs);
+            write_project(&dir, story)?;
+            info!("Imported {} into {}", f.to_string_lossy(), dir.to_string_lossy());
+            imported += 1;
+        }
+    }
+    info!("Imported {} stories, skipped {}", imported, skipped);
+    Ok(())
+}
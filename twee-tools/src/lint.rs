@@ -0,0 +1,657 @@
+use std::{collections::BTreeSet, path::PathBuf};
+
+use percent_encoding::percent_decode_str;
+use regex::Regex;
+use tracing::{error, warn};
+use twee_build::{passage_source, Config, Severity};
+use twee_parser::{is_special_passage, Navigation, Passage, Story};
+
+use crate::build::Error;
+
+/// A problem found in a story by one of the lint rules.
+pub(crate) struct Lint {
+    /// The code of the rule that found the problem.
+    pub rule: &'static str,
+    /// The passage the problem was found in.
+    pub passage: String,
+    pub message: String,
+}
+
+/// The rule codes and their default severity.
+pub(crate) const RULES: &[(&str, Severity)] = &[
+    ("missing-asset", Severity::Error),
+    ("format-mismatch", Severity::Error),
+    ("img-alt", Severity::Warn),
+    ("vague-link-text", Severity::Warn),
+    ("low-contrast", Severity::Warn),
+    ("missing-lang", Severity::Warn),
+    ("near-duplicate", Severity::Warn),
+    ("dead-end", Severity::Warn),
+    ("self-link", Severity::Warn),
+    ("two-passage-loop", Severity::Warn),
+    ("empty-link-text", Severity::Warn),
+    ("duplicate-link-text", Severity::Warn),
+    ("long-link-text", Severity::Warn),
+];
+
+/// The configured severity of a rule.
+pub(crate) fn severity(config: &Config, rule: &str) -> Severity {
+    config.lint.rules.get(rule).copied()
+        .or(RULES.iter().find(|(r, _)| *r == rule).map(|(_, s)| *s))
+        .unwrap_or(Severity::Error)
+}
+
+/// Logs a problem with the file and line of the passage from the last build, if it has one.
+pub(crate) fn print_lint(l: &Lint, severity: Severity) {
+    let source = passage_source(&l.passage);
+    let location = source.as_ref().map(|s| format!(" ({})", s)).unwrap_or_default();
+    let source = source.map(|s| s.to_string());
+    match severity {
+        Severity::Off => {},
+        Severity::Warn => warn!(code = l.rule, passage = l.passage, source, "Passage \"{}\"{}: {}", l.passage, location, l.message),
+        Severity::Error => error!(code = l.rule, passage = l.passage, source, "Passage \"{}\"{}: {}", l.passage, location, l.message),
+    }
+}
+
+/// Runs all lint rules on a built story.
+pub(crate) fn lint_story(config: &Config, story: &Story) -> Vec<Lint> {
+    for r in config.lint.rules.keys() {
+        if ! RULES.iter().any(|(rule, _)| rule == r) {
+            warn!(code = "lint-rule-unknown", "Unknown lint rule in config: {}", r);
+        }
+    }
+    let mut lints = vec![];
+    check_assets(config, story, &mut lints);
+    check_format(story, &mut lints);
+    check_images(story, &mut lints);
+    check_link_text(story, &mut lints);
+    check_choices(config, story, &mut lints);
+    check_contrast(story, &mut lints);
+    check_lang(story, &mut lints);
+    check_duplicates(config, story, &mut lints);
+    check_dead_ends(config, story, &mut lints);
+    check_loops(story, &mut lints);
+    lints.retain(|l| severity(config, l.rule) != Severity::Off);
+    lints
+}
+
+/// Returns the local file paths referenced by `src=`, CSS `url(...)`, SugarCube `[img[...]]` and audio macros in the content.
+pub(crate) fn asset_references(content: &str) -> Vec<String> {
+    let patterns = [
+        Regex::new(r#"\bsrc\s*=\s*(?:"([^"]*)"|'([^']*)')"#).unwrap(),
+        Regex::new(r#"url\(\s*(?:"([^"]*)"|'([^']*)'|([^)"'\s]*))\s*\)"#).unwrap(),
+        Regex::new(r#"\[img\[(?:[^\]|]*\|)?([^\]]*)\]"#).unwrap(),
+    ];
+    let audio = Regex::new(r#"<<(?:cacheaudio|createplaylist|track)\s+([^>]*)>>"#).unwrap();
+    let quoted = Regex::new(r#""([^"]*)"|'([^']*)'"#).unwrap();
+    let mut refs = vec![];
+    for p in &patterns {
+        for c in p.captures_iter(content) {
+            if let Some(m) = c.iter().skip(1).flatten().next() {
+                refs.push(m.as_str().to_string());
+            }
+        }
+    }
+    for c in audio.captures_iter(content) {
+        // The first argument is the track name, the rest are sources.
+        for q in quoted.captures_iter(&c[1]).skip(1) {
+            if let Some(m) = q.iter().skip(1).flatten().next() {
+                refs.push(m.as_str().to_string());
+            }
+        }
+    }
+    refs.retain(|r| is_local(r));
+    refs
+}
+
+fn is_local(r: &str) -> bool {
+    let r = r.trim();
+    // Skip fragments, protocol-relative URLs and paths built from story variables.
+    if r.is_empty() || r.starts_with('#') || r.starts_with("//") || r.starts_with('$') || r.contains("<<") {
+        return false;
+    }
+    // Anything with a URL scheme like http:, data: or blob:
+    if let Some(i) = r.find(':') {
+        if r[..i].chars().all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c)) {
+            return false;
+        }
+    }
+    true
+}
+
+fn asset_roots(config: &Config) -> Vec<PathBuf> {
+    if config.asset_roots.is_empty() {
+        vec![PathBuf::from(".")]
+    } else {
+        config.asset_roots.iter().map(PathBuf::from).collect()
+    }
+}
+
+/// The file path of a reference, without the query and fragment and with percent-encoding like `%20` decoded.
+fn asset_path(r: &str) -> String {
+    let path = r.split(['?', '#']).next().unwrap();
+    percent_decode_str(path).decode_utf8_lossy().to_string()
+}
+
+/// Returns the existing files referenced by the story, resolved against the asset roots.
+pub(crate) fn asset_files(config: &Config, story: &Story) -> Vec<PathBuf> {
+    let roots = asset_roots(config);
+    let mut files = vec![];
+    for p in &story.passages {
+        for r in asset_references(&p.content) {
+            let path = asset_path(&r);
+            if let Some(f) = roots.iter().map(|root| root.join(&path)).find(|f| f.is_file()) {
+                files.push(f);
+            }
+        }
+    }
+    files
+}
+
+/// Reports the files referenced by a built story that don't exist, as warnings or, with `missing_asset_error`, as
+/// errors that fail the build. Turning off the `missing-asset` lint rule turns this off too.
+pub(crate) fn check_build_assets(config: &Config, story: &Story) -> anyhow::Result<()> {
+    if severity(config, "missing-asset") == Severity::Off {
+        return Ok(());
+    }
+    let mut lints = vec![];
+    check_assets(config, story, &mut lints);
+    let severity = if config.missing_asset_error { Severity::Error } else { Severity::Warn };
+    for l in &lints {
+        print_lint(l, severity);
+    }
+    if config.missing_asset_error && ! lints.is_empty() {
+        return Err(Error::MissingAssets(lints.len()).into());
+    }
+    Ok(())
+}
+
+fn check_assets(config: &Config, story: &Story, lints: &mut Vec<Lint>) {
+    let roots = asset_roots(config);
+    for p in &story.passages {
+        for r in asset_references(&p.content) {
+            let path = asset_path(&r);
+            if ! roots.iter().any(|root| root.join(&path).exists()) {
+                lints.push(Lint {
+                    rule: "missing-asset",
+                    passage: p.name.clone(),
+                    message: format!("Referenced file \"{}\" does not exist.", r),
+                });
+            }
+        }
+    }
+}
+
+/// Harlowe macros that are commonly mistaken for other formats' syntax. Only these are checked, so prose in
+/// parentheses like "(note: ...)" isn't reported.
+const HARLOWE_MACROS: &[&str] = &[
+    "set", "put", "move", "if", "else-if", "elseif", "else", "unless", "link", "link-goto", "link-reveal",
+    "link-repeat", "link-rerun", "link-reveal-goto", "goto", "go-to", "display", "print", "either", "random",
+    "history", "visited", "live", "stop", "click", "replace", "append", "prepend", "hook", "a", "dm", "datamap",
+    "array", "cond", "for", "loop", "text-colour", "text-color", "colour", "color", "transition", "t8n", "align",
+    "font", "css", "show", "hide", "hidden", "mouseover", "prompt", "alert", "confirm", "save-game", "load-game",
+    "cycling-link", "dropdown", "input-box", "undo", "enchant", "after", "more", "verbatim",
+];
+
+/// Reports syntax of other story formats in the passages, which mostly happens when copying examples.
+fn check_format(story: &Story, lints: &mut Vec<Lint>) {
+    let Some(format) = story.meta.get("format").and_then(|f| f.as_str()) else {
+        return;
+    };
+    if ! ["Harlowe", "SugarCube", "Chapbook", "Snowman"].contains(&format) {
+        return;
+    }
+    let harlowe = Regex::new(r"\(([a-z][a-z0-9-]*):").unwrap();
+    let checks: [(&str, Regex, &str); 4] = [
+        ("SugarCube", Regex::new(r"<<(?:/?[A-Za-z][\w-]*|=|-)").unwrap(), "SugarCube macro"),
+        ("Snowman", Regex::new(r"<%[=-]?").unwrap(), "Snowman template"),
+        ("Chapbook", Regex::new(r"\{(?:back link|restart link|reveal link|embed passage|cycling link|text input|dropdown menu|link to|random choice)\b[^}]*\}").unwrap(), "Chapbook insert"),
+        ("Chapbook", Regex::new(r"\A(?:[A-Za-z_][\w.]*(?: \([^)\n]*\))?:[^\n]*\n)+--[ \t]*(?:\n|\z)").unwrap(), "Chapbook vars section"),
+    ];
+    for p in &story.passages {
+        if p.tags.iter().any(|t| t == "script" || t == "stylesheet") || p.name == "StoryData" || p.name == "StoryTitle" {
+            continue;
+        }
+        for (f, re, what) in &checks {
+            if *f == format {
+                continue;
+            }
+            if let Some(m) = re.find(&p.content) {
+                lints.push(Lint {
+                    rule: "format-mismatch",
+                    passage: p.name.clone(),
+                    message: format!("\"{}\" looks like a {}, but the story format is {}.", m.as_str().lines().next().unwrap_or(""), what, format),
+                });
+            }
+        }
+        if format != "Harlowe" {
+            if let Some(c) = harlowe.captures_iter(&p.content).find(|c| HARLOWE_MACROS.contains(&&c[1])) {
+                lints.push(Lint {
+                    rule: "format-mismatch",
+                    passage: p.name.clone(),
+                    message: format!("\"{}\" looks like a Harlowe macro, but the story format is {}.", &c[0], format),
+                });
+            }
+        }
+    }
+}
+
+/// Reports images without alternative text for screen readers.
+fn check_images(story: &Story, lints: &mut Vec<Lint>) {
+    let img = Regex::new(r"(?i)<img\b[^>]*>").unwrap();
+    let alt = Regex::new(r"(?i)\balt\s*=").unwrap();
+    // SugarCube uses the title of [img[Title|src]] as alt text.
+    let sugarcube = Regex::new(r"\[img\[([^\]]*)\]").unwrap();
+    for p in &story.passages {
+        for m in img.find_iter(&p.content) {
+            if ! alt.is_match(m.as_str()) {
+                lints.push(Lint {
+                    rule: "img-alt",
+                    passage: p.name.clone(),
+                    message: format!("Image \"{}\" has no alt text.", m.as_str()),
+                });
+            }
+        }
+        for c in sugarcube.captures_iter(&p.content) {
+            if ! c[1].contains('|') {
+                lints.push(Lint {
+                    rule: "img-alt",
+                    passage: p.name.clone(),
+                    message: format!("Image \"{}\" has no title, which is used as alt text.", &c[1]),
+                });
+            }
+        }
+    }
+}
+
+/// Link texts that don't say where the link leads when read out of context by a screen reader.
+const VAGUE_LINK_TEXTS: &[&str] = &["here", "click", "click here", "this", "link", "more", "read more", "go", "next"];
+
+fn check_link_text(story: &Story, lints: &mut Vec<Lint>) {
+    let anchor = Regex::new(r"(?is)<a\b[^>]*>(.*?)</a>").unwrap();
+    for p in &story.passages {
+        let texts = p.links().into_iter().map(|l| l.text)
+            .chain(anchor.captures_iter(&p.content).map(|c| c[1].to_string()));
+        for t in texts {
+            let normalized = t.trim().trim_end_matches(['.', '!', '>']).trim().to_lowercase();
+            if VAGUE_LINK_TEXTS.contains(&normalized.as_str()) {
+                lints.push(Lint {
+                    rule: "vague-link-text",
+                    passage: p.name.clone(),
+                    message: format!("Link text \"{}\" doesn't describe where the link leads.", t.trim()),
+                });
+            }
+        }
+    }
+}
+
+/// Reports links without display text, choices with the same text in one passage, and choices that are too long.
+fn check_choices(config: &Config, story: &Story, lints: &mut Vec<Lint>) {
+    let max_length = config.lint.max_link_text.unwrap_or(80);
+    for p in &story.passages {
+        let mut seen: Vec<String> = vec![];
+        for l in p.links() {
+            let text = l.text.trim();
+            if text.is_empty() {
+                lints.push(Lint {
+                    rule: "empty-link-text",
+                    passage: p.name.clone(),
+                    message: format!("The link \"{}\" has no text to show.", &p.content[l.span.clone()]),
+                });
+                continue;
+            }
+            let normalized = text.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase();
+            if seen.contains(&normalized) {
+                lints.push(Lint {
+                    rule: "duplicate-link-text",
+                    passage: p.name.clone(),
+                    message: format!("The choice \"{}\" appears more than once, so players can't tell the links apart.", text),
+                });
+            } else {
+                seen.push(normalized);
+            }
+            let length = text.chars().count();
+            if length > max_length {
+                lints.push(Lint {
+                    rule: "long-link-text",
+                    passage: p.name.clone(),
+                    message: format!("The choice \"{}\" is {} characters long, more than {}.", text, length, max_length),
+                });
+            }
+        }
+    }
+}
+
+fn parse_hex_color(c: &str) -> Option<[f64; 3]> {
+    let c = c.strip_prefix('#')?;
+    let c: String = match c.len() {
+        3 => c.chars().flat_map(|c| [c, c]).collect(),
+        6 => c.to_string(),
+        _ => return None,
+    };
+    let channel = |i: usize| u8::from_str_radix(&c[i..i + 2], 16).ok().map(|v| v as f64 / 255.0);
+    Some([channel(0)?, channel(2)?, channel(4)?])
+}
+
+/// The WCAG relative luminance of a color.
+fn luminance(c: [f64; 3]) -> f64 {
+    let l = c.map(|v| if v <= 0.03928 { v / 12.92 } else { ((v + 0.055) / 1.055).powf(2.4) });
+    0.2126 * l[0] + 0.7152 * l[1] + 0.0722 * l[2]
+}
+
+/// The WCAG contrast ratio between two colors, from 1 to 21.
+pub(crate) fn contrast_ratio(a: [f64; 3], b: [f64; 3]) -> f64 {
+    let (a, b) = (luminance(a), luminance(b));
+    (a.max(b) + 0.05) / (a.min(b) + 0.05)
+}
+
+/// Reports CSS rules setting both a text and background color with less than the WCAG AA contrast ratio of 4.5:1.
+fn check_contrast(story: &Story, lints: &mut Vec<Lint>) {
+    let rule = Regex::new(r"([^{}]+)\{([^{}]*)\}").unwrap();
+    let color = Regex::new(r"(?i)(?:^|[;\s])color\s*:\s*(#[0-9a-f]{3,6})\b").unwrap();
+    let background = Regex::new(r"(?i)background(?:-color)?\s*:[^;]*?(#[0-9a-f]{3,6})\b").unwrap();
+    for p in story.passages.iter().filter(|p| p.tags.iter().any(|t| t == "stylesheet")) {
+        for r in rule.captures_iter(&p.content) {
+            let (Some(fg), Some(bg)) = (color.captures(&r[2]), background.captures(&r[2])) else {
+                continue;
+            };
+            let (Some(fg_c), Some(bg_c)) = (parse_hex_color(&fg[1]), parse_hex_color(&bg[1])) else {
+                continue;
+            };
+            let ratio = contrast_ratio(fg_c, bg_c);
+            if ratio < 4.5 {
+                lints.push(Lint {
+                    rule: "low-contrast",
+                    passage: p.name.clone(),
+                    message: format!("\"{}\": the contrast of {} on {} is {:.2}:1, less than 4.5:1.", r[1].trim(), &fg[1], &bg[1], ratio),
+                });
+            }
+        }
+    }
+}
+
+/// Reports a missing `lang` in the StoryData, which screen readers need to pick the pronunciation.
+fn check_lang(story: &Story, lints: &mut Vec<Lint>) {
+    if story.meta.get("lang").and_then(|l| l.as_str()).is_none_or(|l| l.is_empty()) {
+        lints.push(Lint {
+            rule: "missing-lang",
+            passage: "StoryData".to_string(),
+            message: "No \"lang\" is set, like \"lang\": \"en\".".to_string(),
+        });
+    }
+}
+
+fn check_duplicates(config: &Config, story: &Story, lints: &mut Vec<Lint>) {
+    let threshold = config.lint.similarity.unwrap_or(0.9);
+    for group in story.near_duplicates(threshold) {
+        lints.push(Lint {
+            rule: "near-duplicate",
+            passage: group[0].clone(),
+            message: format!("The content is at least {:.0}% similar to {}.", threshold * 100.0,
+                group[1..].iter().map(|n| format!("\"{}\"", n)).collect::<Vec<_>>().join(", ")),
+        });
+    }
+}
+
+/// Reports passages players can reach through a link that don't link anywhere and aren't tagged as an ending.
+/// Passages no link leads to, like ones only included in others, aren't reported.
+fn check_dead_ends(config: &Config, story: &Story, lints: &mut Vec<Lint>) {
+    let tag = config.lint.ending_tag.clone().unwrap_or("ending".to_string());
+    let start = story.start_passage().map(|p| p.name.as_str());
+    let linked: BTreeSet<String> = story.passages.iter().flat_map(|p| story.navigation(p)).filter(|n| ! n.embed).map(|n| n.target).collect();
+    for p in story.endings() {
+        if p.tags.contains(&tag) || ! (linked.contains(&p.name) || start == Some(p.name.as_str())) {
+            continue;
+        }
+        lints.push(Lint {
+            rule: "dead-end",
+            passage: p.name.clone(),
+            message: format!("The passage has no links, tag it \"{}\" if it's meant to be an ending.", tag),
+        });
+    }
+}
+
+/// The links and navigation macros of a passage to passages of the story, with where they are in the passage content.
+fn describe_links(p: &Passage, links: &[Navigation]) -> String {
+    links.iter().map(|l| format!("\"{}\" (line {})", &p.content[l.span.clone()], p.content[..l.span.start].matches('\n').count() + 1))
+        .collect::<Vec<_>>().join(", ")
+}
+
+/// Reports passages whose links all lead back to the passage itself, and pairs of passages that only link to each
+/// other, which players can't leave. These are usually link targets that weren't changed after copying a passage.
+/// Passages navigating to a dynamic target, like `(goto: $next)`, may lead anywhere and aren't reported.
+fn check_loops(story: &Story, lints: &mut Vec<Lint>) {
+    let targets = |p: &Passage| -> (Vec<Navigation>, BTreeSet<String>) {
+        let nav = story.navigation(p);
+        if nav.iter().any(|n| n.dynamic) {
+            return (vec![], BTreeSet::new());
+        }
+        let links: Vec<Navigation> = nav.into_iter().filter(|n| ! n.embed && story.passage(&n.target).is_some()).collect();
+        let names = links.iter().map(|l| l.target.clone()).collect();
+        (links, names)
+    };
+    for p in story.passages.iter().filter(|p| ! is_special_passage(p)) {
+        let (links, names) = targets(p);
+        if names.len() != 1 {
+            continue;
+        }
+        let other = names.first().unwrap();
+        if other == &p.name {
+            lints.push(Lint {
+                rule: "self-link",
+                passage: p.name.clone(),
+                message: format!("The passage only links to itself: {}.", describe_links(p, &links)),
+            });
+            continue;
+        }
+        let q = story.passage(other).unwrap();
+        // Each pair is reported once, at the passage that comes first.
+        if story.passages.iter().position(|s| s.name == q.name) < story.passages.iter().position(|s| s.name == p.name) {
+            continue;
+        }
+        let (back, back_names) = targets(q);
+        if back_names.len() == 1 && back_names.contains(&p.name) {
+            lints.push(Lint {
+                rule: "two-passage-loop",
+                passage: p.name.clone(),
+                message: format!("The passage and \"{}\" only link to each other: {} here, and {} there.", q.name, describe_links(p, &links), describe_links(q, &back)),
+            });
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use twee_build::config_from_source;
+    use twee_parser::parse_twee3;
+
+    use super::*;
+    
+    fn test_config(extra: &str) -> Config {
+        config_from_source(&format!("main = \"main.twee\"\nstyle = []\nscript = []\nprebuild = []\n{}", extra)).unwrap()
+    }
+    
+    fn lint_rule(config: &Config, source: &str, rule: &str) -> Vec<Lint> {
+        let (story, _) = parse_twee3(source).unwrap();
+        let mut lints = lint_story(config, &story);
+        lints.retain(|l| l.rule == rule);
+        lints
+    }
+    
+    #[test]
+    fn asset_reference_kinds() {
+        let refs = asset_references(concat!(
+            "<img src=\"img/a.png\"> <audio src='b.mp3'>\n",
+            ".x { background: url(bg.jpg) } .y { background: url( \"c d.png\" ) }\n",
+            "[img[Alt|img/e.png]] [img[f.png]]\n",
+            "<<cacheaudio \"theme\" \"music/theme.mp3\" \"music/theme.ogg\">> <<track \"t\" 'g.wav'>>\n",
+            "<img src=\"https://example.com/x.png\"> <img src=\"data:image/png;base64,AA\"> url(#id) <img src=\"$img\">\n",
+        ));
+        assert_eq!(refs, ["img/a.png", "b.mp3", "bg.jpg", "c d.png", "img/e.png", "f.png", "music/theme.mp3", "music/theme.ogg", "g.wav"]);
+    }
+    
+    #[test]
+    fn missing_assets_in_asset_roots() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        std::fs::create_dir_all(dir.join("images")).unwrap();
+        std::fs::create_dir_all(dir.join("audio")).unwrap();
+        std::fs::write(dir.join("images/img one.png"), "").unwrap();
+        std::fs::write(dir.join("audio/sound.mp3"), "").unwrap();
+        let roots = format!("asset_roots = [{:?}, {:?}]\n", dir.join("images"), dir.join("audio"));
+        let source = ":: Start\n<img src=\"img%20one.png\"> <<cacheaudio \"s\" \"sound.mp3?v=2\">> <img src=\"missing.png\">\n";
+        let config = test_config(&roots);
+        let lints = lint_rule(&config, source, "missing-asset");
+        assert_eq!(lints.len(), 1);
+        assert_eq!(lints[0].message, "Referenced file \"missing.png\" does not exist.");
+        let (story, _) = parse_twee3(source).unwrap();
+        assert_eq!(asset_files(&config, &story), [dir.join("images/img one.png"), dir.join("audio/sound.mp3")]);
+        // Builds only warn, unless configured to fail or the rule is off.
+        check_build_assets(&config, &story).unwrap();
+        let error = check_build_assets(&test_config(&format!("{}missing_asset_error = true\n", roots)), &story).unwrap_err();
+        assert_eq!(error.to_string(), Error::MissingAssets(1).to_string());
+        check_build_assets(&test_config(&format!("{}missing_asset_error = true\n[lint]\nrules.missing-asset = \"off\"\n", roots)), &story).unwrap();
+    }
+    
+    fn assert_ratio(a: &str, b: &str, expected: f64) {
+        let ratio = contrast_ratio(parse_hex_color(a).unwrap(), parse_hex_color(b).unwrap());
+        assert!((ratio - expected).abs() < 0.01, "{} on {}: {} instead of {}", a, b, ratio, expected);
+    }
+    
+    #[test]
+    fn contrast_ratio_extremes() {
+        assert_ratio("#000000", "#ffffff", 21.0);
+        assert_ratio("#ffffff", "#000000", 21.0);
+        assert_ratio("#777777", "#777777", 1.0);
+    }
+    
+    #[test]
+    fn contrast_ratio_known_values() {
+        // Reference values from the WebAIM contrast checker.
+        assert_ratio("#777777", "#ffffff", 4.48);
+        assert_ratio("#767676", "#ffffff", 4.54);
+        assert_ratio("#ff0000", "#ffffff", 4.00);
+        assert_ratio("#0000ff", "#ffffff", 8.59);
+    }
+    
+    #[test]
+    fn parse_short_hex_colors() {
+        assert_eq!(parse_hex_color("#fff"), parse_hex_color("#ffffff"));
+        assert_eq!(parse_hex_color("#09C"), Some([0.0, 0.6, 0.8]));
+        assert_eq!(parse_hex_color("#ffff"), None);
+        assert_eq!(parse_hex_color("fff"), None);
+    }
+    
+    #[test]
+    fn low_contrast_lint() {
+        let (story, _) = parse_twee3(":: Style [stylesheet]\n.bad { color: #777; background: #fff }\n.good { color: #000; background-color: #fff }\n.fg-only { color: #777 }\n").unwrap();
+        let mut lints = vec![];
+        check_contrast(&story, &mut lints);
+        assert_eq!(lints.len(), 1);
+        assert_eq!(lints[0].rule, "low-contrast");
+        assert!(lints[0].message.starts_with("\".bad\""), "{}", lints[0].message);
+    }
+    
+    #[test]
+    fn dead_ends() {
+        let source = ":: StoryData\n{\"start\": \"Start\"}\n\n:: Start\n[[Hall]] [[Finale]] [[Epilogue]]\n\n:: Hall\nNothing here.\n\n:: Finale [ending]\nThe end.\n\n:: Epilogue [fin]\nAfter the end.\n\n:: Unlinked\nOnly included elsewhere.\n";
+        let lints = lint_rule(&test_config(""), source, "dead-end");
+        assert_eq!(lints.iter().map(|l| l.passage.as_str()).collect::<Vec<_>>(), ["Hall", "Epilogue"]);
+        assert_eq!(lints[0].message, "The passage has no links, tag it \"ending\" if it's meant to be an ending.");
+        // With a custom tag, only passages with that tag are endings.
+        let lints = lint_rule(&test_config("[lint]\nending_tag = \"fin\"\n"), source, "dead-end");
+        assert_eq!(lints.iter().map(|l| l.passage.as_str()).collect::<Vec<_>>(), ["Hall", "Finale"]);
+        assert_eq!(lints[0].message, "The passage has no links, tag it \"fin\" if it's meant to be an ending.");
+    }
+    
+    #[test]
+    fn loops() {
+        let source = concat!(
+            ":: StoryData\n{\"start\": \"Start\"}\n\n",
+            ":: Start\n[[Again->Start]]\n\n",
+            ":: A\nGo on.\n[[B]]\n\n:: B\n[[Back->A]] [[A]]\n\n",
+            ":: C\n[[D]]\n\n:: D\n[[C]] [[Exit]]\n\n",
+            ":: E\n[[E]] [[Exit]]\n\n",
+            ":: Exit [ending]\nThe end.\n",
+        );
+        let config = test_config("");
+        let lints = lint_rule(&config, source, "self-link");
+        assert_eq!(lints.len(), 1);
+        assert_eq!((lints[0].passage.as_str(), lints[0].message.as_str()), ("Start", "The passage only links to itself: \"[[Again->Start]]\" (line 1)."));
+        // Loops with another exit, like C and D or E, aren't reported, and the pair is reported once.
+        let lints = lint_rule(&config, source, "two-passage-loop");
+        assert_eq!(lints.len(), 1);
+        assert_eq!(lints[0].passage, "A");
+        assert_eq!(lints[0].message, "The passage and \"B\" only link to each other: \"[[B]]\" (line 2) here, and \"[[Back->A]]\" (line 1), \"[[A]]\" (line 1) there.");
+    }
+    
+    #[test]
+    fn choices() {
+        let source = ":: Start\n[[->A]] [[Go on|A]] [[go  ON|B]] [[A very long choice text->C]] [[Short->C]]\n\n:: Other\n[[Go on|A]]\n\n:: A\n\n:: B\n\n:: C\n";
+        let config = test_config("[lint]\nmax_link_text = 20\n");
+        let lints = lint_rule(&config, source, "empty-link-text");
+        assert_eq!(lints.len(), 1);
+        assert_eq!(lints[0].message, "The link \"[[->A]]\" has no text to show.");
+        // Choices differing only in case and spaces are the same, but only within a passage.
+        let lints = lint_rule(&config, source, "duplicate-link-text");
+        assert_eq!(lints.len(), 1);
+        assert_eq!((lints[0].passage.as_str(), lints[0].message.as_str()), ("Start", "The choice \"go  ON\" appears more than once, so players can't tell the links apart."));
+        let lints = lint_rule(&config, source, "long-link-text");
+        assert_eq!(lints.len(), 1);
+        assert_eq!(lints[0].message, "The choice \"A very long choice text\" is 23 characters long, more than 20.");
+        assert!(lint_rule(&test_config(""), source, "long-link-text").is_empty());
+    }
+    
+    #[test]
+    fn near_duplicate_message() {
+        let text = "The rain keeps falling on the old stone bridge while the river below rises slowly.";
+        let source = format!(":: A\n{}\n\n:: B\n{}!\n", text, text);
+        let lints = lint_rule(&test_config(""), &source, "near-duplicate");
+        assert_eq!(lints.len(), 1);
+        assert_eq!(lints[0].message, "The content is at least 90% similar to \"B\".");
+    }
+    
+    /// A story in the format, with its syntax in the passage "Own" and the syntax of other formats in "Other".
+    fn format_story(format: &str, version: &str, own: &str, other: &str) -> String {
+        format!(":: StoryData\n{{\"ifid\": \"8F0A7B1C-3A8E-4C5D-9C2B-1D2E3F4A5B6C\", \"format\": {:?}, \"format-version\": {:?}}}\n\n:: Own\n{}\n\n:: Other\n{}\n\n:: Code [script]\n<<set $x to 1>> (set: $y to 2)\n", format, version, own, other)
+    }
+    
+    #[test]
+    fn format_mismatch() {
+        let config = test_config("");
+        let source = format_story("SugarCube", "2.36.1", "<<set $x to 1>>", "(set: $x to 1) {back link}\n<% s.x = 1 %>");
+        let lints = lint_rule(&config, &source, "format-mismatch");
+        let messages: Vec<(&str, &str)> = lints.iter().map(|l| (l.passage.as_str(), l.message.as_str())).collect();
+        assert_eq!(messages, [
+            ("Other", "\"<%\" looks like a Snowman template, but the story format is SugarCube."),
+            ("Other", "\"{back link}\" looks like a Chapbook insert, but the story format is SugarCube."),
+            ("Other", "\"(set:\" looks like a Harlowe macro, but the story format is SugarCube."),
+        ]);
+        let source = format_story("Harlowe", "3.3.9", "(set: $x to 1)", "<<if $x>>yes<</if>>");
+        let lints = lint_rule(&config, &source, "format-mismatch");
+        assert_eq!(lints.len(), 1);
+        assert_eq!(lints[0].message, "\"<<if\" looks like a SugarCube macro, but the story format is Harlowe.");
+        let source = format_story("Chapbook", "2.2.0", "x: 1\n--\n{back link}", "<<set $x to 1>>");
+        assert_eq!(lint_rule(&config, &source, "format-mismatch").len(), 1);
+    }
+    
+    #[test]
+    fn format_mismatch_unknown_format() {
+        let source = format_story("Paperthin", "1.0.0", "", "<<set $x to 1>> (set: $x to 1) <% s.x = 1 %>");
+        assert!(lint_rule(&test_config(""), &source, "format-mismatch").is_empty());
+        let source = ":: Start\n<<set $x to 1>> (set: $x to 1)\n";
+        assert!(lint_rule(&test_config(""), source, "format-mismatch").is_empty());
+    }
+    
+    #[test]
+    fn format_mismatch_other_versions() {
+        // The syntax doesn't depend on the version of the format.
+        for version in ["1.0.0", "2.36.1", "", "not a version"] {
+            let source = format_story("SugarCube", version, "<<set $x to 1>>", "(set: $x to 1)");
+            let lints = lint_rule(&test_config(""), &source, "format-mismatch");
+            assert_eq!(lints.len(), 1, "{}", version);
+            assert_eq!(lints[0].passage, "Other");
+        }
+    }
+}
@@ -0,0 +1,97 @@
+use std::collections::BTreeMap;
+
+use clap::ValueEnum;
+use serde::Serialize;
+use twee_build::{build_story, load_config};
+
+use crate::{search::{display_path, passage_sources}, spellcheck::prose, stats::{is_prose, words}};
+
+/// The order of `list passages`.
+#[derive(Debug, Clone, Copy, Default, ValueEnum)]
+pub(crate) enum ListSort {
+    /// The order in the story.
+    #[default]
+    Source,
+    Name,
+    /// Most words first.
+    Words,
+    /// By twee file, and in story order within a file.
+    File,
+}
+
+/// A passage in the output of `list passages`.
+#[derive(Serialize)]
+pub(crate) struct PassageEntry {
+    pub name: String,
+    pub tags: Vec<String>,
+    pub words: usize,
+    /// The twee file the passage is in, if it isn't generated by the build.
+    pub file: Option<String>,
+}
+
+/// A tag in the output of `list tags`.
+#[derive(Serialize)]
+pub(crate) struct TagEntry {
+    pub tag: String,
+    pub passages: usize,
+    pub words: usize,
+}
+
+pub(crate) fn passage_entries() -> anyhow::Result<Vec<PassageEntry>> {
+    let config = load_config()?;
+    let story = build_story(&config, false)?;
+    let sources = passage_sources()?;
+    let format = story.meta.get("format").and_then(|f| f.as_str()).unwrap_or("");
+    Ok(story.passages.iter().map(|p| PassageEntry {
+        name: p.name.clone(),
+        tags: p.tags.clone(),
+        words: if is_prose(p) { words(&prose(p, format)).len() } else { 0 },
+        file: sources.get(&p.name).map(|(f, _, _)| display_path(f)),
+    }).collect())
+}
+
+/// Prints the passages of the story in the current directory with their tags, word counts and twee files,
+/// optionally only those with a tag.
+pub(crate) fn list_passages(tag: Option<String>, sort: ListSort, json: bool) -> anyhow::Result<()> {
+    let mut passages = passage_entries()?;
+    if let Some(tag) = tag {
+        passages.retain(|p| p.tags.contains(&tag));
+    }
+    match sort {
+        ListSort::Source => {},
+        ListSort::Name => passages.sort_by(|a, b| a.name.cmp(&b.name)),
+        ListSort::Words => passages.sort_by_key(|p| std::cmp::Reverse(p.words)),
+        ListSort::File => passages.sort_by(|a, b| a.file.cmp(&b.file)),
+    }
+    if json {
+        println!("{}", serde_json::to_string_pretty(&passages)?);
+        return Ok(());
+    }
+    println!("{:<30} {:>7}  {:<20} Tags", "Passage", "Words", "File");
+    for p in &passages {
+        println!("{:<30} {:>7}  {:<20} {}", p.name, p.words, p.file.as_deref().unwrap_or("(generated)"), p.tags.join(" "));
+    }
+    Ok(())
+}
+
+/// Prints the tags of the story in the current directory with the number of passages and words.
+pub(crate) fn list_tags(json: bool) -> anyhow::Result<()> {
+    let mut tags: BTreeMap<String, (usize, usize)> = BTreeMap::new();
+    for p in passage_entries()? {
+        for t in p.tags {
+            let e = tags.entry(t).or_default();
+            e.0 += 1;
+            e.1 += p.words;
+        }
+    }
+    let tags: Vec<TagEntry> = tags.into_iter().map(|(tag, (passages, words))| TagEntry { tag, passages, words }).collect();
+    if json {
+        println!("{}", serde_json::to_string_pretty(&tags)?);
+        return Ok(());
+    }
+    println!("{:<30} {:>8} {:>7}", "Tag", "Passages", "Words");
+    for t in &tags {
+        println!("{:<30} {:>8} {:>7}", t.tag, t.passages, t.words);
+    }
+    Ok(())
+}
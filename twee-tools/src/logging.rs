@@ -0,0 +1,35 @@
+use std::io::IsTerminal;
+
+use clap::ValueEnum;
+use tracing::Level;
+
+#[derive(Debug, Clone, Copy, Default, ValueEnum)]
+pub(crate) enum LogFormat {
+    /// Human-readable lines.
+    #[default]
+    Text,
+    /// One JSON object per line, for build servers.
+    Json,
+}
+
+/// Sets up the logger.  
+/// `verbosity` is the number of -v options minus the number of -q options, 0 logs warnings and info messages.
+pub(crate) fn init_logging(verbosity: i8, format: LogFormat, timestamps: bool) {
+    let level = match verbosity {
+        i8::MIN..=-2 => Level::ERROR,
+        -1 => Level::WARN,
+        0 => Level::INFO,
+        1 => Level::DEBUG,
+        _ => Level::TRACE,
+    };
+    let builder = tracing_subscriber::fmt()
+        .with_max_level(level)
+        .with_writer(std::io::stderr)
+        .with_ansi(std::io::stderr().is_terminal())
+        .with_target(false);
+    match (format, timestamps) {
+        (LogFormat::Json, _) => builder.json().init(),
+        (LogFormat::Text, true) => builder.init(),
+        (LogFormat::Text, false) => builder.without_time().init(),
+    }
+}
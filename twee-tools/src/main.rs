@@ -1,24 +1,110 @@
 
-use std::{fs::File, io::{stderr, Read, Write}, path::PathBuf, process::Stdio, sync::OnceLock, thread::sleep, time::Duration};
+use std::{collections::BTreeMap, fs::File, io::{Read, Write}, path::{Path, PathBuf}, thread::sleep, time::{Duration, Instant}};
 
 use anyhow::Ok;
 use clap::{Parser, Subcommand, ValueEnum};
 use notify::{Event, Watcher};
-use rand::{RngCore, SeedableRng};
-use twee_parser::{parse_archive, parse_html, parse_twee3, serde_json::Value, serialize_html, serialize_twee3, xmltree::EmitterConfig, Story};
+use tracing::{debug, error, info, warn};
+use twee_build::{absolute_path, build_html, build_story, build_timings, clear_build_inputs, config_schema, format_source, include_graph, load_config, load_config_in, output_path, passage_source, print_warning, read_file, storydata_html, track_input, write_output, Build, BuildInputs, IncludeEntry, IncludeKind, Profile, Project, Severity, StoryFormat, DRY_RUN, OFFLINE};
+use twee_parser::{export_jsonl, parse_archive, parse_html, parse_twee3, serialize_json, serialize_twee3, MappedArchive, Story, StorydataBlock, Warning};
 
 const DEFAULT_CONFIG: &str = include_str!("../config.toml.default");
 const DEFAULT_TWEE: &str = include_str!("../story.twee.default");
 const DEFAULT_JS: &str = include_str!("../story.js.default");
 const DEFAULT_CSS: &str = include_str!("../story.css.default");
-
-static FORMAT_HARLOWE: OnceLock<String> = OnceLock::new();
-static FORMAT_CHAPBOOK: OnceLock<String> = OnceLock::new();
-static FORMAT_SNOWMAN: OnceLock<String> = OnceLock::new();
-static FORMAT_SUGARCUBE: OnceLock<String> = OnceLock::new();
+/// The number of included files `build --timings` lists.
+const SLOWEST_INCLUDES: usize = 5;
 
 mod build;
 use build::*;
+mod vendor;
+use vendor::*;
+mod workspace;
+use workspace::*;
+mod lint;
+use lint::*;
+mod size;
+use size::*;
+mod package;
+use package::*;
+mod publish;
+use publish::*;
+mod export;
+use export::*;
+mod play;
+use play::*;
+mod browser;
+use browser::*;
+mod clean;
+use clean::*;
+mod logging;
+use logging::*;
+mod upgrade;
+use upgrade::*;
+mod spellcheck;
+use spellcheck::*;
+mod stats;
+use stats::*;
+mod ifid;
+use ifid::*;
+mod import;
+use import::*;
+mod obsidian;
+use obsidian::*;
+mod sqlite;
+use sqlite::*;
+mod api;
+use api::*;
+mod websocket;
+mod collab;
+mod sync;
+use sync::*;
+mod history;
+use history::*;
+mod notification;
+use notification::*;
+mod reload;
+use reload::*;
+mod serve;
+use serve::*;
+mod split;
+use split::*;
+mod compat;
+use compat::*;
+mod endings;
+use endings::*;
+mod csv;
+mod choices;
+use choices::*;
+mod dialogue;
+use dialogue::*;
+mod characters;
+use characters::*;
+mod todo;
+use todo::*;
+mod add;
+use add::*;
+mod search;
+use search::*;
+mod diff;
+mod replace;
+use replace::*;
+mod list;
+use list::*;
+mod show;
+use show::*;
+mod storydata;
+use storydata::*;
+mod tag;
+use tag::*;
+mod merge;
+use merge::*;
+mod check;
+use check::*;
+mod site;
+use site::*;
+mod docx;
+use docx::*;
 
 
 
@@ -29,64 +115,60 @@ use build::*;
 #[command(version)]
 struct Cli {
     
+    /// Reports the files that would be written or removed instead of changing them.
+    #[arg(long, global = true)]
+    dry_run: bool,
+    
+    /// Uses only cached copies of remote includes, and fails if one isn't cached.
+    #[arg(long, global = true)]
+    offline: bool,
+    
+    /// Logs more details. Can be given twice.
+    #[arg(short, long, global = true, action = clap::ArgAction::Count)]
+    verbose: u8,
+    
+    /// Only logs warnings, or only errors if given twice.
+    #[arg(short, long, global = true, action = clap::ArgAction::Count)]
+    quiet: u8,
+    
+    /// The format of log messages.
+    #[arg(long, global = true, value_enum, default_value_t)]
+    log_format: LogFormat,
+    
     #[command(subcommand)]
     command: Command
 }
 
 
-#[derive(Debug, Clone, Copy, ValueEnum)]
-enum StoryFormat {
-    Harlowe,
-    Chapbook,
-    Snowman,
-    Sugarcube,
+/// The output formats of `decompile`.
+#[derive(Debug, Clone, Copy, Default, ValueEnum)]
+enum Emit {
+    /// A .twee file.
+    #[default]
+    Twee,
+    /// A Twine JSON file.
+    Json,
+    /// A project directory with config.toml, story.twee, story.js and story.css, like `import-twine` creates.
+    Project,
 }
 
 
-impl StoryFormat {
-    fn format_name(&self) -> String {
-        match self {
-            StoryFormat::Harlowe => "Harlowe",
-            StoryFormat::Chapbook => "Chapbook",
-            StoryFormat::Snowman => "Snowman",
-            StoryFormat::Sugarcube => "SugarCube",
-        }.to_owned()
-    }
-    
-    fn from_name(name: &str) -> anyhow::Result<Self> {
-        Ok(match name {
-            "Harlowe" => Self::Harlowe,
-            "Chapbook" => Self::Chapbook,
-            "Snowman" => Self::Snowman,
-            "SugarCube" => Self::Sugarcube,
-            _ => {
-                return Err(Error::UnknownStoryFormat(name.to_string()).into());
-            }
-        })
-    }
-    
-    fn format_version(&self) -> String {
-        match self {
-            StoryFormat::Harlowe => "3.3.8",
-            StoryFormat::Chapbook => "1.2.3",
-            StoryFormat::Snowman => "2.0.2",
-            StoryFormat::Sugarcube => "2.36.1",
-        }.to_string()
-    }
-    
-    fn format_contents(&self) -> String {
-        match self {
-            StoryFormat::Harlowe => FORMAT_HARLOWE.get().unwrap().clone(),
-            StoryFormat::Chapbook => FORMAT_CHAPBOOK.get().unwrap().clone(),
-            StoryFormat::Snowman => FORMAT_SNOWMAN.get().unwrap().clone(),
-            StoryFormat::Sugarcube => FORMAT_SUGARCUBE.get().unwrap().clone(),
-        }
-    }
-    
+/// The representations `build --emit` writes to standard output.
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum BuildEmit {
+    /// The final HTML, like --stdout.
+    Html,
+    /// The story as one .twee file.
+    Twee,
+    /// The story as Twine JSON.
+    Json,
+    /// Only the <tw-storydata> element, without the story format.
+    Storydata,
+    /// JSON Lines with an object per passage, including its links.
+    Jsonl,
 }
 
 
-
 #[derive(Debug, Subcommand)]
 enum Command {
     /// Unpack a Twine HTML archive into .twee files
@@ -96,13 +178,60 @@ enum Command {
         /// The directory to create the .twee files in
         #[arg(default_value = ".")]
         dir: String,
+        /// Gives stories with the same IFID as an earlier story in the archive a new one.
+        #[arg(long)]
+        fix_ifids: bool,
     },
     /// Decompiles a Twine HTML story into a .twee file
     Decompile {
         /// The file to decompile
         file: PathBuf,
-        /// The file to write. Defaults to <story title>.twee
+        /// The file to write, or the directory for --emit project. Defaults to the story title with the extension of the format
         out: Option<PathBuf>,
+        /// The output format
+        #[arg(long, value_enum, default_value_t)]
+        emit: Emit,
+        /// Writes images and other files embedded as base64 data URIs to this directory next to the output, and
+        /// references them by path instead
+        #[arg(long, num_args = 0..=1, default_missing_value = "assets", value_name = "DIR")]
+        extract_assets: Option<String>,
+    },
+    /// Imports the stories of a Twine story library folder, an archive or a story file into a twee project each
+    ImportTwine {
+        /// The folder or file to import
+        path: PathBuf,
+        /// The directory to create the projects in
+        #[arg(default_value = ".")]
+        out: PathBuf,
+    },
+    /// Imports a folder of Markdown notes with [[wikilinks]], like an Obsidian vault, into a twee project with a passage per note
+    ImportObsidian {
+        /// The folder of notes
+        vault: PathBuf,
+        /// The story format to use
+        format: StoryFormat,
+        /// The directory to create the project in
+        #[arg(default_value = ".")]
+        out: PathBuf,
+        /// The title of the story. Defaults to the name of the folder
+        #[arg(long)]
+        title: Option<String>,
+        /// The note to start at. Defaults to the note named Start, or else the first note
+        #[arg(long)]
+        start: Option<String>,
+    },
+    /// Imports the stories of a SQLite database written by `export sqlite` into a twee project each
+    ImportSqlite {
+        /// The database
+        db: PathBuf,
+        /// The directory to create the projects in
+        #[arg(default_value = ".")]
+        out: PathBuf,
+    },
+    /// Syncs the story with the Twine desktop app's story library
+    Sync {
+        #[command(subcommand)]
+        direction: SyncDirection,
     },
     /// Initializes a new Twine project
     Init {
@@ -117,7 +246,66 @@ enum Command {
         dir: PathBuf,
     },
     
-    /// Builds the Story in the current directory.
+    /// Adds, removes or renames a tag in the passages of the twee files. Use --dry-run to only list the passages that
+    /// would change.
+    Tag {
+        #[command(subcommand)]
+        action: TagAction,
+    },
+    
+    /// Merges three versions of a twee file passage by passage, for use as a Git merge driver. Passages changed in both
+    /// versions are merged line by line. The result is written to the file of our version.
+    MergeDriver {
+        /// The common ancestor, %O in the Git driver.
+        base: PathBuf,
+        
+        /// Our version, %A in the Git driver.
+        ours: PathBuf,
+        
+        /// Their version, %B in the Git driver.
+        theirs: PathBuf,
+    },
+    
+    /// Sets a field of the StoryData in the main twee file, like the format-version. Values the story formats or Twine
+    /// don't understand are refused.
+    SetMeta {
+        /// The field.
+        key: String,
+        
+        /// The value, as a string.
+        value: String,
+        
+        /// Takes the value as JSON, for values that aren't strings like the `tag-colors`.
+        #[arg(long)]
+        json: bool,
+    },
+    
+    /// Prints a field of the StoryData in the main twee file, or all of it.
+    GetMeta {
+        /// The field.
+        key: Option<String>,
+    },
+    
+    /// Adds a new passage to the twee file of the passage linking to it, or of the passages sharing its tags, or to the
+    /// main twee file.
+    Add {
+        /// The name of the passage.
+        name: String,
+        
+        /// The tags of the passage, separated by commas.
+        #[arg(short, long, value_delimiter = ',')]
+        tags: Vec<String>,
+        
+        /// The template from the `[add]` section of the config to fill the passage with.
+        #[arg(long)]
+        template: Option<String>,
+        
+        /// Adds a link to the new passage to the end of this passage.
+        #[arg(short, long)]
+        from: Option<String>,
+    },
+    
+    /// Builds the Story in the current directory, or all stories of the workspace.
     Build {
         /// Enables the debug mode of the story format.
         #[arg(short, long)]
@@ -126,17 +314,467 @@ enum Command {
         /// Writes the HTML to standard output instead of the file in config.toml
         #[arg(short, long)]
         stdout: bool,
+        
+        /// Writes the fully processed story to standard output in the given representation instead of building the HTML file
+        #[arg(long, value_enum, conflicts_with = "stdout")]
+        emit: Option<BuildEmit>,
+        
+        /// Prints how the output size is distributed. Also enabled when max_size is set in config.toml.
+        #[arg(long)]
+        size_report: bool,
+        
+        /// Opens the built Story in the default browser.
+        #[arg(short, long)]
+        open: bool,
+        
+        /// Gives workspace members with the same IFID as an earlier member a new one.
+        #[arg(long)]
+        fix_ifids: bool,
+        
+        /// Prints the tree of included files, and which of them were skipped as they were already included.
+        #[arg(long)]
+        explain_includes: bool,
+        
+        /// Prints how long each stage of the build took, and the slowest included files.
+        #[arg(long)]
+        timings: bool,
+        
+        /// Builds up to this many workspace members at the same time. Defaults to the number of CPUs.
+        #[arg(short, long)]
+        jobs: Option<usize>,
     },
     
-    /// Builds the Story in the current directory on any changes.
+    /// Builds the Story in the current directory, or all stories of the workspace, on any changes.
     Watch {
         /// Enables the debug mode of the story format.
         #[arg(short, long)]
         debug: bool,
+        
+        /// Opens the built Story in the default browser after the first build.
+        #[arg(short, long)]
+        open: bool,
+        
+        /// Notifies when a rebuild fails or succeeds again after failing.
+        #[arg(short, long, value_enum)]
+        notify: Option<Notify>,
+        
+        /// Reloads the story in the browser after a rebuild, keeping the story state.
+        #[arg(long)]
+        hot_reload: bool,
+    },
+    
+    /// Builds the Story like watch and serves the current directory over HTTP, reloading the browsers playing it after
+    /// each rebuild.
+    Serve {
+        /// Enables the debug mode of the story format.
+        #[arg(short, long)]
+        debug: bool,
+        
+        /// The address to listen on. Use 0.0.0.0 to play the story on other devices in the network, like a phone.
+        #[arg(long, default_value = "127.0.0.1")]
+        host: String,
+        
+        /// The port to listen on.
+        #[arg(short, long, default_value_t = 8000)]
+        port: u16,
+        
+        /// Opens the served Story in the default browser.
+        #[arg(short, long)]
+        open: bool,
+        
+        /// Notifies when a rebuild fails or succeeds again after failing.
+        #[arg(short, long, value_enum)]
+        notify: Option<Notify>,
+        
+        /// Keeps all browsers playing the Story at the same passage, e.g. to review a walkthrough together on a desktop
+        /// and a phone.
+        #[arg(long)]
+        sync_navigation: bool,
+    },
+    
+    /// Reports changes from an old version of the story that break players' saves, like removed passages.
+    Compat {
+        /// The old version, as an HTML file, archive or twee file
+        old: PathBuf,
+        /// The new version. Defaults to the story in the current directory
+        new: Option<PathBuf>,
+    },
+    
+    /// Builds the story split into one story per chapter tag, e.g. for episodic releases.
+    Split {
+        /// The prefix of the tags to split by, like `chapter` for `chapter1`, `chapter2`. Overrides the config.toml.
+        prefix: Option<String>,
+        
+        /// The directory to write the parts to. Overrides the config.toml.
+        #[arg(short, long)]
+        out: Option<PathBuf>,
+        
+        /// Enables the debug mode of the story format.
+        #[arg(short, long)]
+        debug: bool,
+    },
+    
+    /// Opens the built Story in the current directory in the default browser.
+    Open,
+    
+    /// Builds the Story and packs it into a zip with its assets, ready for uploading to e.g. itch.io.
+    Package {
+        /// Enables the debug mode of the story format.
+        #[arg(short, long)]
+        debug: bool,
+    },
+    
+    /// Publishes the Story.
+    Publish {
+        #[command(subcommand)]
+        target: PublishTarget,
+    },
+    
+    /// Exports the Story into other formats.
+    Export {
+        #[command(subcommand)]
+        target: ExportTarget,
+    },
+    
+    /// Serves a JSON API on localhost for editors and web frontends: listing, reading and changing passages, building,
+    /// linting and the story graph.
+    Api {
+        /// The port to listen on.
+        #[arg(short, long, default_value_t = 8080)]
+        port: u16,
+        
+        /// Allows web pages from this origin, like http://localhost:3000, to use the API.
+        #[arg(long)]
+        allow_origin: Option<String>,
+        
+        /// Rebuilds on changes like `watch`, and sends the changed passages, build results and lint problems to the
+        /// clients of the /events WebSocket.
+        #[arg(short, long)]
+        watch: bool,
+    },
+    
+    /// Plays the Story in the terminal by following the links between passages.
+    Play {
+        /// The passage to start at. Defaults to the start passage.
+        #[arg(short, long)]
+        start: Option<String>,
+        
+        /// Writes the names of the visited passages to this file when quitting.
+        #[arg(short, long)]
+        record: Option<PathBuf>,
+    },
+    
+    /// Removes the built Story, packages, caches and vendored formats of the project in the current directory, or all projects of the workspace.
+    Clean,
+    
+    /// Checks the Story in the current directory, or all stories of the workspace, for common problems.
+    Lint,
+    
+    /// Quickly parses and validates the story without writing anything, e.g. in a Git pre-commit hook. With files or
+    /// --staged, only those files are checked, each on its own.
+    Check {
+        /// Twee files to check instead of the story, `-` reads from stdin.
+        files: Vec<String>,
+        
+        /// Checks the versions of the twee files staged in Git.
+        #[arg(short, long)]
+        staged: bool,
+    },
+    
+    /// Searches the names, tags and text of the passages with a regex, after includes, and prints the matches with the
+    /// file and line they are in.
+    Search {
+        /// The regex to search for.
+        pattern: String,
+        
+        /// Ignores the case of letters.
+        #[arg(short, long)]
+        ignore_case: bool,
+    },
+    
+    /// Lists the passages or tags of the story.
+    List {
+        #[command(subcommand)]
+        target: ListTarget,
+    },
+    
+    /// Prints a passage as it ends up in the built story, after includes and the other build steps, and the file it
+    /// comes from.
+    Show {
+        /// The name of the passage.
+        passage: String,
+        
+        /// Prints the passage as the element in the built HTML instead of Twee.
+        #[arg(long)]
+        html: bool,
+        
+        /// Builds with the debug mode of the story format enabled.
+        #[arg(short, long)]
+        debug: bool,
+    },
+    
+    /// Replaces the matches of a regex in the passages of the twee files and prints the changes as a diff. The files
+    /// are only changed with --write.
+    Replace {
+        /// The regex to search for.
+        pattern: String,
+        
+        /// The replacement. `$1` or `${name}` insert capture groups, `$$` inserts a `$`.
+        replacement: String,
+        
+        /// Also replaces in passage names.
+        #[arg(long)]
+        include_names: bool,
+        
+        /// Ignores the case of letters.
+        #[arg(short, long)]
+        ignore_case: bool,
+        
+        /// Writes the changes to the twee files.
+        #[arg(short, long)]
+        write: bool,
+    },
+    
+    /// Copies the story format, the format.js files of the `formats` config value and the remote includes into the
+    /// vendor directory, which is preferred over the bundled formats and the network when building.
+    Vendor {
+        /// Vendors all bundled story formats instead of only the one the story uses.
+        #[arg(short, long)]
+        all: bool,
+    },
+    
+    /// Prints the number of passages, words and links of the story.
+    Stats {
+        /// Also prints the Flesch-Kincaid grade level and Flesch reading ease of each passage and the whole story.
+        #[arg(short, long)]
+        readability: bool,
+        
+        /// Also prints the word counts of the passages with each tag, and the progress towards the targets from the config.
+        #[arg(short, long)]
+        by_tag: bool,
+        
+        /// Also prints the complexity of each passage and the whole story, the hubs with the most links and the
+        /// bottlenecks every route to an ending goes through.
+        #[arg(short, long)]
+        complexity: bool,
+        
+        /// Prints the statistics, including the word counts by tag, as JSON.
+        #[arg(short, long)]
+        json: bool,
+    },
+    
+    /// Lists the routes from the start passage to each ending, i.e. each passage without links, and the endings that
+    /// can't be reached.
+    Endings {
+        /// The maximum number of passages in a route.
+        #[arg(long, default_value_t = 100)]
+        max_depth: usize,
+        
+        /// The maximum number of routes to find.
+        #[arg(long, default_value_t = 10000)]
+        max_paths: usize,
+        
+        /// Also prints each route.
+        #[arg(short, long)]
+        routes: bool,
+        
+        /// Prints the routes as JSON.
+        #[arg(short, long)]
+        json: bool,
+    },
+    
+    /// Lists the passages each character speaks in or is mentioned in, and how many passages each pair of characters
+    /// shares. Characters are listed in the config or found with the dialogue convention.
+    Characters {
+        /// Prints the passages and shared passages as JSON.
+        #[arg(short, long)]
+        json: bool,
+    },
+    
+    /// Checks the spelling of the passage text with a Hunspell dictionary, skipping markup and code.
+    Spellcheck,
+    
+    /// Lists the notes left in the passages, like `TODO: ...`, `FIXME(name): ...` and HTML comments, with the file and
+    /// line they are in.
+    Todo {
+        /// Only lists the notes for this person, from `TODO(name)` or `TODO @name`.
+        #[arg(short, long)]
+        assignee: Option<String>,
+        
+        /// Prints the notes as JSON.
+        #[arg(short, long)]
+        json: bool,
+        
+        /// Exits with an error if there are any notes, e.g. to keep them out of releases.
+        #[arg(long)]
+        fail: bool,
+    },
+    
+    /// Migrates the project to the current twee-tools version and bundled story format version, and reports the steps
+    /// that have to be done by hand. The only config change so far is turning `prebuild` and `postbuild` lists into
+    /// `[[prebuild]]` and `[[postbuild]]` tables.
+    Upgrade,
+    
+    /// Works with the config.toml.
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
     },
 }
 
 
+#[derive(Debug, Subcommand)]
+enum SyncDirection {
+    /// Builds the story into the Twine library.
+    Push {
+        /// Overwrites changes made in Twine since the last sync.
+        #[arg(short, long)]
+        force: bool,
+    },
+    /// Writes the changes made in Twine back into the twee files.
+    Pull {
+        /// Takes the Twine version of passages that were also changed in the project.
+        #[arg(short, long)]
+        force: bool,
+    },
+}
+
+
+#[derive(Debug, Subcommand)]
+enum TagAction {
+    /// Adds a tag to the passages.
+    Add {
+        /// The tag.
+        tag: String,
+        /// Only changes the passages with a name matching this regex.
+        #[arg(short = 'm', long = "match")]
+        pattern: Option<String>,
+    },
+    /// Removes a tag from the passages.
+    Remove {
+        /// The tag.
+        tag: String,
+        /// Only changes the passages with a name matching this regex.
+        #[arg(short = 'm', long = "match")]
+        pattern: Option<String>,
+    },
+    /// Renames a tag in the passages and the tag colors of the StoryData.
+    Rename {
+        /// The tag.
+        tag: String,
+        /// The new name of the tag.
+        new: String,
+        /// Only changes the passages with a name matching this regex.
+        #[arg(short = 'm', long = "match")]
+        pattern: Option<String>,
+    },
+}
+
+
+#[derive(Debug, Subcommand)]
+enum ListTarget {
+    /// Lists the passages with their word counts, twee files and tags.
+    Passages {
+        /// Only lists the passages with this tag.
+        #[arg(short, long)]
+        tag: Option<String>,
+        /// The order of the passages.
+        #[arg(short, long, value_enum, default_value_t)]
+        sort: ListSort,
+        /// Prints the passages as JSON.
+        #[arg(short, long)]
+        json: bool,
+    },
+    /// Lists the tags with the number of passages and words tagged with them.
+    Tags {
+        /// Prints the tags as JSON.
+        #[arg(short, long)]
+        json: bool,
+    },
+}
+
+
+#[derive(Debug, Subcommand)]
+enum ConfigCommand {
+    /// Prints a JSON Schema of the config.toml for editor autocompletion.
+    Schema,
+}
+
+
+
+#[derive(Debug, Subcommand)]
+enum PublishTarget {
+    /// Packages the Story and uploads it to itch.io using butler.
+    Itch {
+        /// The itch.io channel to push to, e.g. html5. Defaults to itch.channel from config.toml.
+        #[arg(short, long)]
+        channel: Option<String>,
+        
+        /// Enables the debug mode of the story format.
+        #[arg(short, long)]
+        debug: bool,
+    },
+}
+
+
+#[derive(Debug, Subcommand)]
+enum ExportTarget {
+    /// Generates a Tauri project that wraps the Story into a desktop application.
+    Desktop {
+        /// Enables the debug mode of the story format.
+        #[arg(short, long)]
+        debug: bool,
+    },
+    /// Writes a CSV file with a row for each choice: the passage, the link text, the target, the tags and the word
+    /// count of the passage.
+    Choices {
+        /// The file to write.
+        #[arg(short, long, default_value = "choices.csv")]
+        out: PathBuf,
+    },
+    /// Writes the lines of dialogue, grouped by character, into a script for voice-over recording. Lines are
+    /// recognized by the pattern and tag prefix in the `[dialogue]` section of the config.
+    Dialogue {
+        /// The file to write. Defaults to dialogue.csv or dialogue.fountain.
+        #[arg(short, long)]
+        out: Option<PathBuf>,
+        /// The format of the script.
+        #[arg(short, long, value_enum, default_value_t)]
+        format: DialogueFormat,
+    },
+    /// Renders each passage as its own HTML page with plain links, for a static site that works without JavaScript and
+    /// can be archived or crawled. Macros, variables and other markup of the story format are left out.
+    Site {
+        /// The directory to write the site into.
+        #[arg(short, long, default_value = "site")]
+        out: PathBuf,
+    },
+    /// Writes the story in reading order into a Word document for editorial review, with a heading for each passage,
+    /// the links as footnotes referencing the heading of their target and the metadata on the front page.
+    Docx {
+        /// The file to write. Defaults to the name of the HTML file with .docx.
+        #[arg(short, long)]
+        out: Option<PathBuf>,
+    },
+    /// Writes the story, or all stories of the workspace, into passages, links and metadata tables of a SQLite database
+    /// for analysis with SQL. Needs the sqlite3 command line shell.
+    Sqlite {
+        /// The database to write. Tables from an earlier export are replaced, other tables are kept.
+        #[arg(short, long, default_value = "story.db")]
+        out: PathBuf,
+        /// Writes the stories of this archive or story file instead.
+        #[arg(long)]
+        from: Option<PathBuf>,
+    },
+    /// Writes each passage as a Markdown note with its tags and metadata in the front matter and the links as
+    /// [[wikilinks]], for browsing the story in Obsidian.
+    Obsidian {
+        /// The directory to write the notes into.
+        #[arg(short, long, default_value = "vault")]
+        out: PathBuf,
+    },
+}
+
 
 type Result = anyhow::Result<(), anyhow::Error>;
 
@@ -144,36 +782,72 @@ type Result = anyhow::Result<(), anyhow::Error>;
 
 
 
-fn unpack(file: PathBuf, dir: PathBuf) -> Result {
+/// Archives at least this large are mapped instead of read by `unpack`.
+const MMAP_THRESHOLD: u64 = 16 * 1024 * 1024;
+
+/// Unpacks the stories of the archive into twee files, mapping it if it's at least `mmap_threshold` bytes large.
+fn unpack(file: PathBuf, dir: PathBuf, fix_ifids: bool, mmap_threshold: u64) -> Result {
     if ! dir.exists() {
         return Err(Error::DirNotFound(dir.to_string_lossy().to_string()).into());
     }
-    let mut file = if let std::result::Result::Ok(f) = File::open(&file) {
-        f
+    let size = if let std::result::Result::Ok(m) = std::fs::metadata(&file) {
+        m.len()
     } else {
         return Err(Error::FileNotFound(file.to_string_lossy().to_string()).into());
     };
+    // Large archives are mapped, so the stories are parsed one at a time from the file.
+    let archive = if size >= mmap_threshold {
+        // SAFETY: This assumes that no other process changes or truncates the archive while it's unpacked. Changes
+        // would alter the borrowed bytes, and reading past the end of a truncated file raises SIGBUS. Unpacking itself
+        // only reads the archive.
+        Some(unsafe { MappedArchive::open(&file) }?)
+    } else {
+        None
+    };
     let mut i = 0;
-    let mut content = String::new();
-    file.read_to_string(&mut content)?;
-    let archive = parse_archive(&content)?;
-    for (story, warnings) in archive {
+    let blocks: Vec<StorydataBlock> = archive.iter().flat_map(MappedArchive::stories).collect();
+    // Archives wrapped in elements like `<script type="text/html">` can escape the tags, so the scan doesn't find them.
+    // These are parsed at once instead, like small archives.
+    let parsed: Vec<(Story, Vec<Warning>)> = match &archive {
+        None => parse_archive(&read_file(&file)?)?,
+        Some(a) if blocks.is_empty() => parse_archive(std::str::from_utf8(a.bytes())?)?,
+        Some(_) => vec![],
+    };
+    let (names, ifids): (Vec<String>, Vec<Option<String>>) = if blocks.is_empty() {
+        parsed.iter().map(|(s, _)| (s.title.clone(), s.meta.get("ifid").and_then(|i| i.as_str()).map(str::to_string))).unzip()
+    } else {
+        blocks.iter().map(|b| (b.attribute("name").unwrap_or_default(), b.attribute("ifid"))).unzip()
+    };
+    let ifids: Vec<Option<String>> = ifids.into_iter().map(|i| i.map(|i| i.trim().to_uppercase()).filter(|i| ! i.is_empty())).collect();
+    let duplicates = duplicate_ifids(&ifids);
+    if ! fix_ifids {
+        for (d, first) in &duplicates {
+            let ifid = ifids[*d].clone().unwrap_or_default();
+            warn!(code = "duplicate-ifid", "\"{}\" has the same IFID {} as \"{}\". Run with --fix-ifids to give it a new one.", names[*d], ifid, names[*first]);
+        }
+    }
+    let stories = parsed.into_iter().map(std::result::Result::Ok).chain(blocks.iter().map(StorydataBlock::parse));
+    for (n, story) in stories.enumerate() {
+        let (mut story, warnings) = story?;
         for w in warnings {
             print_warning(w);
         }
+        if let Some((_, first)) = duplicates.iter().find(|(d, _)| *d == n).filter(|_| fix_ifids) {
+            info!("Gave \"{}\" a new IFID, it had the same as \"{}\"", story.title, names[*first]);
+            story.meta.insert("ifid".to_string(), gen_ifid().into());
+        }
         let title = if ! story.title.is_empty() {
                 story.title.clone()
             } else {
                 i += 1;
                 String::from("story-") + &i.to_string()
             };
-        let mut file = File::create(dir.join(title + ".twee"))?;
-        file.write_all(serialize_twee3(&story).as_bytes())?
+        write_output(dir.join(title + ".twee"), serialize_twee3(&story).as_bytes())?;
     }
     Ok(())
 }
 
-fn decompile(file: PathBuf, out: Option<PathBuf>) -> Result {
+fn decompile(file: PathBuf, out: Option<PathBuf>, emit: Emit, extract_assets: Option<String>) -> Result {
     let mut f = if let std::result::Result::Ok(f) = File::open(&file) {
         f
     } else {
@@ -181,7 +855,7 @@ fn decompile(file: PathBuf, out: Option<PathBuf>) -> Result {
     };
     let mut content = String::new();
     f.read_to_string(&mut content)?;
-    let (story, warnings) = parse_html(&content)?;
+    let (mut story, warnings) = parse_html(&content)?;
     for w in warnings {
         print_warning(w);
     }
@@ -190,41 +864,32 @@ fn decompile(file: PathBuf, out: Option<PathBuf>) -> Result {
     } else {
         String::from("story")
     };
-    let mut file = if let Some(out) = out {
-        File::create(out)?
-    } else {
-        File::create(file.parent().unwrap().join(title + ".twee"))?
-    };
-    file.write_all(serialize_twee3(&story).as_bytes())?;
+    let dir = file.parent().unwrap();
+    let out = out.unwrap_or(match emit {
+        Emit::Twee => dir.join(title + ".twee"),
+        Emit::Json => dir.join(title + ".json"),
+        Emit::Project => dir.join(project_dir_name(&title)),
+    });
+    if let Some(assets) = extract_assets {
+        let base = if let Emit::Project = emit { out.as_path() } else { out.parent().unwrap() };
+        for a in story.extract_data_uris(&assets) {
+            write_output(base.join(&a.path), &a.data)?;
+        }
+    }
+    match emit {
+        Emit::Twee => write_output(out, serialize_twee3(&story).as_bytes())?,
+        Emit::Json => write_output(out, serialize_json(&story).as_bytes())?,
+        Emit::Project => write_project(&out, story)?,
+    }
     Ok(())
 }
 
-fn gen_ifid() -> String {
-    let mut r = rand::rngs::StdRng::from_entropy();
-    let mut s = String::new();
-    fn hex_bytes(b: &[u8]) -> String {
-        b.iter().map(|b| format!("{:X}", b)).fold("".to_string(), |a, b| a + &b)
-    }
-    let mut uuid: [u8; 16] = [0; 16];
-    r.fill_bytes(&mut uuid[..]);
-    s += &hex_bytes(&uuid[0..4]);
-    s += "-";
-    s += &hex_bytes(&uuid[4..6]);
-    s += "-";
-    s += &hex_bytes(&uuid[6..8]);
-    s += "-";
-    s += &hex_bytes(&uuid[8..10]);
-    s += "-";
-    s += &hex_bytes(&uuid[10..16]);
-    s
-}
-
 fn init(dir: PathBuf, format: StoryFormat, title: String) -> Result {
     if ! dir.exists() {
         return Err(Error::DirNotFound(dir.to_string_lossy().to_string()).into());
     }
     if dir.join("config.toml").exists() {
-        writeln!(stderr(), "Project already initialized")?;
+        warn!("Project already initialized");
         return Ok(());
     }
     let mut story = parse_twee3(DEFAULT_TWEE).unwrap().0;
@@ -233,129 +898,487 @@ fn init(dir: PathBuf, format: StoryFormat, title: String) -> Result {
     story.meta.insert("format".to_string(), format.format_name().into());
     story.meta.insert("format-version".to_string(), format.format_version().into());
     
-    fn write_file(path: PathBuf, contents: &str) -> Result {
-        let mut f = File::create(path)?;
-        f.write_all(contents.as_bytes())?;
-        Ok(())
-    }
-    
-    write_file(dir.join("story.css"), DEFAULT_CSS)?;
-    write_file(dir.join("story.js"), DEFAULT_JS)?;
-    write_file(dir.join("story.twee"), &serialize_twee3(&story))?;
-    write_file(dir.join("config.toml"), DEFAULT_CONFIG)?;
+    write_output(dir.join("story.css"), DEFAULT_CSS.as_bytes())?;
+    write_output(dir.join("story.js"), DEFAULT_JS.as_bytes())?;
+    write_output(dir.join("story.twee"), serialize_twee3(&story).as_bytes())?;
+    write_output(dir.join("config.toml"), DEFAULT_CONFIG.as_bytes())?;
     Ok(())
 }
 
 
 
 
-fn build(debug: bool) -> anyhow::Result<PathBuf> {
-    if ! PathBuf::from("config.toml").exists() {
-        return Err(Error::FileNotFound("config.toml".to_string()).into());
+fn build(options: &BuildOptions) -> anyhow::Result<Build> {
+    build_in(Path::new(""), options)
+}
+
+/// Builds the story of the project in the directory.
+fn build_in(dir: &Path, options: &BuildOptions) -> anyhow::Result<Build> {
+    let start = Instant::now();
+    let project = Project::load(dir.join("config.toml"))?;
+    let prebuild = Instant::now();
+    project.prebuild(&options.profile())?;
+    let prebuild = prebuild.elapsed();
+    let Build { out, mut html, story, format } = project.build(&options.profile())?;
+    let config = &project.config;
+    for f in asset_files(config, &story) {
+        track_input(&f);
     }
-    let config: Config = toml::from_str(&read_file("config.toml")?)?;
-    if ! config.prebuild.is_empty() {
-        let mut c = std::process::Command::new(config.prebuild[0].clone());
-        c.args(&config.prebuild[1..]);
-        c.stdin(Stdio::null());
-        let mut c = c.spawn()?;
-        if ! c.wait()?.success() {
-            return Err(Error::PrebuildError.into());
-        }
+    check_build_assets(config, &story)?;
+    if options.explain_includes {
+        explain_includes();
     }
-    let story = build_story(&config, debug)?;
-    let format = {
-        if let Some(Value::String(s)) = story.meta.get("format") {
-            StoryFormat::from_name(s)?
-        } else {
-            writeln!(stderr(), "No story format")?;
-            return Err(Error::UnknownStoryFormat("".to_string()).into());
+    if options.size_report || config.max_size.is_some() {
+        SizeReport::new(&story, format_source(format, &story, config)?.len(), html.len()).print();
+    }
+    check_size_budget(config, html.len())?;
+    if options.hot_reload {
+        html = hot_reload(&out, &html)?;
+    }
+    let write = Instant::now();
+    write_output(&out, html.as_bytes())?;
+    if options.keep && config.builds.keep {
+        keep_build(config, &out, &html)?;
+    }
+    let write = write.elapsed();
+    let postbuild = Instant::now();
+    project.postbuild(&options.profile(), &out)?;
+    if options.timings {
+        print_timings(prebuild, write, postbuild.elapsed(), start.elapsed());
+    }
+    Ok(Build { out, html, story, format })
+}
+
+/// The story as Twine JSON, with the file and line each passage comes from in `source`.
+fn json_with_sources(story: &Story) -> anyhow::Result<String> {
+    let mut json: serde_json::Value = serde_json::from_str(&serialize_json(story))?;
+    for p in json.get_mut("passages").and_then(|p| p.as_array_mut()).into_iter().flatten() {
+        let source = p.get("name").and_then(|n| n.as_str()).and_then(passage_source);
+        if let (Some(p), Some(source)) = (p.as_object_mut(), source) {
+            p.insert("source".to_string(), serde_json::to_value(source)?);
         }
+    }
+    Ok(serde_json::to_string_pretty(&json)?)
+}
+
+/// Prints how long the stages of the last build took, and the included files that took the longest.
+fn print_timings(prebuild: Duration, write: Duration, postbuild: Duration, total: Duration) {
+    let mut stages = vec![("prebuild".to_string(), prebuild)];
+    stages.extend(build_timings());
+    stages.extend([("write".to_string(), write), ("postbuild".to_string(), postbuild)]);
+    info!("Timings:");
+    for (stage, time) in stages {
+        info!("  {:<24} {:>10.2?}", stage, time);
+    }
+    info!("  {:<24} {:>10.2?}", "Total", total);
+    let files = slowest_includes();
+    if files.is_empty() {
+        return;
+    }
+    info!("Slowest included files:");
+    for e in files {
+        info!("  {:<24} {:>10.2?}", e.path, e.time);
+    }
+}
+
+/// The included twee files and archives of the last build that took the longest, slowest first.
+fn slowest_includes() -> Vec<IncludeEntry> {
+    let mut files: Vec<IncludeEntry> = include_graph().map(|g| g.entries).unwrap_or_default().into_iter()
+        .filter(|e| matches!(e.kind, IncludeKind::Config | IncludeKind::Twee | IncludeKind::Archive) && ! e.skipped)
+        .collect();
+    // Times include the files an included file includes in turn.
+    files.sort_by_key(|e| std::cmp::Reverse(e.time));
+    files.truncate(SLOWEST_INCLUDES);
+    files
+}
+
+/// Prints the files included by the last build as a tree.
+fn explain_includes() {
+    let Some(graph) = include_graph() else {
+        return;
     };
-    let out = if let Some(out) = config.output {
-        PathBuf::from(out)
+    info!("Includes:");
+    for e in graph.entries {
+        let kind = match e.kind {
+            IncludeKind::Main => " (main)",
+            IncludeKind::Config => " (config.toml)",
+            IncludeKind::Twee => "",
+            IncludeKind::Archive => " (archive)",
+            IncludeKind::Passage => " (passage content)",
+        };
+        info!("  {}{}{}{}", "  ".repeat(e.depth), e.path, kind, if e.skipped { ", skipped: already included" } else { "" });
+    }
+}
+
+/// Lints the story in the project directory, returning the number of problems from rules with error severity.
+fn lint(dir: &Path) -> anyhow::Result<usize> {
+    let config = load_config_in(dir)?;
+    let story = build_story(&config, false)?;
+    let lints = lint_story(&config, &story);
+    for l in &lints {
+        print_lint(l, severity(&config, l.rule));
+    }
+    Ok(lints.iter().filter(|l| severity(&config, l.rule) == Severity::Error).count())
+}
+
+/// Builds the story, or all stories if the current directory is a workspace, returning the absolute output paths.
+fn build_all(options: &BuildOptions) -> anyhow::Result<Vec<PathBuf>> {
+    if let Some(workspace) = Workspace::load()? {
+        check_workspace_ifids(&workspace, options.fix_ifids)?;
+        workspace.for_each_member(|dir| absolute_path(&build_in(dir, options)?.out))
     } else {
-        PathBuf::from(".").join(story.title.clone() + ".html")
-    };
-    let html = build_html(format, &story)?;
-    File::create(out.clone())?.write_all(html.as_bytes())?;
-    Ok(out)
+        Ok(vec![absolute_path(&build(options)?.out)?])
+    }
 }
 
-fn build_html(format: StoryFormat, story: &Story) -> anyhow::Result<String> {
-    let mut html: Vec<u8> = Vec::new();
-    serialize_html(&story).write_with_config(&mut html, EmitterConfig {
-        normalize_empty_elements: false,
-        write_document_declaration: false,
-        ..Default::default()})?;
-    Ok(format.format_contents().replace("{{STORY_NAME}}", &story.title).replace("{{STORY_DATA}}", &String::from_utf8(html).unwrap()))
+/// Builds the members of the workspace in parallel and prints how long each took, returning the absolute output
+/// paths.
+fn build_workspace(workspace: &Workspace, options: &BuildOptions, jobs: Option<usize>) -> anyhow::Result<Vec<PathBuf>> {
+    check_workspace_ifids(workspace, options.fix_ifids)?;
+    let jobs = jobs.unwrap_or_else(|| std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1));
+    let start = Instant::now();
+    let builds = workspace.build_parallel(jobs, |dir| absolute_path(&build_in(dir, options)?.out))?;
+    info!("Build times:");
+    for b in &builds {
+        info!("  {:<24} {:>10.2?}{}", b.dir.to_string_lossy(), b.time, if b.out.is_none() { " (failed)" } else { "" });
+    }
+    info!("  {:<24} {:>10.2?}", "Total", start.elapsed());
+    let failed = builds.iter().filter(|b| b.out.is_none()).count();
+    if failed != 0 {
+        return Err(Error::WorkspaceBuildFailed(failed).into());
+    }
+    Ok(builds.into_iter().filter_map(|b| b.out).collect())
 }
 
-fn watch(debug: bool) -> Result {
-    let mut out = build(debug)?.canonicalize()?;
-    let mut w = notify::recommended_watcher(move |e: std::result::Result<Event, notify::Error>| {
-        let event = e.unwrap();
-        if event.paths.iter().any(|p| {
-            if let std::result::Result::Ok(p) = p.canonicalize() {
-                p == out
-            } else {
-                false
-            }
-        }) {
-            return;
+/// Watches the directories of the inputs of the last build, unwatching directories no longer needed.
+fn update_watches(w: &mut impl Watcher, watched: &mut BTreeMap<PathBuf, bool>, inputs: &BuildInputs) {
+    let dirs = inputs.watch_dirs();
+    for (d, recursive) in watched.iter() {
+        if dirs.get(d) != Some(recursive) {
+            let _ = w.unwatch(d);
         }
-        match event.kind {
-            notify::EventKind::Modify(_m) => {
-                sleep(Duration::from_millis(100));
-                out = build(debug).unwrap().canonicalize().unwrap();
-            },
-            notify::EventKind::Remove(_r) => {
-                sleep(Duration::from_millis(100));
-                out = build(debug).unwrap().canonicalize().unwrap();
-            },
-            _ => {}
+    }
+    for (d, recursive) in &dirs {
+        if watched.get(d) == Some(recursive) {
+            continue;
         }
-        
-    })?;
+        let mode = if *recursive { notify::RecursiveMode::Recursive } else { notify::RecursiveMode::NonRecursive };
+        if let Err(e) = w.watch(d, mode) {
+            debug!("Could not watch {}: {}", d.to_string_lossy(), e);
+        }
+    }
+    *watched = dirs;
+}
+
+/// Calls `rebuild` after each change to an input of the last build, until the watcher stops. `rebuild` returns the
+/// outputs of the build, as changes to them are ignored.
+fn on_changes<F>(mut out: Vec<PathBuf>, mut rebuild: F) -> Result where F: FnMut() -> Vec<PathBuf> {
+    let (tx, rx) = std::sync::mpsc::channel::<std::result::Result<Event, notify::Error>>();
+    let mut w = notify::recommended_watcher(tx)?;
     w.configure(notify::Config::default().with_poll_interval(Duration::from_secs(1)))?;
-    w.watch(&PathBuf::from("."), notify::RecursiveMode::Recursive)?;
-    loop {
-        sleep(Duration::from_secs(1));
+    let mut watched = BTreeMap::new();
+    let mut inputs = BuildInputs::collect();
+    update_watches(&mut w, &mut watched, &inputs);
+    for e in rx.iter() {
+        let event = match e {
+            std::result::Result::Ok(event) => event,
+            Err(e) => {
+                warn!(code = "watch-error", "Could not watch files: {}", e);
+                continue;
+            }
+        };
+        if ! matches!(event.kind, notify::EventKind::Create(_) | notify::EventKind::Modify(_) | notify::EventKind::Remove(_)) {
+            continue;
+        }
+        if ! event.paths.iter().any(|p| ! out.iter().any(|o| o == p || reload_path(o) == *p) && inputs.contains(p)) {
+            continue;
+        }
+        // Editors often write a file in multiple steps, so wait a bit and rebuild only once for all of them.
+        sleep(Duration::from_millis(100));
+        while rx.try_recv().is_ok() {}
+        out = rebuild();
+        inputs = BuildInputs::collect();
+        update_watches(&mut w, &mut watched, &inputs);
     }
+    Ok(())
+}
+
+/// Builds on any changes, calling `built` with the outputs after the first build and each rebuild that succeeds.
+fn watch(options: BuildOptions, open: bool, notify: Option<Notify>, mut built: impl FnMut(&[PathBuf])) -> Result {
+    let mut failed = false;
+    let out = match build_all(&options) {
+        std::result::Result::Ok(out) => out,
+        Err(e) => {
+            error!("Build failed: {:#}", e);
+            failed = true;
+            vec![]
+        }
+    };
+    built(&out);
+    if open {
+        for o in &out {
+            open_in_browser(o)?;
+        }
+    }
+    let mut last = out.clone();
+    on_changes(out, || {
+        clear_build_inputs();
+        match build_all(&options) {
+            std::result::Result::Ok(o) => {
+                if failed {
+                    info!("Build succeeded again");
+                    if let Some(notify) = notify {
+                        notify_user(notify, "Build succeeded", "The story builds again.");
+                    }
+                }
+                failed = false;
+                built(&o);
+                last = o.clone();
+                o
+            },
+            Err(e) => {
+                error!("Build failed: {:#}", e);
+                if ! failed {
+                    if let Some(notify) = notify {
+                        notify_user(notify, "Build failed", format!("{:#}", e).lines().next().unwrap_or(""));
+                    }
+                }
+                failed = true;
+                last.clone()
+            }
+        }
+    })
 }
 
 fn main() -> Result {
-    FORMAT_HARLOWE.set(serde_json::from_str::<serde_json::Value>(include_str!("../formats/harlowe-3.3.8.json")).unwrap().as_object().unwrap().get("source").unwrap().as_str().unwrap().to_string()).unwrap();
-    FORMAT_CHAPBOOK.set(serde_json::from_str::<serde_json::Value>(include_str!("../formats/chapbook-1.2.3.json")).unwrap().as_object().unwrap().get("source").unwrap().as_str().unwrap().to_string()).unwrap();
-    FORMAT_SNOWMAN.set(serde_json::from_str::<serde_json::Value>(include_str!("../formats/snowman-2.0.2.json")).unwrap().as_object().unwrap().get("source").unwrap().as_str().unwrap().to_string()).unwrap();
-    FORMAT_SUGARCUBE.set(serde_json::from_str::<serde_json::Value>(include_str!("../formats/sugarcube-2.36.1.json")).unwrap().as_object().unwrap().get("source").unwrap().as_str().unwrap().to_string()).unwrap();
-    
     let cli = Cli::parse();
+    DRY_RUN.store(cli.dry_run, std::sync::atomic::Ordering::Relaxed);
+    OFFLINE.store(cli.offline, std::sync::atomic::Ordering::Relaxed);
+    init_logging(cli.verbose.min(2) as i8 - cli.quiet.min(2) as i8, cli.log_format, matches!(cli.command, Command::Watch { .. } | Command::Serve { .. }));
     match cli.command {
-        Command::Unpack { file, dir } => unpack(file, PathBuf::from(dir))?,
-        Command::Decompile { file, out } => decompile(file, out)?,
+        Command::Unpack { file, dir, fix_ifids } => unpack(file, PathBuf::from(dir), fix_ifids, MMAP_THRESHOLD)?,
+        Command::Decompile { file, out, emit, extract_assets } => decompile(file, out, emit, extract_assets)?,
+        Command::ImportTwine { path, out } => import_twine(&path, &out)?,
+        Command::ImportObsidian { vault, format, out, title, start } => import_obsidian(&vault, format, &out, title, start)?,
+        Command::ImportSqlite { db, out } => import_sqlite(&db, &out)?,
+        Command::Sync { direction: SyncDirection::Push { force } } => push(Path::new(""), force)?,
+        Command::Sync { direction: SyncDirection::Pull { force } } => pull(Path::new(""), force)?,
         Command::Init { dir , format, title} => init(dir, format, title)?,
-        Command::Build{debug, stdout} => {
-            if stdout {
-                if ! PathBuf::from("config.toml").exists() {
-                    return Err(Error::FileNotFound("config.toml".to_string()).into());
-                }
-                let config: Config = toml::from_str(&read_file("config.toml")?)?;
-                let story = build_story(&config, debug)?;
-                let format = {
-                    if let Some(Value::String(s)) = story.meta.get("format") {
-                        StoryFormat::from_name(s)?
-                    } else {
-                        writeln!(stderr(), "No story format")?;
-                        return Err(Error::UnknownStoryFormat("".to_string()).into());
-                    }
+        Command::Tag{action} => {
+            let (change, pattern) = match action {
+                TagAction::Add{tag, pattern} => (TagChange::Add(tag), pattern),
+                TagAction::Remove{tag, pattern} => (TagChange::Remove(tag), pattern),
+                TagAction::Rename{tag, new, pattern} => (TagChange::Rename(tag, new), pattern),
+            };
+            tag(change, pattern.as_deref())?;
+        },
+        Command::MergeDriver{base, ours, theirs} => {
+            let conflicts = merge_driver(&base, &ours, &theirs)?;
+            if conflicts != 0 {
+                return Err(Error::MergeConflict(conflicts).into());
+            }
+        },
+        Command::SetMeta{key, value, json} => set_meta(&key, &value, json)?,
+        Command::GetMeta{key} => get_meta(key.as_deref())?,
+        Command::Add{name, tags, template, from} => add(&name, tags, template, from)?,
+        Command::Build{debug, stdout, emit, size_report, open, fix_ifids, explain_includes, timings, jobs} => {
+            let emit = emit.or(stdout.then_some(BuildEmit::Html));
+            if emit.is_some() && PathBuf::from(WORKSPACE_FILE).exists() {
+                return Err(Error::WorkspaceStdout.into());
+            }
+            if let Some(emit) = emit {
+                let project = Project::load("config.toml")?;
+                let story = project.story(&Profile { debug })?;
+                let output = match emit {
+                    BuildEmit::Html => build_html(StoryFormat::of_story(&story)?, &story, &project.config)?,
+                    BuildEmit::Twee => serialize_twee3(&story),
+                    BuildEmit::Json => json_with_sources(&story)?,
+                    BuildEmit::Storydata => storydata_html(&story, &project.config)?,
+                    BuildEmit::Jsonl => {
+                        export_jsonl(&story, std::io::stdout().lock())?;
+                        return Ok(());
+                    },
                 };
-                std::io::stdout().write_all(build_html(format, &story)?.as_bytes())?;
+                std::io::stdout().write_all(output.as_bytes())?;
             } else {
-                build(debug)?;
+                let options = BuildOptions { debug, size_report, fix_ifids, explain_includes, timings, keep: true, ..Default::default() };
+                let outputs = if let Some(workspace) = Workspace::load()? {
+                    build_workspace(&workspace, &options, jobs)?
+                } else {
+                    build_all(&options)?
+                };
+                if open {
+                    for out in outputs {
+                        open_in_browser(&out)?;
+                    }
+                }
+            }
+        },
+        Command::Watch{debug, open, notify, hot_reload} => watch(BuildOptions { debug, hot_reload, ..Default::default() }, open, notify, |_| {})?,
+        Command::Serve{debug, host, port, open, notify, sync_navigation} => serve(BuildOptions { debug, ..Default::default() }, &host, port, open, notify, sync_navigation)?,
+        Command::Compat{old, new} => {
+            let count = compat(&old, new.as_deref())?;
+            if count != 0 {
+                return Err(Error::CompatBreaking(count).into());
+            }
+        },
+        Command::Split{prefix, out, debug} => {
+            split(&BuildOptions { debug, ..Default::default() }, prefix, out)?;
+        },
+        Command::Open => {
+            let config = load_config()?;
+            let out = output_path(&config, &build_story(&config, false)?);
+            if ! out.exists() {
+                return Err(Error::FileNotFound(out.to_string_lossy().to_string()).into());
+            }
+            open_in_browser(&out)?;
+        },
+        Command::Package{debug} => {
+            let out = package(&BuildOptions { debug, ..Default::default() })?;
+            info!("Packaged {}", out.to_string_lossy());
+        },
+        Command::Publish{target: PublishTarget::Itch{channel, debug}} => publish_itch(&BuildOptions { debug, ..Default::default() }, channel)?,
+        Command::Export{target: ExportTarget::Desktop{debug}} => {
+            let out = export_desktop(&BuildOptions { debug, ..Default::default() })?;
+            info!("Generated Tauri project in {}, build it with `cargo tauri build` in {}", out.to_string_lossy(), out.join("src-tauri").to_string_lossy());
+        },
+        Command::Export{target: ExportTarget::Choices{out}} => export_choices(&out)?,
+        Command::Export{target: ExportTarget::Dialogue{out, format}} => export_dialogue(out, format)?,
+        Command::Export{target: ExportTarget::Site{out}} => export_site(&out)?,
+        Command::Export{target: ExportTarget::Docx{out}} => export_docx(out)?,
+        Command::Export{target: ExportTarget::Obsidian{out}} => export_obsidian(&out)?,
+        Command::Export{target: ExportTarget::Sqlite{out, from}} => export_sqlite(&out, from)?,
+        Command::Api{port, allow_origin, watch} => api(port, allow_origin, watch)?,
+        Command::Play{start, record} => {
+            let config = load_config()?;
+            play(&build_story(&config, false)?, start, record)?;
+        },
+        Command::Clean => {
+            if let Some(workspace) = Workspace::load()? {
+                workspace.for_each_member(clean)?;
+            } else {
+                clean(Path::new(""))?;
+            }
+        },
+        Command::Search{pattern, ignore_case} => {
+            search(&pattern, ignore_case)?;
+        },
+        Command::List{target: ListTarget::Passages{tag, sort, json}} => list_passages(tag, sort, json)?,
+        Command::List{target: ListTarget::Tags{json}} => list_tags(json)?,
+        Command::Show{passage, html, debug} => show(&passage, html, debug)?,
+        Command::Replace{pattern, replacement, include_names, ignore_case, write} => {
+            replace(&pattern, &replacement, include_names, ignore_case, write)?;
+        },
+        Command::Check{files, staged} => {
+            let count = if staged || ! files.is_empty() {
+                check_files(&files, staged)?
+            } else if let Some(workspace) = Workspace::load()? {
+                workspace.count_problems(|dir| Ok(check_project(dir)))?
+            } else {
+                check_project(Path::new(""))
+            };
+            if count != 0 {
+                return Err(Error::CheckFailed(count).into());
+            }
+        },
+        Command::Lint => {
+            let count = if let Some(workspace) = Workspace::load()? {
+                workspace.count_problems(lint)?
+            } else {
+                lint(Path::new(""))?
+            };
+            if count != 0 {
+                return Err(Error::LintFailed(count).into());
+            }
+        },
+        Command::Stats{readability, by_tag, complexity, json} => stats(readability, by_tag, complexity, json)?,
+        Command::Endings{max_depth, max_paths, routes, json} => endings(max_depth, max_paths, routes, json)?,
+        Command::Characters{json} => characters(json)?,
+        Command::Todo{assignee, json, fail} => {
+            let count = todo(assignee.as_deref(), json)?;
+            if fail && count != 0 {
+                return Err(Error::TodosFound(count).into());
+            }
+        },
+        Command::Spellcheck => {
+            let count = if let Some(workspace) = Workspace::load()? {
+                workspace.for_each_member(spellcheck)?.into_iter().sum()
+            } else {
+                spellcheck(Path::new(""))?
+            };
+            if count != 0 {
+                return Err(Error::SpellcheckFailed(count).into());
+            }
+        },
+        Command::Upgrade => upgrade()?,
+        Command::Config{command: ConfigCommand::Schema} => println!("{}", config_schema()),
+        Command::Vendor{all} => {
+            if let Some(workspace) = Workspace::load()? {
+                workspace.for_each_member(|dir| vendor(dir, all))?;
+            } else {
+                vendor(Path::new(""), all)?;
             }
         },
-        Command::Watch{debug} => watch(debug)?,
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use twee_build::config_from_source;
+
+    use super::*;
+    
+    /// The unpacked files of the archive, making sure mapping it gives the same files as reading it.
+    fn unpack_archive(archive: &str) -> Vec<String> {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        std::fs::write(dir.join("archive.html"), archive).unwrap();
+        unpack(dir.join("archive.html"), dir.join("out"), false, MMAP_THRESHOLD).unwrap_err();
+        let unpacked: Vec<Vec<String>> = [0, u64::MAX].into_iter().map(|threshold| {
+            let out = dir.join(format!("out-{}", threshold));
+            std::fs::create_dir(&out).unwrap();
+            unpack(dir.join("archive.html"), out.clone(), false, threshold).unwrap();
+            let mut unpacked: Vec<String> = std::fs::read_dir(out).unwrap().map(|e| std::fs::read_to_string(e.unwrap().path()).unwrap()).collect();
+            unpacked.sort();
+            unpacked
+        }).collect();
+        assert_eq!(unpacked[0], unpacked[1]);
+        unpacked[0].clone()
+    }
+    
+    #[test]
+    fn unpack_wrapped_archive() {
+        let unpacked = unpack_archive(r#"<html><body><script type="text/html">&lt;tw-storydata name="Wrapped" ifid="8F0A7B1C-3A8E-4C5D-9C2B-1D2E3F4A5B6C"&gt;&lt;tw-passagedata pid="1" name="Start"&gt;Hello&lt;/tw-passagedata&gt;&lt;/tw-storydata&gt;</script></body></html>"#);
+        assert_eq!(unpacked.len(), 1);
+        assert!(unpacked[0].starts_with(":: StoryTitle\nWrapped\n"), "{}", unpacked[0]);
+        assert!(unpacked[0].contains(":: Start\nHello"), "{}", unpacked[0]);
+    }
+    
+    #[test]
+    fn unpack_bare_attributes() {
+        let unpacked = unpack_archive("<tw-storydata hidden\n  name='Bare'\n  ifid=\"8F0A7B1C-3A8E-4C5D-9C2B-1D2E3F4A5B6C\"><tw-passagedata pid=\"1\" name=\"Start\">One</tw-passagedata></tw-storydata>\n<tw-storydata name=\"Other\"><tw-passagedata pid=\"1\" name=\"Start\">Two</tw-passagedata></tw-storydata>");
+        assert_eq!(unpacked.len(), 2);
+        assert!(unpacked[0].starts_with(":: StoryTitle\nBare\n") && unpacked[0].contains("\"hidden\": true"), "{}", unpacked[0]);
+        assert!(unpacked[1].starts_with(":: StoryTitle\nOther\n"), "{}", unpacked[1]);
+    }
+    
+    #[test]
+    fn timings_with_includes() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        std::fs::create_dir_all(dir.join("parts")).unwrap();
+        for i in 0..7 {
+            std::fs::write(dir.join("parts").join(format!("{}.twee", i)), format!(":: Part {}\ntext\n", i)).unwrap();
+        }
+        std::fs::write(dir.join("main.twee"), ":: StoryTitle\nTimings\n\n:: TweeTools\n{\"include\": [\"parts/*.twee\", \"parts/0.twee\"]}\n").unwrap();
+        let config = config_from_source(&format!("main = {:?}\nstyle = []\nscript = []\nprebuild = []\n", dir.join("main.twee").to_string_lossy())).unwrap();
+        build_story(&config, false).unwrap();
+        let stages: Vec<String> = build_timings().into_iter().map(|(stage, _)| stage).collect();
+        assert_eq!(stages, ["parse", "debug", "includes", "toc", "script", "stylesheet", "validate"]);
+        let slowest = slowest_includes();
+        assert_eq!(slowest.len(), SLOWEST_INCLUDES);
+        assert!(slowest.iter().all(|e| e.kind == IncludeKind::Twee && ! e.skipped && e.path.contains("parts")), "{:?}", slowest);
+        assert!(slowest.windows(2).all(|w| w[0].time >= w[1].time), "{:?}", slowest);
+    }
+}
@@ -0,0 +1,97 @@
+use std::{collections::BTreeMap, path::Path};
+
+use tracing::warn;
+use twee_build::{read_file, write_output};
+use twee_parser::passage_spans;
+
+use crate::diff::merge3;
+
+/// The text before the first passage and the passages of twee source, by name, in the order they appear.
+fn passages(source: &str) -> Vec<(String, String)> {
+    let spans = passage_spans(source);
+    let start = spans.first().map(|s| s.span.start).unwrap_or(source.len());
+    let mut passages = vec![(String::new(), source[..start].to_string())];
+    passages.extend(spans.into_iter().map(|s| (s.name, source[s.span].to_string())));
+    passages
+}
+
+/// Merges the versions of a passage. Returns the merged passage, if it's not deleted, and the number of conflicts.
+fn merge_passage(name: &str, base: Option<&str>, ours: Option<&str>, theirs: Option<&str>) -> (Option<String>, usize) {
+    if ours == theirs || theirs == base {
+        return (ours.map(|p| p.to_string()), 0);
+    }
+    if ours == base {
+        return (theirs.map(|p| p.to_string()), 0);
+    }
+    match (ours, theirs) {
+        (Some(ours), Some(theirs)) => {
+            let (merged, conflicts) = merge3(base.unwrap_or_default(), ours, theirs);
+            (Some(merged), conflicts)
+        },
+        (ours, theirs) => {
+            let side = if ours.is_none() { "ours" } else { "theirs" };
+            warn!(code = "merge-deleted", "Passage \"{}\" was deleted in {} and changed in the other version", name, side);
+            let version = |p: Option<&str>| p.map(|p| if p.ends_with('\n') { p.to_string() } else { format!("{}\n", p) }).unwrap_or_default();
+            (Some(format!("<<<<<<< ours\n{}=======\n{}>>>>>>> theirs\n", version(ours), version(theirs))), 1)
+        },
+    }
+}
+
+/// Merges twee files passage by passage, for use as a Git merge driver. Passages changed in only one version take
+/// that version, passages changed in both are merged line by line. The result is written to `ours`. Returns the
+/// number of conflicts.
+pub(crate) fn merge_driver(base: &Path, ours: &Path, theirs: &Path) -> anyhow::Result<usize> {
+    let (o, a, b) = (passages(&read_file(base)?), passages(&read_file(ours)?), passages(&read_file(theirs)?));
+    // Our order, with passages only in theirs after the passage before them in theirs.
+    let mut order: Vec<&str> = a.iter().map(|(n, _)| n.as_str()).collect();
+    for (i, (name, _)) in b.iter().enumerate() {
+        if ! order.contains(&name.as_str()) {
+            let after = b[..i].iter().rev().find_map(|(n, _)| order.iter().position(|o| o == n));
+            order.insert(after.map(|p| p + 1).unwrap_or(order.len()), name);
+        }
+    }
+    let mut merged = String::new();
+    let mut conflicts = 0;
+    let mut seen: BTreeMap<&str, usize> = BTreeMap::new();
+    for name in order {
+        // Duplicate names are merged in order of appearance.
+        let nth = seen.entry(name).or_default();
+        *nth += 1;
+        let nth_of = |v: &[(String, String)]| v.iter().filter(|(n, _)| n == name).nth(*nth - 1).map(|(_, p)| p.clone());
+        let (base, ours, theirs) = (nth_of(&o), nth_of(&a), nth_of(&b));
+        let (passage, c) = merge_passage(name, base.as_deref(), ours.as_deref(), theirs.as_deref());
+        conflicts += c;
+        if let Some(p) = passage {
+            if ! merged.is_empty() && ! merged.ends_with('\n') {
+                merged.push('\n');
+            }
+            merged += &p;
+        }
+    }
+    write_output(ours, merged.as_bytes())?;
+    Ok(conflicts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn merge_passage_changed_on_one_side() {
+        let base = ":: A\nbase\n";
+        assert_eq!(merge_passage("A", Some(base), Some(":: A\nours\n"), Some(base)), (Some(":: A\nours\n".to_string()), 0));
+        assert_eq!(merge_passage("A", Some(base), Some(base), Some(":: A\ntheirs\n")), (Some(":: A\ntheirs\n".to_string()), 0));
+        assert_eq!(merge_passage("A", Some(base), None, Some(base)), (None, 0));
+        assert_eq!(merge_passage("A", None, None, Some(":: A\nnew\n")), (Some(":: A\nnew\n".to_string()), 0));
+    }
+    
+    #[test]
+    fn merge_passage_deleted_and_changed() {
+        let (merged, conflicts) = merge_passage("A", Some(":: A\nbase\n"), None, Some(":: A\nchanged"));
+        assert_eq!(merged.as_deref(), Some("<<<<<<< ours\n=======\n:: A\nchanged\n>>>>>>> theirs\n"));
+        assert_eq!(conflicts, 1);
+        let (merged, conflicts) = merge_passage("A", Some(":: A\nbase\n"), Some(":: A\nchanged\n"), None);
+        assert_eq!(merged.as_deref(), Some("<<<<<<< ours\n:: A\nchanged\n=======\n>>>>>>> theirs\n"));
+        assert_eq!(conflicts, 1);
+    }
+}
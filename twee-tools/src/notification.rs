@@ -0,0 +1,45 @@
+use std::{io::Write, process::{Command, Stdio}};
+
+use clap::ValueEnum;
+use tracing::debug;
+
+/// How `watch` notifies about failed and recovered rebuilds.
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub(crate) enum Notify {
+    /// A desktop notification, falling back to the terminal bell if that isn't possible.
+    Desktop,
+    /// The terminal bell.
+    Bell,
+}
+
+fn desktop_notification(title: &str, body: &str) -> bool {
+    let mut c = if cfg!(target_os = "macos") {
+        let mut c = Command::new("osascript");
+        let quote = |s: &str| format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""));
+        c.arg("-e").arg(format!("display notification {} with title {}", quote(body), quote(title)));
+        c
+    } else if cfg!(target_os = "windows") {
+        return false;
+    } else {
+        let mut c = Command::new("notify-send");
+        c.args(["--app-name=twee", title, body]);
+        c
+    };
+    match c.stdin(Stdio::null()).stdout(Stdio::null()).stderr(Stdio::null()).status() {
+        Ok(s) => s.success(),
+        Err(e) => {
+            debug!("Could not send desktop notification: {}", e);
+            false
+        }
+    }
+}
+
+/// Notifies the user about a change of the build result.
+pub(crate) fn notify_user(notify: Notify, title: &str, body: &str) {
+    if let Notify::Desktop = notify {
+        if desktop_notification(title, body) {
+            return;
+        }
+    }
+    let _ = std::io::stderr().write_all(b"\x07");
+}
@@ -0,0 +1,314 @@
+use std::{collections::{BTreeMap, BTreeSet}, fs::read_dir, path::{Path, PathBuf}};
+
+use regex::{Captures, Regex};
+use serde_json::{Map, Value};
+use tracing::{info, warn};
+use twee_build::{absolute_path, build_story, load_config, read_file, write_output, StoryFormat};
+use twee_parser::{parse_twee3, Passage, Story};
+
+use crate::{build::Error, ifid::gen_ifid, import::write_project, site::{escape_html, is_shown}, DEFAULT_TWEE};
+
+/// Files embedded with `![[...]]` that become `<img>` tags instead of links.
+const IMAGE_EXTENSIONS: &[&str] = &["png", "jpg", "jpeg", "gif", "svg", "webp", "avif", "bmp"];
+
+/// The Markdown files of the vault, skipping hidden directories like `.obsidian` and `.trash`.
+fn markdown_files(dir: &Path, files: &mut Vec<PathBuf>) -> anyhow::Result<()> {
+    for e in read_dir(dir)?.flatten() {
+        let p = e.path();
+        if e.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+        if p.is_dir() {
+            markdown_files(&p, files)?;
+        } else if p.extension().is_some_and(|e| e.eq_ignore_ascii_case("md")) {
+            files.push(p);
+        }
+    }
+    Ok(())
+}
+
+fn unquote(s: &str) -> String {
+    let s = s.trim();
+    for q in ['"', '\''] {
+        if s.len() >= 2 && s.starts_with(q) && s.ends_with(q) {
+            return s[1..s.len() - 1].to_string();
+        }
+    }
+    s.to_string()
+}
+
+/// Parses a YAML front-matter value: a scalar or a flow list like `[a, b]`.
+fn front_matter_value(s: &str) -> Value {
+    let s = s.trim();
+    if let Some(list) = s.strip_prefix('[').and_then(|s| s.strip_suffix(']')) {
+        return Value::Array(list.split(',').map(unquote).filter(|s| ! s.is_empty()).map(Value::String).collect());
+    }
+    match s {
+        "true" => Value::Bool(true),
+        "false" => Value::Bool(false),
+        _ => match s.parse::<serde_json::Number>() {
+            Ok(n) if ! s.starts_with(['"', '\'']) => Value::Number(n),
+            _ => Value::String(unquote(s)),
+        },
+    }
+}
+
+/// Splits a note into its front matter and its content. Only the simple YAML Obsidian writes is understood: `key: value`
+/// pairs, flow lists and block lists of scalars.
+fn split_front_matter(note: &str) -> (Map<String, Value>, &str) {
+    let mut meta = Map::new();
+    let Some(rest) = note.strip_prefix("---\n").or(note.strip_prefix("---\r\n")) else {
+        return (meta, note);
+    };
+    let Some(end) = rest.find("\n---").filter(|&end| rest[end + 4..].starts_with(['\n', '\r']) || rest.len() == end + 4) else {
+        return (meta, note);
+    };
+    let mut key: Option<String> = None;
+    for line in rest[..end].lines() {
+        if let (Some(item), Some(key)) = (line.trim_start().strip_prefix("- "), &key) {
+            if let Some(Value::Array(items)) = meta.get_mut(key) {
+                items.push(Value::String(unquote(item)));
+            }
+        } else if let Some((k, v)) = line.split_once(':').filter(|_| ! line.starts_with([' ', '#'])) {
+            let k = k.trim().to_string();
+            let v = if v.trim().is_empty() { Value::Array(vec![]) } else { front_matter_value(v) };
+            meta.insert(k.clone(), v);
+            key = Some(k);
+        }
+    }
+    let content = rest[end + 4..].trim_start_matches(['\r', '\n']);
+    (meta, content)
+}
+
+/// The values of a front-matter field that lists names, like `tags` or `aliases`, which can also be a single string.
+fn front_matter_list(meta: &Map<String, Value>, key: &str) -> Vec<String> {
+    match meta.get(key) {
+        Some(Value::Array(items)) => items.iter().filter_map(|i| i.as_str()).map(str::to_string).collect(),
+        Some(Value::String(s)) => s.split([',', ' ']).filter(|s| ! s.is_empty()).map(str::to_string).collect(),
+        _ => vec![],
+    }
+}
+
+/// The passage names of the notes: the file name, or the path in the vault for notes with the same file name.
+fn passage_names(vault: &Path, files: &[PathBuf]) -> Vec<String> {
+    let stem = |f: &PathBuf| f.file_stem().unwrap_or_default().to_string_lossy().to_string();
+    files.iter().map(|f| {
+        if files.iter().filter(|o| stem(o).to_lowercase() == stem(f).to_lowercase()).count() == 1 {
+            stem(f)
+        } else {
+            f.strip_prefix(vault).unwrap_or(f).with_extension("").to_string_lossy().replace('\\', "/")
+        }
+    }).collect()
+}
+
+/// Converts the wikilinks of a note into Twine links. Targets are resolved like Obsidian does: by path, file name or
+/// alias, ignoring case and headings.
+fn convert_links(name: &str, content: &str, targets: &BTreeMap<String, String>) -> String {
+    let wikilink = Regex::new(r"(!?)\[\[([^\]|#]*)(#[^\]|]*)?(?:\|([^\]]*))?\]\]").unwrap();
+    wikilink.replace_all(content, |c: &Captures| {
+        let target = c[2].trim();
+        let heading = c.get(3).map(|h| h.as_str().trim_start_matches('#'));
+        let text = c.get(4).map(|t| t.as_str().trim());
+        if c[1].is_empty() && target.is_empty() {
+            // A link to a heading of the same note.
+            return text.or(heading).unwrap_or_default().to_string();
+        }
+        if ! c[1].is_empty() && target.rsplit_once('.').is_some_and(|(_, e)| IMAGE_EXTENSIONS.contains(&e.to_lowercase().as_str())) {
+            return format!("<img src=\"{}\" alt=\"{}\">", escape_html(target), escape_html(text.unwrap_or(target)));
+        }
+        if ! c[1].is_empty() {
+            warn!(code = "obsidian-embed", "Note \"{}\" embeds \"{}\", which is imported as a link", name, target);
+        }
+        let key = target.trim_end_matches(".md").to_lowercase();
+        let passage = match targets.get(&key) {
+            Some(passage) => passage.as_str(),
+            None => {
+                warn!(code = "obsidian-link-missing", "Note \"{}\" links to \"{}\", which doesn't exist", name, target);
+                target
+            },
+        };
+        // Obsidian shows the link as written, e.g. with the alias instead of the note name.
+        let text = text.unwrap_or(target);
+        if text == passage {
+            format!("[[{}]]", passage)
+        } else {
+            format!("[[{}->{}]]", text, passage)
+        }
+    }).to_string()
+}
+
+/// Imports a folder of Markdown notes with `[[wikilinks]]`, like an Obsidian vault, into a twee project in `out`.
+/// Each note becomes a passage, with the tags of its front matter and the other fields as passage metadata.
+pub(crate) fn import_obsidian(vault: &Path, format: StoryFormat, out: &Path, title: Option<String>, start: Option<String>) -> anyhow::Result<()> {
+    if ! vault.is_dir() {
+        return Err(Error::DirNotFound(vault.to_string_lossy().to_string()).into());
+    }
+    if out.join("config.toml").exists() {
+        return Err(Error::ProjectExists(out.to_string_lossy().to_string()).into());
+    }
+    let mut files = vec![];
+    markdown_files(vault, &mut files)?;
+    files.sort();
+    let names = passage_names(vault, &files);
+    let mut notes = vec![];
+    // Link targets by lowercase path, file name and alias.
+    let mut targets = BTreeMap::new();
+    for (f, name) in files.iter().zip(&names) {
+        let source = read_file(f)?;
+        let (meta, content) = split_front_matter(&source);
+        let path = f.strip_prefix(vault).unwrap_or(f).with_extension("").to_string_lossy().replace('\\', "/");
+        for alias in front_matter_list(&meta, "aliases") {
+            targets.entry(alias.to_lowercase()).or_insert(name.clone());
+        }
+        targets.insert(name.to_lowercase(), name.clone());
+        targets.insert(path.to_lowercase(), name.clone());
+        notes.push((name, meta, content.to_string()));
+    }
+    let mut story = parse_twee3(DEFAULT_TWEE).unwrap().0;
+    story.passages.retain(|p| p.name != "Start");
+    story.title = match title {
+        Some(title) => title,
+        None => absolute_path(vault)?.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or("Story".to_string()),
+    };
+    story.meta.insert("ifid".to_string(), gen_ifid().into());
+    story.meta.insert("format".to_string(), format.format_name().into());
+    story.meta.insert("format-version".to_string(), format.format_version().into());
+    for (name, mut meta, content) in notes {
+        let tags = front_matter_list(&meta, "tags").iter().map(|t| t.trim_start_matches('#').replace(' ', "-")).collect();
+        meta.remove("tags");
+        meta.remove("aliases");
+        story.passages.push(Passage { name: name.clone(), tags, meta, content: convert_links(name, &content, &targets) });
+    }
+    let start = match start {
+        Some(start) => Some(targets.get(&start.to_lowercase()).cloned().ok_or(Error::PassageNotFound(start))?),
+        None => targets.get("start").cloned(),
+    };
+    match start.or(names.first().cloned()) {
+        Some(start) => {
+            info!("Starting at \"{}\"", start);
+            story.meta.insert("start".to_string(), start.into());
+        },
+        None => warn!(code = "obsidian-no-notes", "{} has no Markdown notes", vault.to_string_lossy()),
+    }
+    let count = names.len();
+    write_project(out, story)?;
+    info!("Imported {} notes into {}", count, out.to_string_lossy());
+    Ok(())
+}
+
+/// The note names of the passages shown to players. Characters Obsidian doesn't allow in note names are replaced.
+fn note_names(story: &Story) -> BTreeMap<String, String> {
+    let mut taken = BTreeSet::new();
+    let mut names = BTreeMap::new();
+    for p in story.passages.iter().filter(|p| is_shown(p)) {
+        let note: String = p.name.chars().map(|c| if "/\\:*?\"<>|#^[]".contains(c) || c.is_control() { '_' } else { c }).collect();
+        let note = note.trim().trim_matches('.').to_string();
+        let note = if note.is_empty() { "passage".to_string() } else { note };
+        let mut name = note.clone();
+        let mut i = 1;
+        while ! taken.insert(name.to_lowercase()) {
+            i += 1;
+            name = format!("{} {}", note, i);
+        }
+        names.insert(p.name.clone(), name);
+    }
+    names
+}
+
+/// The note of a passage: front matter with the tags and metadata, and the content with the links as wikilinks.
+fn passage_note(p: &Passage, notes: &BTreeMap<String, String>) -> String {
+    let mut note = String::new();
+    let mut front_matter = vec![];
+    if ! p.tags.is_empty() {
+        front_matter.push(format!("tags: {}", Value::from(p.tags.clone())));
+    }
+    if notes[&p.name] != p.name {
+        front_matter.push(format!("aliases: {}", Value::from(vec![p.name.clone()])));
+    }
+    // JSON is valid YAML, so the values can be written as is.
+    front_matter.extend(p.meta.iter().map(|(k, v)| format!("{}: {}", k, v)));
+    if ! front_matter.is_empty() {
+        note += &format!("---\n{}\n---\n", front_matter.join("\n"));
+    }
+    let mut last = 0;
+    for l in p.links() {
+        note += &p.content[last..l.span.start];
+        last = l.span.end;
+        let target = notes.get(&l.target).unwrap_or(&l.target);
+        if l.text == *target {
+            note += &format!("[[{}]]", target);
+        } else {
+            note += &format!("[[{}|{}]]", target, l.text);
+        }
+    }
+    note += &p.content[last..];
+    note
+}
+
+/// Writes each passage of the story in the current directory shown to players as a Markdown note into `out`, with the
+/// links as `[[wikilinks]]`, so the story can be browsed and viewed as a graph in Obsidian.
+pub(crate) fn export_obsidian(out: &Path) -> anyhow::Result<()> {
+    let config = load_config()?;
+    let story = build_story(&config, false)?;
+    let notes = note_names(&story);
+    for p in story.passages.iter().filter(|p| notes.contains_key(&p.name)) {
+        write_output(out.join(notes[&p.name].clone() + ".md"), passage_note(p, &notes).as_bytes())?;
+    }
+    info!("Wrote {} notes to {}", notes.len(), out.to_string_lossy());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    
+    #[test]
+    fn front_matter() {
+        let note = "---\ntags: [intro, \"chapter 1\"]\naliases:\n  - Beginning\n  - 'The Start'\ncount: 3\ndone: true\ntitle: \"42\"\n# comment\n---\n\nText\n";
+        let (meta, content) = split_front_matter(note);
+        assert_eq!(Value::Object(meta.clone()), json!({
+            "tags": ["intro", "chapter 1"],
+            "aliases": ["Beginning", "The Start"],
+            "count": 3,
+            "done": true,
+            "title": "42",
+        }));
+        assert_eq!(content, "Text\n");
+        assert_eq!(front_matter_list(&meta, "aliases"), vec!["Beginning", "The Start"]);
+        let (meta, _) = split_front_matter("---\ntags: a b,c\n---\n");
+        assert_eq!(front_matter_list(&meta, "tags"), vec!["a", "b", "c"]);
+    }
+    
+    #[test]
+    fn without_front_matter() {
+        for note in ["Text\n---\n", "---\nnot closed\n", "---\nkey: value\n---more\n"] {
+            let (meta, content) = split_front_matter(note);
+            assert!(meta.is_empty());
+            assert_eq!(content, note);
+        }
+        let (meta, content) = split_front_matter("---\r\nkey: value\r\n---\r\nText");
+        assert_eq!(meta["key"], "value");
+        assert_eq!(content, "Text");
+    }
+    
+    #[test]
+    fn links() {
+        let targets: BTreeMap<String, String> = [("cave", "Cave"), ("places/cave", "Cave"), ("the cave", "Cave"), ("end", "End")]
+            .into_iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        assert_eq!(convert_links("A", "[[Cave]] [[cave]] [[End|Leave]]", &targets), "[[Cave]] [[cave->Cave]] [[Leave->End]]");
+        assert_eq!(convert_links("A", "[[places/Cave.md#Inside|In]] [[The Cave]]", &targets), "[[In->Cave]] [[The Cave->Cave]]");
+        assert_eq!(convert_links("A", "See [[#Heading]] and [[#Heading|here]].", &targets), "See Heading and here.");
+        assert_eq!(convert_links("A", "[[Missing]]", &targets), "[[Missing]]");
+    }
+    
+    #[test]
+    fn image_embeds() {
+        let targets = BTreeMap::new();
+        assert_eq!(convert_links("A", "![[map.png]]", &targets), "<img src=\"map.png\" alt=\"map.png\">");
+        assert_eq!(
+            convert_links("A", "![[a\"b&c.PNG|say \"hi\" <b>]]", &targets),
+            "<img src=\"a&quot;b&amp;c.PNG\" alt=\"say &quot;hi&quot; &lt;b&gt;\">",
+        );
+    }
+}
@@ -0,0 +1,75 @@
+use std::{io::{Cursor, Write}, path::{Path, PathBuf}};
+
+use tracing::warn;
+use twee_parser::Story;
+use zip::{write::FileOptions, CompressionMethod, ZipWriter};
+use twee_build::{absolute_path, glob, load_config, write_output, Build, Config};
+
+use crate::{build::{BuildOptions, Error}, lint::asset_references};
+
+fn zip_name(p: &Path) -> String {
+    let p = p.strip_prefix(".").unwrap_or(p);
+    p.components().map(|c| c.as_os_str().to_string_lossy().to_string()).collect::<Vec<String>>().join("/")
+}
+
+/// Returns the files to put in the zip, with the names they should have in it.
+pub(crate) fn package_files(config: &Config, story: &Story) -> anyhow::Result<Vec<(PathBuf, String)>> {
+    let mut files: Vec<(PathBuf, String)> = vec![];
+    if config.package.assets.is_empty() {
+        let roots = if config.asset_roots.is_empty() { vec![".".to_string()] } else { config.asset_roots.clone() };
+        for p in &story.passages {
+            for r in asset_references(&p.content) {
+                let r = r.split(['?', '#']).next().unwrap().to_string();
+                if let Some(f) = roots.iter().map(|root| Path::new(root).join(&r)).find(|f| f.is_file()) {
+                    if ! files.iter().any(|(_, n)| n == &r) {
+                        files.push((f, zip_name(Path::new(&r))));
+                    }
+                }
+            }
+        }
+    }
+    for p in config.package.assets.iter().chain(config.package.files.iter()) {
+        let matches = glob(p, PathBuf::new())?;
+        if matches.is_empty() {
+            warn!(code = "no-match", "No matching file found for pattern: {}", p);
+        }
+        for f in matches {
+            let name = zip_name(&f);
+            if f.is_file() && ! files.iter().any(|(_, n)| n == &name) {
+                files.push((f, name));
+            }
+        }
+    }
+    Ok(files)
+}
+
+/// Builds the story and packs it into a zip together with its assets.  
+/// If the HTML file isn't called index.html, an index.html redirecting to it is added, as expected by e.g. itch.io.
+pub(crate) fn package(options: &BuildOptions) -> anyhow::Result<PathBuf> {
+    let Build { out: html_path, html, story, .. } = crate::build(options)?;
+    let config = load_config()?;
+    let html_name = html_path.file_name().ok_or(Error::FileNotFound(html_path.to_string_lossy().to_string()))?.to_string_lossy().to_string();
+    let out = if let Some(out) = &config.package.output {
+        PathBuf::from(out)
+    } else {
+        html_path.with_extension("zip")
+    };
+    let out_abs = absolute_path(&out)?;
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+    zip.start_file(html_name.clone(), options)?;
+    zip.write_all(html.as_bytes())?;
+    if html_name != "index.html" {
+        zip.start_file("index.html", options)?;
+        zip.write_all(format!("<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><meta http-equiv=\"refresh\" content=\"0; url={0}\"></head><body><a href=\"{0}\">{0}</a></body></html>\n", html_name.replace('"', "&quot;")).as_bytes())?;
+    }
+    for (f, name) in package_files(&config, &story)? {
+        if absolute_path(&f)? == out_abs {
+            continue;
+        }
+        zip.start_file(name, options)?;
+        zip.write_all(&std::fs::read(&f)?)?;
+    }
+    write_output(&out, &zip.finish()?.into_inner())?;
+    Ok(out)
+}
@@ -0,0 +1,89 @@
+use std::{fs::File, io::{stdin, stdout, BufRead, Write}, path::PathBuf};
+
+use regex::Regex;
+use tracing::info;
+use twee_parser::{Link, Passage, Story};
+
+use crate::build::Error;
+
+/// Strips the most common markup of the story formats from passage text, replacing links with their number.
+pub(crate) fn strip_markup(p: &Passage, links: &[Link]) -> String {
+    let mut text = String::new();
+    let mut last = 0;
+    for (i, l) in links.iter().enumerate() {
+        text += &p.content[last..l.span.start];
+        text += &format!("[{}] {}", i + 1, l.text);
+        last = l.span.end;
+    }
+    text += &p.content[last..];
+    let rules = [
+        // SugarCube macros and Harlowe macros
+        (r"<<[^>]*>>", ""),
+        (r"\([a-z0-9-]+:[^()]*\)", ""),
+        // HTML tags and comments
+        (r"<!--(?s:.)*?-->", ""),
+        (r"</?[a-zA-Z][^>]*>", ""),
+        // Emphasis
+        (r"''|//|\*\*|__|~~", ""),
+        // Harlowe hooks
+        (r"\|[a-zA-Z0-9_]+>|<[a-zA-Z0-9_]+\|", ""),
+        (r"\n{3,}", "\n\n"),
+    ];
+    for (r, replacement) in rules {
+        text = Regex::new(r).unwrap().replace_all(&text, replacement).to_string();
+    }
+    text.trim().to_string()
+}
+
+/// Lets the user navigate the story in the terminal, following the links of the passages.
+pub(crate) fn play(story: &Story, start: Option<String>, record: Option<PathBuf>) -> anyhow::Result<()> {
+    let start = if let Some(start) = start {
+        story.passage(&start).ok_or(Error::PassageNotFound(start))?
+    } else {
+        story.start_passage().or(story.passage("Start")).ok_or(Error::PassageNotFound("start".to_string()))?
+    };
+    let mut path = vec![start.name.clone()];
+    let mut input = stdin().lock();
+    loop {
+        let current = story.passage(path.last().unwrap()).ok_or(Error::PassageNotFound(path.last().unwrap().clone()))?;
+        let links = current.links();
+        println!("\n=== {} ===\n", current.name);
+        println!("{}\n", strip_markup(current, &links));
+        if links.is_empty() {
+            println!("(No links. b: back, q: quit)");
+        } else {
+            println!("(1-{}: follow link, b: back, q: quit)", links.len());
+        }
+        loop {
+            print!("> ");
+            stdout().flush()?;
+            let mut line = String::new();
+            if input.read_line(&mut line)? == 0 {
+                line = "q".to_string();
+            }
+            let line = line.trim();
+            if line == "q" {
+                if let Some(record) = &record {
+                    File::create(record)?.write_all((path.join("\n") + "\n").as_bytes())?;
+                    info!("Recorded path to {}", record.to_string_lossy());
+                }
+                return Ok(());
+            }
+            if line == "b" {
+                if path.len() > 1 {
+                    path.pop();
+                }
+                break;
+            }
+            if let Some(l) = line.parse::<usize>().ok().and_then(|i| i.checked_sub(1)).and_then(|i| links.get(i)) {
+                if story.passage(&l.target).is_some() {
+                    path.push(l.target.clone());
+                    break;
+                }
+                println!("The passage \"{}\" doesn't exist.", l.target);
+                continue;
+            }
+            println!("Unknown input.");
+        }
+    }
+}
@@ -0,0 +1,26 @@
+use std::process::{Command, Stdio};
+
+use tracing::info;
+use twee_build::{dry_run, load_config};
+
+use crate::{build::{BuildOptions, Error}, package::package};
+
+/// Packages the story and pushes it to itch.io using butler.
+pub(crate) fn publish_itch(options: &BuildOptions, channel: Option<String>) -> anyhow::Result<()> {
+    let config = load_config()?;
+    let project = config.itch.project.clone().ok_or(Error::MissingConfig("itch.project".to_string()))?;
+    let channel = channel.or(config.itch.channel.clone()).ok_or(Error::MissingConfig("itch.channel".to_string()))?;
+    let zip = package(options)?;
+    let butler = config.itch.butler.clone().unwrap_or("butler".to_string());
+    let target = format!("{}:{}", project, channel);
+    if dry_run() {
+        info!("Would run: {} push {} {}", butler, zip.to_string_lossy(), target);
+        return Ok(());
+    }
+    let status = Command::new(&butler).arg("push").arg(&zip).arg(&target).stdin(Stdio::null()).status()
+        .map_err(|e| Error::CommandFailed(format!("{}: {}", butler, e)))?;
+    if ! status.success() {
+        return Err(Error::CommandFailed(format!("{} push exited with {}", butler, status)).into());
+    }
+    Ok(())
+}
@@ -0,0 +1,27 @@
+use std::{collections::hash_map::DefaultHasher, hash::{Hash, Hasher}, path::{Path, PathBuf}};
+use twee_build::write_output;
+
+use crate::export::inject_script;
+
+const HOT_RELOAD_SHIM: &str = include_str!("../templates/reload/hot-reload.js");
+
+/// The path of the script `watch` writes next to a built story to announce the current build.
+pub(crate) fn reload_path(out: &Path) -> PathBuf {
+    let mut name = out.file_name().unwrap_or_default().to_os_string();
+    name.push(".reload.js");
+    out.with_file_name(name)
+}
+
+/// Injects the hot reload shim into the built story and writes the reload script for it.
+///
+/// The shim polls the reload script, and when the build changed, saves the story state and reloads the page.
+pub(crate) fn hot_reload(out: &Path, html: &str) -> anyhow::Result<String> {
+    let mut hasher = DefaultHasher::new();
+    html.hash(&mut hasher);
+    let id = format!("{:016x}", hasher.finish());
+    let file = reload_path(out);
+    let file_name = file.file_name().unwrap_or_default().to_string_lossy();
+    let shim = HOT_RELOAD_SHIM.replace("{{BUILD_ID}}", &id).replace("{{RELOAD_FILE}}", &file_name);
+    write_output(&file, format!("window.__tweeHotReload && window.__tweeHotReload(\"{}\");\n", id).as_bytes())?;
+    Ok(inject_script(html, &shim))
+}
@@ -0,0 +1,56 @@
+use std::{collections::BTreeMap, ops::Range, path::PathBuf};
+
+use regex::RegexBuilder;
+use tracing::{info, warn};
+use twee_build::{build_story, load_config, read_source, source_blocks, write_output, BuildInputs};
+use twee_parser::{escape_content, passage_header, ReplaceOptions, Story};
+
+use crate::{build::Error, diff::unified_diff, search::display_path};
+
+/// Replaces the matches of the regex in the passages of the twee files of the project in the current directory, and
+/// in the passage names with `names`. Prints the changes as a unified diff, and writes them with `write`. Returns the
+/// number of changed passages.
+pub(crate) fn replace(pattern: &str, replacement: &str, names: bool, ignore_case: bool, write: bool) -> anyhow::Result<usize> {
+    let regex = RegexBuilder::new(pattern).case_insensitive(ignore_case).build()
+        .map_err(|e| Error::InvalidPattern(e.to_string()))?;
+    let config = load_config()?;
+    let built = build_story(&config, false)?;
+    let blocks = source_blocks(&BuildInputs::collect().twee_files())?;
+    // All passages in one story, so renaming a passage into an existing name is noticed.
+    let mut story = Story { title: String::new(), passages: blocks.iter().map(|b| b.passage.clone()).collect(), meta: Default::default() };
+    story.replace(&regex, replacement, &ReplaceOptions { names });
+    // Edits per file as (range, replacement).
+    let mut edits: BTreeMap<PathBuf, Vec<(Range<usize>, String)>> = BTreeMap::new();
+    for (b, p) in blocks.iter().zip(&story.passages) {
+        if b.passage == *p {
+            continue;
+        }
+        if p.name != b.passage.name && built.meta.get("start").and_then(|s| s.as_str()) == Some(&b.passage.name) {
+            warn!(code = "replace-start", "Renamed the start passage \"{}\" to \"{}\", update the start in the StoryData", b.passage.name, p.name);
+        }
+        let source = read_source(&b.file)?;
+        let header = if p.name != b.passage.name { passage_header(&p.name, &p.tags, &p.meta) } else { source[b.header.clone()].to_string() };
+        let content = if p.content != b.passage.content { escape_content(&p.content) } else { source[b.body.clone()].to_string() };
+        edits.entry(b.file.clone()).or_default().push((b.header.start..b.body.end, header + &content));
+    }
+    let changed = edits.values().map(|e| e.len()).sum();
+    for (f, mut edits) in edits {
+        let mut source = read_source(&f)?;
+        edits.sort_by_key(|(r, _)| r.start);
+        print!("{}", unified_diff(&display_path(&f), &source, &edits));
+        if write {
+            for (r, replacement) in edits.into_iter().rev() {
+                source.replace_range(r, &replacement);
+            }
+            write_output(&f, source.as_bytes())?;
+        }
+    }
+    if changed == 0 {
+        info!("No matches");
+    } else if write {
+        info!("Changed {} passage(s)", changed);
+    } else {
+        info!("Would change {} passage(s), run with --write to apply the changes", changed);
+    }
+    Ok(changed)
+}
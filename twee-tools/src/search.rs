@@ -0,0 +1,62 @@
+use std::{collections::BTreeMap, path::{Path, PathBuf}};
+
+use regex::RegexBuilder;
+use tracing::info;
+use twee_build::{build_story, load_config, read_source, source_blocks, BuildInputs};
+
+use crate::build::Error;
+
+/// Where a passage comes from: the twee file and the line its text starts at, if it's in a twee file unchanged.
+pub(crate) fn passage_sources() -> anyhow::Result<BTreeMap<String, (PathBuf, usize, String)>> {
+    let mut sources = BTreeMap::new();
+    for b in source_blocks(&BuildInputs::collect().twee_files())? {
+        let line = read_source(&b.file)?[..b.header.end].matches('\n').count() + 1;
+        sources.entry(b.passage.name.clone()).or_insert((b.file, line, b.passage.content));
+    }
+    Ok(sources)
+}
+
+pub(crate) fn display_path(p: &Path) -> String {
+    let cwd = std::env::current_dir().unwrap_or_default();
+    p.strip_prefix(&cwd).unwrap_or(p).to_string_lossy().to_string()
+}
+
+/// Searches the names, tags and text of the passages of the story in the current directory, after includes, and
+/// prints the matches grouped by passage with the file and line they are in. Returns the number of matching passages.
+pub(crate) fn search(pattern: &str, ignore_case: bool) -> anyhow::Result<usize> {
+    let regex = RegexBuilder::new(pattern).case_insensitive(ignore_case).build()
+        .map_err(|e| Error::InvalidPattern(e.to_string()))?;
+    let config = load_config()?;
+    let story = build_story(&config, false)?;
+    let sources = passage_sources()?;
+    let mut passages = 0;
+    let mut matches = 0;
+    for p in &story.passages {
+        let name = regex.is_match(&p.name);
+        let tags: Vec<&String> = p.tags.iter().filter(|t| regex.is_match(t)).collect();
+        let lines: Vec<(usize, &str)> = p.content.lines().enumerate().filter(|(_, l)| regex.is_match(l)).collect();
+        if ! name && tags.is_empty() && lines.is_empty() {
+            continue;
+        }
+        // Line numbers are in the file if the passage text is unchanged by the build, otherwise in the passage.
+        let (source, first_line) = match sources.get(&p.name) {
+            Some((file, line, content)) if content == &p.content => (display_path(file), *line),
+            Some((file, _, _)) => (format!("{}, generated", display_path(file)), 1),
+            None => ("generated".to_string(), 1),
+        };
+        println!("{} ({})", p.name, source);
+        if name {
+            println!("  name: {}", p.name);
+        }
+        if ! tags.is_empty() {
+            println!("  tags: {}", tags.iter().map(|t| t.as_str()).collect::<Vec<_>>().join(" "));
+        }
+        for (i, l) in &lines {
+            println!("  {}: {}", first_line + i, l.trim_end());
+        }
+        passages += 1;
+        matches += name as usize + tags.len() + lines.len();
+    }
+    info!("{} match(es) in {} passage(s)", matches, passages);
+    Ok(passages)
+}
@@ -0,0 +1,174 @@
+use std::{io::{BufRead, BufReader, Write}, net::{TcpListener, TcpStream}, path::{Component, Path, PathBuf}, sync::{Arc, Mutex}};
+
+use percent_encoding::percent_decode_str;
+use serde_json::json;
+use tracing::{debug, info, warn};
+
+use crate::{browser::open_url, build::BuildOptions, export::inject_script, notification::Notify, watch, websocket::Feed};
+
+const SERVE_SHIM: &str = include_str!("../templates/serve/serve.js");
+
+/// The content type of a served file.
+fn content_type(path: &Path) -> &'static str {
+    match path.extension().and_then(|e| e.to_str()).map(|e| e.to_ascii_lowercase()).as_deref() {
+        Some("html" | "htm") => "text/html; charset=utf-8",
+        Some("js") => "text/javascript; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        Some("json") => "application/json",
+        Some("png") => "image/png",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("svg") => "image/svg+xml",
+        Some("webp") => "image/webp",
+        Some("mp3") => "audio/mpeg",
+        Some("ogg") => "audio/ogg",
+        Some("wav") => "audio/wav",
+        Some("mp4") => "video/mp4",
+        Some("webm") => "video/webm",
+        Some("woff2") => "font/woff2",
+        _ => "application/octet-stream",
+    }
+}
+
+/// The file in the served directory a request path refers to, or None if it leaves the directory.
+fn served_file(path: &str) -> Option<PathBuf> {
+    let path = percent_decode_str(path.split(['?', '#']).next().unwrap_or_default()).decode_utf8().ok()?;
+    let file = PathBuf::from(path.trim_start_matches('/'));
+    file.components().all(|c| matches!(c, Component::Normal(_))).then_some(file)
+}
+
+/// Whether a WebSocket request comes from a page of the server, so other web pages can't move the browsers. Requests
+/// without an origin don't come from web pages.
+fn same_origin(origin: Option<&str>, host: Option<&str>) -> bool {
+    let Some(origin) = origin else {
+        return true;
+    };
+    origin.strip_prefix("http://").is_some_and(|o| Some(o) == host)
+}
+
+fn write_response(stream: &mut TcpStream, status: &str, headers: &str, body: &[u8]) -> std::io::Result<()> {
+    let head = format!("HTTP/1.1 {}\r\n{}Content-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n", status, headers, body.len());
+    stream.write_all(head.as_bytes())?;
+    stream.write_all(body)?;
+    stream.flush()
+}
+
+/// Answers a request of a browser: the WebSocket at `/events`, the first output at `/`, and the files of the current
+/// directory, with the shim injected into the outputs.
+fn handle(mut stream: TcpStream, outputs: &Mutex<Vec<PathBuf>>, feed: &Arc<Feed>) -> anyhow::Result<()> {
+    let mut reader = BufReader::new(&mut stream);
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(path)) = (parts.next(), parts.next()) else {
+        return Ok(());
+    };
+    let (method, path) = (method.to_string(), path.to_string());
+    let (mut websocket_key, mut host, mut origin) = (None, None, None);
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+        if let Some((k, v)) = header.split_once(':') {
+            if k.trim().eq_ignore_ascii_case("sec-websocket-key") {
+                websocket_key = Some(v.trim().to_string());
+            } else if k.trim().eq_ignore_ascii_case("host") {
+                host = Some(v.trim().to_string());
+            } else if k.trim().eq_ignore_ascii_case("origin") {
+                origin = Some(v.trim().to_string());
+            }
+        }
+    }
+    if let (Some(key), "/events") = (websocket_key, path.as_str()) {
+        if ! same_origin(origin.as_deref(), host.as_deref()) {
+            write_response(&mut stream, "403 Forbidden", "", b"Only the served story can connect to /events")?;
+            return Ok(());
+        }
+        feed.accept(stream, &key)?;
+        return Ok(());
+    }
+    let outputs = outputs.lock().unwrap().clone();
+    let not_found = ("404 Not Found", String::new(), b"Not found".to_vec());
+    let (status, headers, body) = match served_file(&path) {
+        _ if method != "GET" => ("405 Method Not Allowed", String::new(), b"Use GET".to_vec()),
+        None => not_found,
+        Some(file) if file.as_os_str().is_empty() => match outputs.first() {
+            Some(out) => ("302 Found", format!("Location: /{}\r\n", out.to_string_lossy().replace('\\', "/")), vec![]),
+            None => ("404 Not Found", String::new(), b"The story hasn't been built yet".to_vec()),
+        },
+        Some(file) => match std::fs::read(&file) {
+            Ok(contents) if outputs.contains(&file) => {
+                let shim = SERVE_SHIM.replace("{{SYNC_NAVIGATION}}", &feed.syncs_navigation().to_string());
+                ("200 OK", format!("Content-Type: {}\r\n", content_type(&file)), inject_script(&String::from_utf8_lossy(&contents), &shim).into_bytes())
+            },
+            Ok(contents) => ("200 OK", format!("Content-Type: {}\r\n", content_type(&file)), contents),
+            Err(_) => not_found,
+        },
+    };
+    write_response(&mut stream, status, &headers, &body)?;
+    Ok(())
+}
+
+/// Builds the story, or all stories of the workspace, on any changes like `watch`, and serves the current directory
+/// on the host and port. The built stories get a script that reloads them after a rebuild, and with
+/// `sync_navigation`, keeps all browsers playing them at the same passage: when a browser goes to a passage, the
+/// others are sent there too, and browsers that connect later start at the last one.
+pub(crate) fn serve(options: BuildOptions, host: &str, port: u16, open: bool, notify: Option<Notify>, sync_navigation: bool) -> anyhow::Result<()> {
+    let listener = TcpListener::bind((host, port))?;
+    let url = format!("http://{}:{}/", host, listener.local_addr()?.port());
+    let outputs: Arc<Mutex<Vec<PathBuf>>> = Arc::default();
+    let feed = Arc::new(if sync_navigation { Feed::sync_navigation() } else { Feed::default() });
+    {
+        let (outputs, feed) = (outputs.clone(), feed.clone());
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(stream) = stream else {
+                    continue;
+                };
+                let (outputs, feed) = (outputs.clone(), feed.clone());
+                std::thread::spawn(move || {
+                    if let Err(e) = handle(stream, &outputs, &feed) {
+                        debug!("Could not answer request: {}", e);
+                    }
+                });
+            }
+        });
+    }
+    info!("Serving the story at {}", url);
+    let dir = std::env::current_dir()?;
+    let mut opened = ! open;
+    watch(options, false, notify, |out| {
+        // Outputs outside the current directory can't be served.
+        *outputs.lock().unwrap() = out.iter().filter_map(|o| o.strip_prefix(&dir).ok().map(Path::to_path_buf)).collect();
+        feed.broadcast(&json!({ "type": "reload" }));
+        if ! opened {
+            opened = true;
+            if let Err(e) = open_url(url.as_ref()) {
+                warn!(code = "open-error", "{:#}", e);
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn event_origins() {
+        assert!(same_origin(None, Some("127.0.0.1:8000")));
+        assert!(same_origin(Some("http://192.168.1.2:8000"), Some("192.168.1.2:8000")));
+        assert!(! same_origin(Some("http://example.com"), Some("127.0.0.1:8000")));
+        assert!(! same_origin(Some("http://127.0.0.1:3000"), Some("127.0.0.1:8000")));
+    }
+    
+    #[test]
+    fn served_files() {
+        assert_eq!(served_file("/Story.html?x=1"), Some(PathBuf::from("Story.html")));
+        assert_eq!(served_file("/img/a%20b.png"), Some(PathBuf::from("img/a b.png")));
+        assert_eq!(served_file("/"), Some(PathBuf::new()));
+        assert_eq!(served_file("/../secret"), None);
+        assert_eq!(served_file("/img/%2e%2e/%2e%2e/secret"), None);
+    }
+}
@@ -0,0 +1,41 @@
+use tracing::info;
+use twee_build::{Config, Profile, Project};
+use twee_parser::{serialize_html_with, serialize_twee3_passage, xmltree::{EmitterConfig, XMLNode}, HtmlOptions, StartFallback};
+
+use crate::{build::Error, search::{display_path, passage_sources}};
+
+/// The `<tw-passagedata>` element of the passage in the built story.
+fn passage_html(story: &twee_parser::Story, config: &Config, name: &str) -> anyhow::Result<String> {
+    let options = HtmlOptions { start: StartFallback::First, order: (&config.passage_order).into() };
+    let (storydata, _) = serialize_html_with(story, &options)?;
+    let element = storydata.children.iter().find_map(|c| match c {
+        XMLNode::Element(e) if e.name == "tw-passagedata" && e.attributes.get("name").map(|n| n.as_str()) == Some(name) => Some(e),
+        _ => None,
+    }).ok_or(Error::PassageNotFound(name.to_string()))?;
+    let mut html: Vec<u8> = Vec::new();
+    element.write_with_config(&mut html, EmitterConfig {
+        normalize_empty_elements: false,
+        write_document_declaration: false,
+        ..Default::default()})?;
+    Ok(String::from_utf8(html)?)
+}
+
+/// Prints a passage of the story in the current directory as it ends up in the build, after includes and other
+/// transforms, as Twee or as the HTML element in the built story.
+pub(crate) fn show(name: &str, html: bool, debug: bool) -> anyhow::Result<()> {
+    let project = Project::load("config.toml")?;
+    let story = project.story(&Profile { debug })?;
+    let config = project.config;
+    let passage = story.passage(name).ok_or(Error::PassageNotFound(name.to_string()))?;
+    match passage_sources()?.get(name) {
+        Some((file, line, content)) if *content == passage.content => info!("From {}:{}", display_path(file), line - 1),
+        Some((file, _, _)) => info!("From {}, changed by the build", display_path(file)),
+        None => info!("Generated by the build"),
+    }
+    if html {
+        println!("{}", passage_html(&story, &config, name)?);
+    } else {
+        print!("{}", serialize_twee3_passage(passage).trim_start());
+    }
+    Ok(())
+}
@@ -0,0 +1,156 @@
+use std::{collections::{BTreeMap, BTreeSet}, path::Path};
+
+use tracing::{info, warn};
+use twee_build::{build_story, load_config, write_output};
+use twee_parser::{is_special_passage, Link, Passage, Story};
+
+use crate::{spellcheck::prose, stats::is_prose};
+
+const SITE_PAGE: &str = include_str!("../templates/site/page.html");
+const SITE_STYLE: &str = include_str!("../templates/site/style.css");
+
+pub(crate) fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+/// Whether the passage is shown to players, as opposed to scripts and passages with a special meaning.
+pub(crate) fn is_shown(p: &Passage) -> bool {
+    is_prose(p) && ! is_special_passage(p)
+}
+
+/// The file name of each passage that is shown to players: the start passage is `index.html`, the others are named after the passage.
+fn page_names(story: &Story) -> BTreeMap<String, String> {
+    let start = story.start_passage().or(story.passage("Start")).map(|p| p.name.clone());
+    let mut taken: BTreeSet<String> = ["index", "contents", "style"].into_iter().map(str::to_string).collect();
+    let mut names = BTreeMap::new();
+    for p in story.passages.iter().filter(|p| is_shown(p)) {
+        if Some(&p.name) == start.as_ref() {
+            names.insert(p.name.clone(), "index.html".to_string());
+            continue;
+        }
+        let slug = p.name.to_lowercase().chars().map(|c| if c.is_alphanumeric() { c } else { '-' }).collect::<String>()
+            .split('-').filter(|s| ! s.is_empty()).collect::<Vec<&str>>().join("-");
+        let slug = if slug.is_empty() { "passage".to_string() } else { slug };
+        let mut name = slug.clone();
+        let mut i = 1;
+        while ! taken.insert(name.clone()) {
+            i += 1;
+            name = format!("{}-{}", slug, i);
+        }
+        names.insert(p.name.clone(), name + ".html");
+    }
+    names
+}
+
+/// A piece of a line of prose.
+pub(crate) enum Span {
+    Text(String),
+    Link(Link),
+}
+
+/// Adds text to the lines, starting a new line at each newline and collapsing whitespace.
+fn push_prose(lines: &mut Vec<Vec<Span>>, text: &str) {
+    for (i, part) in text.split('\n').enumerate() {
+        if i > 0 {
+            lines.push(vec![]);
+        }
+        if part.is_empty() {
+            continue;
+        }
+        let mut collapsed = part.split_whitespace().collect::<Vec<_>>().join(" ");
+        if part.starts_with(char::is_whitespace) {
+            collapsed.insert(0, ' ');
+        }
+        if part.ends_with(char::is_whitespace) && ! collapsed.ends_with(' ') {
+            collapsed.push(' ');
+        }
+        lines.last_mut().unwrap().push(Span::Text(collapsed));
+    }
+}
+
+/// Splits the prose of the passage into paragraphs of lines, with the links kept as links. Whitespace is collapsed,
+/// and lines that are empty after leaving out markup don't count.
+pub(crate) fn prose_paragraphs(p: &Passage, format: &str) -> Vec<Vec<Vec<Span>>> {
+    let text = prose(p, format);
+    let mut lines: Vec<Vec<Span>> = vec![vec![]];
+    let mut last = 0;
+    for l in p.links() {
+        push_prose(&mut lines, &text[last..l.span.start]);
+        last = l.span.end;
+        lines.last_mut().unwrap().push(Span::Link(l));
+    }
+    push_prose(&mut lines, &text[last..]);
+    let mut paragraphs: Vec<Vec<Vec<Span>>> = vec![vec![]];
+    for mut line in lines {
+        if let Some(Span::Text(t)) = line.first_mut() {
+            *t = t.trim_start().to_string();
+        }
+        if let Some(Span::Text(t)) = line.last_mut() {
+            *t = t.trim_end().to_string();
+        }
+        line.retain(|s| ! matches!(s, Span::Text(t) if t.is_empty()));
+        if ! line.is_empty() {
+            paragraphs.last_mut().unwrap().push(line);
+        } else if ! paragraphs.last().unwrap().is_empty() {
+            paragraphs.push(vec![]);
+        }
+    }
+    paragraphs.retain(|lines| ! lines.is_empty());
+    paragraphs
+}
+
+/// Renders the prose of the passage into paragraphs, with the links as `<a>` tags to the pages of their targets.
+fn render_passage(p: &Passage, format: &str, pages: &BTreeMap<String, String>) -> String {
+    prose_paragraphs(p, format).iter().map(|lines| {
+        let lines: Vec<String> = lines.iter().map(|spans| spans.iter().map(|s| match s {
+            Span::Text(t) => escape_html(t),
+            Span::Link(l) => match pages.get(&l.target) {
+                Some(page) => format!("<a href=\"{}\">{}</a>", escape_html(page), escape_html(&l.text)),
+                None => {
+                    warn!(code = "site-link-missing", "Passage \"{}\" links to \"{}\", which has no page", p.name, l.target);
+                    format!("<span class=\"missing\">{}</span>", escape_html(&l.text))
+                },
+            },
+        }).collect()).collect();
+        format!("<p>{}</p>", lines.join("<br>\n"))
+    }).collect::<Vec<_>>().join("\n")
+}
+
+fn page(story: &Story, title: &str, content: &str) -> String {
+    let lang = match story.meta.get("lang").and_then(|l| l.as_str()) {
+        Some(lang) => format!(" lang=\"{}\"", escape_html(lang)),
+        None => String::new(),
+    };
+    SITE_PAGE
+        .replace("{{LANG}}", &lang)
+        .replace("{{TITLE}}", &escape_html(&format!("{} - {}", title, story.title)))
+        .replace("{{STORY}}", &escape_html(&story.title))
+        .replace("{{PASSAGE}}", &escape_html(title))
+        .replace("{{CONTENT}}", content)
+}
+
+/// Renders each passage of the story in the current directory as its own HTML page with plain links between them,
+/// without the story format. Macros, variables and other markup are left out.
+pub(crate) fn export_site(out: &Path) -> anyhow::Result<()> {
+    let config = load_config()?;
+    let story = build_story(&config, false)?;
+    let format = story.meta.get("format").and_then(|f| f.as_str()).unwrap_or("");
+    let pages = page_names(&story);
+    if ! pages.values().any(|p| p == "index.html") {
+        warn!(code = "site-no-start", "The story has no start passage, index.html only lists the passages");
+    }
+    let mut contents = String::from("<ul>\n");
+    for p in story.passages.iter().filter(|p| pages.contains_key(&p.name)) {
+        let file = &pages[&p.name];
+        write_output(out.join(file), page(&story, &p.name, &render_passage(p, format, &pages)).as_bytes())?;
+        contents += &format!("<li><a href=\"{}\">{}</a></li>\n", escape_html(file), escape_html(&p.name));
+    }
+    contents += "</ul>";
+    write_output(out.join("contents.html"), page(&story, "Contents", &contents).as_bytes())?;
+    if ! pages.values().any(|p| p == "index.html") {
+        write_output(out.join("index.html"), page(&story, &story.title, &contents).as_bytes())?;
+    }
+    write_output(out.join("style.css"), SITE_STYLE.as_bytes())?;
+    info!("Wrote {} pages to {}", pages.len(), out.to_string_lossy());
+    Ok(())
+}
@@ -0,0 +1,78 @@
+use regex::Regex;
+use tracing::{info, warn};
+use twee_build::Config;
+use twee_parser::Story;
+
+use crate::build::Error;
+
+/// How the bytes of the built HTML file are distributed.
+pub(crate) struct SizeReport {
+    pub format: usize,
+    pub passages: usize,
+    pub scripts: usize,
+    pub styles: usize,
+    /// Files embedded as data URIs in passages, scripts and styles.
+    pub assets: usize,
+    /// Everything else, like the tw-passagedata tags and escaping.
+    pub markup: usize,
+    pub total: usize,
+}
+
+impl SizeReport {
+    pub(crate) fn new(story: &Story, format_size: usize, total: usize) -> Self {
+        let data_uri = Regex::new(r#"data:[^\s"'()<>]+"#).unwrap();
+        let mut report = SizeReport { format: format_size, passages: 0, scripts: 0, styles: 0, assets: 0, markup: 0, total };
+        for p in &story.passages {
+            let assets: usize = data_uri.find_iter(&p.content).map(|m| m.len()).sum();
+            let size = p.content.len() - assets;
+            report.assets += assets;
+            if p.tags.iter().any(|t| t == "script") {
+                report.scripts += size;
+            } else if p.tags.iter().any(|t| t == "stylesheet") {
+                report.styles += size;
+            } else {
+                report.passages += size;
+            }
+        }
+        report.markup = total.saturating_sub(report.format + report.passages + report.scripts + report.styles + report.assets);
+        report
+    }
+    
+    pub(crate) fn print(&self) {
+        let percent = |s: usize| if self.total == 0 { 0.0 } else { s as f64 * 100.0 / self.total as f64 };
+        info!("Output size: {}", format_size(self.total));
+        for (name, size) in [
+            ("Story format", self.format),
+            ("Passages", self.passages),
+            ("Scripts", self.scripts),
+            ("Styles", self.styles),
+            ("Embedded assets", self.assets),
+            ("Markup", self.markup),
+        ] {
+            info!("  {:<16} {:>10} {:>5.1}%", name, format_size(size), percent(size));
+        }
+    }
+}
+
+pub(crate) fn format_size(s: usize) -> String {
+    if s >= 1024 * 1024 {
+        format!("{:.2} MiB", s as f64 / (1024.0 * 1024.0))
+    } else if s >= 1024 {
+        format!("{:.1} KiB", s as f64 / 1024.0)
+    } else {
+        format!("{} B", s)
+    }
+}
+
+/// Warns or errors when the output exceeds the `max_size` from the config.
+pub(crate) fn check_size_budget(config: &Config, total: usize) -> anyhow::Result<()> {
+    if let Some(max) = config.max_size {
+        if total as u64 > max {
+            if config.max_size_error {
+                return Err(Error::SizeBudgetExceeded(format_size(total), format_size(max as usize)).into());
+            }
+            warn!(code = "size-budget", "Output size {} exceeds the maximum size of {}", format_size(total), format_size(max as usize));
+        }
+    }
+    Ok(())
+}
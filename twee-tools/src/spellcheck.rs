@@ -0,0 +1,184 @@
+use std::{collections::HashMap, path::{Path, PathBuf}};
+
+use regex::{Captures, Regex};
+use spellbook::Dictionary;
+use tracing::warn;
+use twee_build::{build_story, load_config_in, read_file, Config};
+use twee_parser::{Passage, Story};
+
+use crate::build::Error;
+
+/// Replaces everything but newlines with spaces, so line numbers and byte positions stay the same.
+fn blank(s: &str) -> String {
+    s.chars().map(|c| if c == '\n' { "\n".to_string() } else { " ".repeat(c.len_utf8()) }).collect()
+}
+
+fn blank_all(text: &str, pattern: &str) -> String {
+    Regex::new(pattern).unwrap().replace_all(text, |c: &Captures| blank(&c[0])).to_string()
+}
+
+/// Blanks Harlowe macros, which can contain nested parentheses.
+fn blank_harlowe_macros(text: &str) -> String {
+    let start = Regex::new(r"\([a-zA-Z][\w-]*:").unwrap();
+    let mut res = String::new();
+    let mut last = 0;
+    while let Some(m) = start.find_at(text, last) {
+        res += &text[last..m.start()];
+        let mut depth = 0;
+        let mut end = text.len();
+        for (i, c) in text[m.start()..].char_indices() {
+            match c {
+                '(' => depth += 1,
+                ')' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        end = m.start() + i + 1;
+                        break;
+                    }
+                },
+                _ => {}
+            }
+        }
+        res += &blank(&text[m.start()..end]);
+        last = end;
+    }
+    res + &text[last..]
+}
+
+/// Returns the passage text with everything that isn't prose blanked out: link targets, macros, variables, code and
+/// HTML tags, depending on the story format.
+pub(crate) fn prose(p: &Passage, format: &str) -> String {
+    let mut text = String::new();
+    let mut last = 0;
+    for l in p.links() {
+        text += &p.content[last..l.span.start];
+        let link = &p.content[l.span.clone()];
+        let i = if link.contains("<-") { link.rfind(&l.text) } else { link.find(&l.text) };
+        match i {
+            Some(i) => {
+                text += &blank(&link[..i]);
+                text += &l.text;
+                text += &blank(&link[i + l.text.len()..]);
+            },
+            None => text += &blank(link),
+        }
+        last = l.span.end;
+    }
+    text += &p.content[last..];
+    if format == "SugarCube" {
+        // Macros look like HTML tags, so they are blanked before them.
+        text = blank_all(&text, r"<<(?s:.)*?>>");
+    }
+    for pattern in [
+        r"<!--(?s:.)*?-->",
+        r"(?i)<script(?s:.)*?</script>",
+        r"(?i)<style(?s:.)*?</style>",
+        r"/\*(?s:.)*?\*/",
+        r"</?[a-zA-Z][^>]*>",
+        r"`[^`]*`",
+        r"\b[a-zA-Z][\w+.-]*://\S+",
+    ] {
+        text = blank_all(&text, pattern);
+    }
+    let patterns: &[&str] = match format {
+        "SugarCube" => &[r"[$_][A-Za-z_][\w.]*", r"@@[^;\n]*;"],
+        "Harlowe" => {
+            text = blank_harlowe_macros(&text);
+            &[r"[$_][A-Za-z_]\w*", r"\?[A-Za-z_]\w*", r"\|[A-Za-z0-9_]+>|<[A-Za-z0-9_]+\|"]
+        },
+        "Chapbook" => &[r"\A(?:[A-Za-z_][\w.]*(?: \([^)\n]*\))?:[^\n]*\n)+--[ \t]*(?:\n|\z)", r"\{[^}]*\}", r"(?m)^\[[^\]\n]*\][ \t]*$"],
+        "Snowman" => &[r"<%(?s:.)*?%>", r"[$_][A-Za-z_][\w.]*"],
+        _ => &[],
+    };
+    for pattern in patterns {
+        text = blank_all(&text, pattern);
+    }
+    text
+}
+
+/// Finds the .aff and .dic file of a system dictionary.
+fn find_dictionary(language: &str) -> Option<PathBuf> {
+    let mut dirs: Vec<PathBuf> = std::env::var("DICPATH").map(|d| std::env::split_paths(&d).collect()).unwrap_or_default();
+    if let Some(home) = std::env::var_os("HOME") {
+        let home = PathBuf::from(home);
+        dirs.push(home.join(".local/share/hunspell"));
+        dirs.push(home.join("Library/Spelling"));
+    }
+    for d in ["/usr/share/hunspell", "/usr/local/share/hunspell", "/usr/share/myspell", "/usr/share/myspell/dicts", "/Library/Spelling"] {
+        dirs.push(PathBuf::from(d));
+    }
+    dirs.into_iter().map(|d| d.join(language)).find(|d| d.with_extension("aff").exists() && d.with_extension("dic").exists())
+}
+
+fn load_dictionary(config: &Config) -> anyhow::Result<Dictionary> {
+    let language = config.spellcheck.language.clone().unwrap_or("en_US".to_string());
+    let path = match &config.spellcheck.dictionary {
+        Some(d) => PathBuf::from(d),
+        None => find_dictionary(&language).ok_or(Error::DictionaryNotFound(language))?,
+    };
+    let aff = read_file(path.with_extension("aff"))?;
+    let dic = read_file(path.with_extension("dic"))?;
+    let mut dict = Dictionary::new(&aff, &dic).map_err(|e| Error::InvalidDictionary(path.to_string_lossy().to_string(), e.to_string()))?;
+    for f in &config.spellcheck.words {
+        for w in read_file(f)?.lines().map(str::trim).filter(|w| ! w.is_empty()) {
+            dict.add(w).map_err(|e| Error::InvalidDictionary(f.clone(), e.to_string()))?;
+        }
+    }
+    Ok(dict)
+}
+
+/// A word not found in the dictionary.
+pub(crate) struct Misspelling {
+    pub passage: String,
+    /// The line in the passage, starting at 1.
+    pub line: usize,
+    pub word: String,
+    pub suggestion: Option<String>,
+}
+
+/// Checks the prose of all passages except scripts and stylesheets.
+pub(crate) fn spellcheck_story(config: &Config, story: &Story, dict: &Dictionary) -> Vec<Misspelling> {
+    let format = story.meta.get("format").and_then(|f| f.as_str()).unwrap_or("");
+    let word = Regex::new(r"\p{L}+(?:['’]\p{L}+)*").unwrap();
+    let mut known: HashMap<String, Option<Option<String>>> = HashMap::new();
+    let mut res = vec![];
+    for p in &story.passages {
+        if p.tags.iter().any(|t| t == "script" || t == "stylesheet") || p.name == "StoryData" {
+            continue;
+        }
+        for (i, line) in prose(p, format).lines().enumerate() {
+            for w in word.find_iter(line) {
+                let w = w.as_str().replace('’', "'");
+                if config.spellcheck.ignore.contains(&w) {
+                    continue;
+                }
+                let result = known.entry(w.clone()).or_insert_with(|| {
+                    if dict.check(&w) {
+                        None
+                    } else {
+                        let mut suggestions = vec![];
+                        dict.suggest(&w, &mut suggestions);
+                        Some(suggestions.into_iter().next())
+                    }
+                });
+                if let Some(suggestion) = result {
+                    res.push(Misspelling { passage: p.name.clone(), line: i + 1, word: w, suggestion: suggestion.clone() });
+                }
+            }
+        }
+    }
+    res
+}
+
+/// Spellchecks the story in the current directory, returning the number of misspellings.
+pub(crate) fn spellcheck(dir: &Path) -> anyhow::Result<usize> {
+    let config = load_config_in(dir)?;
+    let story = build_story(&config, false)?;
+    let dict = load_dictionary(&config)?;
+    let misspellings = spellcheck_story(&config, &story, &dict);
+    for m in &misspellings {
+        let suggestion = m.suggestion.as_ref().map(|s| format!(", did you mean \"{}\"?", s)).unwrap_or_default();
+        warn!(code = "misspelling", passage = m.passage, line = m.line, "Passage \"{}\" line {}: \"{}\" is not in the dictionary{}", m.passage, m.line, m.word, suggestion);
+    }
+    Ok(misspellings.len())
+}
@@ -0,0 +1,37 @@
+use std::path::PathBuf;
+
+use tracing::{info, warn};
+use twee_build::{build_html, write_output, Project, StoryFormat};
+use twee_parser::SplitOptions;
+
+use crate::{build::BuildOptions, ifid::{derived_ifid, story_ifid}};
+
+/// Builds the story split into one story per tag with the prefix, each with an IFID derived from the IFID of the story
+/// and the tag, and writes them into the output directory. Returns the written files.
+pub(crate) fn split(options: &BuildOptions, prefix: Option<String>, out: Option<PathBuf>) -> anyhow::Result<Vec<PathBuf>> {
+    let project = Project::load("config.toml")?;
+    let story = project.story(&options.profile())?;
+    let config = project.config;
+    let format = StoryFormat::of_story(&story)?;
+    let prefix = prefix.or(config.split.prefix.clone()).unwrap_or("chapter".to_string());
+    let out = out.unwrap_or(config.split.output.as_ref().map(PathBuf::from).unwrap_or(config.dir.join("parts")));
+    let defaults = SplitOptions::default();
+    let split_options = SplitOptions {
+        continue_passage: config.split.continue_passage.clone().unwrap_or(defaults.continue_passage),
+        continue_text: config.split.continue_text.clone().unwrap_or(defaults.continue_text),
+    };
+    let parts = story.split_by_tag(&prefix, &split_options);
+    if parts.is_empty() {
+        warn!(code = "split-no-parts", "No passages have a tag starting with \"{}\", nothing to split", prefix);
+    }
+    let parent = story_ifid(&story).unwrap_or(story.title.clone());
+    let mut files = vec![];
+    for mut part in parts {
+        part.story.meta.insert("ifid".to_string(), derived_ifid(&parent, &part.tag).into());
+        let file = out.join(part.story.title.clone() + ".html");
+        write_output(&file, build_html(format, &part.story, &config)?.as_bytes())?;
+        info!("Wrote part {} with {} passages to {}", part.tag, part.story.passages.len(), file.to_string_lossy());
+        files.push(file);
+    }
+    Ok(files)
+}
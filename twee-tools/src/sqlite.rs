@@ -0,0 +1,61 @@
+use std::{collections::BTreeSet, io::Write, path::{Path, PathBuf}, process::{Command, Stdio}};
+
+use tracing::info;
+use twee_build::{build_story, dry_run, load_config, load_config_in, print_warning};
+use twee_parser::{parse_json, serialize_sql, SQL_STORIES_QUERY};
+
+use crate::{build::Error, import::{new_project_dir, read_stories, write_project}, workspace::Workspace};
+
+/// Runs the sqlite3 shell on the database with the SQL as input and returns the output.
+fn sqlite3(db: &Path, sql: &str) -> anyhow::Result<String> {
+    let mut c = Command::new("sqlite3").args(["-bail", "-noheader", "-list"]).arg(db)
+        .stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::piped()).spawn()
+        .map_err(|e| Error::CommandFailed(format!("sqlite3: {}", e)))?;
+    c.stdin.take().unwrap().write_all(sql.as_bytes())?;
+    let out = c.wait_with_output()?;
+    if ! out.status.success() {
+        return Err(Error::CommandFailed(format!("sqlite3 exited with {}: {}", out.status, String::from_utf8_lossy(&out.stderr).trim())).into());
+    }
+    Ok(String::from_utf8(out.stdout)?)
+}
+
+/// Writes the story in the current directory, all stories of the workspace or the stories of an archive or story file
+/// into the tables of a SQLite database, replacing the tables from an earlier export.
+pub(crate) fn export_sqlite(out: &Path, from: Option<PathBuf>) -> anyhow::Result<()> {
+    let stories = if let Some(from) = from {
+        read_stories(&from)?
+    } else if let Some(workspace) = Workspace::load()? {
+        workspace.for_each_member(|dir| build_story(&load_config_in(dir)?, false))?
+    } else {
+        vec![build_story(&load_config()?, false)?]
+    };
+    if dry_run() {
+        info!("Would write {} stories to {}", stories.len(), out.to_string_lossy());
+        return Ok(());
+    }
+    sqlite3(out, &serialize_sql(&stories))?;
+    info!("Wrote {} stories to {}", stories.len(), out.to_string_lossy());
+    Ok(())
+}
+
+/// Reconstructs the stories of a SQLite database written by `export sqlite` into a twee project each, in
+/// subdirectories of `out` named after the stories.
+pub(crate) fn import_sqlite(db: &Path, out: &Path) -> anyhow::Result<()> {
+    if ! db.is_file() {
+        return Err(Error::FileNotFound(db.to_string_lossy().to_string()).into());
+    }
+    let mut dirs = BTreeSet::new();
+    let mut imported = 0;
+    for line in sqlite3(db, SQL_STORIES_QUERY)?.lines().filter(|l| ! l.trim().is_empty()) {
+        let (story, warnings) = parse_json(line)?;
+        for w in warnings {
+            print_warning(w);
+        }
+        let dir = new_project_dir(out, &story.title, &mut dirs);
+        info!("Imported \"{}\" into {}", story.title, dir.to_string_lossy());
+        write_project(&dir, story)?;
+        imported += 1;
+    }
+    info!("Imported {} stories", imported);
+    Ok(())
+}
@@ -0,0 +1,282 @@
+use std::collections::BTreeMap;
+
+use regex::Regex;
+use serde::Serialize;
+use twee_build::{build_story, load_config, Config};
+use twee_parser::{Complexity, Passage, Story};
+
+use crate::spellcheck::prose;
+
+/// The prose words of a passage text.
+pub(crate) fn words(text: &str) -> Vec<&str> {
+    Regex::new(r"\p{L}+(?:['’]\p{L}+)*|\p{N}+").unwrap().find_iter(text).map(|m| m.as_str()).collect()
+}
+
+/// Estimates the syllables of an English word by counting vowel groups.
+fn syllables(word: &str) -> usize {
+    let word = word.to_lowercase();
+    let mut count = 0;
+    let mut previous_vowel = false;
+    for c in word.chars() {
+        let vowel = "aeiouy".contains(c);
+        if vowel && ! previous_vowel {
+            count += 1;
+        }
+        previous_vowel = vowel;
+    }
+    if word.ends_with('e') && ! word.ends_with("le") && count > 1 {
+        count -= 1;
+    }
+    count.max(1)
+}
+
+/// Word, sentence and syllable counts of some prose.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct Readability {
+    pub words: usize,
+    pub sentences: usize,
+    pub syllables: usize,
+}
+
+impl Readability {
+    pub(crate) fn of(text: &str) -> Readability {
+        let words = words(text);
+        let sentences = Regex::new(r"[.!?]+(?:\s|$)").unwrap().find_iter(text).count();
+        Readability {
+            words: words.len(),
+            sentences: if words.is_empty() { 0 } else { sentences.max(1) },
+            syllables: words.iter().map(|w| syllables(w)).sum(),
+        }
+    }
+    
+    pub(crate) fn add(&mut self, other: Readability) {
+        self.words += other.words;
+        self.sentences += other.sentences;
+        self.syllables += other.syllables;
+    }
+    
+    /// The Flesch-Kincaid grade level.
+    pub(crate) fn grade(&self) -> f64 {
+        if self.words == 0 {
+            return 0.0;
+        }
+        0.39 * self.words as f64 / self.sentences as f64 + 11.8 * self.syllables as f64 / self.words as f64 - 15.59
+    }
+    
+    /// The Flesch reading ease, from 0 (very difficult) to 100 (very easy).
+    pub(crate) fn reading_ease(&self) -> f64 {
+        if self.words == 0 {
+            return 0.0;
+        }
+        206.835 - 1.015 * self.words as f64 / self.sentences as f64 - 84.6 * self.syllables as f64 / self.words as f64
+    }
+}
+
+/// Whether a passage contains story text, as opposed to scripts, stylesheets and special passages.
+pub(crate) fn is_prose(p: &Passage) -> bool {
+    ! p.tags.iter().any(|t| t == "script" || t == "stylesheet" || t == "widget") && p.name != "StoryData"
+}
+
+/// Passages with fewer words don't get flagged as outliers.
+const MIN_OUTLIER_WORDS: usize = 20;
+
+fn print_readability(story: &Story) {
+    let format = story.meta.get("format").and_then(|f| f.as_str()).unwrap_or("");
+    let mut total = Readability::default();
+    let passages: Vec<(&str, Readability)> = story.passages.iter().filter(|p| is_prose(p))
+        .map(|p| (p.name.as_str(), Readability::of(&prose(p, format))))
+        .collect();
+    for (_, r) in &passages {
+        total.add(*r);
+    }
+    let grades: Vec<f64> = passages.iter().filter(|(_, r)| r.words >= MIN_OUTLIER_WORDS).map(|(_, r)| r.grade()).collect();
+    let mean = grades.iter().sum::<f64>() / grades.len().max(1) as f64;
+    let deviation = (grades.iter().map(|g| (g - mean).powi(2)).sum::<f64>() / grades.len().max(1) as f64).sqrt();
+    println!("{:<30} {:>7} {:>6} {:>6}", "Passage", "Words", "Grade", "Ease");
+    for (name, r) in &passages {
+        let outlier = r.words >= MIN_OUTLIER_WORDS && deviation > 0.0 && (r.grade() - mean).abs() > 2.0 * deviation;
+        println!("{:<30} {:>7} {:>6.1} {:>6.1}{}", name, r.words, r.grade(), r.reading_ease(), if outlier { "  <- outlier" } else { "" });
+    }
+    println!("{:<30} {:>7} {:>6.1} {:>6.1}", "Total", total.words, total.grade(), total.reading_ease());
+}
+
+/// The word count of the passages with a tag.
+#[derive(Serialize)]
+pub(crate) struct TagStats {
+    pub tag: String,
+    pub passages: usize,
+    pub words: usize,
+    pub target: Option<usize>,
+    /// The percentage of the target reached.
+    pub complete: Option<f64>,
+}
+
+#[derive(Serialize)]
+pub(crate) struct Stats {
+    pub passages: usize,
+    pub words: usize,
+    pub links: usize,
+    pub target: Option<usize>,
+    pub complete: Option<f64>,
+    pub tags: Vec<TagStats>,
+    pub grade: Option<f64>,
+    pub reading_ease: Option<f64>,
+    pub complexity: Option<ComplexityStats>,
+}
+
+/// The complexity of a passage.
+#[derive(Serialize)]
+pub(crate) struct PassageComplexityStats {
+    pub passage: String,
+    pub choices: usize,
+    pub incoming: usize,
+    pub complexity: usize,
+}
+
+/// The structural complexity of the story, see [Story::complexity].
+#[derive(Serialize)]
+pub(crate) struct ComplexityStats {
+    pub overall: usize,
+    pub passages: Vec<PassageComplexityStats>,
+    pub hubs: Vec<String>,
+    pub bottlenecks: Vec<String>,
+}
+
+impl From<Complexity> for ComplexityStats {
+    fn from(c: Complexity) -> Self {
+        let mut passages: Vec<PassageComplexityStats> = c.passages.into_iter().map(|p| PassageComplexityStats {
+            passage: p.name,
+            choices: p.choices,
+            incoming: p.incoming,
+            complexity: p.complexity,
+        }).collect();
+        passages.sort_by_key(|p| std::cmp::Reverse(p.complexity));
+        ComplexityStats { overall: c.overall, passages, hubs: c.hubs, bottlenecks: c.bottlenecks }
+    }
+}
+
+fn print_complexity(c: &ComplexityStats) {
+    println!("Complexity: {}", c.overall);
+    println!();
+    println!("{:<30} {:>7} {:>8} {:>10}", "Passage", "Choices", "Incoming", "Complexity");
+    for p in &c.passages {
+        println!("{:<30} {:>7} {:>8} {:>10}", p.passage, p.choices, p.incoming, p.complexity);
+    }
+    println!();
+    println!("Hubs: {}", if c.hubs.is_empty() { "(none)".to_string() } else { c.hubs.join(", ") });
+    println!("Bottlenecks: {}", if c.bottlenecks.is_empty() { "(none)".to_string() } else { c.bottlenecks.join(", ") });
+}
+
+fn complete(words: usize, target: Option<usize>) -> Option<f64> {
+    target.filter(|t| *t > 0).map(|t| words as f64 * 100.0 / t as f64)
+}
+
+/// Counts the prose passages, words and links, grouping the word counts by tag. Passages without tags are grouped
+/// under an empty tag.
+pub(crate) fn story_stats(config: &Config, story: &Story, readability: bool) -> Stats {
+    let format = story.meta.get("format").and_then(|f| f.as_str()).unwrap_or("");
+    let mut stats = Stats { passages: 0, words: 0, links: 0, target: config.stats.target, complete: None, tags: vec![], grade: None, reading_ease: None, complexity: None };
+    let mut tags: BTreeMap<String, (usize, usize)> = config.stats.targets.keys().map(|t| (t.clone(), (0, 0))).collect();
+    let mut total = Readability::default();
+    for p in story.passages.iter().filter(|p| is_prose(p)) {
+        let text = prose(p, format);
+        let words = words(&text).len();
+        stats.passages += 1;
+        stats.words += words;
+        stats.links += p.links().len();
+        if readability {
+            total.add(Readability::of(&text));
+        }
+        let passage_tags = if p.tags.is_empty() { vec![String::new()] } else { p.tags.clone() };
+        for t in passage_tags {
+            let e = tags.entry(t).or_default();
+            e.0 += 1;
+            e.1 += words;
+        }
+    }
+    stats.complete = complete(stats.words, stats.target);
+    stats.tags = tags.into_iter().map(|(tag, (passages, words))| {
+        let target = config.stats.targets.get(&tag).copied();
+        TagStats { tag, passages, words, target, complete: complete(words, target) }
+    }).collect();
+    if readability {
+        stats.grade = Some(total.grade());
+        stats.reading_ease = Some(total.reading_ease());
+    }
+    stats
+}
+
+fn print_target(words: usize, target: Option<usize>, complete: Option<f64>) -> String {
+    match (target, complete) {
+        (Some(target), Some(complete)) => format!("{:>8} {:>8} {:>6.1}%", words, target, complete),
+        _ => format!("{:>8}", words),
+    }
+}
+
+/// Prints statistics about the story in the current directory.
+pub(crate) fn stats(readability: bool, by_tag: bool, complexity: bool, json: bool) -> anyhow::Result<()> {
+    let config = load_config()?;
+    let story = build_story(&config, false)?;
+    let mut stats = story_stats(&config, &story, readability);
+    if complexity {
+        stats.complexity = Some(story.complexity().into());
+    }
+    if json {
+        println!("{}", serde_json::to_string_pretty(&stats)?);
+        return Ok(());
+    }
+    println!("Passages: {}", stats.passages);
+    println!("Words: {}", stats.words);
+    if let (Some(target), Some(complete)) = (stats.target, stats.complete) {
+        println!("Target: {} ({:.1}% complete)", target, complete);
+    }
+    println!("Links: {}", stats.links);
+    if by_tag {
+        println!();
+        println!("{:<30} {:>8} {:>8} {:>8} {:>7}", "Tag", "Passages", "Words", "Target", "Done");
+        for t in &stats.tags {
+            let name = if t.tag.is_empty() { "(untagged)" } else { &t.tag };
+            println!("{:<30} {:>8} {}", name, t.passages, print_target(t.words, t.target, t.complete));
+        }
+    }
+    if readability {
+        println!();
+        print_readability(&story);
+    }
+    if let Some(c) = &stats.complexity {
+        println!();
+        print_complexity(c);
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn syllables_of_words() {
+        for (word, expected) in [("cat", 1), ("the", 1), ("make", 1), ("table", 2), ("apple", 2), ("water", 2), ("beautiful", 3),
+            ("rhythm", 1), ("queue", 1), ("yesterday", 3), ("Hello", 2), ("ENORMOUS", 3)] {
+            assert_eq!(syllables(word), expected, "{}", word);
+        }
+    }
+    
+    #[test]
+    fn syllables_at_least_one() {
+        assert_eq!(syllables(""), 1);
+        assert_eq!(syllables("hmm"), 1);
+        assert_eq!(syllables("42"), 1);
+    }
+    
+    #[test]
+    fn readability_counts() {
+        let r = Readability::of("The cat sat. It was a table! Why?");
+        assert_eq!(r.words, 8);
+        assert_eq!(r.sentences, 3);
+        assert_eq!(r.syllables, 9);
+        assert_eq!(Readability::of("").sentences, 0);
+        assert_eq!(Readability::of("no end mark").sentences, 1);
+    }
+}
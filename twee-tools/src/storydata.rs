@@ -0,0 +1,99 @@
+use serde_json::Value;
+use tracing::info;
+use twee_build::{load_config, read_file, write_output};
+use twee_parser::{parse_twee3, Warning};
+
+use crate::{build::Error, ifid::is_valid_ifid, upgrade::set_story_data_value};
+
+/// Parses a value given on the command line: as JSON with `json`, otherwise as a string.
+fn parse_value(value: &str, json: bool) -> anyhow::Result<Value> {
+    if json {
+        Ok(serde_json::from_str(value)?)
+    } else {
+        Ok(Value::String(value.to_string()))
+    }
+}
+
+/// Sets a field of the StoryData in the twee source of the main file, refusing values the story formats or Twine
+/// don't understand, like an invalid IFID or tag color. Returns the new source.
+fn set_story_data_checked(source: &str, main: &str, key: &str, value: &Value) -> anyhow::Result<String> {
+    if key == "ifid" && ! value.as_str().is_some_and(is_valid_ifid) {
+        return Err(Error::InvalidStoryData(key.to_string(), format!("{} is not an uppercase UUID like {}", value, crate::ifid::gen_ifid())).into());
+    }
+    let updated = set_story_data_value(source, key, value).ok_or(Error::StoryDataNotFound(main.to_string()))?;
+    let (story, _) = parse_twee3(&updated)?;
+    for w in story.validate() {
+        if let Warning::StoryDataInvalid(field, problem) = w {
+            if field == key {
+                return Err(Error::InvalidStoryData(field, problem).into());
+            }
+        }
+    }
+    Ok(updated)
+}
+
+/// Sets a field of the StoryData in the main twee file, keeping the rest of the file as it is. The value is a string,
+/// or JSON with `json`.
+pub(crate) fn set_meta(key: &str, value: &str, json: bool) -> anyhow::Result<()> {
+    let config = load_config()?;
+    let source = read_file(&config.main)?;
+    let value = parse_value(value, json)?;
+    let updated = set_story_data_checked(&source, &config.main, key, &value)?;
+    write_output(&config.main, updated.as_bytes())?;
+    info!("Set {} to {} in {}", key, value, config.main);
+    Ok(())
+}
+
+/// Prints a field of the StoryData in the main twee file, or all of it without a key. Strings are printed as they
+/// are, other values as JSON.
+pub(crate) fn get_meta(key: Option<&str>) -> anyhow::Result<()> {
+    let config = load_config()?;
+    let (story, _) = parse_twee3(&read_file(&config.main)?)?;
+    match key {
+        Some(key) => match story.meta.get(key) {
+            Some(Value::String(s)) => println!("{}", s),
+            Some(v) => println!("{}", v),
+            None => return Err(Error::MissingStoryData(key.to_string()).into()),
+        },
+        None => println!("{}", serde_json::to_string_pretty(&story.meta)?),
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    const SOURCE: &str = ":: StoryData\n{\"ifid\": \"8F0A7B1C-3A8E-4C5D-9C2B-1D2E3F4A5B6C\", \"format\": \"Harlowe\", \"format-version\": \"3.3.8\"}\n\n:: Start\nHello\n";
+    
+    fn set(key: &str, value: &str, json: bool) -> anyhow::Result<String> {
+        set_story_data_checked(SOURCE, "story.twee", key, &parse_value(value, json)?)
+    }
+    
+    #[test]
+    fn valid_values() {
+        assert!(set("format-version", "3.3.9", false).unwrap().contains("\"format-version\": \"3.3.9\""));
+        assert!(set("start", "1", false).unwrap().contains("\"start\": \"1\""));
+        assert!(set("ifid", "3C6D5A07-1F5E-4C9B-9D0A-2B7E4F8A1C3D", false).is_ok());
+        assert!(set("tag-colors", "{\"chapter\": \"red\"}", true).unwrap().contains("\"tag-colors\": {\"chapter\":\"red\"}"));
+        assert!(set("zoom", "0.6", true).unwrap().contains("\"zoom\": 0.6"));
+    }
+    
+    #[test]
+    fn refused_values() {
+        for (key, value, json) in [
+            ("format-version", "3", true),
+            ("format-version", "three", false),
+            ("format-version", "9.0.0", false),
+            ("ifid", "3c6d5a07-1f5e-4c9b-9d0a-2b7e4f8a1c3d", false),
+            ("tag-colors", "{\"chapter\": \"pink\"}", true),
+            ("tag-colors", "red", false),
+            ("zoom", "large", false),
+        ] {
+            assert!(matches!(set(key, value, json).unwrap_err().downcast::<Error>(), Ok(Error::InvalidStoryData(k, _)) if k == key), "{} {}", key, value);
+        }
+        assert!(set("tag-colors", "{chapter: red}", true).is_err());
+        assert!(matches!(set_story_data_checked(":: Start\nHello\n", "story.twee", "start", &"Start".into()).unwrap_err().downcast::<Error>(), Ok(Error::StoryDataNotFound(_))));
+    }
+}
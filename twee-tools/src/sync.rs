@@ -0,0 +1,249 @@
+use std::{collections::{BTreeMap, BTreeSet}, ops::Range, path::{Path, PathBuf}};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use tracing::{info, warn};
+use twee_build::{build_story, cache_dir, load_config_in, print_warning, read_file, read_source, source_blocks, storydata_html, write_output, Config, SourceCache};
+use twee_parser::{escape_content, parse_archive, passage_header, Passage, Story};
+
+use crate::build::Error;
+
+/// The passage hashes at the last sync, to find out which side changed a passage.
+#[derive(Serialize, Deserialize, Default)]
+struct SyncState {
+    passages: BTreeMap<String, String>,
+}
+
+fn library_dir(config: &Config) -> anyhow::Result<PathBuf> {
+    if let Some(l) = &config.sync.library {
+        return Ok(PathBuf::from(l));
+    }
+    let home = std::env::var_os("HOME").or(std::env::var_os("USERPROFILE")).ok_or(Error::MissingConfig("sync.library".to_string()))?;
+    Ok(PathBuf::from(home).join("Documents").join("Twine").join("Stories"))
+}
+
+fn state_file(config: &Config) -> PathBuf {
+    cache_dir(config).join("sync.json")
+}
+
+fn load_state(config: &Config) -> anyhow::Result<Option<SyncState>> {
+    let f = state_file(config);
+    if ! f.exists() {
+        return Ok(None);
+    }
+    Ok(Some(serde_json::from_str(&read_file(f)?)?))
+}
+
+fn save_state(config: &Config, passages: &[&Passage]) -> anyhow::Result<()> {
+    let state = SyncState { passages: passages.iter().map(|p| (p.name.clone(), passage_hash(p))).collect() };
+    write_output(state_file(config), serde_json::to_string_pretty(&state)?.as_bytes())
+}
+
+/// The [content hash](Passage::content_hash) of a passage as hex, as stored in the sync state.
+fn passage_hash(p: &Passage) -> String {
+    format!("{:016x}", p.content_hash())
+}
+
+/// Story script and stylesheet passages are only pushed, as Twine merges them into one.
+fn is_synced(p: &Passage) -> bool {
+    ! p.tags.iter().any(|t| t == "script" || t == "stylesheet")
+}
+
+fn read_library_story(f: &Path) -> anyhow::Result<Story> {
+    let (story, warnings) = parse_archive(&read_file(f)?)?.into_iter().next().ok_or(twee_parser::Error::HTMLStoryDataNotFound)?;
+    for w in warnings {
+        print_warning(w);
+    }
+    Ok(story)
+}
+
+/// Builds the story in the project directory and writes it into the Twine library, unless the library copy was
+/// changed in Twine since the last sync.
+pub(crate) fn push(dir: &Path, force: bool) -> anyhow::Result<()> {
+    let config = load_config_in(dir)?;
+    let story = build_story(&config, false)?;
+    let file = library_dir(&config)?.join(format!("{}.html", story.title));
+    if file.exists() && ! force {
+        let library = read_library_story(&file)?;
+        let library: BTreeMap<String, String> = library.passages.iter().filter(|p| is_synced(p)).map(|p| (p.name.clone(), passage_hash(p))).collect();
+        let base = match load_state(&config)? {
+            Some(state) => state.passages,
+            None => story.passages.iter().filter(|p| is_synced(p)).map(|p| (p.name.clone(), passage_hash(p))).collect(),
+        };
+        let changed: BTreeSet<&str> = library.keys().chain(base.keys()).filter(|n| library.get(*n) != base.get(*n)).map(|n| n.as_str()).collect();
+        if ! changed.is_empty() {
+            let changed: Vec<&str> = changed.into_iter().collect();
+            return Err(Error::SyncConflict(format!("The library copy was changed in Twine: {}. Pull the changes first, or push with --force to overwrite them.", changed.join(", "))).into());
+        }
+    }
+    write_output(&file, storydata_html(&story, &config)?.as_bytes())?;
+    save_state(&config, &story.passages.iter().filter(|p| is_synced(p)).collect::<Vec<_>>())?;
+    info!("Pushed {} to {}", story.title, file.to_string_lossy());
+    Ok(())
+}
+
+/// Position and size of a passage in the Twine editor.
+fn layout(p: &Passage) -> Map<String, Value> {
+    p.meta.iter().filter(|(k, _)| *k == "position" || *k == "size").map(|(k, v)| (k.clone(), v.clone())).collect()
+}
+
+/// Decompiles the library copy of the story in the project directory back into the twee files the passages are in. Passages changed on both
+/// sides since the last sync are conflicts and left as they are, unless `force` is set, which takes the library
+/// version. New passages are added to the main twee file.
+pub(crate) fn pull(dir: &Path, force: bool) -> anyhow::Result<()> {
+    let config = load_config_in(dir)?;
+    // The twee files of this build, not of others on other threads.
+    let sources = SourceCache::new();
+    let story = sources.enter(|| build_story(&config, false))?;
+    let file = library_dir(&config)?.join(format!("{}.html", story.title));
+    let library = read_library_story(&file)?;
+    let state = load_state(&config)?.unwrap_or_default();
+    let blocks = source_blocks(&sources.twee_files())?;
+    let local: BTreeMap<&str, &Passage> = story.passages.iter().filter(|p| is_synced(p)).map(|p| (p.name.as_str(), p)).collect();
+    let remote: BTreeMap<&str, &Passage> = library.passages.iter().filter(|p| is_synced(p)).map(|p| (p.name.as_str(), p)).collect();
+    // Edits per file as (range, replacement).
+    let mut edits: BTreeMap<PathBuf, Vec<(Range<usize>, String)>> = BTreeMap::new();
+    let mut conflicts = vec![];
+    let mut added = String::new();
+    let mut names: Vec<&str> = remote.keys().copied().chain(state.passages.keys().map(|k| k.as_str())).collect();
+    names.sort();
+    names.dedup();
+    for name in names {
+        let base = state.passages.get(name);
+        let r = remote.get(name);
+        let l = local.get(name);
+        let remote_changed = r.map(|p| passage_hash(p)).as_ref() != base;
+        let local_changed = l.map(|p| passage_hash(p)).as_ref() != base;
+        let same = r.map(|p| passage_hash(p)) == l.map(|p| passage_hash(p));
+        if remote_changed && local_changed && ! same && ! force {
+            conflicts.push(name);
+            continue;
+        }
+        let block = blocks.iter().find(|b| b.passage.name == name);
+        match (r, block) {
+            (Some(r), None) if l.is_none() => {
+                added += &format!("\n{}{}\n", passage_header(&r.name, &r.tags, &layout(r)), escape_content(&r.content));
+            },
+            (_, None) => {
+                if remote_changed && ! same {
+                    warn!(code = "sync-generated", "Passage \"{}\" is generated by the build and can't be pulled", name);
+                }
+            },
+            (Some(r), Some(b)) => {
+                let generated = l.is_some_and(|l| l.content.trim_end() != b.passage.content.trim_end());
+                if generated {
+                    if remote_changed && ! same {
+                        warn!(code = "sync-generated", "Passage \"{}\" is generated by the build, e.g. from an include, and can't be pulled", name);
+                    }
+                    continue;
+                }
+                if ! remote_changed && layout(r) == layout(&b.passage) {
+                    continue;
+                }
+                let mut meta = b.passage.meta.clone();
+                meta.extend(layout(r));
+                let (tags, content) = if remote_changed { (&r.tags, escape_content(&r.content)) } else { (&b.passage.tags, read_source(&b.file)?[b.body.clone()].to_string()) };
+                let header = passage_header(name, tags, &meta);
+                let source = read_source(&b.file)?;
+                if header != source[b.header.clone()] || content != source[b.body.clone()] {
+                    edits.entry(b.file.clone()).or_default().push((b.header.start..b.body.end, header + &content));
+                }
+            },
+            (None, Some(b)) => {
+                if remote_changed {
+                    info!("Passage \"{}\" was deleted in Twine", name);
+                    let source = read_source(&b.file)?;
+                    let end = source[b.body.end..].find("\n::").map(|i| b.body.end + i + 1).unwrap_or(source.len());
+                    edits.entry(b.file.clone()).or_default().push((b.header.start..end, String::new()));
+                }
+            },
+        }
+    }
+    if ! added.is_empty() {
+        let main = PathBuf::from(&config.main).canonicalize()?;
+        let len = read_source(&main)?.len();
+        edits.entry(main).or_default().push((len..len, added));
+    }
+    for c in &conflicts {
+        warn!(code = "sync-conflict", "Passage \"{}\" was changed in Twine and in the project since the last sync", c);
+    }
+    for (f, mut edits) in edits {
+        let mut source = read_source(&f)?;
+        edits.sort_by_key(|(r, _)| std::cmp::Reverse(r.start));
+        for (r, replacement) in edits {
+            source.replace_range(r, &replacement);
+        }
+        write_output(&f, source.as_bytes())?;
+        info!("Updated {}", f.to_string_lossy());
+    }
+    if ! conflicts.is_empty() {
+        return Err(Error::SyncConflict(format!("{} passage(s) were changed on both sides. Resolve them by hand, or pull with --force to take the Twine version.", conflicts.len())).into());
+    }
+    save_state(&config, &remote.values().copied().collect::<Vec<_>>())?;
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    const STORY: &str = ":: StoryTitle\nSynced\n\n:: StoryData\n{\"ifid\": \"8F0A7B1C-3A8E-4C5D-9C2B-1D2E3F4A5B6C\", \"format\": \"Harlowe\", \"format-version\": \"3.3.8\", \"start\": \"Start\"}\n\n:: Start\n[[Room]]\n\n:: Room\nA room.\n\n:: Hall\nA hall.\n";
+    
+    /// A project with its Twine library in the project directory, pushed once.
+    fn pushed_project() -> tempfile::TempDir {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        std::fs::create_dir_all(dir.join("library")).unwrap();
+        std::fs::write(dir.join("config.toml"), "style = []\nscript = []\nprebuild = []\nmain = \"story.twee\"\n\n[sync]\nlibrary = \"library\"\n").unwrap();
+        std::fs::write(dir.join("story.twee"), STORY).unwrap();
+        push(dir, false).unwrap();
+        tmp
+    }
+    
+    /// Changes the library copy like Twine would.
+    fn edit_in_twine(dir: &Path, edit: impl FnOnce(&mut Story)) {
+        let file = dir.join("library").join("Synced.html");
+        let mut story = read_library_story(&file).unwrap();
+        edit(&mut story);
+        std::fs::write(&file, storydata_html(&story, &load_config_in(dir).unwrap()).unwrap()).unwrap();
+    }
+    
+    fn set_content(story: &mut Story, name: &str, content: &str) {
+        story.passages.iter_mut().find(|p| p.name == name).unwrap().content = content.to_string();
+    }
+    
+    #[test]
+    fn pull_twine_edits() {
+        let tmp = pushed_project();
+        let dir = tmp.path();
+        edit_in_twine(dir, |s| set_content(s, "Room", "A bigger room."));
+        pull(dir, false).unwrap();
+        assert_eq!(read_file(dir.join("story.twee")).unwrap(), STORY.replace("A room.", "A bigger room."));
+        // Pushing again doesn't see a change in Twine.
+        push(dir, false).unwrap();
+    }
+    
+    #[test]
+    fn pull_twine_deletions() {
+        let tmp = pushed_project();
+        let dir = tmp.path();
+        edit_in_twine(dir, |s| s.passages.retain(|p| p.name != "Room"));
+        pull(dir, false).unwrap();
+        assert_eq!(read_file(dir.join("story.twee")).unwrap(), STORY.replace(":: Room\nA room.\n\n", ""));
+    }
+    
+    #[test]
+    fn conflicts() {
+        let tmp = pushed_project();
+        let dir = tmp.path();
+        edit_in_twine(dir, |s| set_content(s, "Room", "A room in Twine."));
+        let local = STORY.replace("A room.", "A room in the project.");
+        std::fs::write(dir.join("story.twee"), &local).unwrap();
+        assert!(matches!(pull(dir, false).unwrap_err().downcast::<Error>(), Ok(Error::SyncConflict(_))));
+        assert_eq!(read_file(dir.join("story.twee")).unwrap(), local);
+        assert!(matches!(push(dir, false).unwrap_err().downcast::<Error>(), Ok(Error::SyncConflict(_))));
+        pull(dir, true).unwrap();
+        assert_eq!(read_file(dir.join("story.twee")).unwrap(), STORY.replace("A room.", "A room in Twine."));
+    }
+}
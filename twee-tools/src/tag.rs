@@ -0,0 +1,135 @@
+use std::{collections::BTreeMap, ops::Range, path::{Path, PathBuf}};
+
+use regex::Regex;
+use tracing::{info, warn};
+use serde_json::Value;
+use twee_build::{build_story, dry_run, load_config, read_source, source_blocks, write_output, BuildInputs, SourceBlock};
+use twee_parser::{parse_twee3, passage_header, Passage, Story};
+
+use crate::{build::Error, search::display_path, upgrade::set_story_data_value};
+
+/// A change to the tags of passages.
+pub(crate) enum TagChange {
+    Add(String),
+    Remove(String),
+    Rename(String, String),
+}
+
+/// Whether the tag can be used in a passage header: tags are separated by whitespace.
+pub(crate) fn is_valid_tag(tag: &str) -> bool {
+    ! tag.is_empty() && ! tag.contains(char::is_whitespace)
+}
+
+/// Changes the tags of the passages in the twee files of the project in the current directory, or only of those with
+/// a name matching the regex. Prints the changed passages and returns their number.
+pub(crate) fn tag(change: TagChange, pattern: Option<&str>) -> anyhow::Result<usize> {
+    let tag = match &change {
+        TagChange::Add(t) | TagChange::Rename(_, t) => Some(t),
+        TagChange::Remove(_) => None,
+    };
+    if let Some(t) = tag.filter(|t| ! is_valid_tag(t)) {
+        return Err(Error::InvalidTag(t.clone()).into());
+    }
+    let pattern = pattern.map(Regex::new).transpose().map_err(|e| Error::InvalidPattern(e.to_string()))?;
+    let config = load_config()?;
+    build_story(&config, false)?;
+    let blocks = source_blocks(&BuildInputs::collect().twee_files())?;
+    let main = PathBuf::from(&config.main).canonicalize()?;
+    let (sources, changed) = tag_sources(&blocks, &change, pattern.as_ref(), &main)?;
+    for (f, source) in sources {
+        write_output(&f, source.as_bytes())?;
+    }
+    if dry_run() {
+        info!("Would change the tags of {} passage(s)", changed);
+    } else {
+        info!("Changed the tags of {} passage(s)", changed);
+    }
+    Ok(changed)
+}
+
+/// Applies the change to the passages of the blocks with a name matching the regex, printing the changed passages.
+/// A renamed tag keeps its color in the StoryData of the main file: the color is moved if no passage has the old tag
+/// anymore, and copied otherwise. Returns the new sources of the changed files and the number of changed passages.
+fn tag_sources(blocks: &[SourceBlock], change: &TagChange, pattern: Option<&Regex>, main: &Path) -> anyhow::Result<(BTreeMap<PathBuf, String>, usize)> {
+    // Edits per file as (range, replacement).
+    let mut edits: BTreeMap<PathBuf, Vec<(Range<usize>, String)>> = BTreeMap::new();
+    let mut still_tagged = false;
+    for b in blocks {
+        let mut p: Passage = b.passage.clone();
+        let changed = pattern.is_none_or(|r| r.is_match(&b.passage.name)) && match change {
+            TagChange::Add(t) => p.add_tag(t),
+            TagChange::Remove(t) => p.remove_tag(t),
+            TagChange::Rename(from, to) => p.rename_tag(from, to),
+        };
+        if let TagChange::Rename(from, _) = change {
+            still_tagged |= p.tags.contains(from);
+        }
+        if changed {
+            println!("{} ({}): {}", p.name, display_path(&b.file), p.tags.join(" "));
+            edits.entry(b.file.clone()).or_default().push((b.header.clone(), passage_header(&p.name, &p.tags, &p.meta)));
+        }
+    }
+    let changed = edits.values().map(|e| e.len()).sum();
+    let mut sources = BTreeMap::new();
+    for (f, file_edits) in edits {
+        let mut source = read_source(&f)?;
+        for (r, replacement) in file_edits.into_iter().rev() {
+            source.replace_range(r, &replacement);
+        }
+        sources.insert(f, source);
+    }
+    if let (TagChange::Rename(from, to), true) = (change, changed != 0) {
+        let source = match sources.remove(main) {
+            Some(source) => source,
+            None => read_source(main)?,
+        };
+        let (story, _): (Story, _) = parse_twee3(&source)?;
+        let mut colors = story.meta.get("tag-colors").and_then(|c| c.as_object()).cloned().unwrap_or_default();
+        let color = if still_tagged { colors.get(from).cloned() } else { colors.remove(from) };
+        let source = match color {
+            Some(color) if ! colors.contains_key(to) || ! still_tagged => {
+                colors.entry(to.clone()).or_insert(color);
+                match set_story_data_value(&source, "tag-colors", &Value::Object(colors)) {
+                    Some(updated) => updated,
+                    None => {
+                        warn!(code = "tag-color", "Could not keep the color of tag \"{}\" in the StoryData, set it for \"{}\" by hand", from, to);
+                        source
+                    },
+                }
+            },
+            _ => source,
+        };
+        if source != read_source(main)? {
+            sources.insert(main.to_path_buf(), source);
+        }
+    }
+    Ok((sources, changed))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn rename_matching_passages() {
+        let dir = tempfile::tempdir().unwrap();
+        let main = dir.path().join("main.twee");
+        std::fs::write(&main, ":: StoryData\n{\"tag-colors\": {\"old\": \"red\"}}\n\n:: Chapter 1 [old]\none\n\n:: Other [old]\nother\n").unwrap();
+        let main = main.canonicalize().unwrap();
+        let blocks = source_blocks(std::slice::from_ref(&main)).unwrap();
+        let rename = TagChange::Rename("old".to_string(), "new".to_string());
+        // Passages outside the match keep the old tag, so the color is copied.
+        let (sources, changed) = tag_sources(&blocks, &rename, Some(&Regex::new("^Chapter").unwrap()), &main).unwrap();
+        assert_eq!(changed, 1);
+        assert_eq!(sources[&main], ":: StoryData\n{\"tag-colors\": {\"new\":\"red\",\"old\":\"red\"}}\n\n:: Chapter 1 [new]\none\n\n:: Other [old]\nother\n");
+        // Without passages with the old tag left, the color is moved.
+        let (sources, changed) = tag_sources(&blocks, &rename, None, &main).unwrap();
+        assert_eq!(changed, 2);
+        assert_eq!(sources[&main], ":: StoryData\n{\"tag-colors\": {\"new\":\"red\"}}\n\n:: Chapter 1 [new]\none\n\n:: Other [new]\nother\n");
+        // Nothing is written if no passage matches.
+        let (sources, changed) = tag_sources(&blocks, &rename, Some(&Regex::new("^Missing").unwrap()), &main).unwrap();
+        assert_eq!(changed, 0);
+        assert!(sources.is_empty());
+    }
+}
@@ -0,0 +1,53 @@
+use serde::Serialize;
+use tracing::info;
+use twee_build::{build_story, load_config};
+
+use crate::search::{display_path, passage_sources};
+
+/// A note in the JSON output of `todo`.
+#[derive(Serialize)]
+pub(crate) struct TodoEntry {
+    pub passage: String,
+    /// The twee file of the passage, if it isn't generated by the build.
+    pub file: Option<String>,
+    /// The line in the file, or in the passage if the passage is changed by the build.
+    pub line: usize,
+    pub marker: String,
+    pub assignee: Option<String>,
+    pub text: String,
+}
+
+/// Lists the notes left in the passages of the story in the current directory, like `TODO: ...`, with the file and
+/// line they are in. With `assignee`, only the notes for that person are listed. Returns the number of notes.
+pub(crate) fn todo(assignee: Option<&str>, json: bool) -> anyhow::Result<usize> {
+    let config = load_config()?;
+    let story = build_story(&config, false)?;
+    let sources = passage_sources()?;
+    let markers = config.todo.markers.clone().unwrap_or(vec!["TODO".to_string(), "FIXME".to_string()]);
+    let entries: Vec<TodoEntry> = story.annotations(&markers, config.todo.comments.unwrap_or(true)).into_iter()
+        .filter(|a| assignee.is_none() || a.assignee.as_deref() == assignee)
+        .map(|a| {
+            // Line numbers are in the file if the passage text is unchanged by the build, otherwise in the passage.
+            let content = story.passage(&a.passage).map(|p| p.content.as_str());
+            let (file, line) = match sources.get(&a.passage) {
+                Some((file, line, source)) if Some(source.as_str()) == content => (Some(display_path(file)), line + a.line - 1),
+                _ => (None, a.line),
+            };
+            TodoEntry { passage: a.passage, file, line, marker: a.marker, assignee: a.assignee, text: a.text }
+        })
+        .collect();
+    if json {
+        println!("{}", serde_json::to_string_pretty(&entries)?);
+        return Ok(entries.len());
+    }
+    for e in &entries {
+        let location = match &e.file {
+            Some(f) => format!("{}:{}", f, e.line),
+            None => format!("{}, generated:{}", e.passage, e.line),
+        };
+        let assignee = e.assignee.as_ref().map(|a| format!(" ({})", a)).unwrap_or_default();
+        println!("{}: {}{}: {} [{}]", location, e.marker, assignee, e.text, e.passage);
+    }
+    info!("{} note(s)", entries.len());
+    Ok(entries.len())
+}
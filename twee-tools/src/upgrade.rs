@@ -0,0 +1,175 @@
+use std::{ops::Range, path::Path};
+
+use regex::Regex;
+use serde_json::Value;
+use tracing::{info, warn};
+use twee_build::{config_from_source, read_file, vendored_format_version, write_output, StoryFormat};
+use twee_parser::{parse_twee3, FormatVersion};
+
+/// Turns the `prebuild` and `postbuild` lists of older configs into command tables, keeping comments and formatting.
+/// It's the only config migration so far. Returns the new source and the changes made.
+fn migrate_config(source: &str) -> anyhow::Result<(String, Vec<String>)> {
+    let mut doc = source.parse::<toml_edit::Document>()?;
+    let mut changes = vec![];
+    for stage in ["prebuild", "postbuild"] {
+        // Older configs have a single command as a list of the program and its arguments.
+        let Some(argv) = doc.get(stage).and_then(|i| i.as_array()).filter(|a| ! a.is_empty() && a.iter().all(|v| v.is_str())) else {
+            continue;
+        };
+        let mut command = toml_edit::Table::new();
+        command.insert("argv", toml_edit::value(argv.iter().filter_map(|v| v.as_str()).collect::<toml_edit::Array>()));
+        // The table goes after the others, with the comments above the list.
+        let (key, _) = doc.as_table().get_key_value(stage).unwrap();
+        let comments = key.leaf_decor().prefix().and_then(|p| p.as_str()).unwrap_or("").trim_start_matches('\n');
+        command.decor_mut().set_prefix(format!("\n{}", comments));
+        doc.remove(stage);
+        let mut commands = toml_edit::ArrayOfTables::new();
+        commands.push(command);
+        doc.insert(stage, toml_edit::Item::ArrayOfTables(commands));
+        changes.push(format!("Turned the {} list into a [[{}]] table", stage, stage));
+    }
+    Ok((doc.to_string(), changes))
+}
+
+/// Sets a string value in the StoryData passage of the twee source, keeping the rest of the file as it is.
+/// Returns `None` if the source has no StoryData passage.
+pub(crate) fn set_story_data(source: &str, key: &str, value: &str) -> Option<String> {
+    set_story_data_value(source, key, &Value::from(value))
+}
+
+/// Sets a value in the StoryData passage of the twee source, keeping the rest of the file as it is.
+/// Returns `None` if the source has no StoryData passage.
+pub(crate) fn set_story_data_value(source: &str, key: &str, value: &Value) -> Option<String> {
+    let header = Regex::new(r"(?m)^::\s*StoryData\b[^\n]*\n").unwrap();
+    let m = header.find(source)?;
+    let body_end = source[m.end()..].find("\n::").map(|i| m.end() + i).unwrap_or(source.len());
+    let body = &source[m.end()..body_end];
+    let entry = format!("\"{}\": {}", key, serde_json::to_string(value).unwrap());
+    let i = body.find('{')?;
+    let body = if let Some(r) = object_member(&body[i..], key) {
+        format!("{}{}{}", &body[..i + r.start], entry, &body[i + r.end..])
+    } else {
+        let rest = &body[i + 1..];
+        let comma = if rest.trim_start().starts_with('}') { "" } else { "," };
+        format!("{}\n  {}{}{}", &body[..=i], entry, comma, rest)
+    };
+    Some(format!("{}{}{}", &source[..m.end()], body, &source[body_end..]))
+}
+
+/// Finds the member with the key in the JSON object at the start of `object`, returning the range from its key to the
+/// end of its value. Members of nested objects aren't found. None if the object doesn't have the key or isn't valid.
+fn object_member(object: &str, key: &str) -> Option<Range<usize>> {
+    let whitespace = |i: usize| object[i..].len() - object[i..].trim_start().len();
+    let mut i = 1 + whitespace(1);
+    if object[i..].starts_with('}') {
+        return None;
+    }
+    loop {
+        let start = i;
+        let mut keys = serde_json::Deserializer::from_str(&object[i..]).into_iter::<String>();
+        let k = keys.next()?.ok()?;
+        i += keys.byte_offset();
+        i += whitespace(i);
+        i += object[i..].starts_with(':').then_some(1)?;
+        i += whitespace(i);
+        // The value can be any JSON.
+        let mut values = serde_json::Deserializer::from_str(&object[i..]).into_iter::<Value>();
+        values.next()?.ok()?;
+        i += values.byte_offset();
+        if k == key {
+            return Some(start..i);
+        }
+        i += whitespace(i);
+        i += object[i..].starts_with(',').then_some(1)?;
+        i += whitespace(i);
+    }
+}
+
+/// Migrates the project in the current directory to the current twee-tools version, printing the steps that have
+/// to be done by hand.
+pub(crate) fn upgrade() -> anyhow::Result<()> {
+    let mut manual = vec![];
+    let source = read_file("config.toml")?;
+    let (source, changes) = migrate_config(&source)?;
+    if ! changes.is_empty() {
+        for c in &changes {
+            info!("{}", c);
+        }
+        write_output("config.toml", source.as_bytes())?;
+    }
+    let config = config_from_source(&source)?;
+    let main = read_file(&config.main)?;
+    let (story, _) = parse_twee3(&main)?;
+    match story.meta.get("format").and_then(|f| f.as_str()).map(StoryFormat::from_name) {
+        Some(Ok(format)) => {
+            let latest = format.format_version();
+            let current = story.meta.get("format-version").and_then(|v| v.as_str());
+            match current.map(|c| (c, FormatVersion::parse(c), FormatVersion::parse(&latest))) {
+                Some((c, Some(c_v), Some(l_v))) if c_v.major != l_v.major => {
+                    manual.push(format!("{} {} is a different major version than the bundled {}. Upgrading to it needs changes to the story, see the {} documentation.", format.format_name(), c, latest, format.format_name()));
+                },
+                Some((_, Some(c_v), Some(l_v))) if c_v >= l_v => {},
+                _ => {
+                    if let Some(updated) = set_story_data(&main, "format-version", &latest) {
+                        info!("Set format-version to {} {}", format.format_name(), latest);
+                        write_output(&config.main, updated.as_bytes())?;
+                    } else {
+                        manual.push(format!("Set \"format-version\": \"{}\" in the StoryData passage.", latest));
+                    }
+                },
+            }
+            if let Some(vendored) = vendored_format_version(&config, format) {
+                if FormatVersion::parse(&vendored) < FormatVersion::parse(&latest) {
+                    manual.push(format!("{} {} is vendored, run `twee vendor` to vendor {}.", format.format_name(), vendored, latest));
+                }
+            }
+        },
+        Some(Err(e)) => manual.push(format!("{}. Set a bundled story format in the StoryData passage.", e)),
+        None => {
+            if Path::new(&config.main).exists() && ! main.contains("StoryData") {
+                manual.push(format!("Add a StoryData passage with the story format to {}.", config.main));
+            } else {
+                manual.push("Set the story format in the StoryData passage.".to_string());
+            }
+        },
+    }
+    if manual.is_empty() {
+        info!("No manual steps needed");
+    }
+    for m in manual {
+        warn!(code = "upgrade-manual", "Manual step needed: {}", m);
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn command_lists_become_tables() {
+        let source = "main = \"story.twee\"\nstyle = []\nscript = []\n# Generates the passages.\nprebuild = [\"make\", \"passages\"]\npostbuild = []\n\n[package]\noutput = \"story.zip\"\n";
+        let (migrated, changes) = migrate_config(source).unwrap();
+        assert_eq!(changes, ["Turned the prebuild list into a [[prebuild]] table"]);
+        assert!(migrated.contains("\n\n# Generates the passages.\n[[prebuild]]\nargv = [\"make\", \"passages\"]\n"), "{}", migrated);
+        assert!(migrated.contains("postbuild = []\n") && migrated.contains("[package]\noutput = \"story.zip\"\n"), "{}", migrated);
+        let config = config_from_source(&migrated).unwrap();
+        assert!(matches!(&config.prebuild[0].run, twee_build::CommandLine::Argv(argv) if argv == &["make", "passages"]));
+        assert_eq!(config.package.output.as_deref(), Some("story.zip"));
+        // Migrated configs are left as they are.
+        assert_eq!(migrate_config(&migrated).unwrap(), (migrated.clone(), vec![]));
+    }
+    
+    #[test]
+    fn story_data_top_level_keys() {
+        let source = ":: StoryData\n{\n  \"tag-colors\": {\"start\": \"red\"},\n  \"ifid\": \"X\"\n}\n\n:: Start\nHello\n";
+        let added = set_story_data(source, "start", "Intro").unwrap();
+        assert_eq!(added, ":: StoryData\n{\n  \"start\": \"Intro\",\n  \"tag-colors\": {\"start\": \"red\"},\n  \"ifid\": \"X\"\n}\n\n:: Start\nHello\n");
+        let changed = set_story_data(&added, "start", "Start").unwrap();
+        assert_eq!(changed, added.replace("Intro", "Start"));
+        let source = ":: StoryData\n{\"format\": \"Harlowe\", \"tag-colors\": {\"a\": \"red\"}}\n";
+        assert_eq!(set_story_data_value(source, "tag-colors", &serde_json::json!({"b": "red"})).unwrap(), ":: StoryData\n{\"format\": \"Harlowe\", \"tag-colors\": {\"b\":\"red\"}}\n");
+        assert_eq!(set_story_data(":: StoryData\n{}\n", "ifid", "X").unwrap(), ":: StoryData\n{\n  \"ifid\": \"X\"}\n");
+    }
+}
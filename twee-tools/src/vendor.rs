@@ -0,0 +1,61 @@
+use std::path::Path;
+
+use tracing::info;
+use twee_build::{build_story, fetch_remote, format_file, include_graph, is_url, load_config_in, parse_format_js, read_file, vendor_remote, write_output, IncludeKind, RemoteDirs, StoryFormat};
+
+/// Makes the story of the project in `dir` build without the network and the bundled formats: copies the story format
+/// it uses (or all bundled formats with `all`) into the vendor directory, downloads the format.js files of the
+/// `formats` config value there, and copies the remote files it includes there.
+pub(crate) fn vendor(dir: &Path, all: bool) -> anyhow::Result<()> {
+    let config = load_config_in(dir)?;
+    let story = build_story(&config, false)?;
+    let formats = if all {
+        vec![StoryFormat::Harlowe, StoryFormat::Chapbook, StoryFormat::Snowman, StoryFormat::Sugarcube]
+    } else {
+        vec![StoryFormat::of_story(&story)?]
+    };
+    for format in formats {
+        let f = format_file(&config, &format.format_name(), &format.format_version());
+        write_output(&f, format.format_json().as_bytes())?;
+        info!("Vendored {} {} into {}", format.format_name(), format.format_version(), f.to_string_lossy());
+    }
+    let remote = RemoteDirs::of(&config);
+    for js in &config.formats {
+        let source = if is_url(js) { fetch_remote(&remote.cache, js)? } else { read_file(js)? };
+        let (name, version, json) = parse_format_js(js, &source)?;
+        let f = format_file(&config, &name, &version);
+        write_output(&f, serde_json::to_string(&json)?.as_bytes())?;
+        info!("Vendored {} {} from {} into {}", name, version, js, f.to_string_lossy());
+    }
+    let graph = include_graph().unwrap_or_default();
+    for entry in graph.entries.iter().filter(|e| is_url(&e.path) && ! e.skipped) {
+        let f = vendor_remote(&remote, &entry.path)?;
+        let kind = if entry.kind == IncludeKind::Passage { "passage content" } else { "include" };
+        info!("Vendored the {} {} into {}", kind, entry.path, f.to_string_lossy());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use twee_build::{build_html, format_source};
+    
+    use super::*;
+    
+    #[test]
+    fn vendor_formats() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        std::fs::write(dir.join("config.toml"), "main = \"main.twee\"\nstyle = []\nscript = []\nprebuild = []\nformats = [\"format.js\"]\n").unwrap();
+        std::fs::write(dir.join("main.twee"), ":: StoryTitle\nVendored\n\n:: StoryData\n{\"ifid\": \"8F0A7B1C-3A8E-4C5D-9C2B-1D2E3F4A5B6C\", \"format\": \"SugarCube\", \"format-version\": \"2.37.3\"}\n\n:: Start\nHi\n").unwrap();
+        std::fs::write(dir.join("format.js"), "window.storyFormat({\"name\":\"SugarCube\",\"version\":\"2.37.3\",\"source\":\"<html>{{STORY_NAME}}</html>\"});").unwrap();
+        vendor(dir, false).unwrap();
+        assert!(dir.join("vendor/formats/sugarcube-2.36.1.json").exists());
+        assert!(dir.join("vendor/formats/sugarcube-2.37.3.json").exists());
+        // Builds use the downloaded version the story pins.
+        let config = load_config_in(dir).unwrap();
+        let story = build_story(&config, false).unwrap();
+        assert_eq!(format_source(StoryFormat::Sugarcube, &story, &config).unwrap(), "<html>{{STORY_NAME}}</html>");
+        assert_eq!(build_html(StoryFormat::Sugarcube, &story, &config).unwrap(), "<html>Vendored</html>");
+    }
+}
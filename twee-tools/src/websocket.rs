@@ -0,0 +1,204 @@
+use std::{io::{Read, Write}, net::{Shutdown, TcpStream}, sync::{Arc, Mutex}, time::Duration};
+
+use base64::Engine;
+use serde_json::{json, Value};
+use sha1::{Digest, Sha1};
+use tracing::debug;
+
+/// The GUID the server appends to the key of the client in the handshake, from RFC 6455.
+const HANDSHAKE_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// The `Sec-WebSocket-Accept` answer to the `Sec-WebSocket-Key` of a client.
+pub(crate) fn accept_key(key: &str) -> String {
+    base64::engine::general_purpose::STANDARD.encode(Sha1::digest((key.trim().to_string() + HANDSHAKE_GUID).as_bytes()))
+}
+
+/// A frame with the opcode, which isn't masked when sent by the server.
+fn frame(opcode: u8, payload: &[u8]) -> Vec<u8> {
+    let mut frame = vec![0x80 | opcode];
+    let len = payload.len();
+    if len < 126 {
+        frame.push(len as u8);
+    } else if len <= u16::MAX as usize {
+        frame.push(126);
+        frame.extend_from_slice(&(len as u16).to_be_bytes());
+    } else {
+        frame.push(127);
+        frame.extend_from_slice(&(len as u64).to_be_bytes());
+    }
+    frame.extend_from_slice(payload);
+    frame
+}
+
+fn text_frame(text: &str) -> Vec<u8> {
+    frame(0x1, text.as_bytes())
+}
+
+/// Messages from clients larger than this close the connection.
+const MAX_MESSAGE: u64 = 64 * 1024;
+
+/// The opcodes of the frames a client sends that are handled.
+enum Frame {
+    Text(String),
+    Close(Vec<u8>),
+    Ping(Vec<u8>),
+    Other,
+}
+
+/// Reads a frame sent by a client, which is always masked. Fragmented messages aren't supported, as the clients only
+/// send small messages.
+fn read_frame(stream: &mut impl Read) -> std::io::Result<Frame> {
+    let mut head = [0; 2];
+    stream.read_exact(&mut head)?;
+    let len = match head[1] & 0x7f {
+        126 => {
+            let mut len = [0; 2];
+            stream.read_exact(&mut len)?;
+            u16::from_be_bytes(len) as u64
+        },
+        127 => {
+            let mut len = [0; 8];
+            stream.read_exact(&mut len)?;
+            u64::from_be_bytes(len)
+        },
+        len => len as u64,
+    };
+    if len > MAX_MESSAGE {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "message too large"));
+    }
+    let mut mask = [0; 4];
+    stream.read_exact(&mut mask)?;
+    let mut payload = vec![0; len as usize];
+    stream.read_exact(&mut payload)?;
+    for (i, b) in payload.iter_mut().enumerate() {
+        *b ^= mask[i % 4];
+    }
+    Ok(match head[0] & 0x0f {
+        0x1 => Frame::Text(String::from_utf8_lossy(&payload).to_string()),
+        0x8 => Frame::Close(payload),
+        0x9 => Frame::Ping(payload),
+        _ => Frame::Other,
+    })
+}
+
+/// The WebSocket clients that receive the events of the API or of `serve`.
+#[derive(Default)]
+pub(crate) struct Feed {
+    clients: Mutex<Vec<TcpStream>>,
+    /// Whether `navigate` messages of clients are sent to the other clients, see [Feed::sync_navigation].
+    sync: bool,
+    /// The passage of the last `navigate` message, sent to new clients.
+    passage: Mutex<Option<String>>,
+}
+
+impl Feed {
+    /// A feed that also syncs the navigation of the clients: when a client sends a `navigate` message with the
+    /// `passage` it went to, the message is sent to the other clients, so they go there too.
+    pub(crate) fn sync_navigation() -> Feed {
+        Feed { sync: true, ..Default::default() }
+    }
+
+    /// Whether the feed syncs the navigation of the clients.
+    pub(crate) fn syncs_navigation(&self) -> bool {
+        self.sync
+    }
+
+    /// Finishes the handshake with a client that asked to upgrade to a WebSocket and adds it to the clients.
+    pub(crate) fn accept(self: &Arc<Self>, mut stream: TcpStream, key: &str) -> std::io::Result<()> {
+        let response = format!("HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n", accept_key(key));
+        stream.write_all(response.as_bytes())?;
+        // A client that stops reading must not block the others, but clients may wait long between messages.
+        stream.set_write_timeout(Some(Duration::from_secs(5)))?;
+        stream.set_read_timeout(None)?;
+        if let Some(passage) = self.passage.lock().unwrap().as_ref() {
+            stream.write_all(&text_frame(&json!({ "type": "navigate", "passage": passage }).to_string()))?;
+        }
+        let mut reader = stream.try_clone()?;
+        self.clients.lock().unwrap().push(stream);
+        let feed = self.clone();
+        std::thread::spawn(move || loop {
+            match read_frame(&mut reader) {
+                Ok(Frame::Text(text)) => feed.receive(&text),
+                Ok(Frame::Ping(payload)) => {
+                    // Locked, so the pong isn't sent in the middle of an event.
+                    let _clients = feed.clients.lock().unwrap();
+                    if reader.write_all(&frame(0xA, &payload)).is_err() {
+                        break;
+                    }
+                },
+                Ok(Frame::Close(payload)) => {
+                    // The close is echoed with its status code, and the client is dropped on the next broadcast.
+                    let _clients = feed.clients.lock().unwrap();
+                    let _ = reader.write_all(&frame(0x8, &payload[..payload.len().min(2)]));
+                    let _ = reader.shutdown(Shutdown::Both);
+                    break;
+                },
+                Ok(Frame::Other) => {},
+                Err(e) => {
+                    debug!("Event client went away: {}", e);
+                    let _ = reader.shutdown(Shutdown::Both);
+                    break;
+                },
+            }
+        });
+        Ok(())
+    }
+
+    /// Handles a message of a client.
+    fn receive(&self, text: &str) {
+        let Ok(message) = serde_json::from_str::<Value>(text) else {
+            debug!("Ignored a message that isn't JSON: {}", text);
+            return;
+        };
+        match (message["type"].as_str(), message["passage"].as_str()) {
+            (Some("navigate"), Some(passage)) if self.sync => {
+                *self.passage.lock().unwrap() = Some(passage.to_string());
+                self.broadcast(&json!({ "type": "navigate", "passage": passage }));
+            },
+            _ => debug!("Ignored message: {}", text),
+        }
+    }
+
+    /// Sends the event to all clients as JSON, dropping clients that have gone away.
+    pub(crate) fn broadcast(&self, event: &Value) {
+        let frame = text_frame(&event.to_string());
+        self.clients.lock().unwrap().retain_mut(|c| match c.write_all(&frame) {
+            Ok(()) => true,
+            Err(e) => {
+                debug!("Dropped event client: {}", e);
+                false
+            },
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn handshake_accept_key() {
+        // The example of RFC 6455, section 1.3.
+        assert_eq!(accept_key("dGhlIHNhbXBsZSBub25jZQ=="), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+    }
+    
+    #[test]
+    fn client_frames() {
+        // A masked "Hello" and a masked ping with "Hello", from RFC 6455, section 5.7.
+        let mut hello: &[u8] = &[0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58];
+        assert!(matches!(read_frame(&mut hello), Ok(Frame::Text(t)) if t == "Hello"));
+        let mut ping: &[u8] = &[0x89, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58];
+        assert!(matches!(read_frame(&mut ping), Ok(Frame::Ping(p)) if p == b"Hello"));
+        let mut close: &[u8] = &[0x88, 0x82, 0, 0, 0, 0, 0x03, 0xe8];
+        assert!(matches!(read_frame(&mut close), Ok(Frame::Close(p)) if p == [0x03, 0xe8]));
+        let mut too_large: &[u8] = &[0x81, 0xff, 0, 0, 0, 0, 0, 0, 0, 1];
+        assert!(read_frame(&mut too_large).is_err());
+    }
+    
+    #[test]
+    fn server_frames() {
+        assert_eq!(text_frame("Hello"), [0x81, 0x05, 0x48, 0x65, 0x6c, 0x6c, 0x6f]);
+        assert_eq!(text_frame(&"a".repeat(256))[..4], [0x81, 126, 0x01, 0x00]);
+        assert_eq!(frame(0xA, b"Hello")[..2], [0x8a, 0x05]);
+    }
+}
@@ -0,0 +1,113 @@
+use std::{path::{Path, PathBuf}, sync::{atomic::{AtomicUsize, Ordering}, Mutex}, time::{Duration, Instant}};
+
+use serde::Deserialize;
+use tracing::{error, error_span, warn};
+use twee_build::{glob, read_file, track_input, SourceCache};
+
+use crate::build::Error;
+
+pub(crate) const WORKSPACE_FILE: &str = "workspace.toml";
+
+/// A workspace groups multiple story projects, each in their own directory with a config.toml.
+#[derive(Deserialize)]
+pub struct Workspace {
+    pub members: Vec<String>,
+}
+
+impl Workspace {
+    /// Loads the workspace.toml in the current directory, if there is one.
+    pub(crate) fn load() -> anyhow::Result<Option<Workspace>> {
+        track_input(Path::new(WORKSPACE_FILE));
+        if ! PathBuf::from(WORKSPACE_FILE).exists() {
+            return Ok(None);
+        }
+        Ok(Some(toml::from_str(&read_file(WORKSPACE_FILE)?)?))
+    }
+    
+    /// Resolves the member patterns to the project directories, in the order they are listed.
+    pub(crate) fn member_dirs(&self) -> anyhow::Result<Vec<PathBuf>> {
+        let mut dirs: Vec<PathBuf> = vec![];
+        for m in &self.members {
+            let matches = glob(m, PathBuf::new())?;
+            if matches.is_empty() {
+                return Err(Error::DirNotFound(m.clone()).into());
+            }
+            for d in matches {
+                if d.join("config.toml").exists() {
+                    if ! dirs.contains(&d) {
+                        dirs.push(d);
+                    }
+                } else {
+                    warn!(code = "workspace-member", "Workspace member {} has no config.toml and has been skipped", d.to_string_lossy());
+                }
+            }
+        }
+        Ok(dirs)
+    }
+    
+    /// Runs `f` with the directory of each member.  
+    /// Included files are cached across members, so shared includes are only read once.
+    pub(crate) fn for_each_member<T, F>(&self, mut f: F) -> anyhow::Result<Vec<T>> where F: FnMut(&Path) -> anyhow::Result<T> {
+        let members = self.member_dirs()?;
+        SourceCache::new().enter(|| {
+            let mut res = vec![];
+            for m in members {
+                res.push(f(&m).map_err(|e| e.context(format!("Workspace member {}", m.to_string_lossy())))?);
+            }
+            Ok(res)
+        })
+    }
+    
+    /// Runs `f` with the directory of each member and sums the problems it finds. A member `f` fails for is logged and
+    /// counted as one problem, and the other members are still run.
+    pub(crate) fn count_problems<F>(&self, mut f: F) -> anyhow::Result<usize> where F: FnMut(&Path) -> anyhow::Result<usize> {
+        let members = self.member_dirs()?;
+        Ok(SourceCache::new().enter(|| members.iter().map(|m| {
+            let _span = error_span!("member", dir = %m.to_string_lossy()).entered();
+            f(m).unwrap_or_else(|e| {
+                error!("{:#}", e);
+                1
+            })
+        }).sum()))
+    }
+    
+    /// Runs `build` with the directory of each member on up to `jobs` threads at the same time.  
+    /// The threads share the cache of included files and story formats, so shared files are still only read once.
+    /// The logs of each build are in a span with the member directory, so they can be told apart.
+    pub(crate) fn build_parallel<F>(&self, jobs: usize, build: F) -> anyhow::Result<Vec<MemberBuild>> where F: Fn(&Path) -> anyhow::Result<PathBuf> + Sync {
+        let members = self.member_dirs()?;
+        let cache = SourceCache::new();
+        let next = AtomicUsize::new(0);
+        let results: Mutex<Vec<Option<MemberBuild>>> = Mutex::new(members.iter().map(|_| None).collect());
+        std::thread::scope(|s| {
+            for _ in 0..jobs.clamp(1, members.len().max(1)) {
+                s.spawn(|| loop {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(dir) = members.get(i) else {
+                        break;
+                    };
+                    // At the error level, so the member is also shown when only errors are logged.
+                    let _span = error_span!("member", dir = %dir.to_string_lossy()).entered();
+                    let start = Instant::now();
+                    let out = match cache.enter(|| build(dir)) {
+                        Ok(out) => Some(out),
+                        Err(e) => {
+                            error!("Build failed: {:#}", e);
+                            None
+                        },
+                    };
+                    results.lock().unwrap()[i] = Some(MemberBuild { dir: dir.clone(), out, time: start.elapsed() });
+                });
+            }
+        });
+        Ok(results.into_inner().unwrap().into_iter().flatten().collect())
+    }
+}
+
+/// The result of building a workspace member with [Workspace::build_parallel].
+pub(crate) struct MemberBuild {
+    pub dir: PathBuf,
+    /// The absolute path of the output, or None if the build failed.
+    pub out: Option<PathBuf>,
+    pub time: Duration,
+}
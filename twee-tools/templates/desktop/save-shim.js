@@ -0,0 +1,49 @@
+/* Generated by twee-tools. Mirrors localStorage, where story formats keep their saves, into the app data directory. */
+(function () {
+	if (!window.__TAURI__) {
+		return;
+	}
+	var invoke = window.__TAURI__.core.invoke;
+	var dump = function () {
+		var data = {};
+		for (var i = 0; i < localStorage.length; i++) {
+			var key = localStorage.key(i);
+			data[key] = localStorage.getItem(key);
+		}
+		return JSON.stringify(data);
+	};
+	var timeout = null;
+	var store = function () {
+		clearTimeout(timeout);
+		timeout = setTimeout(function () {
+			invoke("store_saves", { data: dump() });
+		}, 500);
+	};
+	var setItem = Storage.prototype.setItem;
+	var removeItem = Storage.prototype.removeItem;
+	Storage.prototype.setItem = function () {
+		setItem.apply(this, arguments);
+		if (this === localStorage) {
+			store();
+		}
+	};
+	Storage.prototype.removeItem = function () {
+		removeItem.apply(this, arguments);
+		if (this === localStorage) {
+			store();
+		}
+	};
+	if (!sessionStorage.getItem("twee-tools-saves-restored")) {
+		sessionStorage.setItem("twee-tools-saves-restored", "1");
+		invoke("load_saves").then(function (saved) {
+			if (saved && saved !== dump()) {
+				var data = JSON.parse(saved);
+				localStorage.clear();
+				for (var key in data) {
+					setItem.call(localStorage, key, data[key]);
+				}
+				location.reload();
+			}
+		});
+	}
+})();
@@ -0,0 +1,48 @@
+(function () {
+    var build = "{{BUILD_ID}}";
+    var file = "{{RELOAD_FILE}}";
+    var key = "twee-hot-reload";
+    // Harlowe keeps its session in sessionStorage and resumes it on reload by itself.
+    // SugarCube's state is saved explicitly and restored once the story is ready.
+    function saveState() {
+        try {
+            if (window.SugarCube && SugarCube.State && SugarCube.State.marshalForSave) {
+                sessionStorage.setItem(key, JSON.stringify(SugarCube.State.marshalForSave()));
+            }
+        } catch (e) {
+            console.warn("Could not save the story state for hot reload", e);
+        }
+    }
+    function restoreState() {
+        var saved = sessionStorage.getItem(key);
+        if (saved === null) {
+            return;
+        }
+        sessionStorage.removeItem(key);
+        try {
+            SugarCube.State.unmarshalForSave(JSON.parse(saved));
+            SugarCube.Engine.show();
+        } catch (e) {
+            console.warn("Could not restore the story state after hot reload", e);
+        }
+    }
+    window.__tweeHotReload = function (id) {
+        if (id !== build) {
+            saveState();
+            location.reload();
+        }
+    };
+    document.addEventListener("DOMContentLoaded", function () {
+        if (window.jQuery) {
+            jQuery(document).one(":storyready", restoreState);
+        }
+    });
+    setInterval(function () {
+        var s = document.createElement("script");
+        s.src = file + "?" + Date.now();
+        s.onload = s.onerror = function () {
+            s.remove();
+        };
+        document.head.appendChild(s);
+    }, 1000);
+})();
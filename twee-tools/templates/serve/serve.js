@@ -0,0 +1,74 @@
+(function () {
+    var syncNavigation = {{SYNC_NAVIGATION}};
+    // The passage the story is at, and how to go to another one, for each story format.
+    function current() {
+        try {
+            if (window.SugarCube && SugarCube.State) {
+                return SugarCube.State.passage;
+            }
+            if (window.Harlowe && Harlowe.API_ACCESS) {
+                return Harlowe.API_ACCESS.STATE.passage;
+            }
+            if (window.engine && engine.state) {
+                var trail = engine.state.get("trail");
+                return trail && trail[trail.length - 1];
+            }
+        } catch (e) {}
+        return undefined;
+    }
+    function go(name) {
+        if (window.SugarCube && SugarCube.Engine) {
+            SugarCube.Engine.play(name);
+        } else if (window.Harlowe && Harlowe.API_ACCESS) {
+            Harlowe.API_ACCESS.ENGINE.goToPassage(name);
+        } else if (typeof window.go === "function") {
+            window.go(name);
+        }
+    }
+    // The last passage that was shown here or sent by the others, and the passage to go to once the story is ready.
+    var shown;
+    var next;
+    var socket;
+    function connect() {
+        socket = new WebSocket((location.protocol === "https:" ? "wss://" : "ws://") + location.host + "/events");
+        socket.onmessage = function (message) {
+            var event = JSON.parse(message.data);
+            if (event.type === "reload") {
+                location.reload();
+            } else if (event.type === "navigate") {
+                next = event.passage;
+            }
+        };
+        socket.onclose = function () {
+            setTimeout(connect, 1000);
+        };
+    }
+    connect();
+    if (! syncNavigation) {
+        return;
+    }
+    setInterval(function () {
+        var passage = current();
+        if (passage === undefined) {
+            return;
+        }
+        if (next !== undefined) {
+            var target = next;
+            next = undefined;
+            if (target !== passage) {
+                // Set first, so the passage isn't sent back to the others.
+                shown = target;
+                go(target);
+                return;
+            }
+        }
+        if (passage !== shown) {
+            // The passage the story starts at isn't sent, so opening the story doesn't move the others.
+            var started = shown !== undefined;
+            shown = passage;
+            if (started && socket.readyState === WebSocket.OPEN) {
+                socket.send(JSON.stringify({ type: "navigate", passage: passage }));
+            }
+        }
+    }, 250);
+})();